@@ -7,7 +7,7 @@ fn monitoring_benchmark(c: &mut Criterion) {
     let rt = Runtime::new().unwrap();
 
     let guardian = rt.block_on(async {
-        AngeGardien::new().await.unwrap()
+        AngeGardien::new(None).await.unwrap()
     });
 
     c.bench_function("system_state_update", |b| {
@@ -0,0 +1,896 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{SecondsFormat, Utc};
+use serde::{Serialize, Deserialize};
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use sysinfo::{System, SystemExt};
+use tokio::net::{UdpSocket, UnixDatagram};
+use tokio::sync::{Mutex, Notify, RwLock};
+use crate::{SecurityAlert, AlertSeverity};
+use tracing::{error, info, warn};
+
+pub const RATE_LIMITER_SOURCE: &str = "Alert Rate Limiter";
+
+const SYSLOG_APP_NAME: &str = "ange-gardien";
+// security/authorization messages
+const SYSLOG_FACILITY_AUTH: u8 = 4;
+// 32473 is the IANA example enterprise number, reserved for private SD-IDs
+const SYSLOG_SD_ID: &str = "alert@32473";
+
+const WEBHOOK_MAX_ATTEMPTS: u32 = 3;
+const WEBHOOK_INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Outbound alert destinations built from the config file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AlertingConfig {
+    /// Repeats of an alert within this window increment its count instead of
+    /// adding a new alert
+    pub dedup_window_secs: u64,
+    /// Most recent alerts kept in the live state; older ones remain in the database
+    pub max_live_alerts: usize,
+    pub syslog: Option<SyslogConfig>,
+    pub webhooks: Vec<WebhookConfig>,
+}
+
+impl Default for AlertingConfig {
+    fn default() -> Self {
+        Self {
+            dedup_window_secs: 300,
+            max_live_alerts: 100,
+            syslog: None,
+            webhooks: Vec::new(),
+        }
+    }
+}
+
+impl AlertingConfig {
+    pub fn dedup_window(&self) -> Duration {
+        Duration::from_secs(self.dedup_window_secs)
+    }
+
+    /// How often rate-limit summaries are due: the shortest summary interval
+    /// among the configured sinks, at least a second.
+    pub fn summary_interval(&self) -> Duration {
+        self.syslog.iter().map(|syslog| &syslog.rate_limit)
+            .chain(self.webhooks.iter().map(|webhook| &webhook.rate_limit))
+            .map(RateLimitConfig::summary_interval)
+            .min()
+            .unwrap_or_else(|| RateLimitConfig::default().summary_interval())
+            .max(Duration::from_secs(1))
+    }
+
+    /// Merges `incoming` into the live alert list and trims it to `max_live_alerts`.
+    /// Returns the alerts that were new rather than repeats.
+    pub fn record(&self, live: &mut Vec<SecurityAlert>, incoming: Vec<SecurityAlert>) -> Vec<SecurityAlert> {
+        let fresh = deduplicate(live, incoming, self.dedup_window());
+        trim_oldest(live, self.max_live_alerts);
+        fresh
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyslogConfig {
+    pub target: SyslogTarget,
+    pub rate_limit: RateLimitConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyslogTarget {
+    /// Local syslog socket, e.g. `/var/run/syslog` on macOS or `/dev/log` for journald
+    Unix(PathBuf),
+    /// Remote collector over UDP
+    Udp(SocketAddr),
+}
+
+impl Default for SyslogConfig {
+    fn default() -> Self {
+        let socket = if cfg!(target_os = "macos") { "/var/run/syslog" } else { "/dev/log" };
+        Self {
+            target: SyslogTarget::Unix(PathBuf::from(socket)),
+            rate_limit: RateLimitConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// JSON body with `{{severity}}`, `{{description}}`, `{{source}}`, `{{timestamp}}`
+    /// and `{{recommendation}}` placeholders, e.g. `{"text": "[{{severity}}] {{description}}"}`
+    /// for Slack. Defaults to the alert serialized as JSON.
+    pub template: Option<String>,
+    /// Alerts waiting for delivery; the oldest is dropped when full
+    pub queue_size: usize,
+    pub timeout_secs: u64,
+    pub rate_limit: RateLimitConfig,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            template: None,
+            queue_size: 100,
+            timeout_secs: 10,
+            rate_limit: RateLimitConfig::default(),
+        }
+    }
+}
+
+/// An outbound destination for security alerts (syslog, webhooks, ...).
+#[async_trait]
+pub trait AlertSink: Send + Sync {
+    fn name(&self) -> &str;
+
+    async fn emit(&self, alert: &SecurityAlert) -> Result<()>;
+
+    /// Sends anything held back, such as a rate limiter's summary of
+    /// suppressed alerts. Called on a timer, so nothing is stranded once
+    /// alerts stop arriving.
+    async fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Fans alerts out to every registered sink. A failing sink is logged and
+/// skipped so it never affects the others or the monitoring loop.
+#[derive(Default)]
+pub struct AlertDispatcher {
+    sinks: RwLock<Vec<Arc<dyn AlertSink>>>,
+}
+
+impl AlertDispatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds the sinks configured in `config`, each behind its own rate limiter.
+    pub async fn from_config(config: &AlertingConfig) -> Result<Self> {
+        let dispatcher = Self::new();
+        if let Some(syslog) = &config.syslog {
+            let sink = SyslogSink::connect(&syslog.target).await?;
+            dispatcher.add_sink(Arc::new(RateLimitedSink::new(sink, syslog.rate_limit.clone()))).await;
+        }
+        for webhook in &config.webhooks {
+            let sink = WebhookSink::new(webhook.clone())?;
+            dispatcher.add_sink(Arc::new(RateLimitedSink::new(sink, webhook.rate_limit.clone()))).await;
+        }
+        Ok(dispatcher)
+    }
+
+    pub async fn add_sink(&self, sink: Arc<dyn AlertSink>) {
+        info!("Registered alert sink {}", sink.name());
+        self.sinks.write().await.push(sink);
+    }
+
+    pub async fn sink_count(&self) -> usize {
+        self.sinks.read().await.len()
+    }
+
+    /// Logs each alert as a structured event, then sends it to every sink.
+    pub async fn dispatch(&self, alerts: &[SecurityAlert]) {
+        if alerts.is_empty() {
+            return;
+        }
+
+        for alert in alerts {
+            warn!(
+                severity = %alert.severity,
+                source = %alert.source,
+                pid = alert.pid,
+                would_enforce = alert.would_enforce,
+                "{}",
+                alert.description
+            );
+        }
+
+        let sinks = self.sinks.read().await;
+        for sink in sinks.iter() {
+            for alert in alerts {
+                if let Err(e) = sink.emit(alert).await {
+                    error!("Failed to send alert to {}: {}", sink.name(), e);
+                }
+            }
+        }
+    }
+
+    /// Flushes every sink, e.g. sending rate-limit summaries that are due.
+    pub async fn flush(&self) {
+        let sinks = self.sinks.read().await;
+        for sink in sinks.iter() {
+            if let Err(e) = sink.flush().await {
+                error!("Failed to flush alert sink {}: {}", sink.name(), e);
+            }
+        }
+    }
+}
+
+enum SyslogTransport {
+    Unix(UnixDatagram),
+    Udp(UdpSocket),
+}
+
+/// Sends alerts as RFC 5424 syslog messages with the alert fields as structured data.
+pub struct SyslogSink {
+    transport: SyslogTransport,
+    hostname: String,
+}
+
+impl SyslogSink {
+    pub async fn connect(target: &SyslogTarget) -> Result<Self> {
+        let transport = match target {
+            SyslogTarget::Unix(path) => {
+                let socket = UnixDatagram::unbound()?;
+                socket.connect(path)?;
+                SyslogTransport::Unix(socket)
+            }
+            SyslogTarget::Udp(address) => {
+                let bind: SocketAddr = if address.is_ipv4() {
+                    ([0, 0, 0, 0], 0).into()
+                } else {
+                    ([0u16; 8], 0).into()
+                };
+                let socket = UdpSocket::bind(bind).await?;
+                socket.connect(address).await?;
+                SyslogTransport::Udp(socket)
+            }
+        };
+
+        Ok(Self {
+            transport,
+            hostname: System::new().host_name().unwrap_or_else(|| "-".to_string()),
+        })
+    }
+
+    fn format(&self, alert: &SecurityAlert) -> String {
+        format_rfc5424(alert, &self.hostname, std::process::id())
+    }
+}
+
+#[async_trait]
+impl AlertSink for SyslogSink {
+    fn name(&self) -> &str {
+        "syslog"
+    }
+
+    async fn emit(&self, alert: &SecurityAlert) -> Result<()> {
+        let message = self.format(alert);
+        match &self.transport {
+            SyslogTransport::Unix(socket) => socket.send(message.as_bytes()).await?,
+            SyslogTransport::Udp(socket) => socket.send(message.as_bytes()).await?,
+        };
+        Ok(())
+    }
+}
+
+fn syslog_severity(severity: AlertSeverity) -> u8 {
+    match severity {
+        AlertSeverity::Critical => 2, // crit
+        AlertSeverity::High => 3,     // err
+        AlertSeverity::Medium => 4,   // warning
+        AlertSeverity::Low => 5,      // notice
+    }
+}
+
+fn format_rfc5424(alert: &SecurityAlert, hostname: &str, pid: u32) -> String {
+    let priority = SYSLOG_FACILITY_AUTH * 8 + syslog_severity(alert.severity);
+    let mut structured = format!(
+        "[{} severity=\"{}\" source=\"{}\"",
+        SYSLOG_SD_ID,
+        alert.severity,
+        escape_sd_value(&alert.source)
+    );
+    if let Some(recommendation) = &alert.recommendation {
+        structured.push_str(&format!(" recommendation=\"{}\"", escape_sd_value(recommendation)));
+    }
+    structured.push(']');
+
+    format!(
+        "<{}>1 {} {} {} {} ALERT {} \u{feff}{}",
+        priority,
+        alert.timestamp.to_rfc3339_opts(SecondsFormat::Millis, true),
+        hostname,
+        SYSLOG_APP_NAME,
+        pid,
+        structured,
+        alert.description
+    )
+}
+
+/// Escapes `"`, `\` and `]`, which RFC 5424 forbids unescaped in PARAM-VALUE.
+fn escape_sd_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Adds `incoming` to `existing`, folding each alert into an earlier one with the
+/// same severity, source and rule (or description, for alerts without a rule)
+/// first raised within `window`. Returns the alerts that were genuinely new,
+/// which are the ones worth sending out.
+pub fn deduplicate(
+    existing: &mut Vec<SecurityAlert>,
+    incoming: Vec<SecurityAlert>,
+    window: Duration,
+) -> Vec<SecurityAlert> {
+    let window = chrono::Duration::from_std(window).unwrap_or(chrono::TimeDelta::MAX);
+    let mut fresh = Vec::new();
+
+    for alert in incoming {
+        let duplicate = existing.iter_mut().rev().find(|candidate| {
+            candidate.severity == alert.severity
+                && candidate.source == alert.source
+                && match (&candidate.rule, &alert.rule) {
+                    (Some(a), Some(b)) => a == b,
+                    (None, None) => candidate.description == alert.description,
+                    _ => false,
+                }
+                && alert.timestamp - candidate.timestamp <= window
+        });
+
+        match duplicate {
+            Some(candidate) => candidate.count += alert.count,
+            None => {
+                existing.push(alert.clone());
+                fresh.push(alert);
+            }
+        }
+    }
+
+    fresh
+}
+
+/// Drops the oldest alerts so at most `max` remain. Alerts are appended as they
+/// are raised, so the front of the list is the oldest.
+pub fn trim_oldest(alerts: &mut Vec<SecurityAlert>, max: usize) {
+    if alerts.len() > max {
+        let excess = alerts.len() - max;
+        alerts.drain(..excess);
+    }
+}
+
+/// Bounded FIFO that drops its oldest entry when full. A tokio `mpsc` channel
+/// can only reject new items, but for alerts the newest are the most useful.
+struct DropOldestQueue {
+    items: std::sync::Mutex<VecDeque<SecurityAlert>>,
+    capacity: usize,
+    notify: Notify,
+    closed: std::sync::atomic::AtomicBool,
+}
+
+impl DropOldestQueue {
+    fn new(capacity: usize) -> Self {
+        Self {
+            items: std::sync::Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity: capacity.max(1),
+            notify: Notify::new(),
+            closed: std::sync::atomic::AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the alert that was dropped to make room, if any.
+    fn push(&self, alert: SecurityAlert) -> Option<SecurityAlert> {
+        let dropped = {
+            let mut items = self.items.lock().unwrap();
+            let dropped = if items.len() >= self.capacity { items.pop_front() } else { None };
+            items.push_back(alert);
+            dropped
+        };
+        self.notify.notify_one();
+        dropped
+    }
+
+    /// Waits for the next alert; `None` once the queue is closed and drained.
+    async fn pop(&self) -> Option<SecurityAlert> {
+        loop {
+            if let Some(alert) = self.items.lock().unwrap().pop_front() {
+                return Some(alert);
+            }
+            if self.closed.load(std::sync::atomic::Ordering::Acquire) {
+                return None;
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    fn close(&self) {
+        self.closed.store(true, std::sync::atomic::Ordering::Release);
+        self.notify.notify_one();
+    }
+}
+
+/// POSTs alerts as JSON to a webhook. `emit` only enqueues, so a slow or
+/// unreachable endpoint never blocks the monitoring loop; a background task
+/// delivers with retries and exponential backoff.
+pub struct WebhookSink {
+    name: String,
+    queue: Arc<DropOldestQueue>,
+}
+
+impl WebhookSink {
+    pub fn new(config: WebhookConfig) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()?;
+        let queue = Arc::new(DropOldestQueue::new(config.queue_size));
+        let name = format!("webhook {}", config.url);
+
+        let worker_queue = Arc::clone(&queue);
+        tokio::spawn(async move {
+            while let Some(alert) = worker_queue.pop().await {
+                if let Err(e) = Self::deliver(&client, &config, &alert).await {
+                    error!("Failed to deliver alert to webhook {}: {}", config.url, e);
+                }
+            }
+        });
+
+        Ok(Self { name, queue })
+    }
+
+    async fn deliver(client: &reqwest::Client, config: &WebhookConfig, alert: &SecurityAlert) -> Result<()> {
+        let body = match &config.template {
+            Some(template) => render_template(template, alert),
+            None => serde_json::to_string(alert)?,
+        };
+
+        let mut backoff = WEBHOOK_INITIAL_BACKOFF;
+        for attempt in 1..=WEBHOOK_MAX_ATTEMPTS {
+            let result = client.post(&config.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.clone())
+                .send()
+                .await;
+
+            let retryable = match result {
+                Ok(response) if response.status().is_success() => return Ok(()),
+                Ok(response) if response.status().is_server_error() => {
+                    format!("server error {}", response.status())
+                }
+                Ok(response) => {
+                    return Err(anyhow::anyhow!("webhook rejected alert with {}", response.status()));
+                }
+                Err(e) if e.is_timeout() || e.is_connect() => e.to_string(),
+                Err(e) => return Err(e.into()),
+            };
+
+            if attempt == WEBHOOK_MAX_ATTEMPTS {
+                return Err(anyhow::anyhow!("giving up after {} attempts: {}", attempt, retryable));
+            }
+            warn!("Webhook delivery attempt {} failed ({}), retrying in {:?}", attempt, retryable, backoff);
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for WebhookSink {
+    fn drop(&mut self) {
+        self.queue.close();
+    }
+}
+
+#[async_trait]
+impl AlertSink for WebhookSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn emit(&self, alert: &SecurityAlert) -> Result<()> {
+        if let Some(dropped) = self.queue.push(alert.clone()) {
+            warn!("{} queue is full, dropped oldest alert: {}", self.name, dropped.description);
+        }
+        Ok(())
+    }
+}
+
+/// Fills the template placeholders with JSON-escaped alert fields.
+fn render_template(template: &str, alert: &SecurityAlert) -> String {
+    let escape = |value: &str| {
+        let quoted = serde_json::to_string(value).unwrap_or_default();
+        quoted[1..quoted.len() - 1].to_string()
+    };
+
+    template
+        .replace("{{severity}}", &alert.severity.to_string())
+        .replace("{{description}}", &escape(&alert.description))
+        .replace("{{source}}", &escape(&alert.source))
+        .replace("{{timestamp}}", &alert.timestamp.to_rfc3339())
+        .replace("{{recommendation}}", &escape(alert.recommendation.as_deref().unwrap_or("")))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Sustained number of alerts per second allowed through
+    pub rate_per_sec: f64,
+    /// Number of alerts that may be sent back-to-back before throttling
+    pub burst: u32,
+    /// How often suppressed alerts are coalesced into a summary message
+    pub summary_interval_secs: u64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            rate_per_sec: 1.0,
+            burst: 10,
+            summary_interval_secs: 60,
+        }
+    }
+}
+
+impl RateLimitConfig {
+    pub fn summary_interval(&self) -> Duration {
+        Duration::from_secs(self.summary_interval_secs)
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+#[derive(Default)]
+struct SuppressedAlerts {
+    total: u64,
+    by_description: HashMap<String, u64>,
+    max_severity: Option<AlertSeverity>,
+}
+
+/// Wraps a sink with a token-bucket limiter. Alerts over the limit are not sent
+/// individually; instead they are counted and periodically sent as one summary.
+/// Each sink gets its own limiter so a slow destination never throttles another.
+pub struct RateLimitedSink<S: AlertSink> {
+    inner: S,
+    config: RateLimitConfig,
+    bucket: Mutex<TokenBucket>,
+    suppressed: Mutex<SuppressedAlerts>,
+    last_summary: Mutex<Instant>,
+}
+
+impl<S: AlertSink> RateLimitedSink<S> {
+    pub fn new(inner: S, config: RateLimitConfig) -> Self {
+        let now = Instant::now();
+        Self {
+            bucket: Mutex::new(TokenBucket {
+                tokens: config.burst as f64,
+                last_refill: now,
+            }),
+            inner,
+            config,
+            suppressed: Mutex::new(SuppressedAlerts::default()),
+            last_summary: Mutex::new(now),
+        }
+    }
+
+    async fn try_acquire(&self) -> bool {
+        let mut bucket = self.bucket.lock().await;
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.config.rate_per_sec)
+            .min(self.config.burst as f64);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    async fn record_suppressed(&self, alert: &SecurityAlert) {
+        let mut suppressed = self.suppressed.lock().await;
+        suppressed.total += 1;
+        *suppressed.by_description.entry(alert.description.clone()).or_insert(0) += 1;
+        suppressed.max_severity = Some(match suppressed.max_severity {
+            Some(current) => current.max(alert.severity),
+            None => alert.severity,
+        });
+    }
+
+    /// Sends a summary of suppressed alerts if the summary interval has elapsed.
+    pub async fn flush_summary(&self) -> Result<()> {
+        let mut last_summary = self.last_summary.lock().await;
+        if last_summary.elapsed() < self.config.summary_interval() {
+            return Ok(());
+        }
+
+        let suppressed = std::mem::take(&mut *self.suppressed.lock().await);
+        *last_summary = Instant::now();
+        drop(last_summary);
+
+        if suppressed.total == 0 {
+            return Ok(());
+        }
+
+        info!(
+            "Sending summary of {} rate-limited alerts to {}",
+            suppressed.total,
+            self.inner.name()
+        );
+        self.inner.emit(&Self::summary_alert(&suppressed, self.config.summary_interval())).await
+    }
+
+    fn summary_alert(suppressed: &SuppressedAlerts, interval: Duration) -> SecurityAlert {
+        let mut top: Vec<(&String, &u64)> = suppressed.by_description.iter().collect();
+        top.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        let top = top.into_iter()
+            .take(3)
+            .map(|(description, count)| format!("{} (x{})", description, count))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        SecurityAlert {
+            timestamp: Utc::now(),
+            severity: suppressed.max_severity.unwrap_or(AlertSeverity::Low),
+            description: format!(
+                "{} alerts in the last {}s, top: {}",
+                suppressed.total,
+                interval.as_secs(),
+                top
+            ),
+            source: RATE_LIMITER_SOURCE.to_string(),
+            recommendation: None,
+            count: 1,
+            would_enforce: false,
+            rule: None,
+            pid: None,
+            remote_ip: None,
+        }
+    }
+}
+
+#[async_trait]
+impl<S: AlertSink> AlertSink for RateLimitedSink<S> {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    async fn emit(&self, alert: &SecurityAlert) -> Result<()> {
+        self.flush_summary().await?;
+
+        if self.try_acquire().await {
+            self.inner.emit(alert).await
+        } else {
+            warn!("Alert sink {} is rate limited, coalescing alert", self.inner.name());
+            self.record_suppressed(alert).await;
+            Ok(())
+        }
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.flush_summary().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct RecordingSink {
+        sent: Arc<Mutex<Vec<SecurityAlert>>>,
+    }
+
+    #[async_trait]
+    impl AlertSink for RecordingSink {
+        fn name(&self) -> &str {
+            "recording"
+        }
+
+        async fn emit(&self, alert: &SecurityAlert) -> Result<()> {
+            self.sent.lock().await.push(alert.clone());
+            Ok(())
+        }
+    }
+
+    fn alert(severity: AlertSeverity, description: &str) -> SecurityAlert {
+        SecurityAlert {
+            timestamp: Utc::now(),
+            severity,
+            description: description.to_string(),
+            source: "test".to_string(),
+            recommendation: None,
+            count: 1,
+            would_enforce: false,
+            rule: None,
+            pid: None,
+            remote_ip: None,
+        }
+    }
+
+    #[test]
+    fn test_rfc5424_format() {
+        let mut alert = alert(AlertSeverity::Critical, "Port scan from 10.0.0.5");
+        alert.source = "Network \"Monitor\"]".to_string();
+        alert.timestamp = "2024-03-01T12:00:00Z".parse().unwrap();
+
+        let message = format_rfc5424(&alert, "host.local", 42);
+        assert!(message.starts_with("<34>1 2024-03-01T12:00:00.000Z host.local ange-gardien 42 ALERT "));
+        assert!(message.contains("[alert@32473 severity=\"Critical\" source=\"Network \\\"Monitor\\\"\\]\"]"));
+        assert!(message.ends_with("\u{feff}Port scan from 10.0.0.5"));
+    }
+
+    #[test]
+    fn test_deduplicate_within_window() {
+        let mut existing = Vec::new();
+        let fresh = deduplicate(&mut existing, vec![alert(AlertSeverity::High, "CPU high")], Duration::from_secs(300));
+        assert_eq!(fresh.len(), 1);
+
+        for _ in 0..59 {
+            let fresh = deduplicate(&mut existing, vec![alert(AlertSeverity::High, "CPU high")], Duration::from_secs(300));
+            assert!(fresh.is_empty());
+        }
+        assert_eq!(existing.len(), 1);
+        assert_eq!(existing[0].count, 60);
+        assert_eq!(existing[0].to_string(), "CPU high (x60)");
+
+        // Different severity is a different alert
+        deduplicate(&mut existing, vec![alert(AlertSeverity::Low, "CPU high")], Duration::from_secs(300));
+        assert_eq!(existing.len(), 2);
+
+        // Outside the window a repeat starts a new alert
+        let mut late = alert(AlertSeverity::High, "CPU high");
+        late.timestamp = existing[0].timestamp + chrono::Duration::minutes(6);
+        let fresh = deduplicate(&mut existing, vec![late], Duration::from_secs(300));
+        assert_eq!(fresh.len(), 1);
+        assert_eq!(existing.len(), 3);
+    }
+
+    #[test]
+    fn test_record_trims_live_alerts() {
+        let config = AlertingConfig {
+            max_live_alerts: 3,
+            ..AlertingConfig::default()
+        };
+        let mut live = Vec::new();
+        for i in 0..5 {
+            config.record(&mut live, vec![alert(AlertSeverity::Low, &format!("alert {}", i))]);
+        }
+
+        let descriptions: Vec<&str> = live.iter().map(|a| a.description.as_str()).collect();
+        assert_eq!(descriptions, vec!["alert 2", "alert 3", "alert 4"]);
+    }
+
+    #[test]
+    fn test_webhook_template_escapes_fields() {
+        let alert = alert(AlertSeverity::High, "Connection to \"evil\" host");
+        let body = render_template(r#"{"text": "[{{severity}}] {{description}} ({{source}})"}"#, &alert);
+
+        let parsed: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(parsed["text"], "[High] Connection to \"evil\" host (test)");
+    }
+
+    #[tokio::test]
+    async fn test_webhook_queue_drops_oldest() {
+        let queue = DropOldestQueue::new(2);
+        assert!(queue.push(alert(AlertSeverity::Low, "first")).is_none());
+        assert!(queue.push(alert(AlertSeverity::Low, "second")).is_none());
+        let dropped = queue.push(alert(AlertSeverity::Low, "third")).unwrap();
+        assert_eq!(dropped.description, "first");
+
+        queue.close();
+        assert_eq!(queue.pop().await.unwrap().description, "second");
+        assert_eq!(queue.pop().await.unwrap().description, "third");
+        assert!(queue.pop().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_dispatcher_fans_out() {
+        let first = RecordingSink::default();
+        let second = RecordingSink::default();
+        let dispatcher = AlertDispatcher::new();
+        dispatcher.add_sink(Arc::new(first.clone())).await;
+        dispatcher.add_sink(Arc::new(second.clone())).await;
+
+        dispatcher.dispatch(&[alert(AlertSeverity::Low, "a"), alert(AlertSeverity::High, "b")]).await;
+        assert_eq!(first.sent.lock().await.len(), 2);
+        assert_eq!(second.sent.lock().await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_burst_then_coalesce() {
+        let recorder = RecordingSink::default();
+        let sink = RateLimitedSink::new(recorder.clone(), RateLimitConfig {
+            rate_per_sec: 0.0,
+            burst: 2,
+            summary_interval_secs: 1,
+        });
+
+        for _ in 0..5 {
+            sink.emit(&alert(AlertSeverity::Medium, "CPU high")).await.unwrap();
+        }
+        sink.emit(&alert(AlertSeverity::Critical, "Port scan")).await.unwrap();
+        assert_eq!(recorder.sent.lock().await.len(), 2);
+
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        sink.flush_summary().await.unwrap();
+
+        let sent = recorder.sent.lock().await;
+        assert_eq!(sent.len(), 3);
+        let summary = &sent[2];
+        assert_eq!(summary.source, RATE_LIMITER_SOURCE);
+        assert_eq!(summary.severity, AlertSeverity::Critical);
+        assert!(summary.description.starts_with("4 alerts"));
+        assert!(summary.description.contains("CPU high (x3)"));
+    }
+
+    #[test]
+    fn test_sink_rate_limits_from_config() {
+        let config: AlertingConfig = toml::from_str(
+            "[syslog]\n\
+             target = { udp = \"127.0.0.1:514\" }\n\
+             rate_limit = { rate_per_sec = 5.0, burst = 50 }\n\
+             [[webhooks]]\n\
+             url = \"https://hooks.example.com/alerts\"\n",
+        ).unwrap();
+
+        let syslog = config.syslog.unwrap();
+        assert_eq!(syslog.rate_limit.rate_per_sec, 5.0);
+        assert_eq!(syslog.rate_limit.burst, 50);
+        assert_eq!(syslog.rate_limit.summary_interval(), Duration::from_secs(60));
+        assert_eq!(config.webhooks[0].rate_limit.burst, RateLimitConfig::default().burst);
+    }
+
+    #[tokio::test]
+    async fn test_dispatcher_flush_sends_summary_after_storm() {
+        let recorder = RecordingSink::default();
+        let dispatcher = AlertDispatcher::new();
+        dispatcher.add_sink(Arc::new(RateLimitedSink::new(recorder.clone(), RateLimitConfig {
+            rate_per_sec: 0.0,
+            burst: 1,
+            summary_interval_secs: 1,
+        }))).await;
+
+        dispatcher.dispatch(&[
+            alert(AlertSeverity::High, "Port scan"),
+            alert(AlertSeverity::High, "Port scan"),
+            alert(AlertSeverity::High, "Port scan"),
+        ]).await;
+        assert_eq!(recorder.sent.lock().await.len(), 1);
+
+        // Not due yet
+        dispatcher.flush().await;
+        assert_eq!(recorder.sent.lock().await.len(), 1);
+
+        // No further alerts arrive, the timer's flush still reports the suppressed ones
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        dispatcher.flush().await;
+        let sent = recorder.sent.lock().await;
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[1].source, RATE_LIMITER_SOURCE);
+        assert!(sent[1].description.contains("Port scan (x2)"));
+    }
+
+    #[test]
+    fn test_summary_interval_is_shortest_sink_interval() {
+        let mut config = AlertingConfig::default();
+        assert_eq!(config.summary_interval(), Duration::from_secs(60));
+
+        config.webhooks.push(WebhookConfig {
+            rate_limit: RateLimitConfig { summary_interval_secs: 15, ..RateLimitConfig::default() },
+            ..WebhookConfig::default()
+        });
+        config.webhooks.push(WebhookConfig::default());
+        assert_eq!(config.summary_interval(), Duration::from_secs(15));
+
+        config.webhooks[0].rate_limit.summary_interval_secs = 0;
+        assert_eq!(config.summary_interval(), Duration::from_secs(1));
+    }
+}
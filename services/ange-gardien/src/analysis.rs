@@ -1,34 +1,310 @@
 use anyhow::Result;
 use linfa::prelude::*;
-use linfa_clustering::{DbscanParams, Dbscan};
-use ndarray::{Array1, Array2, Axis};
-use crate::{SystemState, SecurityAlert, AlertSeverity};
-use std::collections::VecDeque;
+use linfa_clustering::Dbscan;
+use ndarray::{Array1, Array2, ArrayView1, Axis};
+use crate::{SystemState, SecurityAlert, AlertSeverity, ProcessInfo};
+use crate::store::StateStore;
+use crate::database::UsageSample;
+use crate::features::{feature_matrix, state_features, FEATURE_COUNT};
+#[cfg(feature = "python")]
+use crate::ensemble::EnsembleDetector;
+use crate::monitor::build_process_tree;
+use crate::network::{Protocol, EPHEMERAL_PORT_START};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::Path;
 use std::sync::Arc;
-use tokio::sync::RwLock;
-use chrono::{DateTime, Utc, Duration};
-use log::{info, warn};
+#[cfg(feature = "python")]
+use tokio::sync::Mutex;
+use chrono::{DateTime, Local, Timelike, Utc, Duration};
+use tracing::{info, warn};
 use linfa_nn::{distance::{L2Dist, Distance}, CommonNearestNeighbour};
+use serde::{Serialize, Deserialize};
 
 const HISTORY_WINDOW: usize = 3600; // 1 hour of data points (1 per second)
 const ANOMALY_THRESHOLD: f64 = 2.0; // Standard deviations for anomaly detection
+const DBSCAN_MIN_POINTS: usize = 5;
+const DBSCAN_TOLERANCE: f64 = 0.5;
+const SCORE_HISTORY: usize = 100;
+const MAX_FEEDBACK_STATES: usize = 500;
+const MODEL_FORMAT_VERSION: u32 = 2;
+pub const ANOMALY_DETECTOR_SOURCE: &str = "AnomalyDetector";
+pub const LISTENING_PORT_SOURCE: &str = "Listening Ports";
+pub const BASELINE_SOURCE: &str = "Hourly Baseline";
+pub const PROCESS_SPAWN_SOURCE: &str = "Process Spawn";
+const INTERACTIVE_SHELLS: &[&str] = &["sh", "bash", "zsh", "dash", "ksh", "tcsh", "csh", "fish"];
+/// Days of stored history the hour-of-day baseline is fitted on
+pub const BASELINE_DAYS: i64 = 7;
+/// Hour buckets with fewer samples than this are not judged
+const BASELINE_MIN_SAMPLES: usize = 30;
+/// Floor for the baseline spread, in percentage points, so a perfectly flat
+/// hour doesn't turn a 1% wobble into an alert
+const BASELINE_MIN_STD_DEV: f64 = 1.0;
+/// Recent states kept for `AnalysisRule::evaluate`
+const RULE_HISTORY: usize = 60;
 
 pub struct AnomalyDetector {
     history: Vec<SystemState>,
-    model: Option<Dbscan<f64, L2Dist, CommonNearestNeighbour>>,
+    /// Feature vectors of a saved training window, oldest first. Live states
+    /// displace them as the window fills up again
+    restored: VecDeque<[f64; FEATURE_COUNT]>,
+    /// Scaled states the detector was fitted on. DBSCAN only labels the points
+    /// it clusters, so new states are clustered together with these
+    fitted: Option<Array2<f64>>,
+    clusters: Vec<ClusterSummary>,
+    noise_points: usize,
+    recent_scores: VecDeque<AnomalyScore>,
+    feedback: VecDeque<SystemState>,
+    scaler: Option<FeatureScaler>,
+    /// Connections to other ports count towards the disallowed-port feature
+    allowed_ports: Vec<u16>,
+}
+
+/// Per-feature z-score standardization, equivalent to scikit-learn's
+/// `StandardScaler`. Without it the byte counters dominate the L2 distance and
+/// the DBSCAN tolerance is meaningless.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeatureScaler {
+    pub mean: Vec<f64>,
+    pub std_dev: Vec<f64>,
+}
+
+impl FeatureScaler {
+    pub fn fit(features: &Array2<f64>) -> Self {
+        let mean = features.mean_axis(Axis(0))
+            .unwrap_or_else(|| Array1::zeros(features.ncols()));
+        // Constant features get unit scale rather than a division by zero
+        let std_dev = features.std_axis(Axis(0), 0.0)
+            .mapv(|s| if s > f64::EPSILON { s } else { 1.0 });
+
+        Self {
+            mean: mean.to_vec(),
+            std_dev: std_dev.to_vec(),
+        }
+    }
+
+    pub fn transform(&self, features: &Array2<f64>) -> Array2<f64> {
+        let mut scaled = features.clone();
+        for mut row in scaled.axis_iter_mut(Axis(0)) {
+            for (i, value) in row.iter_mut().enumerate() {
+                *value = (*value - self.mean[i]) / self.std_dev[i];
+            }
+        }
+        scaled
+    }
+
+    pub fn transform_point(&self, features: &[f64]) -> Vec<f64> {
+        features.iter()
+            .enumerate()
+            .map(|(i, value)| (value - self.mean[i]) / self.std_dev[i])
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClusterSummary {
+    pub id: usize,
+    pub size: usize,
+    pub centroid: Vec<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnomalyScore {
+    pub timestamp: DateTime<Utc>,
+    /// Distance to the nearest cluster centroid, in units of the DBSCAN tolerance
+    pub score: f64,
+    pub is_anomaly: bool,
+}
+
+/// Point-in-time view of what the detector has learned, for debugging and tuning.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DetectorSnapshot {
+    pub sample_count: usize,
+    pub trained: bool,
+    pub min_points: usize,
+    pub tolerance: f64,
+    pub clusters: Vec<ClusterSummary>,
+    pub noise_points: usize,
+    pub recent_scores: Vec<AnomalyScore>,
+    /// Scaling applied before clustering; cluster centroids are in scaled units
+    pub scaler: Option<FeatureScaler>,
+}
+
+/// Outcome of fitting the detectors on stored history.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrainingReport {
+    /// States loaded from the store
+    pub states: usize,
+    /// The fitted DBSCAN detector; its `sample_count` is the thinned window
+    pub dbscan: DetectorSnapshot,
+    /// Whether the IsolationForest was fitted too
+    pub isolation_forest: bool,
+}
+
+/// On-disk form of a detector: the feature vectors of its training window,
+/// which it is refit on at load. Feedback states are kept in the store, not
+/// here.
+#[derive(Serialize, Deserialize)]
+struct PersistedModel {
+    version: u32,
+    min_points: usize,
+    tolerance: f64,
+    /// Ports the disallowed-port feature of `samples` was counted against
+    allowed_ports: Vec<u16>,
+    samples: Vec<[f64; FEATURE_COUNT]>,
 }
 
 impl AnomalyDetector {
     pub fn new() -> Self {
         Self {
             history: Vec::new(),
-            model: None,
+            restored: VecDeque::new(),
+            fitted: None,
+            clusters: Vec::new(),
+            noise_points: 0,
+            recent_scores: VecDeque::with_capacity(SCORE_HISTORY),
+            feedback: VecDeque::new(),
+            scaler: None,
+            allowed_ports: Vec::new(),
+        }
+    }
+
+    /// Records a state an operator marked as a false positive. Feedback states are
+    /// always part of the "normal" training set, so the model is refit on the next
+    /// detection pass. One labeled state is too few to form a DBSCAN cluster, so
+    /// states within the tolerance of one are also never flagged. This assumes
+    /// operators label correctly: a real attack marked as a false positive
+    /// teaches the detector to ignore it.
+    pub fn record_false_positive(&mut self, state: SystemState) {
+        if self.feedback.len() == MAX_FEEDBACK_STATES {
+            self.feedback.pop_front();
+        }
+        self.feedback.push_back(state);
+        self.fitted = None;
+    }
+
+    pub fn feedback_count(&self) -> usize {
+        self.feedback.len()
+    }
+
+    pub fn sample_count(&self) -> usize {
+        self.restored.len() + self.history.len()
+    }
+
+    pub fn clusters(&self) -> &[ClusterSummary] {
+        &self.clusters
+    }
+
+    pub fn recent_scores(&self) -> impl Iterator<Item = &AnomalyScore> {
+        self.recent_scores.iter()
+    }
+
+    pub fn latest_score(&self) -> Option<&AnomalyScore> {
+        self.recent_scores.back()
+    }
+
+    pub fn snapshot(&self) -> DetectorSnapshot {
+        DetectorSnapshot {
+            sample_count: self.sample_count(),
+            trained: self.fitted.is_some(),
+            min_points: DBSCAN_MIN_POINTS,
+            tolerance: DBSCAN_TOLERANCE,
+            clusters: self.clusters.clone(),
+            noise_points: self.noise_points,
+            recent_scores: self.recent_scores.iter().cloned().collect(),
+            scaler: self.scaler.clone(),
+        }
+    }
+
+    pub fn scaler(&self) -> Option<&FeatureScaler> {
+        self.scaler.as_ref()
+    }
+
+    pub fn allowed_ports(&self) -> &[u16] {
+        &self.allowed_ports
+    }
+
+    /// Changing the allowed ports changes the feature space, so the model is
+    /// refit on the next detection pass.
+    pub fn set_allowed_ports(&mut self, ports: &[u16]) {
+        if self.allowed_ports != ports {
+            self.allowed_ports = ports.to_vec();
+            // Saved vectors counted disallowed ports against the old list
+            self.restored.clear();
+            self.fitted = None;
+        }
+    }
+
+    pub fn save_model(&self, path: &Path) -> Result<()> {
+        let samples: Vec<[f64; FEATURE_COUNT]> = self.restored.iter()
+            .copied()
+            .chain(self.history.iter().map(|state| state_features(state, &self.allowed_ports)))
+            .collect();
+        let persisted = PersistedModel {
+            version: MODEL_FORMAT_VERSION,
+            min_points: DBSCAN_MIN_POINTS,
+            tolerance: DBSCAN_TOLERANCE,
+            allowed_ports: self.allowed_ports.clone(),
+            samples,
+        };
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Write then rename so a crash mid-save never leaves a truncated model
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec(&persisted)?)?;
+        std::fs::rename(&tmp, path)?;
+        info!("Saved anomaly model with {} samples to {}", persisted.samples.len(), path.display());
+        Ok(())
+    }
+
+    /// Restores a detector saved with `save_model` and refits it immediately,
+    /// so detection resumes without a cold-start period.
+    pub fn load_model(path: &Path) -> Result<Self> {
+        let persisted: PersistedModel = serde_json::from_slice(&std::fs::read(path)?)?;
+        if persisted.version != MODEL_FORMAT_VERSION {
+            return Err(anyhow::anyhow!(
+                "Unsupported anomaly model version {} in {}",
+                persisted.version,
+                path.display()
+            ));
+        }
+        if persisted.min_points != DBSCAN_MIN_POINTS || persisted.tolerance != DBSCAN_TOLERANCE {
+            warn!("Anomaly model was trained with different DBSCAN parameters; refitting with current ones");
+        }
+
+        let mut detector = Self::new();
+        detector.allowed_ports = persisted.allowed_ports;
+        let skip = persisted.samples.len().saturating_sub(HISTORY_WINDOW);
+        detector.restored = persisted.samples.into_iter().skip(skip).collect();
+
+        if detector.sample_count() >= 10 {
+            let features = detector.extract_features();
+            detector.train_model(&features);
+        }
+
+        info!("Loaded anomaly model with {} samples from {}", detector.sample_count(), path.display());
+        Ok(detector)
+    }
+
+    /// Replaces the training window with `states` and refits. Longer
+    /// histories are thinned evenly to the window size, so the whole period
+    /// is represented rather than just its last hour.
+    pub fn fit(&mut self, states: &[SystemState]) {
+        let stride = states.len().div_ceil(HISTORY_WINDOW).max(1);
+        self.history = states.iter().step_by(stride).cloned().collect();
+        self.restored.clear();
+        self.fitted = None;
+
+        if self.history.len() >= 10 {
+            let features = self.extract_features();
+            self.train_model(&features);
         }
     }
 
     pub fn add_state(&mut self, state: SystemState) {
         self.history.push(state);
-        if self.history.len() > 1000 {
+        if self.sample_count() > HISTORY_WINDOW && self.restored.pop_front().is_none() {
             self.history.remove(0);
         }
     }
@@ -36,7 +312,7 @@ impl AnomalyDetector {
     pub fn detect_anomalies(&mut self) -> Vec<SecurityAlert> {
         let mut alerts = Vec::new();
         
-        if self.history.len() < 10 {
+        if self.history.is_empty() || self.sample_count() < 10 {
             return alerts;
         }
 
@@ -44,26 +320,55 @@ impl AnomalyDetector {
         let features = self.extract_features();
         
         // Train model if needed
-        if self.model.is_none() {
+        if self.fitted.is_none() {
             self.train_model(&features);
         }
 
         // Detect anomalies
-        if let Some(model) = &self.model {
+        if let Some(fitted) = &self.fitted {
             let latest_state = &self.history[self.history.len() - 1];
-            let latest_features = self.state_to_features(latest_state);
+            let latest_features = state_features(latest_state, &self.allowed_ports).to_vec();
+            let latest_features = match &self.scaler {
+                Some(scaler) => scaler.transform_point(&latest_features),
+                None => latest_features,
+            };
+            let score = self.score(&latest_features);
             
-            let dataset = DatasetBase::from(Array2::from_shape_vec((1, latest_features.len()), latest_features).unwrap());
-            let prediction = model.predict(&dataset);
+            let near_false_positive = self.near_false_positive(&latest_features);
+            let mut records = fitted.clone();
+            records.push_row(ArrayView1::from(&latest_features))
+                .expect("Feature vector length matches the fitted data");
+            let is_noise = match dbscan_labels(&records) {
+                Ok(labels) => labels[labels.len() - 1].is_none(),
+                Err(e) => {
+                    warn!("Failed to cluster the latest state: {}", e);
+                    false
+                }
+            };
+            let is_anomaly = is_noise && !near_false_positive;
+
+            if self.recent_scores.len() == SCORE_HISTORY {
+                self.recent_scores.pop_front();
+            }
+            self.recent_scores.push_back(AnomalyScore {
+                timestamp: latest_state.timestamp,
+                score,
+                is_anomaly,
+            });
 
             // Check if the latest state is an anomaly
-            if prediction[0] == -1 {
+            if is_anomaly {
                 alerts.push(SecurityAlert {
                     timestamp: Utc::now(),
                     severity: AlertSeverity::Medium,
                     description: "Anomalous system behavior detected".to_string(),
-                    source: "AnomalyDetector".to_string(),
-                    recommendation: Some("Investigate unusual system activity".to_string()),
+                    source: ANOMALY_DETECTOR_SOURCE.to_string(),
+                    recommendation: Some(anomaly_recommendation(latest_state)),
+                    count: 1,
+                    would_enforce: false,
+                    rule: None,
+                    pid: None,
+                    remote_ip: None,
                 });
             }
         }
@@ -72,46 +377,650 @@ impl AnomalyDetector {
     }
 
     fn extract_features(&self) -> Array2<f64> {
-        let n_samples = self.history.len();
-        let n_features = 5; // CPU, Memory, Disk, Network I/O, Process Count
-        
-        let mut features = Vec::with_capacity(n_samples * n_features);
-        
-        for state in &self.history {
-            let state_features = self.state_to_features(state);
-            features.extend(state_features);
-        }
-        
-        Array2::from_shape_vec((n_samples, n_features), features)
+        let n_samples = self.sample_count() + self.feedback.len();
+        let mut features: Vec<f64> = self.restored.iter().flatten().copied().collect();
+        features.extend(feature_matrix(self.history.iter().chain(self.feedback.iter()), &self.allowed_ports));
+
+        Array2::from_shape_vec((n_samples, FEATURE_COUNT), features)
             .expect("Failed to create feature matrix")
     }
 
-    fn state_to_features(&self, state: &SystemState) -> Vec<f64> {
-        vec![
-            state.cpu_usage as f64,
-            state.memory_usage as f64,
-            state.disk_usage as f64,
-            state.network_stats.bytes_sent as f64 + state.network_stats.bytes_received as f64,
-            state.active_processes.len() as f64,
+    fn train_model(&mut self, features: &Array2<f64>) {
+        let scaler = FeatureScaler::fit(features);
+        let features = scaler.transform(features);
+        self.scaler = Some(scaler);
+
+        self.summarize_clusters(&features);
+        self.fitted = Some(features);
+    }
+
+    fn summarize_clusters(&mut self, features: &Array2<f64>) {
+        let labels = match dbscan_labels(features) {
+            Ok(labels) => labels,
+            Err(e) => {
+                warn!("Failed to compute cluster assignments: {}", e);
+                return;
+            }
+        };
+
+        let mut sums: Vec<(usize, Array1<f64>)> = Vec::new();
+        let mut noise_points = 0;
+        for (row, label) in features.axis_iter(Axis(0)).zip(labels.iter()) {
+            match label {
+                Some(id) => {
+                    if *id >= sums.len() {
+                        sums.resize(*id + 1, (0, Array1::zeros(features.ncols())));
+                    }
+                    sums[*id].0 += 1;
+                    sums[*id].1 += &row;
+                }
+                None => noise_points += 1,
+            }
+        }
+
+        self.noise_points = noise_points;
+        self.clusters = sums.into_iter()
+            .enumerate()
+            .filter(|(_, (size, _))| *size > 0)
+            .map(|(id, (size, sum))| ClusterSummary {
+                id,
+                size,
+                centroid: (sum / size as f64).to_vec(),
+            })
+            .collect();
+    }
+
+    /// Whether `features`, scaled like the model's, lie within the DBSCAN
+    /// tolerance of a state labeled as a false positive
+    fn near_false_positive(&self, features: &[f64]) -> bool {
+        let point = Array1::from(features.to_vec());
+        self.feedback.iter().any(|state| {
+            let labeled = state_features(state, &self.allowed_ports).to_vec();
+            let labeled = match &self.scaler {
+                Some(scaler) => scaler.transform_point(&labeled),
+                None => labeled,
+            };
+            L2Dist.distance(point.view(), Array1::from(labeled).view()) <= DBSCAN_TOLERANCE
+        })
+    }
+
+    fn score(&self, features: &[f64]) -> f64 {
+        let point = Array1::from(features.to_vec());
+        self.clusters.iter()
+            .map(|cluster| L2Dist.distance(point.view(), Array1::from(cluster.centroid.clone()).view()))
+            .fold(None, |min: Option<f64>, d| Some(min.map_or(d, |m| m.min(d))))
+            .map(|distance| distance / DBSCAN_TOLERANCE)
+            .unwrap_or(f64::INFINITY)
+    }
+}
+
+/// Cluster label of each row of `records`, `None` for noise.
+fn dbscan_labels(records: &Array2<f64>) -> Result<Array1<Option<usize>>> {
+    Dbscan::params_with(DBSCAN_MIN_POINTS, L2Dist, CommonNearestNeighbour::KdTree)
+        .tolerance(DBSCAN_TOLERANCE)
+        .transform(records)
+        .map_err(|e| anyhow::anyhow!("Invalid DBSCAN parameters: {}", e))
+}
+
+/// Mean and spread of one metric within an hour bucket.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct MetricBaseline {
+    pub mean: f64,
+    pub std_dev: f64,
+}
+
+impl MetricBaseline {
+    /// From the total weight, weighted sum and weighted sum of squares of the values
+    fn from_sums(weight: f64, sum: f64, squares: f64) -> Self {
+        let mean = sum / weight;
+        let variance = (squares / weight - mean * mean).max(0.0);
+        Self { mean, std_dev: variance.sqrt() }
+    }
+
+    /// How many standard deviations `value` sits above the mean.
+    pub fn z_score(&self, value: f64) -> f64 {
+        (value - self.mean) / self.std_dev.max(BASELINE_MIN_STD_DEV)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HourBaseline {
+    pub samples: usize,
+    pub cpu: MetricBaseline,
+    pub memory: MetricBaseline,
+    pub disk: MetricBaseline,
+}
+
+/// CPU, memory and disk usage statistics per local hour of the day, so that
+/// load that is normal at 3am (backups) isn't judged against noon.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BaselineModel {
+    hours: Vec<HourBaseline>,
+}
+
+impl BaselineModel {
+    pub fn fit(states: &[SystemState]) -> Self {
+        let mut fit = BaselineFit::default();
+        for state in states {
+            fit.add(UsageSample::of(state));
+        }
+        fit.finish()
+    }
+
+    pub fn hour(&self, hour: usize) -> Option<&HourBaseline> {
+        self.hours.get(hour)
+    }
+
+    /// Alerts for each metric more than `ANOMALY_THRESHOLD` standard deviations
+    /// above its baseline for the state's hour. Usage below the baseline is not
+    /// reported.
+    pub fn check(&self, state: &SystemState) -> Vec<SecurityAlert> {
+        let hour = hour_of(state.timestamp);
+        let baseline = match self.hours.get(hour) {
+            Some(baseline) if baseline.samples >= BASELINE_MIN_SAMPLES => baseline,
+            _ => return Vec::new(),
+        };
+
+        [
+            ("CPU", state.cpu_usage, &baseline.cpu),
+            ("Memory", state.memory_usage, &baseline.memory),
+            ("Disk", state.disk_usage, &baseline.disk),
         ]
+        .into_iter()
+        .filter(|(_, value, metric)| metric.z_score(*value as f64) > ANOMALY_THRESHOLD)
+        .map(|(name, value, metric)| SecurityAlert {
+            timestamp: Utc::now(),
+            severity: AlertSeverity::Medium,
+            description: format!(
+                "{} usage {:.1}% is {:.1} standard deviations above the {:02}:00 baseline of {:.1}%",
+                name,
+                value,
+                metric.z_score(value as f64),
+                hour,
+                metric.mean
+            ),
+            source: BASELINE_SOURCE.to_string(),
+            recommendation: Some(format!("Check what is driving {} usage at this hour", name.to_lowercase())),
+            count: 1,
+            would_enforce: false,
+            rule: None,
+            pid: None,
+            remote_ip: None,
+        })
+        .collect()
     }
+}
 
-    fn train_model(&mut self, features: &Array2<f64>) {
-        let dataset = DatasetBase::from(features.clone());
-        
-        let params = DbscanParams::new(5)
-            .tolerance(0.5)
-            .distance(L2Dist)
-            .algorithm(CommonNearestNeighbour::new());
-            
-        self.model = Some(Dbscan::params(params).fit(&dataset).expect("Failed to train DBSCAN model"));
+fn hour_of(timestamp: DateTime<Utc>) -> usize {
+    timestamp.with_timezone(&Local).hour() as usize
+}
+
+/// Running per-hour sums for one metric
+#[derive(Debug, Clone, Copy, Default)]
+struct MetricSums {
+    sum: f64,
+    squares: f64,
+}
+
+impl MetricSums {
+    fn add(&mut self, value: f32, weight: f64) {
+        let value = value as f64;
+        self.sum += value * weight;
+        self.squares += value * value * weight;
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct HourSums {
+    samples: u64,
+    cpu: MetricSums,
+    memory: MetricSums,
+    disk: MetricSums,
+}
+
+/// Builds a `BaselineModel` one sample at a time, so fitting on a week of
+/// history doesn't hold it all in memory. A rolled-up minute counts as the
+/// samples it replaced.
+#[derive(Debug, Clone, Default)]
+pub struct BaselineFit {
+    hours: [HourSums; 24],
+}
+
+impl BaselineFit {
+    pub fn add(&mut self, sample: UsageSample) {
+        if sample.samples == 0 {
+            return;
+        }
+        let weight = sample.samples as f64;
+        let hour = &mut self.hours[hour_of(sample.timestamp)];
+        hour.samples += sample.samples as u64;
+        hour.cpu.add(sample.cpu_usage, weight);
+        hour.memory.add(sample.memory_usage, weight);
+        hour.disk.add(sample.disk_usage, weight);
+    }
+
+    /// Samples added so far, counting each rolled-up minute as the samples it replaced
+    pub fn samples(&self) -> u64 {
+        self.hours.iter().map(|hour| hour.samples).sum()
     }
+
+    pub fn finish(&self) -> BaselineModel {
+        let hours = self.hours.iter()
+            .map(|hour| {
+                if hour.samples == 0 {
+                    return HourBaseline::default();
+                }
+                let weight = hour.samples as f64;
+                let metric = |sums: MetricSums| MetricBaseline::from_sums(weight, sums.sum, sums.squares);
+                HourBaseline {
+                    samples: hour.samples as usize,
+                    cpu: metric(hour.cpu),
+                    memory: metric(hour.memory),
+                    disk: metric(hour.disk),
+                }
+            })
+            .collect();
+
+        BaselineModel { hours }
+    }
+}
+
+/// A detection run on every collected state. Implement it to add
+/// organisation-specific checks and register it with
+/// `Analyzer::register_rule`.
+pub trait AnalysisRule: Send + Sync {
+    /// Alerts for `state`. `history` holds the states collected before it,
+    /// oldest first, up to the last minute or so at the default poll interval.
+    fn evaluate(&self, state: &SystemState, history: &[SystemState]) -> Vec<SecurityAlert>;
+
+    /// Fallback recommendation for this rule's alerts that don't carry their own
+    fn recommend(&self) -> Option<String> {
+        None
+    }
+}
+
+/// Points the operator at the busiest process, the usual suspect when a
+/// whole state looks anomalous.
+pub(crate) fn anomaly_recommendation(state: &SystemState) -> String {
+    let busiest = state.active_processes.iter()
+        .max_by(|a, b| a.cpu_usage.total_cmp(&b.cpu_usage));
+    match busiest {
+        Some(process) => format!(
+            "Investigate PID {} ({}, {:.1}% CPU); consider `kill -9 {}` if it is not expected",
+            process.pid, process.name, process.cpu_usage, process.pid
+        ),
+        None => "Investigate unusual system activity".to_string(),
+    }
+}
+
+/// The DBSCAN detector as a rule: each state joins its window and is
+/// scored against it.
+struct AnomalyRule {
+    detector: Arc<std::sync::RwLock<AnomalyDetector>>,
+}
+
+impl AnalysisRule for AnomalyRule {
+    fn evaluate(&self, state: &SystemState, _history: &[SystemState]) -> Vec<SecurityAlert> {
+        let mut detector = self.detector.write().unwrap_or_else(|e| e.into_inner());
+        detector.add_state(state.clone());
+        detector.detect_anomalies()
+    }
+
+    fn recommend(&self) -> Option<String> {
+        Some("Investigate unusual system activity".to_string())
+    }
+}
+
+/// Deviations from the hour-of-day baseline, once one is fitted.
+struct BaselineRule {
+    model: Arc<std::sync::RwLock<Option<BaselineModel>>>,
+}
+
+impl AnalysisRule for BaselineRule {
+    fn evaluate(&self, state: &SystemState, _history: &[SystemState]) -> Vec<SecurityAlert> {
+        self.model.read().unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+            .map_or_else(Vec::new, |model| model.check(state))
+    }
+
+    fn recommend(&self) -> Option<String> {
+        Some("Compare current usage with the same hour on previous days".to_string())
+    }
+}
+
+/// A listening port, its protocol and the owning pid when known
+pub type Listener = (u16, Protocol, Option<u32>);
+
+/// Alerts when a process named in the policy's server processes has an
+/// interactive shell as a direct child, e.g. a web server or browser spawning
+/// `sh`. Each shell is reported once.
+struct ProcessSpawnRule {
+    server_processes: Arc<std::sync::RwLock<Vec<String>>>,
+    /// Shell pids already reported
+    reported: std::sync::Mutex<HashSet<u32>>,
+}
+
+impl AnalysisRule for ProcessSpawnRule {
+    fn evaluate(&self, state: &SystemState, _history: &[SystemState]) -> Vec<SecurityAlert> {
+        let servers = self.server_processes.read().unwrap_or_else(|e| e.into_inner());
+        let mut reported = self.reported.lock().unwrap_or_else(|e| e.into_inner());
+        spawned_shells(state, &servers, &mut reported)
+    }
+}
+
+/// Listeners that appeared since the baseline and aren't on an allowed port,
+/// judged on the snapshot handed over with `Analyzer::set_listeners` for this
+/// state. The first snapshot only records the baseline, and each new listener
+/// is reported once. Listeners on ephemeral ports are rated High, since that
+/// is where backdoors tend to hide.
+struct ListeningPortRule {
+    listeners: Arc<std::sync::Mutex<Option<Vec<Listener>>>>,
+    /// Every listener seen so far; `None` until the first snapshot
+    baseline: std::sync::Mutex<Option<HashSet<(u16, Protocol)>>>,
+    detector: Arc<std::sync::RwLock<AnomalyDetector>>,
+}
+
+impl AnalysisRule for ListeningPortRule {
+    fn evaluate(&self, _state: &SystemState, _history: &[SystemState]) -> Vec<SecurityAlert> {
+        // Each snapshot is judged once; none is set when listing failed
+        let Some(listeners) = self.listeners.lock().unwrap_or_else(|e| e.into_inner()).take() else {
+            return Vec::new();
+        };
+        let allowed_ports = self.detector.read().unwrap_or_else(|e| e.into_inner()).allowed_ports().to_vec();
+        let mut baseline = self.baseline.lock().unwrap_or_else(|e| e.into_inner());
+        new_listeners(&listeners, &allowed_ports, &mut baseline)
+    }
+}
+
+/// Async front end to the detection rules, shared between the monitoring
+/// loop, the API and operator feedback.
+pub struct Analyzer {
+    detector: Arc<std::sync::RwLock<AnomalyDetector>>,
+    /// Listener snapshot for the next state, taken by the listening-port rule
+    listeners: Arc<std::sync::Mutex<Option<Vec<Listener>>>>,
+    baseline: Arc<std::sync::RwLock<Option<BaselineModel>>>,
+    /// Run in registration order; the built-in rules first
+    rules: std::sync::RwLock<Vec<Box<dyn AnalysisRule>>>,
+    history: std::sync::Mutex<VecDeque<SystemState>>,
+    /// Process names whose interactive-shell children are flagged
+    server_processes: Arc<std::sync::RwLock<Vec<String>>>,
+    /// When set, DBSCAN verdicts are combined with the IsolationForest's
+    #[cfg(feature = "python")]
+    ensemble: Option<Arc<Mutex<EnsembleDetector>>>,
+}
+
+impl Analyzer {
+    pub fn new() -> Self {
+        Self::with_detector(AnomalyDetector::new())
+    }
+
+    pub fn with_detector(detector: AnomalyDetector) -> Self {
+        let detector = Arc::new(std::sync::RwLock::new(detector));
+        let baseline = Arc::new(std::sync::RwLock::new(None));
+        let listeners = Arc::new(std::sync::Mutex::new(None));
+        let server_processes = Arc::new(std::sync::RwLock::new(Vec::new()));
+        let rules: Vec<Box<dyn AnalysisRule>> = vec![
+            Box::new(AnomalyRule { detector: Arc::clone(&detector) }),
+            Box::new(BaselineRule { model: Arc::clone(&baseline) }),
+            Box::new(ProcessSpawnRule {
+                server_processes: Arc::clone(&server_processes),
+                reported: std::sync::Mutex::new(HashSet::new()),
+            }),
+            Box::new(ListeningPortRule {
+                listeners: Arc::clone(&listeners),
+                baseline: std::sync::Mutex::new(None),
+                detector: Arc::clone(&detector),
+            }),
+        ];
+        Self {
+            detector,
+            listeners,
+            baseline,
+            rules: std::sync::RwLock::new(rules),
+            history: std::sync::Mutex::new(VecDeque::with_capacity(RULE_HISTORY)),
+            server_processes,
+            #[cfg(feature = "python")]
+            ensemble: None,
+        }
+    }
+
+    #[cfg(feature = "python")]
+    pub fn with_ensemble(mut self, ensemble: EnsembleDetector) -> Self {
+        self.ensemble = Some(Arc::new(Mutex::new(ensemble)));
+        self
+    }
+
+    /// Restores a saved detector, falling back to an untrained one when the
+    /// file is missing or unreadable.
+    pub fn load_model(path: &Path) -> Self {
+        if !path.exists() {
+            return Self::new();
+        }
+
+        match AnomalyDetector::load_model(path) {
+            Ok(detector) => Self::with_detector(detector),
+            Err(e) => {
+                warn!("Failed to load anomaly model from {}: {}", path.display(), e);
+                Self::new()
+            }
+        }
+    }
+
+    pub async fn save_model(&self, path: &Path) -> Result<()> {
+        self.read_detector().save_model(path)
+    }
+
+    /// Keeps the disallowed-port feature in line with the security policies.
+    pub async fn set_allowed_ports(&self, ports: &[u16]) {
+        self.write_detector().set_allowed_ports(ports);
+    }
+
+    /// Keeps the process-spawn rule in line with the security policies.
+    pub async fn set_server_processes(&self, names: &[String]) {
+        *self.server_processes.write().unwrap_or_else(|e| e.into_inner()) = names.to_vec();
+    }
+
+    /// Hands the listening-port rule the listeners to judge with the next state.
+    pub async fn set_listeners(&self, listeners: Vec<Listener>) {
+        *self.listeners.lock().unwrap_or_else(|e| e.into_inner()) = Some(listeners);
+    }
+
+    /// Adds a rule, run on every state after those already registered.
+    pub fn register_rule(&self, rule: Box<dyn AnalysisRule>) {
+        self.rules.write().unwrap_or_else(|e| e.into_inner()).push(rule);
+    }
+
+    /// Runs every rule on the state, then adds it to the history later states
+    /// are evaluated against.
+    pub async fn analyze_state(&self, state: &SystemState) -> Result<Vec<SecurityAlert>> {
+        #[cfg_attr(not(feature = "python"), allow(unused_mut))]
+        let mut alerts: Vec<SecurityAlert> = {
+            let mut history = self.history.lock().unwrap_or_else(|e| e.into_inner());
+            let alerts = self.rules.read().unwrap_or_else(|e| e.into_inner())
+                .iter()
+                .flat_map(|rule| {
+                    let mut alerts = rule.evaluate(state, history.make_contiguous());
+                    for alert in alerts.iter_mut().filter(|alert| alert.recommendation.is_none()) {
+                        alert.recommendation = rule.recommend();
+                    }
+                    alerts
+                })
+                .collect();
+            if history.len() == RULE_HISTORY {
+                history.pop_front();
+            }
+            history.push_back(state.clone());
+            alerts
+        };
+
+        #[cfg(feature = "python")]
+        self.apply_ensemble(state, &mut alerts).await;
+
+        Ok(alerts)
+    }
+
+    fn read_detector(&self) -> std::sync::RwLockReadGuard<'_, AnomalyDetector> {
+        self.detector.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write_detector(&self) -> std::sync::RwLockWriteGuard<'_, AnomalyDetector> {
+        self.detector.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Replaces DBSCAN's verdict on `state` with the ensemble's, when one is
+    /// configured. A failed vote leaves DBSCAN's alerts in place.
+    #[cfg(feature = "python")]
+    async fn apply_ensemble(&self, state: &SystemState, alerts: &mut Vec<SecurityAlert>) {
+        let Some(ensemble) = &self.ensemble else {
+            return;
+        };
+        let (dbscan, allowed_ports) = {
+            let detector = self.read_detector();
+            let dbscan = detector.latest_score()
+                .filter(|score| score.timestamp == state.timestamp)
+                .cloned();
+            (dbscan, detector.allowed_ports().to_vec())
+        };
+        let Some(dbscan) = dbscan else {
+            return;
+        };
+
+        let mut ensemble = ensemble.lock().await;
+        match ensemble.vote(state, &dbscan, &allowed_ports).await {
+            Ok(vote) => {
+                alerts.retain(|alert| alert.source != ANOMALY_DETECTOR_SOURCE);
+                if vote.is_anomaly {
+                    alerts.push(ensemble.alert(&vote, state));
+                }
+            }
+            Err(e) => warn!("Ensemble vote failed, using DBSCAN alone: {}", e),
+        }
+    }
+
+    /// Refits the hour-of-day baseline on the last `days` days of stored
+    /// states, streaming their usage rather than loading them whole.
+    pub async fn update_baseline(&self, db: &dyn StateStore, days: i64) -> Result<()> {
+        let mut fit = BaselineFit::default();
+        db.for_each_usage_since(Utc::now() - Duration::days(days), &mut |sample| {
+            fit.add(sample);
+            Ok(())
+        }).await?;
+        info!("Updated hourly baseline from {} stored samples", fit.samples());
+        self.set_baseline(fit.finish()).await;
+        Ok(())
+    }
+
+    pub async fn set_baseline(&self, model: BaselineModel) {
+        *self.baseline.write().unwrap_or_else(|e| e.into_inner()) = Some(model);
+    }
+
+    pub async fn record_false_positive(&self, state: SystemState) {
+        self.write_detector().record_false_positive(state);
+    }
+
+    /// Seeds the detector with false positives persisted by earlier runs.
+    pub async fn load_feedback(&self, states: Vec<SystemState>) {
+        let mut detector = self.write_detector();
+        for state in states {
+            detector.record_false_positive(state);
+        }
+    }
+
+    pub async fn snapshot(&self) -> DetectorSnapshot {
+        self.read_detector().snapshot()
+    }
+}
+
+fn spawned_shells(state: &SystemState, server_processes: &[String], reported: &mut HashSet<u32>) -> Vec<SecurityAlert> {
+    let processes: HashMap<u32, &ProcessInfo> = state.active_processes.iter()
+        .map(|process| (process.pid, process))
+        .collect();
+    let tree = build_process_tree(state.active_processes.iter().map(|p| (p.pid, p.ppid)));
+
+    // Forget shells that have exited so a reused pid is judged afresh
+    reported.retain(|pid| processes.contains_key(pid));
+
+    let mut alerts = Vec::new();
+    for server in state.active_processes.iter().filter(|p| server_processes.contains(&p.name)) {
+        for child in tree.get(&server.pid).into_iter().flatten().filter_map(|pid| processes.get(pid)) {
+            if !INTERACTIVE_SHELLS.contains(&child.name.as_str()) || !reported.insert(child.pid) {
+                continue;
+            }
+
+            warn!("{} (PID: {}) spawned shell {} (PID: {})", server.name, server.pid, child.name, child.pid);
+            alerts.push(SecurityAlert {
+                timestamp: Utc::now(),
+                severity: AlertSeverity::High,
+                description: format!(
+                    "{} (PID: {}) spawned interactive shell {} (PID: {}): {}",
+                    server.name,
+                    server.pid,
+                    child.name,
+                    child.pid,
+                    child.command
+                ),
+                source: PROCESS_SPAWN_SOURCE.to_string(),
+                recommendation: Some(format!("Check {} for compromise and inspect the shell's activity", server.name)),
+                count: 1,
+                would_enforce: false,
+                rule: None,
+                pid: Some(child.pid),
+                remote_ip: None,
+            });
+        }
+    }
+
+    alerts
+}
+
+fn new_listeners(
+    listeners: &[Listener],
+    allowed_ports: &[u16],
+    baseline: &mut Option<HashSet<(u16, Protocol)>>,
+) -> Vec<SecurityAlert> {
+    let seen = match baseline.as_mut() {
+        Some(seen) => seen,
+        None => {
+            let initial = listeners.iter()
+                .map(|(port, protocol, _)| (*port, protocol.clone()))
+                .collect();
+            *baseline = Some(initial);
+            return Vec::new();
+        }
+    };
+
+    let mut alerts = Vec::new();
+    for (port, protocol, pid) in listeners {
+        if !seen.insert((*port, protocol.clone())) || allowed_ports.contains(port) {
+            continue;
+        }
+
+        let owner = pid.map_or_else(|| "an unknown process".to_string(), |pid| format!("PID {}", pid));
+        warn!("New {:?} listener on port {} opened by {}", protocol, port, owner);
+        alerts.push(SecurityAlert {
+            timestamp: Utc::now(),
+            severity: if *port >= EPHEMERAL_PORT_START { AlertSeverity::High } else { AlertSeverity::Medium },
+            description: format!("New {:?} listener on port {} opened by {}", protocol, port, owner),
+            source: LISTENING_PORT_SOURCE.to_string(),
+            recommendation: Some(format!(
+                "Verify that {} should accept connections, or add port {} to allowed_ports",
+                owner,
+                port
+            )),
+            count: 1,
+            would_enforce: false,
+            rule: None,
+            pid: *pid,
+            remote_ip: None,
+        });
+    }
+
+    alerts
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::NetworkStats;
 
     #[test]
     fn test_anomaly_detector() {
@@ -124,10 +1033,7 @@ mod tests {
                 cpu_usage: 30.0,
                 memory_usage: 40.0,
                 disk_usage: 50.0,
-                network_stats: NetworkStats::default(),
-                active_processes: vec![],
-                security_alerts: vec![],
-                system_metrics: None,
+                ..Default::default()
             };
             detector.add_state(state);
         }
@@ -138,14 +1044,494 @@ mod tests {
             cpu_usage: 95.0,
             memory_usage: 90.0,
             disk_usage: 95.0,
-            network_stats: NetworkStats::default(),
-            active_processes: vec![],
-            security_alerts: vec![],
-            system_metrics: None,
+            ..Default::default()
         };
         detector.add_state(anomalous_state);
         
         let alerts = detector.detect_anomalies();
         assert!(!alerts.is_empty());
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_false_positive_feedback_forces_retrain() {
+        let mut detector = AnomalyDetector::new();
+        let state = SystemState {
+            timestamp: Utc::now(),
+            cpu_usage: 30.0,
+            memory_usage: 40.0,
+            disk_usage: 50.0,
+            ..Default::default()
+        };
+        for _ in 0..10 {
+            detector.add_state(state.clone());
+        }
+        detector.detect_anomalies();
+        assert!(detector.snapshot().trained);
+
+        detector.record_false_positive(SystemState { cpu_usage: 95.0, ..state });
+        assert_eq!(detector.feedback_count(), 1);
+        assert!(!detector.snapshot().trained);
+        assert_eq!(detector.extract_features().nrows(), 11);
+    }
+
+    #[test]
+    fn test_false_positive_silences_similar_states() {
+        let normal = SystemState {
+            timestamp: Utc::now(),
+            cpu_usage: 30.0,
+            memory_usage: 40.0,
+            disk_usage: 50.0,
+            ..Default::default()
+        };
+        let spike = SystemState { cpu_usage: 95.0, memory_usage: 90.0, disk_usage: 95.0, ..normal.clone() };
+        let similar = SystemState { cpu_usage: 94.0, memory_usage: 89.5, disk_usage: 95.0, ..normal.clone() };
+        let detector = || {
+            let mut detector = AnomalyDetector::new();
+            for i in 0..20 {
+                detector.add_state(SystemState { cpu_usage: 30.0 + (i % 3) as f32, ..normal.clone() });
+            }
+            detector.add_state(spike.clone());
+            assert_eq!(detector.detect_anomalies().len(), 1);
+            detector
+        };
+
+        // Unlabeled, the repeat is flagged like the original
+        let mut unlabeled = detector();
+        unlabeled.add_state(similar.clone());
+        assert_eq!(unlabeled.detect_anomalies().len(), 1);
+
+        let mut labeled = detector();
+        labeled.record_false_positive(spike.clone());
+        labeled.add_state(similar.clone());
+        assert!(labeled.detect_anomalies().is_empty());
+        assert!(!labeled.latest_score().unwrap().is_anomaly);
+
+        // Unrelated anomalies still alert
+        labeled.add_state(SystemState { memory_usage: 5.0, disk_usage: 5.0, ..normal.clone() });
+        assert_eq!(labeled.detect_anomalies().len(), 1);
+    }
+
+    #[test]
+    fn test_fit_thins_long_history() {
+        let mut detector = AnomalyDetector::new();
+        let states: Vec<SystemState> = (0..HISTORY_WINDOW * 2)
+            .map(|i| SystemState {
+                timestamp: Utc::now(),
+                cpu_usage: (i % 7) as f32,
+                memory_usage: 40.0,
+                disk_usage: 50.0,
+                ..Default::default()
+            })
+            .collect();
+
+        detector.fit(&states);
+        assert_eq!(detector.sample_count(), HISTORY_WINDOW);
+        assert!(detector.snapshot().trained);
+    }
+
+    #[test]
+    fn test_feature_scaler_standardizes_columns() {
+        let features = Array2::from_shape_vec(
+            (4, 2),
+            vec![10.0, 1e9, 20.0, 2e9, 30.0, 3e9, 40.0, 4e9],
+        ).unwrap();
+
+        let scaler = FeatureScaler::fit(&features);
+        let scaled = scaler.transform(&features);
+        for column in scaled.axis_iter(Axis(1)) {
+            assert!(column.mean().unwrap().abs() < 1e-9);
+            assert!((column.std(0.0) - 1.0).abs() < 1e-9);
+        }
+        // Both columns move in lockstep, so they scale to the same values
+        assert!((scaled[[0, 0]] - scaled[[0, 1]]).abs() < 1e-9);
+
+        let constant = Array2::from_elem((3, 1), 5.0);
+        let scaler = FeatureScaler::fit(&constant);
+        assert_eq!(scaler.transform_point(&[5.0]), vec![0.0]);
+    }
+
+    #[test]
+    fn test_save_and_load_model() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("anomaly_model.json");
+
+        let mut detector = AnomalyDetector::new();
+        let state = SystemState {
+            timestamp: Utc::now(),
+            cpu_usage: 30.0,
+            memory_usage: 40.0,
+            disk_usage: 50.0,
+            ..Default::default()
+        };
+        for _ in 0..10 {
+            detector.add_state(state.clone());
+        }
+        detector.record_false_positive(SystemState { cpu_usage: 95.0, ..state });
+        detector.save_model(&path).unwrap();
+
+        let restored = AnomalyDetector::load_model(&path).unwrap();
+        assert_eq!(restored.sample_count(), 10);
+        // Feedback is reloaded from the store rather than the model file
+        assert_eq!(restored.feedback_count(), 0);
+        assert!(restored.snapshot().trained);
+        let saved = std::fs::read_to_string(&path).unwrap();
+        assert!(!saved.contains("active_processes"));
+    }
+
+    #[test]
+    fn test_loaded_model_detects_on_first_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("anomaly_model.json");
+
+        let mut detector = AnomalyDetector::new();
+        let state = SystemState {
+            timestamp: Utc::now(),
+            cpu_usage: 30.0,
+            memory_usage: 40.0,
+            disk_usage: 50.0,
+            ..Default::default()
+        };
+        for _ in 0..20 {
+            detector.add_state(state.clone());
+        }
+        detector.save_model(&path).unwrap();
+
+        let mut restored = AnomalyDetector::load_model(&path).unwrap();
+        restored.add_state(SystemState { cpu_usage: 100.0, memory_usage: 100.0, ..state });
+        assert_eq!(restored.sample_count(), 21);
+        assert_eq!(restored.detect_anomalies().len(), 1);
+    }
+
+    #[test]
+    fn test_detector_snapshot() {
+        let mut detector = AnomalyDetector::new();
+        for _ in 0..10 {
+            detector.add_state(SystemState {
+                timestamp: Utc::now(),
+                cpu_usage: 30.0,
+                memory_usage: 40.0,
+                disk_usage: 50.0,
+                ..Default::default()
+            });
+        }
+        detector.detect_anomalies();
+
+        let snapshot = detector.snapshot();
+        assert_eq!(snapshot.sample_count, 10);
+        assert!(snapshot.trained);
+        assert_eq!(snapshot.clusters.len(), 1);
+        assert_eq!(snapshot.clusters[0].size, 10);
+        assert_eq!(snapshot.recent_scores.len(), 1);
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        assert!(json.contains("\"sample_count\":10"));
+    }
+
+    #[tokio::test]
+    async fn test_analyzer_analyze_state() {
+        let analyzer = Analyzer::new();
+        let state = SystemState {
+            timestamp: Utc::now(),
+            cpu_usage: 30.0,
+            memory_usage: 40.0,
+            disk_usage: 50.0,
+            ..Default::default()
+        };
+
+        for _ in 0..10 {
+            assert!(analyzer.analyze_state(&state).await.unwrap().is_empty());
+        }
+
+        let anomalous = SystemState { cpu_usage: 95.0, memory_usage: 90.0, ..state };
+        let alerts = analyzer.analyze_state(&anomalous).await.unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].source, ANOMALY_DETECTOR_SOURCE);
+        assert_eq!(analyzer.snapshot().await.sample_count, 11);
+    }
+
+    /// Alerts when CPU has risen across the last three states.
+    struct RisingCpu;
+
+    impl AnalysisRule for RisingCpu {
+        fn evaluate(&self, state: &SystemState, history: &[SystemState]) -> Vec<SecurityAlert> {
+            let [.., before, previous] = history else {
+                return Vec::new();
+            };
+            if before.cpu_usage >= previous.cpu_usage || previous.cpu_usage >= state.cpu_usage {
+                return Vec::new();
+            }
+            vec![SecurityAlert {
+                timestamp: state.timestamp,
+                severity: AlertSeverity::Low,
+                description: "CPU rising".to_string(),
+                source: "Rising CPU".to_string(),
+                recommendation: None,
+                count: 1,
+                would_enforce: false,
+                rule: None,
+                pid: None,
+                remote_ip: None,
+            }]
+        }
+
+        fn recommend(&self) -> Option<String> {
+            Some("Find what is ramping up".to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_registered_rule_sees_history() {
+        let analyzer = Analyzer::new();
+        analyzer.register_rule(Box::new(RisingCpu));
+        let state = |cpu_usage| SystemState {
+            timestamp: Utc::now(),
+            cpu_usage,
+            memory_usage: 40.0,
+            disk_usage: 50.0,
+            ..Default::default()
+        };
+
+        assert!(analyzer.analyze_state(&state(10.0)).await.unwrap().is_empty());
+        assert!(analyzer.analyze_state(&state(20.0)).await.unwrap().is_empty());
+        let alerts = analyzer.analyze_state(&state(30.0)).await.unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].source, "Rising CPU");
+        assert_eq!(alerts[0].recommendation.as_deref(), Some("Find what is ramping up"));
+        assert!(analyzer.analyze_state(&state(25.0)).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_new_listening_ports() {
+        let analyzer = Analyzer::new();
+        analyzer.set_allowed_ports(&[22, 443]).await;
+        let state = SystemState {
+            timestamp: Utc::now(),
+            cpu_usage: 10.0,
+            memory_usage: 10.0,
+            disk_usage: 10.0,
+            ..Default::default()
+        };
+        let check = |listeners: Vec<Listener>| {
+            let analyzer = &analyzer;
+            let state = &state;
+            async move {
+                analyzer.set_listeners(listeners).await;
+                analyzer.analyze_state(state).await.unwrap()
+            }
+        };
+
+        // The first snapshot is the baseline, even for ports outside the allowlist
+        let baseline = vec![(22, Protocol::TCP, Some(1)), (8000, Protocol::TCP, Some(2))];
+        assert!(check(baseline.clone()).await.is_empty());
+
+        let mut listeners = baseline.clone();
+        listeners.push((443, Protocol::TCP, Some(3)));
+        listeners.push((5353, Protocol::UDP, None));
+        listeners.push((51234, Protocol::TCP, Some(4)));
+        let alerts = check(listeners.clone()).await;
+        assert_eq!(alerts.len(), 2);
+        assert_eq!(alerts[0].severity, AlertSeverity::Medium);
+        assert!(alerts[0].description.contains("5353"));
+        assert_eq!(alerts[1].severity, AlertSeverity::High);
+        assert!(alerts[1].description.contains("PID 4"));
+
+        // Already reported
+        assert!(check(listeners).await.is_empty());
+    }
+
+    #[test]
+    fn test_baseline_weights_rollups() {
+        let noon = Local::now()
+            .with_hour(12).unwrap()
+            .with_minute(0).unwrap()
+            .with_timezone(&Utc);
+        let sample = |cpu_usage, samples| UsageSample {
+            timestamp: noon,
+            cpu_usage,
+            memory_usage: 40.0,
+            disk_usage: 50.0,
+            samples,
+        };
+
+        // A rolled-up minute of 60 samples at 10% against one raw sample at 70%
+        let mut fit = BaselineFit::default();
+        fit.add(sample(10.0, 60));
+        fit.add(sample(70.0, 1));
+        assert_eq!(fit.samples(), 61);
+
+        let mut replicated = BaselineFit::default();
+        for _ in 0..60 {
+            replicated.add(sample(10.0, 1));
+        }
+        replicated.add(sample(70.0, 1));
+
+        let weighted = fit.finish();
+        let expected = replicated.finish();
+        let hour = weighted.hour(12).unwrap();
+        assert_eq!(hour.samples, 61);
+        assert!((hour.cpu.mean - (10.0 * 60.0 + 70.0) / 61.0).abs() < 1e-9);
+        assert!((hour.cpu.mean - expected.hour(12).unwrap().cpu.mean).abs() < 1e-9);
+        assert!((hour.cpu.std_dev - expected.hour(12).unwrap().cpu.std_dev).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_hourly_baseline() {
+        let at = |hour: u32, cpu: f32| {
+            let timestamp = Local::now()
+                .with_hour(hour).unwrap()
+                .with_minute(0).unwrap()
+                .with_timezone(&Utc);
+            SystemState {
+                timestamp,
+                cpu_usage: cpu,
+                memory_usage: 40.0,
+                disk_usage: 50.0,
+                ..Default::default()
+            }
+        };
+
+        // Busy backups at 3am, quiet at noon
+        let mut history = Vec::new();
+        for i in 0..BASELINE_MIN_SAMPLES {
+            history.push(at(3, 88.0 + (i % 5) as f32));
+            history.push(at(12, 10.0 + (i % 5) as f32));
+        }
+        let model = BaselineModel::fit(&history);
+        assert_eq!(model.hour(3).unwrap().samples, BASELINE_MIN_SAMPLES);
+        assert!((model.hour(12).unwrap().cpu.mean - 12.0).abs() < 1e-9);
+
+        assert!(model.check(&at(3, 91.0)).is_empty());
+        let alerts = model.check(&at(12, 91.0));
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].source, BASELINE_SOURCE);
+        assert!(alerts[0].description.starts_with("CPU usage"));
+
+        // Hours without enough history are not judged
+        assert!(model.check(&at(20, 99.0)).is_empty());
+
+        let analyzer = Analyzer::new();
+        analyzer.set_baseline(model).await;
+        let alerts = analyzer.analyze_state(&at(12, 91.0)).await.unwrap();
+        assert!(alerts.iter().any(|alert| alert.source == BASELINE_SOURCE));
+    }
+
+    #[test]
+    fn test_anomaly_recommendation_names_busiest_process() {
+        let process = |pid: u32, name: &str, cpu_usage: f32| ProcessInfo {
+            pid,
+            ppid: 1,
+            name: name.to_string(),
+            cpu_usage,
+            memory_usage: 0.0,
+            threads: 1,
+            start_time: Utc::now(),
+            command: name.to_string(),
+            disk_bytes_read: 0,
+            disk_bytes_written: 0,
+        };
+        let mut state = SystemState {
+            timestamp: Utc::now(),
+            cpu_usage: 90.0,
+            memory_usage: 10.0,
+            disk_usage: 10.0,
+            ..Default::default()
+        };
+        assert_eq!(anomaly_recommendation(&state), "Investigate unusual system activity");
+
+        state.active_processes = vec![process(10, "launchd", 0.5), process(4242, "xmrig", 85.0)];
+        assert_eq!(
+            anomaly_recommendation(&state),
+            "Investigate PID 4242 (xmrig, 85.0% CPU); consider `kill -9 4242` if it is not expected"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_server_spawning_shell() {
+        let process = |pid: u32, ppid: u32, name: &str| ProcessInfo {
+            pid,
+            ppid,
+            name: name.to_string(),
+            cpu_usage: 0.0,
+            memory_usage: 0.0,
+            threads: 1,
+            start_time: Utc::now(),
+            command: name.to_string(),
+            disk_bytes_read: 0,
+            disk_bytes_written: 0,
+        };
+        let mut state = SystemState {
+            timestamp: Utc::now(),
+            cpu_usage: 10.0,
+            memory_usage: 10.0,
+            disk_usage: 10.0,
+            active_processes: vec![
+                process(1, 0, "launchd"),
+                process(100, 1, "nginx"),
+                process(101, 100, "nginx"),
+                process(102, 100, "sh"),
+                process(200, 1, "Terminal"),
+                process(201, 200, "zsh"),
+                // Orphaned shell whose parent already exited
+                process(300, 299, "bash"),
+            ],
+            ..Default::default()
+        };
+        let analyzer = Analyzer::new();
+        analyzer.set_server_processes(&["nginx".to_string()]).await;
+        let alerts = analyzer.analyze_state(&state).await.unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert!(alerts[0].description.contains("PID: 102"));
+
+        // Reported once
+        assert!(analyzer.analyze_state(&state).await.unwrap().is_empty());
+
+        // A shell reusing the pid after the first exited is reported again
+        state.active_processes.retain(|p| p.pid != 102);
+        assert!(analyzer.analyze_state(&state).await.unwrap().is_empty());
+        state.active_processes.push(process(102, 101, "bash"));
+        assert_eq!(analyzer.analyze_state(&state).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_spawn_and_listener_checks_run_as_rules() {
+        let process = |pid: u32, ppid: u32, name: &str| ProcessInfo {
+            pid,
+            ppid,
+            name: name.to_string(),
+            cpu_usage: 0.0,
+            memory_usage: 0.0,
+            threads: 1,
+            start_time: Utc::now(),
+            command: name.to_string(),
+            disk_bytes_read: 0,
+            disk_bytes_written: 0,
+        };
+        let state = SystemState {
+            timestamp: Utc::now(),
+            cpu_usage: 10.0,
+            memory_usage: 10.0,
+            disk_usage: 10.0,
+            active_processes: vec![process(100, 1, "nginx"), process(102, 100, "sh")],
+            ..Default::default()
+        };
+        let from = |alerts: &[SecurityAlert], source: &str| alerts.iter().filter(|a| a.source == source).count();
+
+        let analyzer = Analyzer::new();
+        analyzer.set_allowed_ports(&[22]).await;
+        analyzer.set_server_processes(&["nginx".to_string()]).await;
+        analyzer.set_listeners(vec![(22, Protocol::TCP, Some(1))]).await;
+        let alerts = analyzer.analyze_state(&state).await.unwrap();
+        assert_eq!(from(&alerts, PROCESS_SPAWN_SOURCE), 1);
+        // The first snapshot is the baseline
+        assert_eq!(from(&alerts, LISTENING_PORT_SOURCE), 0);
+
+        analyzer.set_listeners(vec![(22, Protocol::TCP, Some(1)), (51234, Protocol::TCP, Some(102))]).await;
+        let alerts = analyzer.analyze_state(&state).await.unwrap();
+        assert_eq!(from(&alerts, PROCESS_SPAWN_SOURCE), 0);
+        assert_eq!(from(&alerts, LISTENING_PORT_SOURCE), 1);
+
+        // A snapshot is judged once; without a new one the rule stays quiet
+        let alerts = analyzer.analyze_state(&state).await.unwrap();
+        assert_eq!(from(&alerts, LISTENING_PORT_SOURCE), 0);
+    }
+}
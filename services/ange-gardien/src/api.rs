@@ -0,0 +1,184 @@
+use anyhow::Result;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+use crate::analysis::{Analyzer, DetectorSnapshot};
+use crate::database::SystemStatistics;
+use crate::incidents::Incident;
+use crate::store::StateStore;
+use crate::metrics;
+use crate::monitor::{ProcessHistory, SystemMonitor};
+use crate::{SystemState, SecurityAlert, AlertSeverity};
+
+#[derive(Clone)]
+pub(crate) struct ApiState {
+    pub state: Arc<RwLock<SystemState>>,
+    pub db: Arc<dyn StateStore>,
+    pub analyzer: Arc<Analyzer>,
+    pub monitor: Arc<SystemMonitor>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SinceQuery {
+    /// RFC 3339 timestamp; defaults to one hour ago
+    since: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RangeQuery {
+    /// RFC 3339 timestamps; default to the last hour
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AlertsQuery {
+    since: Option<DateTime<Utc>>,
+    /// When set, alerts come back oldest first for the closed range
+    until: Option<DateTime<Utc>>,
+    /// Case-insensitive, e.g. `high` for High and Critical only
+    #[serde(default, deserialize_with = "deserialize_severity")]
+    min_severity: Option<AlertSeverity>,
+}
+
+fn deserialize_severity<'de, D>(deserializer: D) -> Result<Option<AlertSeverity>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = Option::<String>::deserialize(deserializer)?;
+    value.map(|s| s.parse().map_err(serde::de::Error::custom)).transpose()
+}
+
+impl SinceQuery {
+    fn since(&self) -> DateTime<Utc> {
+        self.since.unwrap_or_else(|| Utc::now() - Duration::hours(1))
+    }
+}
+
+struct ApiError(anyhow::Error);
+
+impl From<anyhow::Error> for ApiError {
+    fn from(error: anyhow::Error) -> Self {
+        ApiError(error)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (StatusCode::INTERNAL_SERVER_ERROR, self.0.to_string()).into_response()
+    }
+}
+
+pub(crate) fn router(api_state: ApiState) -> Router {
+    Router::new()
+        .route("/state", get(get_state))
+        .route("/states", get(get_states))
+        .route("/alerts", get(get_alerts))
+        .route("/alerts/breakdown", get(get_alert_breakdown))
+        .route("/incidents", get(get_incidents))
+        .route("/stats", get(get_stats))
+        .route("/metrics", get(get_metrics))
+        .route("/processes/:pid/history", get(get_process_history))
+        .route("/debug/detector", get(get_detector))
+        .with_state(api_state)
+}
+
+/// Serves the API until `shutdown` is cancelled.
+pub(crate) async fn serve(api_state: ApiState, bind: SocketAddr, shutdown: CancellationToken) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(bind).await?;
+    info!("HTTP API listening on {}", bind);
+
+    axum::serve(listener, router(api_state))
+        .with_graceful_shutdown(async move { shutdown.cancelled().await })
+        .await?;
+
+    Ok(())
+}
+
+async fn get_state(State(api): State<ApiState>) -> Json<SystemState> {
+    Json(api.state.read().await.clone())
+}
+
+async fn get_alerts(
+    State(api): State<ApiState>,
+    Query(query): Query<AlertsQuery>,
+) -> Result<Json<Vec<SecurityAlert>>, ApiError> {
+    let since = SinceQuery { since: query.since }.since();
+    let Some(until) = query.until else {
+        return Ok(Json(api.db.get_alerts_since(since, query.min_severity).await?));
+    };
+
+    let mut alerts = api.db.get_alerts_between(since, until).await?;
+    if let Some(min_severity) = query.min_severity {
+        alerts.retain(|alert| alert.severity >= min_severity);
+    }
+    Ok(Json(alerts))
+}
+
+async fn get_incidents(
+    State(api): State<ApiState>,
+    Query(query): Query<SinceQuery>,
+) -> Result<Json<Vec<Incident>>, ApiError> {
+    Ok(Json(api.db.get_incidents_since(query.since()).await?))
+}
+
+async fn get_states(
+    State(api): State<ApiState>,
+    Query(query): Query<RangeQuery>,
+) -> Result<Json<Vec<SystemState>>, ApiError> {
+    let since = SinceQuery { since: query.since }.since();
+    let until = query.until.unwrap_or_else(Utc::now);
+    Ok(Json(api.db.get_system_states_between(since, until).await?))
+}
+
+#[derive(Debug, Serialize)]
+struct AlertBreakdownEntry {
+    source: String,
+    severity: AlertSeverity,
+    count: i64,
+}
+
+async fn get_alert_breakdown(
+    State(api): State<ApiState>,
+    Query(query): Query<SinceQuery>,
+) -> Result<Json<Vec<AlertBreakdownEntry>>, ApiError> {
+    let breakdown = api.db.get_alert_breakdown(query.since()).await?;
+    Ok(Json(breakdown.into_iter()
+        .map(|(source, severity, count)| AlertBreakdownEntry { source, severity, count })
+        .collect()))
+}
+
+async fn get_stats(
+    State(api): State<ApiState>,
+    Query(query): Query<SinceQuery>,
+) -> Result<Json<SystemStatistics>, ApiError> {
+    Ok(Json(api.db.get_statistics(query.since()).await?))
+}
+
+async fn get_process_history(
+    State(api): State<ApiState>,
+    Path(pid): Path<u32>,
+) -> Result<Json<ProcessHistory>, StatusCode> {
+    api.monitor.get_process_history(pid).await
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn get_detector(State(api): State<ApiState>) -> Json<DetectorSnapshot> {
+    Json(api.analyzer.snapshot().await)
+}
+
+async fn get_metrics(State(api): State<ApiState>) -> impl IntoResponse {
+    // Render under the read guard only; it is released before the response is sent
+    let body = metrics::render(&*api.state.read().await);
+    ([(header::CONTENT_TYPE, metrics::CONTENT_TYPE)], body)
+}
@@ -0,0 +1,199 @@
+use anyhow::Result;
+use chrono::Utc;
+use tracing::warn;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, RwLock};
+use tokio_util::sync::CancellationToken;
+use crate::analysis::AnalysisRule;
+use crate::store::StateStore;
+use crate::security::SecurityPolicies;
+use crate::{
+    alerting, analysis, incidents, monitor, network, security, AngeGardien, Config, NetworkStats,
+    SystemState,
+};
+
+/// Assembles an `AngeGardien`, for embedding the service or testing it with
+/// pieces swapped out. Anything not set comes from the config.
+pub struct AngeGardienBuilder {
+    config: Config,
+    store: Option<Arc<dyn StateStore>>,
+    policies: Option<SecurityPolicies>,
+    poll_interval: Option<Duration>,
+    rules: Vec<Box<dyn AnalysisRule>>,
+    network: bool,
+    python: bool,
+}
+
+impl Default for AngeGardienBuilder {
+    fn default() -> Self {
+        Self {
+            config: Config::default(),
+            store: None,
+            policies: None,
+            poll_interval: None,
+            rules: Vec::new(),
+            network: true,
+            python: true,
+        }
+    }
+}
+
+impl AngeGardienBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Persists to `store` instead of the store named in the config, e.g. an
+    /// `InMemoryStore` in tests.
+    pub fn with_store(mut self, store: Arc<dyn StateStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Uses `policies` instead of the config's policy file or inline table.
+    pub fn with_policies(mut self, policies: SecurityPolicies) -> Self {
+        self.policies = Some(policies);
+        self
+    }
+
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = Some(interval);
+        self
+    }
+
+    /// Runs `rule` on every collected state, after the built-in rules and any
+    /// added before it.
+    pub fn with_rule(mut self, rule: Box<dyn AnalysisRule>) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Skips connection monitoring, and the checks built on it, entirely.
+    pub fn disable_network(mut self) -> Self {
+        self.network = false;
+        self
+    }
+
+    /// Leaves out the Python IsolationForest even when `anomaly_ensemble` is
+    /// set, so anomaly detection is DBSCAN alone.
+    pub fn disable_python(mut self) -> Self {
+        self.python = false;
+        self
+    }
+
+    pub async fn build(self) -> Result<AngeGardien> {
+        let mut config = self.config;
+        if let Some(interval) = self.poll_interval {
+            config.poll_interval_ms = interval.as_millis() as u64;
+        }
+        if !self.python {
+            config.anomaly_ensemble = None;
+        }
+        let policies = match self.policies {
+            Some(policies) => {
+                policies.validate()?;
+                policies
+            }
+            None => config.security_policies()?,
+        };
+        let db = match self.store {
+            Some(store) => store,
+            None => AngeGardien::open_store(&config)?,
+        };
+
+        let monitor = Arc::new(monitor::SystemMonitor::with_container_mode(config.container_mode));
+        let network_monitor = if self.network {
+            Some(Arc::new(Self::network_monitor(&config)?))
+        } else {
+            None
+        };
+        #[cfg_attr(not(feature = "python"), allow(unused_mut))]
+        let mut analyzer = match &config.anomaly_model_path {
+            Some(path) => analysis::Analyzer::load_model(path),
+            None => analysis::Analyzer::new(),
+        };
+        #[cfg(feature = "python")]
+        if let Some(mode) = config.anomaly_ensemble {
+            match crate::ensemble::EnsembleDetector::new(mode) {
+                Ok(detector) => analyzer = analyzer.with_ensemble(detector),
+                Err(e) => warn!("Failed to start the IsolationForest, using DBSCAN alone: {}", e),
+            }
+        }
+        #[cfg(not(feature = "python"))]
+        if config.anomaly_ensemble.is_some() {
+            warn!("anomaly_ensemble needs the python feature, which this build lacks; using DBSCAN alone");
+        }
+        for rule in self.rules {
+            analyzer.register_rule(rule);
+        }
+        let analyzer = Arc::new(analyzer);
+        analyzer.load_feedback(db.get_anomaly_feedback().await?).await;
+        if let Err(e) = analyzer.update_baseline(&*db, analysis::BASELINE_DAYS).await {
+            warn!("Failed to fit the hourly baseline: {}", e);
+        }
+        let security = Arc::new(
+            security::SecurityManager::new(Some(policies))?
+                .with_enforcement_mode(config.enforcement_mode),
+        );
+        if let Some(path) = &config.known_hashes_path {
+            security.load_known_hashes(path, config.enroll_hashes).await?;
+        }
+        let alert_dispatcher = Arc::new(alerting::AlertDispatcher::from_config(&config.alerting).await?);
+
+        let initial_state = SystemState {
+            timestamp: Utc::now(),
+            cpu_usage: 0.0,
+            per_core_cpu: Vec::new(),
+            memory_usage: 0.0,
+            disk_usage: 0.0,
+            disks: Vec::new(),
+            network_stats: NetworkStats::default(),
+            active_processes: Vec::new(),
+            security_alerts: Vec::new(),
+            system_metrics: None,
+            collection_duration_ms: 0,
+        };
+
+        Ok(AngeGardien {
+            state: Arc::new(RwLock::new(initial_state)),
+            db,
+            monitor,
+            network_monitor,
+            analyzer,
+            security,
+            alert_dispatcher,
+            incidents: Arc::new(Mutex::new(incidents::IncidentCorrelator::new(config.incident_window()))),
+            redaction: config.redaction.clone(),
+            poll_interval: Arc::new(RwLock::new(config.poll_interval())),
+            shutdown: CancellationToken::new(),
+            tasks: Mutex::new(Vec::new()),
+            config,
+        })
+    }
+
+    fn network_monitor(config: &Config) -> Result<network::NetworkMonitor> {
+        let network_monitor = network::NetworkMonitor::new()?
+            .with_fan_out(config.fan_out.clone())
+            .with_beaconing(config.beaconing.clone())
+            .with_icmp(config.icmp.clone())
+            .with_interfaces(config.capture_interfaces.clone(), config.exclude_interfaces.clone())
+            .with_connection_ttl(config.connection_ttl())
+            .with_capture_filter(config.capture_filter.clone())?;
+        #[cfg(feature = "geoip")]
+        let network_monitor = match crate::geoip::GeoIp::from_config(&config.geoip) {
+            Some(geoip) => network_monitor.with_geoip(geoip),
+            None => network_monitor,
+        };
+        #[cfg(not(feature = "geoip"))]
+        if config.geoip.is_configured() {
+            warn!("geoip databases are configured but this build lacks the geoip feature; skipping enrichment");
+        }
+        Ok(network_monitor)
+    }
+}
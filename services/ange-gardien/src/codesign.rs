@@ -0,0 +1,202 @@
+use anyhow::Result;
+use core_foundation::array::CFArray;
+use core_foundation::base::{CFType, TCFType};
+use core_foundation::dictionary::CFDictionary;
+use core_foundation::string::CFString;
+use core_foundation::url::CFURL;
+use core_foundation_sys::base::{CFRelease, CFTypeRef};
+use core_foundation_sys::string::CFStringRef;
+use security_framework::certificate::SecCertificate;
+use serde::{Serialize, Deserialize};
+use std::path::Path;
+
+/// The authority name that stands for Apple's own platform binaries.
+pub const APPLE_AUTHORITY: &str = "Apple";
+
+/// Who signed a binary, as recorded in its code signature.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SigningInfo {
+    /// Bundle or binary identifier, e.g. `com.apple.ls`
+    pub identifier: Option<String>,
+    pub team_id: Option<String>,
+    /// Common name of the leaf certificate, e.g. `Developer ID Application: ...`
+    pub authority: Option<String>,
+}
+
+/// Builds a code requirement satisfied by binaries signed by one of
+/// `authorities` (leaf certificate common-name prefixes, or `Apple` for
+/// platform binaries) or by a Developer ID in one of `team_ids`. Ad-hoc and
+/// self-signed binaries never satisfy it.
+pub fn requirement(authorities: &[String], team_ids: &[String]) -> Result<String> {
+    let quote = |value: &str| -> Result<String> {
+        if value.contains('"') || value.contains('\\') {
+            return Err(anyhow::anyhow!("Invalid signing authority or team ID: {}", value));
+        }
+        Ok(format!("\"{}\"", value))
+    };
+
+    let mut clauses = Vec::new();
+    for authority in authorities {
+        if authority == APPLE_AUTHORITY {
+            continue;
+        }
+        clauses.push(format!("certificate leaf[subject.CN] = {}", quote(&format!("{}*", authority))?));
+    }
+    for team_id in team_ids {
+        clauses.push(format!("certificate leaf[subject.OU] = {}", quote(team_id)?));
+    }
+
+    let mut alternatives = Vec::new();
+    if authorities.iter().any(|authority| authority == APPLE_AUTHORITY) {
+        alternatives.push("anchor apple".to_string());
+    }
+    if !clauses.is_empty() {
+        alternatives.push(format!("(anchor apple generic and ({}))", clauses.join(" or ")));
+    }
+    if alternatives.is_empty() {
+        return Err(anyhow::anyhow!("No signing authorities or team IDs are allowed"));
+    }
+    Ok(alternatives.join(" or "))
+}
+
+/// Validates the code signature of the binary or bundle at `path` against
+/// `requirement` and returns who signed it. Unsigned, tampered, ad-hoc
+/// signed and otherwise non-matching binaries are errors.
+pub fn verify(path: &Path, requirement: &str) -> Result<SigningInfo> {
+    let url = CFURL::from_path(path, false)
+        .ok_or_else(|| anyhow::anyhow!("Invalid path {}", path.display()))?;
+    let requirement_text = CFString::new(requirement);
+
+    unsafe {
+        let mut code = std::ptr::null();
+        check(
+            ffi::SecStaticCodeCreateWithPath(url.as_concrete_TypeRef(), ffi::DEFAULT_FLAGS, &mut code),
+            path,
+        )?;
+        let code = Owned(code);
+
+        let mut compiled = std::ptr::null();
+        let status = ffi::SecRequirementCreateWithString(
+            requirement_text.as_concrete_TypeRef(),
+            ffi::DEFAULT_FLAGS,
+            &mut compiled,
+        );
+        if status != 0 {
+            return Err(anyhow::anyhow!("Invalid code requirement {} ({})", requirement, status));
+        }
+        let compiled = Owned(compiled);
+
+        check(ffi::SecStaticCodeCheckValidity(code.0, ffi::VALIDITY_FLAGS, compiled.0), path)?;
+
+        let mut info = std::ptr::null();
+        check(ffi::SecCodeCopySigningInformation(code.0, ffi::SIGNING_INFORMATION, &mut info), path)?;
+        Ok(signing_info(&CFDictionary::wrap_under_create_rule(info)))
+    }
+}
+
+fn check(status: i32, path: &Path) -> Result<()> {
+    match status {
+        0 => Ok(()),
+        ffi::ERR_UNSIGNED => Err(anyhow::anyhow!("{} is not signed", path.display())),
+        ffi::ERR_REQUIREMENT_FAILED => Err(anyhow::anyhow!(
+            "{} is not signed by an allowed authority",
+            path.display()
+        )),
+        ffi::ERR_SIGNATURE_FAILED => Err(anyhow::anyhow!(
+            "{} has an invalid signature; it may have been modified",
+            path.display()
+        )),
+        status => Err(anyhow::anyhow!("Failed to verify the signature of {} ({})", path.display(), status)),
+    }
+}
+
+unsafe fn signing_info(info: &CFDictionary<CFString, CFType>) -> SigningInfo {
+    let string = |key: CFStringRef| {
+        info.find(CFString::wrap_under_get_rule(key))
+            .and_then(|value| value.downcast::<CFString>())
+            .map(|value| value.to_string())
+    };
+    // The certificate chain is an untyped array; the leaf comes first
+    let authority = info.find(CFString::wrap_under_get_rule(ffi::kSecCodeInfoCertificates))
+        .and_then(|value| value.downcast::<CFArray>())
+        .and_then(|certificates| {
+            certificates.get(0).map(|leaf| SecCertificate::wrap_under_get_rule(*leaf as _))
+        })
+        .map(|leaf| leaf.subject_summary());
+
+    SigningInfo {
+        identifier: string(ffi::kSecCodeInfoIdentifier),
+        team_id: string(ffi::kSecCodeInfoTeamIdentifier),
+        authority,
+    }
+}
+
+/// Releases a Security framework object on drop.
+struct Owned(CFTypeRef);
+
+impl Drop for Owned {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            unsafe { CFRelease(self.0) }
+        }
+    }
+}
+
+mod ffi {
+    use core_foundation_sys::base::CFTypeRef;
+    use core_foundation_sys::dictionary::CFDictionaryRef;
+    use core_foundation_sys::string::CFStringRef;
+    use core_foundation_sys::url::CFURLRef;
+
+    pub const DEFAULT_FLAGS: u32 = 0;
+    /// kSecCSCheckAllArchitectures | kSecCSCheckNestedCode | kSecCSStrictValidate
+    pub const VALIDITY_FLAGS: u32 = (1 << 0) | (1 << 3) | (1 << 4);
+    /// kSecCSSigningInformation
+    pub const SIGNING_INFORMATION: u32 = 1 << 1;
+
+    pub const ERR_UNSIGNED: i32 = -67062;
+    pub const ERR_SIGNATURE_FAILED: i32 = -67061;
+    pub const ERR_REQUIREMENT_FAILED: i32 = -67050;
+
+    #[link(name = "Security", kind = "framework")]
+    extern "C" {
+        pub static kSecCodeInfoIdentifier: CFStringRef;
+        pub static kSecCodeInfoTeamIdentifier: CFStringRef;
+        pub static kSecCodeInfoCertificates: CFStringRef;
+
+        pub fn SecStaticCodeCreateWithPath(path: CFURLRef, flags: u32, code: *mut CFTypeRef) -> i32;
+        pub fn SecRequirementCreateWithString(text: CFStringRef, flags: u32, requirement: *mut CFTypeRef) -> i32;
+        pub fn SecStaticCodeCheckValidity(code: CFTypeRef, flags: u32, requirement: CFTypeRef) -> i32;
+        pub fn SecCodeCopySigningInformation(code: CFTypeRef, flags: u32, information: *mut CFDictionaryRef) -> i32;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requirement_from_policies() {
+        let authorities = vec![APPLE_AUTHORITY.to_string(), "Developer ID Application".to_string()];
+        assert_eq!(
+            requirement(&authorities, &["ABCDE12345".to_string()]).unwrap(),
+            "anchor apple or (anchor apple generic and (\
+             certificate leaf[subject.CN] = \"Developer ID Application*\" or \
+             certificate leaf[subject.OU] = \"ABCDE12345\"))"
+        );
+        assert_eq!(requirement(&[APPLE_AUTHORITY.to_string()], &[]).unwrap(), "anchor apple");
+        assert!(requirement(&[], &[]).is_err());
+        assert!(requirement(&["Evil\" or anchor trusted".to_string()], &[]).is_err());
+    }
+
+    #[test]
+    fn test_verify_platform_binary() {
+        let info = verify(Path::new("/bin/ls"), "anchor apple").unwrap();
+        assert_eq!(info.identifier.as_deref(), Some("com.apple.ls"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let unsigned = dir.path().join("unsigned");
+        std::fs::write(&unsigned, b"#!/bin/sh\n").unwrap();
+        assert!(verify(&unsigned, "anchor apple").is_err());
+    }
+}
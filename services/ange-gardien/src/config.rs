@@ -0,0 +1,259 @@
+use anyhow::Result;
+use serde::{Serialize, Deserialize};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::info;
+use crate::alerting::AlertingConfig;
+use crate::bundle::RedactionOptions;
+use crate::container::ContainerMode;
+use crate::ensemble::EnsembleMode;
+use crate::geoip::GeoIpConfig;
+use crate::network::{BeaconConfig, FanOutConfig, IcmpConfig};
+use crate::security::{EnforcementMode, SecurityPolicies, DEFAULT_SERVICE_USER};
+
+/// Service configuration. Every field has a default, so a config file only
+/// needs to contain the settings it changes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub poll_interval_ms: u64,
+    /// SQLite database file; defaults to the platform data directory
+    pub database_path: Option<PathBuf>,
+    /// Postgres connection URL; when set it is used instead of SQLite (needs
+    /// the `postgres` feature)
+    pub database_url: Option<String>,
+    /// Where the anomaly detector is saved on shutdown and restored on start;
+    /// the detector starts cold on every run when unset
+    pub anomaly_model_path: Option<PathBuf>,
+    /// Combine DBSCAN with the Python IsolationForest (`and` or `or`); DBSCAN
+    /// alone when unset
+    pub anomaly_ensemble: Option<EnsembleMode>,
+    pub container_mode: ContainerMode,
+    pub redaction: RedactionOptions,
+    pub api: ApiConfig,
+    pub alerting: AlertingConfig,
+    /// Separate policy file; when set it replaces the inline `security` table
+    pub security_policy_file: Option<PathBuf>,
+    pub security: SecurityPolicies,
+    /// `monitor` reports violations without terminating processes, denying
+    /// execs or dropping privileges, for tuning policies before enforcing
+    pub enforcement_mode: EnforcementMode,
+    /// How long states and alerts are kept before the cleanup task deletes them
+    pub retention_days: u32,
+    /// How often the cleanup task runs
+    pub cleanup_interval_secs: u64,
+    /// States older than this are down-sampled to one-minute rollups
+    pub rollup_after_hours: u32,
+    /// SQLite writes are batched: states are committed together once this
+    /// many are queued or `write_flush_interval_secs` has passed
+    pub write_batch_size: usize,
+    pub write_flush_interval_secs: u64,
+    /// Account the service switches to after startup when launched as root;
+    /// startup fails if it doesn't exist
+    pub service_user: String,
+    /// Allowlist of approved binary SHA-256 hashes, one `<hex>  <path>` line
+    /// each; binaries with other hashes are flagged when set
+    pub known_hashes_path: Option<PathBuf>,
+    /// Add the hashes of running binaries to `known_hashes_path` instead of
+    /// flagging them, to record a baseline
+    pub enroll_hashes: bool,
+    /// MaxMind databases for tagging connections with country and ASN (needs
+    /// the `geoip` feature); skipped when unset
+    pub geoip: GeoIpConfig,
+    /// Per-process limits on distinct remote ports and hosts before a
+    /// scanning alert is raised
+    pub fan_out: FanOutConfig,
+    /// When reconnects to one endpoint are regular enough to flag as
+    /// beaconing
+    pub beaconing: BeaconConfig,
+    /// Interfaces to capture packets on, by name; `*` at the end matches a
+    /// prefix. Every interface that is up when unset
+    pub capture_interfaces: Option<Vec<String>>,
+    /// Interfaces never captured on, e.g. `bridge*` for VM and container
+    /// bridges; applied after `capture_interfaces`
+    pub exclude_interfaces: Vec<String>,
+    /// Only capture packets matching this pcap filter, in tcpdump syntax
+    /// (e.g. `tcp and not port 22`); startup fails if it doesn't compile
+    pub capture_filter: Option<String>,
+    /// ICMP packet and echo payload rates above which an alert is raised
+    pub icmp: IcmpConfig,
+    /// Captured connections with no packets for this long are dropped
+    pub connection_ttl_secs: u64,
+    /// Related alerts this close together are grouped into one incident
+    pub incident_window_secs: u64,
+}
+
+/// HTTP API settings; only used when built with the `api` feature.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApiConfig {
+    pub enabled: bool,
+    pub bind: SocketAddr,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            bind: SocketAddr::from(([127, 0, 0, 1], 8787)),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            poll_interval_ms: 1000,
+            database_path: None,
+            database_url: None,
+            anomaly_model_path: None,
+            anomaly_ensemble: None,
+            container_mode: ContainerMode::default(),
+            redaction: RedactionOptions::default(),
+            api: ApiConfig::default(),
+            alerting: AlertingConfig::default(),
+            security_policy_file: None,
+            security: SecurityPolicies::default(),
+            enforcement_mode: EnforcementMode::default(),
+            retention_days: 7,
+            cleanup_interval_secs: 3600,
+            rollup_after_hours: 24,
+            write_batch_size: 10,
+            write_flush_interval_secs: 10,
+            service_user: DEFAULT_SERVICE_USER.to_string(),
+            known_hashes_path: None,
+            enroll_hashes: false,
+            geoip: GeoIpConfig::default(),
+            fan_out: FanOutConfig::default(),
+            beaconing: BeaconConfig::default(),
+            capture_interfaces: None,
+            exclude_interfaces: Vec::new(),
+            capture_filter: None,
+            icmp: IcmpConfig::default(),
+            connection_ttl_secs: 300,
+            incident_window_secs: 30,
+        }
+    }
+}
+
+impl Config {
+    /// Loads a TOML or JSON config, chosen by file extension (TOML otherwise).
+    /// A missing file yields the defaults; a malformed one is an error.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if !path.exists() {
+            info!("Config file {} not found, using defaults", path.display());
+            return Ok(Self::default());
+        }
+
+        let contents = std::fs::read_to_string(path)?;
+        let is_json = path.extension().is_some_and(|ext| ext == "json");
+
+        if is_json {
+            serde_json::from_str(&contents).map_err(|e| {
+                anyhow::anyhow!(
+                    "Failed to parse config {} at line {}: {}",
+                    path.display(),
+                    e.line(),
+                    e
+                )
+            })
+        } else {
+            toml::from_str(&contents).map_err(|e| {
+                let line = e.span()
+                    .map(|span| contents[..span.start].matches('\n').count() + 1)
+                    .unwrap_or(0);
+                anyhow::anyhow!(
+                    "Failed to parse config {} at line {}: {}",
+                    path.display(),
+                    line,
+                    e.message()
+                )
+            })
+        }
+    }
+
+    /// The policies in effect: the policy file if one is configured, otherwise
+    /// the inline `security` table.
+    pub fn security_policies(&self) -> Result<SecurityPolicies> {
+        match &self.security_policy_file {
+            Some(path) => SecurityPolicies::from_file(path),
+            None => {
+                self.security.validate()?;
+                Ok(self.security.clone())
+            }
+        }
+    }
+
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval_ms)
+    }
+
+    pub fn retention(&self) -> chrono::Duration {
+        chrono::Duration::days(self.retention_days as i64)
+    }
+
+    pub fn rollup_after(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.rollup_after_hours as i64)
+    }
+
+    pub fn cleanup_interval(&self) -> Duration {
+        Duration::from_secs(self.cleanup_interval_secs.max(1))
+    }
+
+    pub fn write_flush_interval(&self) -> Duration {
+        Duration::from_secs(self.write_flush_interval_secs)
+    }
+
+    pub fn connection_ttl(&self) -> Duration {
+        Duration::from_secs(self.connection_ttl_secs)
+    }
+
+    pub fn incident_window(&self) -> Duration {
+        Duration::from_secs(self.incident_window_secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_missing_file_uses_defaults() {
+        let config = Config::from_path("/nonexistent/ange-gardien.toml").unwrap();
+        assert_eq!(config.poll_interval(), Duration::from_secs(1));
+        assert!(config.database_path.is_none());
+        assert_eq!(config.retention(), chrono::Duration::days(7));
+        assert_eq!(config.cleanup_interval(), Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn test_partial_config_keeps_defaults() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "poll_interval_ms = 250\n").unwrap();
+
+        let config = Config::from_path(&path).unwrap();
+        assert_eq!(config.poll_interval(), Duration::from_millis(250));
+        assert_eq!(config.container_mode, ContainerMode::Auto);
+
+        let json_path = dir.path().join("config.json");
+        std::fs::write(&json_path, r#"{ "database_path": "/tmp/monitor.db" }"#).unwrap();
+
+        let config = Config::from_path(&json_path).unwrap();
+        assert_eq!(config.poll_interval_ms, 1000);
+        assert_eq!(config.database_path, Some(PathBuf::from("/tmp/monitor.db")));
+    }
+
+    #[test]
+    fn test_parse_error_reports_line() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "poll_interval_ms = 250\ncontainer_mode = [\n").unwrap();
+
+        let error = Config::from_path(&path).unwrap_err().to_string();
+        assert!(error.contains("line 2"), "{}", error);
+    }
+}
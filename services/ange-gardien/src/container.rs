@@ -0,0 +1,205 @@
+use anyhow::Result;
+use serde::{Serialize, Deserialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tracing::info;
+
+const CGROUP_V2_ROOT: &str = "/sys/fs/cgroup";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum ContainerMode {
+    /// Use cgroup limits when a container is detected, host values otherwise
+    #[default]
+    Auto,
+    /// Always report host-wide values
+    Host,
+    /// Always read cgroup v2 files, even when no container is detected
+    Cgroup,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Environment {
+    Host,
+    Container { runtime: String },
+}
+
+/// Detects whether we are running inside a container.
+///
+/// Checked in order: the `container` environment variable (set by systemd-nspawn
+/// and podman), the `/.dockerenv` and `/run/.containerenv` marker files, and
+/// finally runtime names appearing in `/proc/1/cgroup`.
+pub fn detect_environment() -> Environment {
+    if let Ok(runtime) = std::env::var("container") {
+        if !runtime.is_empty() {
+            return Environment::Container { runtime };
+        }
+    }
+
+    if Path::new("/.dockerenv").exists() {
+        return Environment::Container { runtime: "docker".to_string() };
+    }
+
+    if Path::new("/run/.containerenv").exists() {
+        return Environment::Container { runtime: "podman".to_string() };
+    }
+
+    if let Ok(cgroup) = fs::read_to_string("/proc/1/cgroup") {
+        for runtime in ["docker", "containerd", "kubepods", "lxc"] {
+            if cgroup.contains(runtime) {
+                return Environment::Container { runtime: runtime.to_string() };
+            }
+        }
+    }
+
+    Environment::Host
+}
+
+/// Reads resource limits and usage from a cgroup v2 directory.
+#[derive(Debug, Clone)]
+pub struct CgroupV2 {
+    root: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CpuSample {
+    usage_usec: u64,
+    taken_at: Instant,
+}
+
+impl CgroupV2 {
+    /// Resolves the cgroup to read according to `mode`, or `None` for the host view.
+    pub fn for_mode(mode: ContainerMode) -> Option<Self> {
+        let use_cgroup = match mode {
+            ContainerMode::Host => false,
+            ContainerMode::Cgroup => true,
+            ContainerMode::Auto => match detect_environment() {
+                Environment::Host => false,
+                Environment::Container { runtime } => {
+                    info!("Detected {} container, reading cgroup resource limits", runtime);
+                    true
+                }
+            },
+        };
+
+        if use_cgroup {
+            Self::at(CGROUP_V2_ROOT)
+        } else {
+            None
+        }
+    }
+
+    /// Opens the cgroup v2 hierarchy at `root`, if it is one.
+    pub fn at<P: AsRef<Path>>(root: P) -> Option<Self> {
+        let root = root.as_ref();
+        if root.join("cgroup.controllers").exists() {
+            Some(Self { root: root.to_path_buf() })
+        } else {
+            None
+        }
+    }
+
+    fn read(&self, file: &str) -> Result<String> {
+        Ok(fs::read_to_string(self.root.join(file))?.trim().to_string())
+    }
+
+    /// Memory limit in bytes, or `None` when unlimited.
+    pub fn memory_limit(&self) -> Result<Option<u64>> {
+        let value = self.read("memory.max")?;
+        if value == "max" {
+            return Ok(None);
+        }
+        Ok(Some(value.parse()?))
+    }
+
+    pub fn memory_current(&self) -> Result<u64> {
+        Ok(self.read("memory.current")?.parse()?)
+    }
+
+    /// CPU limit as a number of cores, or `None` when unlimited.
+    pub fn cpu_limit(&self) -> Result<Option<f64>> {
+        let value = self.read("cpu.max")?;
+        let mut parts = value.split_whitespace();
+        let quota = parts.next().unwrap_or("max");
+        let period: f64 = parts.next().unwrap_or("100000").parse()?;
+
+        if quota == "max" || period <= 0.0 {
+            return Ok(None);
+        }
+        Ok(Some(quota.parse::<f64>()? / period))
+    }
+
+    pub fn cpu_sample(&self) -> Result<CpuSample> {
+        let stat = self.read("cpu.stat")?;
+        let usage_usec = stat.lines()
+            .find_map(|line| line.strip_prefix("usage_usec "))
+            .ok_or_else(|| anyhow::anyhow!("usage_usec missing from cpu.stat"))?
+            .trim()
+            .parse()?;
+
+        Ok(CpuSample {
+            usage_usec,
+            taken_at: Instant::now(),
+        })
+    }
+
+    /// CPU usage between two samples as a percentage of the cgroup's CPU limit
+    /// (or of `host_cores` when the cgroup is unlimited).
+    pub fn cpu_usage_percent(&self, previous: &CpuSample, current: &CpuSample, host_cores: usize) -> Result<f32> {
+        let elapsed_usec = current.taken_at.duration_since(previous.taken_at).as_micros() as f64;
+        if elapsed_usec <= 0.0 {
+            return Ok(0.0);
+        }
+
+        let cores = self.cpu_limit()?.unwrap_or(host_cores.max(1) as f64);
+        let used_usec = current.usage_usec.saturating_sub(previous.usage_usec) as f64;
+        Ok((used_usec / (elapsed_usec * cores) * 100.0).clamp(0.0, 100.0) as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_cgroup_limits() {
+        let dir = tempdir().unwrap();
+        assert!(CgroupV2::at(dir.path()).is_none());
+
+        fs::write(dir.path().join("cgroup.controllers"), "cpu memory").unwrap();
+        fs::write(dir.path().join("memory.max"), "536870912\n").unwrap();
+        fs::write(dir.path().join("memory.current"), "268435456\n").unwrap();
+        fs::write(dir.path().join("cpu.max"), "200000 100000\n").unwrap();
+
+        let cgroup = CgroupV2::at(dir.path()).unwrap();
+        assert_eq!(cgroup.memory_limit().unwrap(), Some(536870912));
+        assert_eq!(cgroup.memory_current().unwrap(), 268435456);
+        assert_eq!(cgroup.cpu_limit().unwrap(), Some(2.0));
+
+        fs::write(dir.path().join("memory.max"), "max\n").unwrap();
+        fs::write(dir.path().join("cpu.max"), "max 100000\n").unwrap();
+        assert_eq!(cgroup.memory_limit().unwrap(), None);
+        assert_eq!(cgroup.cpu_limit().unwrap(), None);
+    }
+
+    #[test]
+    fn test_cgroup_cpu_usage() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("cgroup.controllers"), "cpu").unwrap();
+        fs::write(dir.path().join("cpu.max"), "100000 100000\n").unwrap();
+        let cgroup = CgroupV2::at(dir.path()).unwrap();
+
+        let start = Instant::now();
+        let previous = CpuSample { usage_usec: 1_000_000, taken_at: start };
+        let current = CpuSample {
+            usage_usec: 1_500_000,
+            taken_at: start + Duration::from_secs(1),
+        };
+
+        // Half a second of CPU over one second with a one-core limit
+        let usage = cgroup.cpu_usage_percent(&previous, &current, 8).unwrap();
+        assert!((usage - 50.0).abs() < 0.01);
+    }
+}
@@ -1,18 +1,25 @@
 use anyhow::Result;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, DurationRound, Utc};
 use diesel::prelude::*;
 use diesel::sqlite::{Sqlite, SqliteConnection};
-use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::connection::{DefaultLoadingMode, SimpleConnection};
+use diesel::r2d2::{ConnectionManager, CustomizeConnection, Pool};
 use diesel::sql_types::Timestamp;
 use diesel::serialize::{ToSql, Output};
 use diesel::deserialize::{FromSql, FromSqlRow};
 use diesel::expression::AsExpression;
+use serde::{Serialize, Deserialize};
 use serde_json;
-use std::path::PathBuf;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use directories::ProjectDirs;
 use crate::{SystemState, SecurityAlert, NetworkStats, AlertSeverity};
-use log::{info, error};
+use tracing::{info, error};
 use crate::time::TimeStamp;
+use crate::store::StateStore;
+use crate::incidents::Incident;
+use async_trait::async_trait;
 
 #[derive(FromSqlRow, AsExpression)]
 #[diesel(sql_type = Timestamp)]
@@ -59,6 +66,8 @@ table! {
         network_stats -> Text,
         processes -> Text,
         alerts -> Text,
+        system_metrics -> Nullable<Text>,
+        collection_duration_ms -> Nullable<BigInt>,
     }
 }
 
@@ -70,6 +79,45 @@ table! {
         description -> Text,
         source -> Text,
         recommendation -> Nullable<Text>,
+        count -> Integer,
+        would_enforce -> Bool,
+        rule -> Nullable<Text>,
+        pid -> Nullable<BigInt>,
+        remote_ip -> Nullable<Text>,
+    }
+}
+
+table! {
+    system_states_1m (bucket) {
+        bucket -> Timestamp,
+        samples -> Integer,
+        cpu_avg -> Float,
+        cpu_min -> Float,
+        cpu_max -> Float,
+        memory_avg -> Float,
+        memory_min -> Float,
+        memory_max -> Float,
+        disk_avg -> Float,
+        disk_min -> Float,
+        disk_max -> Float,
+    }
+}
+
+table! {
+    anomaly_feedback (id) {
+        id -> Nullable<Integer>,
+        timestamp -> Timestamp,
+        recorded_at -> Timestamp,
+        state -> Text,
+    }
+}
+
+table! {
+    incidents (id) {
+        id -> Text,
+        started_at -> Timestamp,
+        severity -> Text,
+        alerts -> Text,
     }
 }
 
@@ -85,6 +133,8 @@ struct SystemStateRecord {
     network_stats: String,
     processes: String,
     alerts: String,
+    system_metrics: Option<String>,
+    collection_duration_ms: Option<i64>,
 }
 
 #[derive(Debug, Queryable, Insertable, Selectable)]
@@ -97,10 +147,86 @@ struct SecurityAlertRecord {
     description: String,
     source: String,
     recommendation: Option<String>,
+    count: i32,
+    would_enforce: bool,
+    rule: Option<String>,
+    pid: Option<i64>,
+    remote_ip: Option<String>,
+}
+
+/// Averages of one minute of `system_states`, down-sampled by
+/// `rollup_old_states`. The bucket's peaks are only read by
+/// `get_metric_distribution`, which queries them directly.
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = system_states_1m)]
+#[diesel(check_for_backend(Sqlite))]
+struct RollupRecord {
+    bucket: TimeStamp,
+    cpu_avg: f32,
+    memory_avg: f32,
+    disk_avg: f32,
+}
+
+#[derive(Debug, Queryable, Insertable, Selectable)]
+#[diesel(table_name = anomaly_feedback)]
+#[diesel(check_for_backend(Sqlite))]
+struct AnomalyFeedbackRecord {
+    id: Option<i32>,
+    timestamp: TimeStamp,
+    recorded_at: TimeStamp,
+    state: String,
+}
+
+#[derive(Debug, Queryable, Insertable, Selectable)]
+#[diesel(table_name = incidents)]
+#[diesel(check_for_backend(Sqlite))]
+struct IncidentRecord {
+    id: String,
+    started_at: TimeStamp,
+    severity: String,
+    alerts: String,
+}
+
+/// Resolution of the `system_states_1m` rollup table
+const ROLLUP_BUCKET: chrono::Duration = chrono::Duration::minutes(1);
+
+/// States buffered for `store_state` are written once this many are queued...
+const DEFAULT_BATCH_SIZE: usize = 10;
+/// ...or once this long has passed since the last write.
+const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+/// Latest one-off data migration; see `Database::migrate`
+pub(crate) const SCHEMA_VERSION: i32 = 1;
+
+#[derive(Debug)]
+struct ConnectionOptions;
+
+impl CustomizeConnection<SqliteConnection, diesel::r2d2::Error> for ConnectionOptions {
+    fn on_acquire(&self, connection: &mut SqliteConnection) -> Result<(), diesel::r2d2::Error> {
+        // WAL makes NORMAL durable enough, and readers wait out the writer
+        // rather than failing with SQLITE_BUSY
+        connection
+            .batch_execute("PRAGMA synchronous = NORMAL; PRAGMA busy_timeout = 5000;")
+            .map_err(diesel::r2d2::Error::QueryError)
+    }
+}
+
+struct WriteBuffer {
+    states: Vec<SystemState>,
+    last_flush: Instant,
+}
+
+impl WriteBuffer {
+    fn take(&mut self) -> Vec<SystemState> {
+        self.last_flush = Instant::now();
+        std::mem::take(&mut self.states)
+    }
 }
 
 pub struct Database {
     pool: Pool<ConnectionManager<SqliteConnection>>,
+    buffer: Mutex<WriteBuffer>,
+    batch_size: usize,
+    flush_interval: Duration,
 }
 
 impl Database {
@@ -108,23 +234,53 @@ impl Database {
         let project_dirs = ProjectDirs::from("com", "ange-gardien", "monitor")
             .ok_or_else(|| anyhow::anyhow!("Failed to get project directories"))?;
         
-        let data_dir = project_dirs.data_dir();
-        std::fs::create_dir_all(data_dir)?;
+        Self::with_path(&project_dirs.data_dir().join("monitor.db"))
+    }
+
+    fn database_size(connection: &mut SqliteConnection) -> Result<u64> {
+        let size = diesel::sql_query(
+            "SELECT page_count * page_size AS bytes FROM pragma_page_count(), pragma_page_size()"
+        )
+        .get_result::<DatabaseSize>(connection)?;
+        Ok(size.bytes.max(0) as u64)
+    }
+
+    pub fn with_path(database_url: &Path) -> Result<Self> {
+        if let Some(parent) = database_url.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
         
-        let database_url = data_dir.join("monitor.db");
         let manager = ConnectionManager::<SqliteConnection>::new(database_url.to_str().unwrap());
         let pool = Pool::builder()
             .max_size(10)
+            .connection_customizer(Box::new(ConnectionOptions))
             .build(manager)?;
 
         // Initialize database
         let mut connection = pool.get()?;
         Self::initialize_database(&mut connection)?;
 
-        Ok(Self { pool })
+        Ok(Self {
+            pool,
+            buffer: Mutex::new(WriteBuffer { states: Vec::new(), last_flush: Instant::now() }),
+            batch_size: DEFAULT_BATCH_SIZE,
+            flush_interval: DEFAULT_FLUSH_INTERVAL,
+        })
+    }
+
+    /// Writes buffered states once `batch_size` are queued or `flush_interval`
+    /// has passed since the last write, whichever comes first. A batch of one
+    /// writes every state immediately.
+    pub fn with_write_batch(mut self, batch_size: usize, flush_interval: Duration) -> Self {
+        self.batch_size = batch_size.max(1);
+        self.flush_interval = flush_interval;
+        self
     }
 
     fn initialize_database(connection: &mut SqliteConnection) -> Result<()> {
+        // Persistent for the file, so readers never block the monitor's writes
+        connection.batch_execute("PRAGMA journal_mode = WAL")?;
+
         diesel::sql_query(
             r#"
             CREATE TABLE IF NOT EXISTS system_states (
@@ -135,7 +291,9 @@ impl Database {
                 disk_usage REAL NOT NULL,
                 network_stats TEXT NOT NULL,
                 processes TEXT NOT NULL,
-                alerts TEXT NOT NULL
+                alerts TEXT NOT NULL,
+                system_metrics TEXT,
+                collection_duration_ms INTEGER
             )
             "#,
         ).execute(connection)?;
@@ -148,7 +306,95 @@ impl Database {
                 severity TEXT NOT NULL,
                 description TEXT NOT NULL,
                 source TEXT NOT NULL,
-                recommendation TEXT
+                recommendation TEXT,
+                count INTEGER NOT NULL DEFAULT 1,
+                would_enforce BOOLEAN NOT NULL DEFAULT 0,
+                rule TEXT,
+                pid INTEGER,
+                remote_ip TEXT
+            )
+            "#,
+        ).execute(connection)?;
+
+        // Databases created before uptime was kept lack system_metrics
+        if let Err(e) = diesel::sql_query(
+            "ALTER TABLE system_states ADD COLUMN system_metrics TEXT"
+        ).execute(connection) {
+            if !e.to_string().contains("duplicate column") {
+                return Err(e.into());
+            }
+        }
+        // ...and collection_duration_ms, added with cycle timing
+        if let Err(e) = diesel::sql_query(
+            "ALTER TABLE system_states ADD COLUMN collection_duration_ms INTEGER"
+        ).execute(connection) {
+            if !e.to_string().contains("duplicate column") {
+                return Err(e.into());
+            }
+        }
+        // ...those created before alert deduplication lack the count column
+        if let Err(e) = diesel::sql_query(
+            "ALTER TABLE security_alerts ADD COLUMN count INTEGER NOT NULL DEFAULT 1"
+        ).execute(connection) {
+            if !e.to_string().contains("duplicate column") {
+                return Err(e.into());
+            }
+        }
+        // ...those created before monitor mode lack would_enforce...
+        if let Err(e) = diesel::sql_query(
+            "ALTER TABLE security_alerts ADD COLUMN would_enforce BOOLEAN NOT NULL DEFAULT 0"
+        ).execute(connection) {
+            if !e.to_string().contains("duplicate column") {
+                return Err(e.into());
+            }
+        }
+        // ...and those created before alerts were attributed lack rule, pid and remote_ip
+        for column in ["rule TEXT", "pid INTEGER", "remote_ip TEXT"] {
+            if let Err(e) = diesel::sql_query(
+                format!("ALTER TABLE security_alerts ADD COLUMN {}", column)
+            ).execute(connection) {
+                if !e.to_string().contains("duplicate column") {
+                    return Err(e.into());
+                }
+            }
+        }
+
+        diesel::sql_query(
+            r#"
+            CREATE TABLE IF NOT EXISTS anomaly_feedback (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                timestamp TIMESTAMP NOT NULL,
+                recorded_at TIMESTAMP NOT NULL,
+                state TEXT NOT NULL
+            )
+            "#,
+        ).execute(connection)?;
+
+        diesel::sql_query(
+            r#"
+            CREATE TABLE IF NOT EXISTS system_states_1m (
+                bucket TIMESTAMP PRIMARY KEY,
+                samples INTEGER NOT NULL,
+                cpu_avg REAL NOT NULL,
+                cpu_min REAL NOT NULL,
+                cpu_max REAL NOT NULL,
+                memory_avg REAL NOT NULL,
+                memory_min REAL NOT NULL,
+                memory_max REAL NOT NULL,
+                disk_avg REAL NOT NULL,
+                disk_min REAL NOT NULL,
+                disk_max REAL NOT NULL
+            )
+            "#,
+        ).execute(connection)?;
+
+        diesel::sql_query(
+            r#"
+            CREATE TABLE IF NOT EXISTS incidents (
+                id TEXT PRIMARY KEY,
+                started_at TIMESTAMP NOT NULL,
+                severity TEXT NOT NULL,
+                alerts TEXT NOT NULL
             )
             "#,
         ).execute(connection)?;
@@ -161,70 +407,281 @@ impl Database {
             "CREATE INDEX IF NOT EXISTS idx_security_alerts_timestamp ON security_alerts(timestamp)"
         ).execute(connection)?;
 
-        Ok(())
+        Self::migrate(connection)
+    }
+
+    /// Runs the one-off data migrations newer than the file's `user_version`.
+    fn migrate(connection: &mut SqliteConnection) -> Result<()> {
+        let version = diesel::sql_query("SELECT user_version AS version FROM pragma_user_version()")
+            .get_result::<SchemaVersion>(connection)?
+            .version;
+
+        connection.transaction::<_, anyhow::Error, _>(|connection| {
+            if version < 1 {
+                // Alerts used to be inserted again with every state that still
+                // carried them; keep the latest copy of each before making them unique
+                let removed = diesel::sql_query(
+                    "DELETE FROM security_alerts WHERE id NOT IN \
+                     (SELECT MAX(id) FROM security_alerts GROUP BY timestamp, severity, source, description)"
+                ).execute(connection)?;
+                if removed > 0 {
+                    info!("Removed {} duplicate security alerts", removed);
+                }
+                diesel::sql_query(
+                    "CREATE UNIQUE INDEX IF NOT EXISTS idx_security_alerts_identity \
+                     ON security_alerts(timestamp, severity, source, description)"
+                ).execute(connection)?;
+            }
+
+            if version < SCHEMA_VERSION {
+                diesel::sql_query(format!("PRAGMA user_version = {}", SCHEMA_VERSION)).execute(connection)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn record_to_state(record: SystemStateRecord) -> SystemState {
+        SystemState {
+            timestamp: record.timestamp.inner(),
+            cpu_usage: record.cpu_usage,
+            per_core_cpu: Vec::new(),
+            memory_usage: record.memory_usage,
+            disk_usage: record.disk_usage,
+            disks: Vec::new(),
+            network_stats: serde_json::from_str(&record.network_stats).unwrap_or_else(|_| NetworkStats {
+                bytes_sent: 0,
+                bytes_received: 0,
+                connections: Vec::new(),
+                suspicious_activity: Vec::new(),
+                bytes_sent_per_sec: 0.0,
+                bytes_received_per_sec: 0.0,
+                dns_cache_hits: 0,
+                dns_cache_misses: 0,
+                icmp_packets: 0,
+                icmp_types: Vec::new(),
+            }),
+            active_processes: serde_json::from_str(&record.processes).unwrap_or_default(),
+            security_alerts: serde_json::from_str(&record.alerts).unwrap_or_default(),
+            system_metrics: record.system_metrics
+                .and_then(|metrics| serde_json::from_str(&metrics).ok()),
+            collection_duration_ms: record.collection_duration_ms.map_or(0, |ms| ms.max(0) as u64),
+        }
+    }
+
+    fn record_to_alert(record: SecurityAlertRecord) -> SecurityAlert {
+        SecurityAlert {
+            timestamp: record.timestamp.inner(),
+            severity: parse_severity(&record.severity),
+            description: record.description,
+            source: record.source,
+            recommendation: record.recommendation,
+            count: record.count.max(1) as u32,
+            would_enforce: record.would_enforce,
+            rule: record.rule,
+            pid: record.pid.and_then(|pid| u32::try_from(pid).ok()),
+            remote_ip: record.remote_ip.and_then(|ip| ip.parse().ok()),
+        }
     }
 
-    pub async fn store_state(&self, state: &SystemState) -> Result<()> {
+    /// A rollup bucket as a state carrying the bucket's averages; per-process
+    /// and network detail isn't kept at this resolution.
+    fn rollup_to_state(record: RollupRecord) -> SystemState {
+        SystemState {
+            timestamp: record.bucket.inner(),
+            cpu_usage: record.cpu_avg,
+            per_core_cpu: Vec::new(),
+            memory_usage: record.memory_avg,
+            disk_usage: record.disk_avg,
+            disks: Vec::new(),
+            network_stats: NetworkStats::default(),
+            active_processes: Vec::new(),
+            security_alerts: Vec::new(),
+            system_metrics: None,
+            collection_duration_ms: 0,
+        }
+    }
+
+    fn lock_buffer(&self) -> std::sync::MutexGuard<'_, WriteBuffer> {
+        self.buffer.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Writes `states` and their alerts in a single transaction.
+    fn write_states(&self, states: &[SystemState]) -> Result<()> {
+        if states.is_empty() {
+            return Ok(());
+        }
         let mut connection = self.pool.get()?;
-        
-        let record = SystemStateRecord {
-            id: None,
-            timestamp: TimeStamp::from(state.timestamp),
-            cpu_usage: state.cpu_usage,
-            memory_usage: state.memory_usage,
-            disk_usage: state.disk_usage,
-            network_stats: serde_json::to_string(&state.network_stats)?,
-            processes: serde_json::to_string(&state.active_processes)?,
-            alerts: serde_json::to_string(&state.security_alerts)?,
+
+        connection.transaction::<_, anyhow::Error, _>(|connection| {
+            for state in states {
+                let record = SystemStateRecord {
+                    id: None,
+                    timestamp: TimeStamp::from(state.timestamp),
+                    cpu_usage: state.cpu_usage,
+                    memory_usage: state.memory_usage,
+                    disk_usage: state.disk_usage,
+                    network_stats: serde_json::to_string(&state.network_stats)?,
+                    processes: serde_json::to_string(&state.active_processes)?,
+                    alerts: serde_json::to_string(&state.security_alerts)?,
+                    system_metrics: state.system_metrics.as_ref()
+                        .map(serde_json::to_string)
+                        .transpose()?,
+                    collection_duration_ms: Some(state.collection_duration_ms as i64),
+                };
+
+                diesel::insert_into(system_states::table)
+                    .values(&record)
+                    .execute(connection)?;
+
+                // Store security alerts separately for better querying. The
+                // live list carries an alert through many states, so each is
+                // stored once and replaced as its count grows.
+                for alert in &state.security_alerts {
+                    let alert_record = SecurityAlertRecord {
+                        id: None,
+                        timestamp: TimeStamp::from(alert.timestamp),
+                        severity: alert.severity.to_string(),
+                        description: alert.description.clone(),
+                        source: alert.source.clone(),
+                        recommendation: alert.recommendation.clone(),
+                        count: alert.count as i32,
+                        would_enforce: alert.would_enforce,
+                        rule: alert.rule.clone(),
+                        pid: alert.pid.map(i64::from),
+                        remote_ip: alert.remote_ip.map(|ip| ip.to_string()),
+                    };
+
+                    diesel::replace_into(security_alerts::table)
+                        .values(&alert_record)
+                        .execute(connection)?;
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+#[async_trait]
+impl StateStore for Database {
+    async fn store_state(&self, state: &SystemState) -> Result<()> {
+        let batch = {
+            let mut buffer = self.lock_buffer();
+            buffer.states.push(state.clone());
+            if buffer.states.len() < self.batch_size
+                && buffer.last_flush.elapsed() < self.flush_interval
+            {
+                return Ok(());
+            }
+            buffer.take()
         };
 
-        diesel::insert_into(system_states::table)
-            .values(&record)
-            .execute(&mut connection)?;
+        self.write_states(&batch)
+    }
 
-        // Store security alerts separately for better querying
-        for alert in &state.security_alerts {
-            let alert_record = SecurityAlertRecord {
-                id: None,
-                timestamp: TimeStamp::from(alert.timestamp),
-                severity: format!("{:?}", alert.severity),
-                description: alert.description.clone(),
-                source: alert.source.clone(),
-                recommendation: alert.recommendation.clone(),
-            };
-
-            diesel::insert_into(security_alerts::table)
-                .values(&alert_record)
-                .execute(&mut connection)?;
-        }
+    async fn flush(&self) -> Result<()> {
+        let batch = self.lock_buffer().take();
+        self.write_states(&batch)
+    }
 
-        Ok(())
+    async fn rollup_old_states(&self, older_than: DateTime<Utc>) -> Result<usize> {
+        self.flush().await?;
+        let mut connection = self.pool.get()?;
+        // Only whole minutes, so a bucket is never split across two passes
+        let cutoff = TimeStamp::from(older_than.duration_trunc(ROLLUP_BUCKET)?);
+
+        connection.transaction::<_, anyhow::Error, _>(|connection| {
+            // Timestamps are stored as epoch seconds. A bucket that already
+            // exists (late writes) is merged, weighting averages by sample count.
+            diesel::sql_query(
+                r#"
+                INSERT INTO system_states_1m
+                SELECT
+                    (timestamp / 60) * 60, COUNT(*),
+                    AVG(cpu_usage), MIN(cpu_usage), MAX(cpu_usage),
+                    AVG(memory_usage), MIN(memory_usage), MAX(memory_usage),
+                    AVG(disk_usage), MIN(disk_usage), MAX(disk_usage)
+                FROM system_states
+                WHERE timestamp < ?
+                GROUP BY timestamp / 60
+                ON CONFLICT(bucket) DO UPDATE SET
+                    cpu_avg = (cpu_avg * samples + excluded.cpu_avg * excluded.samples) / (samples + excluded.samples),
+                    cpu_min = MIN(cpu_min, excluded.cpu_min),
+                    cpu_max = MAX(cpu_max, excluded.cpu_max),
+                    memory_avg = (memory_avg * samples + excluded.memory_avg * excluded.samples) / (samples + excluded.samples),
+                    memory_min = MIN(memory_min, excluded.memory_min),
+                    memory_max = MAX(memory_max, excluded.memory_max),
+                    disk_avg = (disk_avg * samples + excluded.disk_avg * excluded.samples) / (samples + excluded.samples),
+                    disk_min = MIN(disk_min, excluded.disk_min),
+                    disk_max = MAX(disk_max, excluded.disk_max),
+                    samples = samples + excluded.samples
+                "#
+            )
+            .bind::<Timestamp, _>(&cutoff)
+            .execute(connection)?;
+
+            Ok(diesel::delete(system_states::table)
+                .filter(system_states::timestamp.lt(&cutoff))
+                .execute(connection)?)
+        })
     }
 
-    pub async fn get_alerts_since(&self, since: DateTime<Utc>) -> Result<Vec<SecurityAlert>> {
+    async fn get_alerts_since(
+        &self,
+        since: DateTime<Utc>,
+        min_severity: Option<AlertSeverity>,
+    ) -> Result<Vec<SecurityAlert>> {
+        self.flush().await?;
         let mut connection = self.pool.get()?;
         let since_ts = TimeStamp::from(since);
         
-        let records = security_alerts::table
+        let mut query = security_alerts::table
             .filter(security_alerts::timestamp.gt(since_ts))
             .order_by(security_alerts::timestamp.desc())
             .select(SecurityAlertRecord::as_select())
+            .into_boxed();
+        if let Some(min_severity) = min_severity {
+            let severities: Vec<String> = min_severity.and_above().map(|s| s.to_string()).collect();
+            query = query.filter(security_alerts::severity.eq_any(severities));
+        }
+        let records = query.load::<SecurityAlertRecord>(&mut connection)?;
+
+        Ok(records.into_iter().map(Self::record_to_alert).collect())
+    }
+
+    async fn get_alerts_between(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<SecurityAlert>> {
+        self.flush().await?;
+        let mut connection = self.pool.get()?;
+
+        let records = security_alerts::table
+            .filter(security_alerts::timestamp.between(TimeStamp::from(start), TimeStamp::from(end)))
+            .order_by(security_alerts::timestamp.asc())
+            .select(SecurityAlertRecord::as_select())
             .load::<SecurityAlertRecord>(&mut connection)?;
 
-        let alerts = records.into_iter()
-            .map(|record| SecurityAlert {
-                timestamp: record.timestamp.inner(),
-                severity: serde_json::from_str(&record.severity).unwrap_or(AlertSeverity::Low),
-                description: record.description,
-                source: record.source,
-                recommendation: record.recommendation,
-            })
-            .collect();
+        Ok(records.into_iter().map(Self::record_to_alert).collect())
+    }
+
+    async fn get_alert_breakdown(&self, since: DateTime<Utc>) -> Result<Vec<(String, AlertSeverity, i64)>> {
+        self.flush().await?;
+        let mut connection = self.pool.get()?;
+        let since_ts = TimeStamp::from(since);
 
-        Ok(alerts)
+        let rows = diesel::sql_query(
+            "SELECT source, severity, COUNT(*) AS count FROM security_alerts \
+             WHERE timestamp > ? GROUP BY source, severity ORDER BY count DESC, source ASC"
+        )
+        .bind::<Timestamp, _>(&since_ts)
+        .load::<SourceSeverityCount>(&mut connection)?;
+
+        Ok(rows.into_iter().map(SourceSeverityCount::into_tuple).collect())
     }
 
-    pub async fn get_system_states(&self, limit: i64) -> Result<Vec<SystemState>> {
+    async fn get_system_states(&self, limit: i64) -> Result<Vec<SystemState>> {
+        self.flush().await?;
         let mut connection = self.pool.get()?;
         
         let records = system_states::table
@@ -233,110 +690,958 @@ impl Database {
             .select(SystemStateRecord::as_select())
             .load::<SystemStateRecord>(&mut connection)?;
 
-        let states = records.into_iter()
-            .map(|record| SystemState {
-                timestamp: record.timestamp.inner(),
-                cpu_usage: record.cpu_usage,
-                memory_usage: record.memory_usage,
-                disk_usage: record.disk_usage,
-                network_stats: serde_json::from_str(&record.network_stats).unwrap_or_else(|_| NetworkStats {
-                    bytes_sent: 0,
-                    bytes_received: 0,
-                    connections: Vec::new(),
-                    suspicious_activity: Vec::new(),
-                }),
-                active_processes: serde_json::from_str(&record.processes).unwrap_or_default(),
-                security_alerts: serde_json::from_str(&record.alerts).unwrap_or_default(),
-                system_metrics: None,
-            })
+        let mut states: Vec<SystemState> = records.into_iter()
+            .map(Self::record_to_state)
             .collect();
 
+        // Rolled-up minutes are all older than the raw rows, so they continue the list
+        let remaining = limit - states.len() as i64;
+        if remaining > 0 {
+            let rollups = system_states_1m::table
+                .order_by(system_states_1m::bucket.desc())
+                .limit(remaining)
+                .select(RollupRecord::as_select())
+                .load::<RollupRecord>(&mut connection)?;
+            states.extend(rollups.into_iter().map(Self::rollup_to_state));
+        }
+
         Ok(states)
     }
 
-    pub async fn cleanup_old_records(&self, older_than: DateTime<Utc>) -> Result<()> {
+    async fn get_system_states_between(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<SystemState>> {
+        self.flush().await?;
+        let mut connection = self.pool.get()?;
+        let start_ts = TimeStamp::from(start);
+        let end_ts = TimeStamp::from(end);
+
+        let rollups = system_states_1m::table
+            .filter(system_states_1m::bucket.between(&start_ts, &end_ts))
+            .order_by(system_states_1m::bucket.asc())
+            .select(RollupRecord::as_select())
+            .load::<RollupRecord>(&mut connection)?;
+
+        let records = system_states::table
+            .filter(system_states::timestamp.between(&start_ts, &end_ts))
+            .order_by(system_states::timestamp.asc())
+            .select(SystemStateRecord::as_select())
+            .load::<SystemStateRecord>(&mut connection)?;
+
+        Ok(rollups.into_iter()
+            .map(Self::rollup_to_state)
+            .chain(records.into_iter().map(Self::record_to_state))
+            .collect())
+    }
+
+    async fn for_each_state_since(
+        &self,
+        since: DateTime<Utc>,
+        visit: &mut (dyn FnMut(SystemState) -> Result<()> + Send),
+    ) -> Result<()> {
+        self.flush().await?;
+        let mut connection = self.pool.get()?;
+        let since_ts = TimeStamp::from(since);
+
+        // Rolled-up minutes all precede the raw rows
+        let rollups = system_states_1m::table
+            .filter(system_states_1m::bucket.ge(&since_ts))
+            .order_by(system_states_1m::bucket.asc())
+            .select(RollupRecord::as_select())
+            .load_iter::<RollupRecord, DefaultLoadingMode>(&mut connection)?;
+        for record in rollups {
+            visit(Self::rollup_to_state(record?))?;
+        }
+
+        let records = system_states::table
+            .filter(system_states::timestamp.ge(&since_ts))
+            .order_by(system_states::timestamp.asc())
+            .select(SystemStateRecord::as_select())
+            .load_iter::<SystemStateRecord, DefaultLoadingMode>(&mut connection)?;
+        for record in records {
+            visit(Self::record_to_state(record?))?;
+        }
+
+        Ok(())
+    }
+
+    async fn for_each_usage_since(
+        &self,
+        since: DateTime<Utc>,
+        visit: &mut (dyn FnMut(UsageSample) -> Result<()> + Send),
+    ) -> Result<()> {
+        self.flush().await?;
+        let mut connection = self.pool.get()?;
+        let since_ts = TimeStamp::from(since);
+
+        let rollups = system_states_1m::table
+            .filter(system_states_1m::bucket.ge(&since_ts))
+            .order_by(system_states_1m::bucket.asc())
+            .select((
+                system_states_1m::bucket,
+                system_states_1m::cpu_avg,
+                system_states_1m::memory_avg,
+                system_states_1m::disk_avg,
+                system_states_1m::samples,
+            ))
+            .load_iter::<(TimeStamp, f32, f32, f32, i32), DefaultLoadingMode>(&mut connection)?;
+        for row in rollups {
+            let (bucket, cpu_usage, memory_usage, disk_usage, samples) = row?;
+            visit(UsageSample {
+                timestamp: bucket.inner(),
+                cpu_usage,
+                memory_usage,
+                disk_usage,
+                samples: samples.max(0) as u32,
+            })?;
+        }
+
+        let records = system_states::table
+            .filter(system_states::timestamp.ge(&since_ts))
+            .order_by(system_states::timestamp.asc())
+            .select((
+                system_states::timestamp,
+                system_states::cpu_usage,
+                system_states::memory_usage,
+                system_states::disk_usage,
+            ))
+            .load_iter::<(TimeStamp, f32, f32, f32), DefaultLoadingMode>(&mut connection)?;
+        for row in records {
+            let (timestamp, cpu_usage, memory_usage, disk_usage) = row?;
+            visit(UsageSample {
+                timestamp: timestamp.inner(),
+                cpu_usage,
+                memory_usage,
+                disk_usage,
+                samples: 1,
+            })?;
+        }
+
+        Ok(())
+    }
+
+    async fn for_each_alert_since(
+        &self,
+        since: DateTime<Utc>,
+        visit: &mut (dyn FnMut(SecurityAlert) -> Result<()> + Send),
+    ) -> Result<()> {
+        self.flush().await?;
+        let mut connection = self.pool.get()?;
+
+        let records = security_alerts::table
+            .filter(security_alerts::timestamp.ge(TimeStamp::from(since)))
+            .order_by(security_alerts::timestamp.asc())
+            .select(SecurityAlertRecord::as_select())
+            .load_iter::<SecurityAlertRecord, DefaultLoadingMode>(&mut connection)?;
+        for record in records {
+            visit(Self::record_to_alert(record?))?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the most recent stored state at or before `at`.
+    async fn get_state_at(&self, at: DateTime<Utc>) -> Result<Option<SystemState>> {
+        self.flush().await?;
+        let mut connection = self.pool.get()?;
+        let at_ts = TimeStamp::from(at);
+
+        let record = system_states::table
+            .filter(system_states::timestamp.le(&at_ts))
+            .order_by(system_states::timestamp.desc())
+            .select(SystemStateRecord::as_select())
+            .first::<SystemStateRecord>(&mut connection)
+            .optional()?;
+
+        if let Some(record) = record {
+            return Ok(Some(Self::record_to_state(record)));
+        }
+
+        let rollup = system_states_1m::table
+            .filter(system_states_1m::bucket.le(at_ts))
+            .order_by(system_states_1m::bucket.desc())
+            .select(RollupRecord::as_select())
+            .first::<RollupRecord>(&mut connection)
+            .optional()?;
+
+        Ok(rollup.map(Self::rollup_to_state))
+    }
+
+    async fn store_anomaly_feedback(&self, state: &SystemState) -> Result<()> {
+        let mut connection = self.pool.get()?;
+
+        let record = AnomalyFeedbackRecord {
+            id: None,
+            timestamp: TimeStamp::from(state.timestamp),
+            recorded_at: TimeStamp::now(),
+            state: serde_json::to_string(state)?,
+        };
+
+        diesel::insert_into(anomaly_feedback::table)
+            .values(&record)
+            .execute(&mut connection)?;
+
+        Ok(())
+    }
+
+    /// States operators have labelled as false positives, oldest first.
+    async fn get_anomaly_feedback(&self) -> Result<Vec<SystemState>> {
+        let mut connection = self.pool.get()?;
+
+        let records = anomaly_feedback::table
+            .order_by(anomaly_feedback::recorded_at.asc())
+            .select(AnomalyFeedbackRecord::as_select())
+            .load::<AnomalyFeedbackRecord>(&mut connection)?;
+
+        Ok(records.into_iter()
+            .filter_map(|record| serde_json::from_str(&record.state).ok())
+            .collect())
+    }
+
+    async fn store_incident(&self, incident: &Incident) -> Result<()> {
+        let mut connection = self.pool.get()?;
+
+        let record = IncidentRecord {
+            id: incident.id.clone(),
+            started_at: TimeStamp::from(incident.started_at),
+            severity: incident.severity.to_string(),
+            alerts: serde_json::to_string(&incident.alerts)?,
+        };
+
+        diesel::replace_into(incidents::table)
+            .values(&record)
+            .execute(&mut connection)?;
+
+        Ok(())
+    }
+
+    async fn get_incidents_since(&self, since: DateTime<Utc>) -> Result<Vec<Incident>> {
+        let mut connection = self.pool.get()?;
+
+        let records = incidents::table
+            .filter(incidents::started_at.gt(TimeStamp::from(since)))
+            .order_by(incidents::started_at.desc())
+            .select(IncidentRecord::as_select())
+            .load::<IncidentRecord>(&mut connection)?;
+
+        Ok(records.into_iter()
+            .map(|record| Incident {
+                id: record.id,
+                started_at: record.started_at.inner(),
+                severity: parse_severity(&record.severity),
+                alerts: serde_json::from_str(&record.alerts).unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    async fn cleanup_old_records(&self, older_than: DateTime<Utc>) -> Result<CleanupReport> {
+        self.flush().await?;
         let mut connection = self.pool.get()?;
         let older_than_ts = TimeStamp::from(older_than);
         
-        diesel::delete(system_states::table)
+        let states_deleted = diesel::delete(system_states::table)
             .filter(system_states::timestamp.lt(&older_than_ts))
-            .execute(&mut connection)?;
+            .execute(&mut connection)?
+            + diesel::delete(system_states_1m::table)
+                .filter(system_states_1m::bucket.lt(&older_than_ts))
+                .execute(&mut connection)?;
 
-        diesel::delete(security_alerts::table)
+        let alerts_deleted = diesel::delete(security_alerts::table)
             .filter(security_alerts::timestamp.lt(&older_than_ts))
             .execute(&mut connection)?;
 
+        diesel::delete(incidents::table)
+            .filter(incidents::started_at.lt(&older_than_ts))
+            .execute(&mut connection)?;
+
         // Vacuum database to reclaim space
+        let size_before = Self::database_size(&mut connection)?;
         diesel::sql_query("VACUUM").execute(&mut connection)?;
+        let size_after = Self::database_size(&mut connection)?;
 
-        Ok(())
+        Ok(CleanupReport {
+            states_deleted,
+            alerts_deleted,
+            bytes_reclaimed: size_before.saturating_sub(size_after),
+        })
     }
 
-    pub async fn get_statistics(&self, since: DateTime<Utc>) -> Result<SystemStatistics> {
+    async fn get_statistics(&self, since: DateTime<Utc>) -> Result<SystemStatistics> {
+        let until = Utc::now();
+        let distributions = [
+            self.get_metric_distribution(Metric::Cpu, since, until).await?,
+            self.get_metric_distribution(Metric::Memory, since, until).await?,
+            self.get_metric_distribution(Metric::Disk, since, until).await?,
+        ];
         let mut connection = self.pool.get()?;
         let since_ts = TimeStamp::from(since);
         
+        // Raw rows and rolled-up minutes together, each minute weighted by
+        // the number of samples it replaced
         let stats = diesel::sql_query(
             r#"
             SELECT 
-                AVG(cpu_usage) as avg_cpu,
-                AVG(memory_usage) as avg_memory,
-                AVG(disk_usage) as avg_disk,
-                COUNT(*) as total_records,
+                COALESCE(SUM(cpu) / SUM(samples), 0) as avg_cpu,
+                COALESCE(SUM(memory) / SUM(samples), 0) as avg_memory,
+                COALESCE(SUM(disk) / SUM(samples), 0) as avg_disk,
+                COALESCE(SUM(samples), 0) as total_records,
                 (SELECT COUNT(*) FROM security_alerts WHERE timestamp > ?) as alert_count
-            FROM system_states
-            WHERE timestamp > ?
+            FROM (
+                SELECT cpu_usage as cpu, memory_usage as memory, disk_usage as disk, 1 as samples
+                FROM system_states WHERE timestamp > ?
+                UNION ALL
+                SELECT cpu_avg * samples, memory_avg * samples, disk_avg * samples, samples
+                FROM system_states_1m WHERE bucket > ?
+            )
             "#
         )
         .bind::<Timestamp, _>(&since_ts)
         .bind::<Timestamp, _>(&since_ts)
-        .get_result::<SystemStatistics>(&mut connection)?;
+        .bind::<Timestamp, _>(&since_ts)
+        .get_result::<Averages>(&mut connection)?;
+
+        let severities = diesel::sql_query(
+            "SELECT severity, COUNT(*) AS count FROM security_alerts WHERE timestamp > ? GROUP BY severity"
+        )
+        .bind::<Timestamp, _>(&since_ts)
+        .load::<SeverityCount>(&mut connection)?;
+
+        Ok(SystemStatistics::new(stats, distributions, SeverityCounts::from_rows(severities)))
+    }
+
+    async fn get_metric_distribution(
+        &self,
+        metric: Metric,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> Result<Distribution> {
+        self.flush().await?;
+        let mut connection = self.pool.get()?;
+        let since_ts = TimeStamp::from(since);
+        let until_ts = TimeStamp::from(until);
+
+        // Let SQLite do the sorting; only the single metric column is loaded
+        let values = diesel::sql_query(format!(
+            "SELECT {column} AS value FROM system_states \
+             WHERE timestamp >= ? AND timestamp <= ? ORDER BY {column} ASC",
+            column = metric.column()
+        ))
+        .bind::<Timestamp, _>(&since_ts)
+        .bind::<Timestamp, _>(&until_ts)
+        .load::<MetricValue>(&mut connection)?;
+
+        let rollups = diesel::sql_query(format!(
+            "SELECT {prefix}_avg AS value, {prefix}_max AS peak, samples FROM system_states_1m \
+             WHERE bucket >= ? AND bucket <= ?",
+            prefix = metric.rollup_prefix()
+        ))
+        .bind::<Timestamp, _>(&since_ts)
+        .bind::<Timestamp, _>(&until_ts)
+        .load::<RollupValue>(&mut connection)?;
+
+        let mut values: Vec<f64> = values.into_iter().map(|v| v.value as f64).collect();
+        if rollups.is_empty() {
+            return Ok(Distribution::from_sorted(&values));
+        }
+
+        // A rolled-up minute stands in for its samples at their average, which
+        // smooths percentiles there; its true peak is still kept for `max`
+        let peak = rollups.iter().map(|r| r.peak as f64).fold(f64::MIN, f64::max);
+        for rollup in &rollups {
+            values.extend(std::iter::repeat_n(rollup.value as f64, rollup.samples.max(0) as usize));
+        }
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+        let mut distribution = Distribution::from_sorted(&values);
+        distribution.max = distribution.max.max(peak);
+        Ok(distribution)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Metric {
+    Cpu,
+    Memory,
+    Disk,
+}
+
+impl Metric {
+    pub(crate) fn column(&self) -> &'static str {
+        match self {
+            Metric::Cpu => "cpu_usage",
+            Metric::Memory => "memory_usage",
+            Metric::Disk => "disk_usage",
+        }
+    }
+
+    /// Column prefix in the `system_states_1m` rollup table
+    fn rollup_prefix(&self) -> &'static str {
+        match self {
+            Metric::Cpu => "cpu",
+            Metric::Memory => "memory",
+            Metric::Disk => "disk",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Distribution {
+    pub samples: usize,
+    pub p50: f64,
+    pub p90: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl Distribution {
+    /// Builds a distribution from values already sorted in ascending order,
+    /// using the nearest-rank percentile definition.
+    pub(crate) fn from_sorted(values: &[f64]) -> Self {
+        let percentile = |p: f64| -> f64 {
+            if values.is_empty() {
+                return 0.0;
+            }
+            let rank = ((p / 100.0) * values.len() as f64).ceil() as usize;
+            values[rank.clamp(1, values.len()) - 1]
+        };
+
+        Self {
+            samples: values.len(),
+            p50: percentile(50.0),
+            p90: percentile(90.0),
+            p95: percentile(95.0),
+            p99: percentile(99.0),
+            max: values.last().copied().unwrap_or(0.0),
+        }
+    }
+}
 
-        Ok(stats)
+#[derive(QueryableByName)]
+struct MetricValue {
+    #[diesel(sql_type = diesel::sql_types::Float)]
+    value: f32,
+}
+
+#[derive(QueryableByName)]
+pub(crate) struct SchemaVersion {
+    #[diesel(sql_type = diesel::sql_types::Integer)]
+    pub(crate) version: i32,
+}
+
+#[derive(QueryableByName)]
+struct DatabaseSize {
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    bytes: i64,
+}
+
+/// CPU, memory and disk usage of one stored row. A rolled-up minute stands
+/// in for the `samples` states it replaced.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct UsageSample {
+    pub timestamp: DateTime<Utc>,
+    pub cpu_usage: f32,
+    pub memory_usage: f32,
+    pub disk_usage: f32,
+    pub samples: u32,
+}
+
+impl UsageSample {
+    pub fn of(state: &SystemState) -> Self {
+        Self {
+            timestamp: state.timestamp,
+            cpu_usage: state.cpu_usage,
+            memory_usage: state.memory_usage,
+            disk_usage: state.disk_usage,
+            samples: 1,
+        }
     }
 }
 
+/// What a retention pass removed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct CleanupReport {
+    pub states_deleted: usize,
+    pub alerts_deleted: usize,
+    /// File space given back by VACUUM; zero for backends that don't vacuum
+    pub bytes_reclaimed: u64,
+}
+
 #[derive(QueryableByName)]
-struct SystemStatistics {
+struct RollupValue {
+    #[diesel(sql_type = diesel::sql_types::Float)]
+    value: f32,
+    #[diesel(sql_type = diesel::sql_types::Float)]
+    peak: f32,
+    #[diesel(sql_type = diesel::sql_types::Integer)]
+    samples: i32,
+}
+
+/// The aggregate part of `SystemStatistics` that backends compute in SQL.
+#[derive(QueryableByName)]
+pub(crate) struct Averages {
     #[diesel(sql_type = diesel::sql_types::Double)]
-    avg_cpu: f64,
+    pub avg_cpu: f64,
     #[diesel(sql_type = diesel::sql_types::Double)]
-    avg_memory: f64,
+    pub avg_memory: f64,
     #[diesel(sql_type = diesel::sql_types::Double)]
-    avg_disk: f64,
+    pub avg_disk: f64,
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    pub total_records: i64,
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    pub alert_count: i64,
+}
+
+#[derive(QueryableByName)]
+pub(crate) struct SourceSeverityCount {
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    pub source: String,
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    pub severity: String,
     #[diesel(sql_type = diesel::sql_types::BigInt)]
-    total_records: i64,
+    pub count: i64,
+}
+
+impl SourceSeverityCount {
+    pub(crate) fn into_tuple(self) -> (String, AlertSeverity, i64) {
+        (self.source, parse_severity(&self.severity), self.count)
+    }
+}
+
+/// Severities are stored in their `Display` form; anything unrecognised reads as Low.
+pub(crate) fn parse_severity(severity: &str) -> AlertSeverity {
+    severity.parse().unwrap_or(AlertSeverity::Low)
+}
+
+#[derive(QueryableByName)]
+pub(crate) struct SeverityCount {
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    pub severity: String,
     #[diesel(sql_type = diesel::sql_types::BigInt)]
-    alert_count: i64,
+    pub count: i64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct SeverityCounts {
+    pub low: i64,
+    pub medium: i64,
+    pub high: i64,
+    pub critical: i64,
+}
+
+impl SeverityCounts {
+    pub fn add(&mut self, severity: AlertSeverity, count: i64) {
+        match severity {
+            AlertSeverity::Low => self.low += count,
+            AlertSeverity::Medium => self.medium += count,
+            AlertSeverity::High => self.high += count,
+            AlertSeverity::Critical => self.critical += count,
+        }
+    }
+
+    pub(crate) fn from_rows(rows: Vec<SeverityCount>) -> Self {
+        let mut counts = Self::default();
+        for row in rows {
+            counts.add(parse_severity(&row.severity), row.count);
+        }
+        counts
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SystemStatistics {
+    pub avg_cpu: f64,
+    pub avg_memory: f64,
+    pub avg_disk: f64,
+    pub cpu: Distribution,
+    pub memory: Distribution,
+    pub disk: Distribution,
+    pub total_records: i64,
+    pub alert_count: i64,
+    pub alerts_by_severity: SeverityCounts,
+}
+
+impl SystemStatistics {
+    pub(crate) fn new(
+        averages: Averages,
+        [cpu, memory, disk]: [Distribution; 3],
+        alerts_by_severity: SeverityCounts,
+    ) -> Self {
+        Self {
+            avg_cpu: averages.avg_cpu,
+            avg_memory: averages.avg_memory,
+            avg_disk: averages.avg_disk,
+            cpu,
+            memory,
+            disk,
+            total_records: averages.total_records,
+            alert_count: averages.alert_count,
+            alerts_by_severity,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use tempfile::tempdir;
+    use crate::SystemMetrics;
 
     #[tokio::test]
     async fn test_database_creation() {
-        let db = Database::new();
+        let dir = tempdir().unwrap();
+        let db = Database::with_path(&dir.path().join("monitor.db"));
         assert!(db.is_ok());
     }
 
     #[tokio::test]
-    async fn test_store_and_retrieve_state() {
-        let db = Database::new().unwrap();
+    async fn test_migrations_recorded_once() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("monitor.db");
+        drop(Database::with_path(&path).unwrap());
+
+        // Reopening finds the migrations already applied
+        let db = Database::with_path(&path).unwrap();
+        let mut connection = db.pool.get().unwrap();
+        let version = diesel::sql_query("SELECT user_version AS version FROM pragma_user_version()")
+            .get_result::<SchemaVersion>(&mut connection)
+            .unwrap()
+            .version;
+        assert_eq!(version, SCHEMA_VERSION);
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_store_and_retrieve_state() {
+        let dir = tempdir().unwrap();
+        let db = Database::with_path(&dir.path().join("monitor.db")).unwrap();
         let state = SystemState {
             timestamp: Utc::now(),
             cpu_usage: 50.0,
             memory_usage: 60.0,
             disk_usage: 70.0,
-            network_stats: Default::default(),
-            active_processes: vec![],
-            security_alerts: vec![],
-            system_metrics: None,
+            security_alerts: vec![SecurityAlert {
+                timestamp: Utc::now(),
+                severity: AlertSeverity::High,
+                description: "test".to_string(),
+                source: "test".to_string(),
+                recommendation: None,
+                count: 1,
+                would_enforce: false,
+                rule: None,
+                pid: None,
+                remote_ip: None,
+            }],
+            ..Default::default()
         };
 
         assert!(db.store_state(&state).await.is_ok());
         let states = db.get_system_states(1).await.unwrap();
         assert_eq!(states.len(), 1);
+
+        let breakdown = db.get_alert_breakdown(Utc::now() - chrono::Duration::hours(1)).await.unwrap();
+        assert_eq!(breakdown, vec![("test".to_string(), AlertSeverity::High, 1)]);
+
+        let stats = db.get_statistics(Utc::now() - chrono::Duration::hours(1)).await.unwrap();
+        assert_eq!(stats.alert_count, 1);
+        assert_eq!(stats.alerts_by_severity.high, 1);
+        assert_eq!(stats.cpu.p95, 50.0);
+    }
+
+    #[tokio::test]
+    async fn test_system_metrics_persisted() {
+        let dir = tempdir().unwrap();
+        let db = Database::with_path(&dir.path().join("monitor.db")).unwrap();
+        let boot_time = DateTime::parse_from_rfc3339("2024-03-01T08:00:00Z").unwrap().with_timezone(&Utc);
+        let state = SystemState {
+            timestamp: Utc::now(),
+            cpu_usage: 10.0,
+            memory_usage: 20.0,
+            disk_usage: 30.0,
+            system_metrics: Some(SystemMetrics {
+                uptime: 3600,
+                boot_time: Some(boot_time),
+                ..SystemMetrics::default()
+            }),
+            collection_duration_ms: 340,
+            ..Default::default()
+        };
+
+        db.store_state(&state).await.unwrap();
+        let states = db.get_system_states(1).await.unwrap();
+        let metrics = states[0].system_metrics.as_ref().unwrap();
+        assert_eq!(metrics.uptime, 3600);
+        assert_eq!(metrics.boot_time, Some(boot_time));
+        assert_eq!(states[0].collection_duration_ms, 340);
+    }
+
+    #[tokio::test]
+    async fn test_alert_stored_once_across_states() {
+        let dir = tempdir().unwrap();
+        let db = Database::with_path(&dir.path().join("monitor.db")).unwrap();
+        let mut state = SystemState {
+            timestamp: Utc::now(),
+            cpu_usage: 50.0,
+            memory_usage: 60.0,
+            disk_usage: 70.0,
+            security_alerts: vec![SecurityAlert {
+                timestamp: Utc::now(),
+                severity: AlertSeverity::High,
+                description: "CPU usage too high".to_string(),
+                source: "Security Policy Check".to_string(),
+                recommendation: None,
+                count: 1,
+                would_enforce: false,
+                rule: None,
+                pid: None,
+                remote_ip: None,
+            }],
+            ..Default::default()
+        };
+
+        db.store_state(&state).await.unwrap();
+        // Still live a cycle later, repeated in the meantime
+        state.timestamp = state.timestamp + chrono::Duration::seconds(1);
+        state.security_alerts[0].count = 2;
+        db.store_state(&state).await.unwrap();
+
+        let since = Utc::now() - chrono::Duration::hours(1);
+        let breakdown = db.get_alert_breakdown(since).await.unwrap();
+        assert_eq!(breakdown, vec![("Security Policy Check".to_string(), AlertSeverity::High, 1)]);
+        let stats = db.get_statistics(since).await.unwrap();
+        assert_eq!(stats.alert_count, 1);
+        assert_eq!(stats.alerts_by_severity.high, 1);
+        let alerts = db.get_alerts_since(since, None).await.unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].count, 2);
+    }
+
+    #[tokio::test]
+    async fn test_incident_replaced_as_it_grows() {
+        let dir = tempdir().unwrap();
+        let db = Database::with_path(&dir.path().join("monitor.db")).unwrap();
+        let alert = |severity: AlertSeverity, description: &str| SecurityAlert {
+            timestamp: Utc::now(),
+            severity,
+            description: description.to_string(),
+            source: "test".to_string(),
+            recommendation: None,
+            count: 1,
+            would_enforce: false,
+            rule: None,
+            pid: None,
+            remote_ip: None,
+        };
+        let mut incident = Incident {
+            id: "20240301080000000-1".to_string(),
+            alerts: vec![alert(AlertSeverity::Medium, "Process dropper (PID: 4242) is running from /tmp")],
+            severity: AlertSeverity::Medium,
+            started_at: Utc::now(),
+        };
+
+        db.store_incident(&incident).await.unwrap();
+        incident.alerts.push(alert(AlertSeverity::High, "Unauthorized network connection to port 4444"));
+        incident.severity = AlertSeverity::High;
+        db.store_incident(&incident).await.unwrap();
+
+        let incidents = db.get_incidents_since(Utc::now() - chrono::Duration::hours(1)).await.unwrap();
+        assert_eq!(incidents.len(), 1);
+        assert_eq!(incidents[0].id, incident.id);
+        assert_eq!(incidents[0].severity, AlertSeverity::High);
+        assert_eq!(incidents[0].alerts.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_alert_severity_round_trip() {
+        let dir = tempdir().unwrap();
+        let db = Database::with_path(&dir.path().join("monitor.db")).unwrap();
+        let severities = [
+            AlertSeverity::Low,
+            AlertSeverity::Medium,
+            AlertSeverity::High,
+            AlertSeverity::Critical,
+        ];
+        let state = SystemState {
+            timestamp: Utc::now(),
+            cpu_usage: 50.0,
+            memory_usage: 60.0,
+            disk_usage: 70.0,
+            security_alerts: severities.iter()
+                .map(|severity| SecurityAlert {
+                    timestamp: Utc::now(),
+                    severity: *severity,
+                    description: format!("{:?} alert", severity),
+                    source: "test".to_string(),
+                    recommendation: None,
+                    count: 1,
+                    would_enforce: false,
+                    rule: None,
+                    pid: None,
+                    remote_ip: None,
+                })
+                .collect(),
+            ..Default::default()
+        };
+
+        db.store_state(&state).await.unwrap();
+        let since = Utc::now() - chrono::Duration::hours(1);
+        let alerts = db.get_alerts_since(since, None).await.unwrap();
+        for severity in severities {
+            let alert = alerts.iter()
+                .find(|a| a.description == format!("{:?} alert", severity))
+                .unwrap();
+            assert_eq!(alert.severity, severity);
+        }
+
+        let urgent = db.get_alerts_since(since, Some(AlertSeverity::High)).await.unwrap();
+        let mut urgent: Vec<AlertSeverity> = urgent.iter().map(|a| a.severity).collect();
+        urgent.sort();
+        assert_eq!(urgent, vec![AlertSeverity::High, AlertSeverity::Critical]);
+    }
+
+    #[tokio::test]
+    async fn test_alert_attribution_round_trip() {
+        let dir = tempdir().unwrap();
+        let db = Database::with_path(&dir.path().join("monitor.db")).unwrap();
+        let alert = SecurityAlert {
+            timestamp: Utc::now(),
+            severity: AlertSeverity::High,
+            description: "Connection to a blocked address".to_string(),
+            source: "test".to_string(),
+            recommendation: None,
+            count: 1,
+            would_enforce: false,
+            rule: Some("blocked-ip".to_string()),
+            pid: Some(4242),
+            remote_ip: Some("203.0.113.7".parse().unwrap()),
+        };
+        let state = SystemState {
+            timestamp: Utc::now(),
+            cpu_usage: 50.0,
+            memory_usage: 60.0,
+            disk_usage: 70.0,
+            security_alerts: vec![alert.clone()],
+            ..Default::default()
+        };
+
+        db.store_state(&state).await.unwrap();
+        let alerts = db.get_alerts_since(Utc::now() - chrono::Duration::hours(1), None).await.unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].rule, alert.rule);
+        assert_eq!(alerts[0].pid, alert.pid);
+        assert_eq!(alerts[0].remote_ip, alert.remote_ip);
+    }
+
+    #[tokio::test]
+    async fn test_batched_writes() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("monitor.db");
+        let writer = Database::with_path(&path).unwrap()
+            .with_write_batch(3, Duration::from_secs(3600));
+        let reader = Database::with_path(&path).unwrap();
+        let state = SystemState {
+            timestamp: Utc::now(),
+            cpu_usage: 50.0,
+            memory_usage: 60.0,
+            disk_usage: 70.0,
+            ..Default::default()
+        };
+
+        writer.store_state(&state).await.unwrap();
+        writer.store_state(&state).await.unwrap();
+        assert!(reader.get_system_states(10).await.unwrap().is_empty());
+
+        writer.store_state(&state).await.unwrap();
+        assert_eq!(reader.get_system_states(10).await.unwrap().len(), 3);
+
+        writer.store_state(&state).await.unwrap();
+        writer.flush().await.unwrap();
+        assert_eq!(reader.get_system_states(10).await.unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_rollup_old_states() {
+        let dir = tempdir().unwrap();
+        let db = Database::with_path(&dir.path().join("monitor.db")).unwrap();
+        let now = Utc::now();
+        let old_minute = (now - chrono::Duration::days(2)).duration_trunc(ROLLUP_BUCKET).unwrap();
+        let state_at = |timestamp, cpu_usage| SystemState {
+            timestamp,
+            cpu_usage,
+            memory_usage: 60.0,
+            disk_usage: 70.0,
+            ..Default::default()
+        };
+
+        db.store_state(&state_at(old_minute, 10.0)).await.unwrap();
+        db.store_state(&state_at(old_minute + chrono::Duration::seconds(30), 30.0)).await.unwrap();
+        db.store_state(&state_at(now, 80.0)).await.unwrap();
+
+        let rolled_up = db.rollup_old_states(now - chrono::Duration::hours(24)).await.unwrap();
+        assert_eq!(rolled_up, 2);
+
+        let states = db.get_system_states_since(now - chrono::Duration::days(3)).await.unwrap();
+        assert_eq!(states.len(), 2);
+        assert_eq!(states[0].timestamp, old_minute);
+        assert_eq!(states[0].cpu_usage, 20.0);
+        assert_eq!(states[1].cpu_usage, 80.0);
+
+        // The rollup still counts as the samples it replaced
+        let stats = db.get_statistics(now - chrono::Duration::days(3)).await.unwrap();
+        assert_eq!(stats.total_records, 3);
+        assert!((stats.avg_cpu - 40.0).abs() < 1e-6);
+        assert_eq!(stats.cpu.max, 80.0);
+        assert_eq!(stats.cpu.p50, 20.0);
+
+        let recent = db.get_system_states(10).await.unwrap();
+        assert_eq!(recent.iter().map(|s| s.cpu_usage).collect::<Vec<_>>(), vec![80.0, 20.0]);
+    }
+
+    #[tokio::test]
+    async fn test_usage_samples_weight_rollups() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::with_path(&dir.path().join("monitor.db")).unwrap();
+        let now = Utc::now();
+        let old_minute = (now - chrono::Duration::days(2)).duration_trunc(ROLLUP_BUCKET).unwrap();
+        let state_at = |timestamp, cpu_usage| SystemState {
+            timestamp,
+            cpu_usage,
+            memory_usage: 60.0,
+            disk_usage: 70.0,
+            ..Default::default()
+        };
+
+        for second in 0..3 {
+            db.store_state(&state_at(old_minute + chrono::Duration::seconds(second * 10), 20.0)).await.unwrap();
+        }
+        db.store_state(&state_at(now, 80.0)).await.unwrap();
+        db.rollup_old_states(now - chrono::Duration::hours(24)).await.unwrap();
+
+        let mut samples = Vec::new();
+        db.for_each_usage_since(now - chrono::Duration::days(3), &mut |sample| {
+            samples.push(sample);
+            Ok(())
+        }).await.unwrap();
+
+        assert_eq!(samples.len(), 2);
+        assert_eq!((samples[0].timestamp, samples[0].cpu_usage, samples[0].samples), (old_minute, 20.0, 3));
+        assert_eq!((samples[1].cpu_usage, samples[1].samples), (80.0, 1));
+    }
+
+    #[test]
+    fn test_distribution_percentiles() {
+        let values: Vec<f64> = (1..=100).map(|v| v as f64).collect();
+        let distribution = Distribution::from_sorted(&values);
+        assert_eq!(distribution.samples, 100);
+        assert_eq!(distribution.p50, 50.0);
+        assert_eq!(distribution.p90, 90.0);
+        assert_eq!(distribution.p95, 95.0);
+        assert_eq!(distribution.p99, 99.0);
+        assert_eq!(distribution.max, 100.0);
+
+        let empty = Distribution::from_sorted(&[]);
+        assert_eq!(empty.samples, 0);
+        assert_eq!(empty.max, 0.0);
     }
 } 
\ No newline at end of file
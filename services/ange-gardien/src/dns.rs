@@ -0,0 +1,212 @@
+use lru::LruCache;
+use serde::{Serialize, Deserialize};
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use trust_dns_resolver::TokioAsyncResolver;
+use trust_dns_resolver::config::{ResolverConfig, ResolverOpts};
+use trust_dns_resolver::error::ResolveErrorKind;
+
+const CACHE_CAPACITY: usize = 4096;
+const QUEUE_SIZE: usize = 1024;
+/// Used when the answer carries no usable TTL
+const DEFAULT_TTL: Duration = Duration::from_secs(300);
+/// How long "no PTR record" is remembered
+const NEGATIVE_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct DnsCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+#[derive(Debug, Clone)]
+struct CachedName {
+    /// `None` records that the address has no PTR record
+    name: Option<String>,
+    expires_at: Instant,
+}
+
+/// Reverse lookup answers, each kept until its TTL runs out. Negative answers
+/// are cached as well so hosts without a PTR record aren't re-queried on every
+/// new connection.
+pub struct DnsCache {
+    entries: LruCache<IpAddr, CachedName>,
+    hits: u64,
+    misses: u64,
+}
+
+impl DnsCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: LruCache::new(NonZeroUsize::new(capacity.max(1)).unwrap()),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Returns the cached answer for `ip` (`Some(None)` for a cached negative),
+    /// counting a hit, or `None` for a miss when absent or expired.
+    pub fn get(&mut self, ip: IpAddr, now: Instant) -> Option<Option<String>> {
+        let answer = self.peek(ip, now);
+        if answer.is_some() {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+        answer
+    }
+
+    /// Like `get`, but without touching the counters.
+    pub fn peek(&mut self, ip: IpAddr, now: Instant) -> Option<Option<String>> {
+        match self.entries.get(&ip) {
+            Some(entry) if entry.expires_at > now => Some(entry.name.clone()),
+            Some(_) => {
+                self.entries.pop(&ip);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&mut self, ip: IpAddr, name: Option<String>, ttl: Duration, now: Instant) {
+        self.entries.put(ip, CachedName { name, expires_at: now + ttl });
+    }
+
+    pub fn stats(&self) -> DnsCacheStats {
+        DnsCacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            entries: self.entries.len(),
+        }
+    }
+}
+
+/// Reverse DNS kept off the packet path. Capture tasks only consult the cache
+/// and enqueue misses; a background worker drains the queue with `resolve`.
+pub struct ReverseDns {
+    resolver: TokioAsyncResolver,
+    cache: Mutex<DnsCache>,
+    in_flight: Mutex<HashSet<IpAddr>>,
+    queue: mpsc::Sender<IpAddr>,
+    receiver: Mutex<Option<mpsc::Receiver<IpAddr>>>,
+}
+
+impl ReverseDns {
+    pub fn new() -> Self {
+        let (queue, receiver) = mpsc::channel(QUEUE_SIZE);
+        Self {
+            resolver: TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default()),
+            cache: Mutex::new(DnsCache::new(CACHE_CAPACITY)),
+            in_flight: Mutex::new(HashSet::new()),
+            queue,
+            receiver: Mutex::new(Some(receiver)),
+        }
+    }
+
+    /// Returns the cached name for `ip`, or queues it for resolution and
+    /// returns `None`. Never waits: when the queue is full the lookup is
+    /// dropped and retried on the next new connection to that host.
+    pub fn name_or_enqueue(&self, ip: IpAddr) -> Option<String> {
+        if let Some(answer) = self.lock_cache().get(ip, Instant::now()) {
+            return answer;
+        }
+        let _ = self.queue.try_send(ip);
+        None
+    }
+
+    pub fn cache_stats(&self) -> DnsCacheStats {
+        self.lock_cache().stats()
+    }
+
+    /// Hands out the queue of addresses awaiting resolution; only the first
+    /// caller (the worker) gets it.
+    pub fn take_queue(&self) -> Option<mpsc::Receiver<IpAddr>> {
+        self.receiver.lock().unwrap_or_else(|e| e.into_inner()).take()
+    }
+
+    /// Resolves `ip` and caches the answer for the record's TTL. Returns `None`
+    /// when there is no name, on failure, and when a lookup for the same
+    /// address is already running, since that one will fill the cache.
+    pub async fn resolve(&self, ip: IpAddr) -> Option<String> {
+        if let Some(answer) = self.lock_cache().peek(ip, Instant::now()) {
+            return answer;
+        }
+        if !self.in_flight.lock().unwrap_or_else(|e| e.into_inner()).insert(ip) {
+            return None;
+        }
+
+        let result = self.resolver.reverse_lookup(ip).await;
+        self.in_flight.lock().unwrap_or_else(|e| e.into_inner()).remove(&ip);
+
+        let now = Instant::now();
+        let (name, ttl) = match result {
+            Ok(response) => {
+                let ttl = response.valid_until().saturating_duration_since(now);
+                let name = response.iter().next().map(|name| name.to_string());
+                (name, if ttl.is_zero() { DEFAULT_TTL } else { ttl })
+            }
+            Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => (None, NEGATIVE_TTL),
+            // Timeouts and the like are worth retrying later
+            Err(_) => return None,
+        };
+
+        self.lock_cache().insert(ip, name.clone(), ttl, now);
+        name
+    }
+
+    fn lock_cache(&self) -> std::sync::MutexGuard<'_, DnsCache> {
+        self.cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dns_cache_ttl_and_counters() {
+        let mut cache = DnsCache::new(16);
+        let now = Instant::now();
+        let named: IpAddr = "192.0.2.1".parse().unwrap();
+        let unnamed: IpAddr = "192.0.2.2".parse().unwrap();
+
+        assert_eq!(cache.get(named, now), None);
+        cache.insert(named, Some("host.example.".to_string()), Duration::from_secs(300), now);
+        cache.insert(unnamed, None, NEGATIVE_TTL, now);
+
+        assert_eq!(cache.get(named, now), Some(Some("host.example.".to_string())));
+        assert_eq!(cache.get(unnamed, now), Some(None));
+
+        // The negative entry expires first
+        let later = now + NEGATIVE_TTL + Duration::from_secs(1);
+        assert_eq!(cache.get(unnamed, later), None);
+        assert!(cache.get(named, later).is_some());
+        assert_eq!(cache.get(named, now + Duration::from_secs(301)), None);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (3, 3));
+        assert_eq!(stats.entries, 0);
+    }
+
+    #[tokio::test]
+    async fn test_cache_and_queue() {
+        let dns = ReverseDns::new();
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let mut queue = dns.take_queue().unwrap();
+        assert!(dns.take_queue().is_none());
+
+        assert_eq!(dns.name_or_enqueue(ip), None);
+        assert_eq!(queue.try_recv().unwrap(), ip);
+
+        dns.lock_cache().insert(ip, Some("host.example.".to_string()), DEFAULT_TTL, Instant::now());
+        assert_eq!(dns.name_or_enqueue(ip), Some("host.example.".to_string()));
+        assert!(queue.try_recv().is_err());
+        assert_eq!(dns.cache_stats().hits, 1);
+        assert_eq!(dns.cache_stats().misses, 1);
+    }
+}
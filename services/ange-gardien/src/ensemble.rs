@@ -0,0 +1,203 @@
+use serde::{Serialize, Deserialize};
+#[cfg(feature = "python")]
+use {
+    anyhow::Result,
+    chrono::Utc,
+    std::collections::VecDeque,
+    tracing::info,
+    crate::analysis::{anomaly_recommendation, AnomalyScore, ANOMALY_DETECTOR_SOURCE},
+    crate::python::PythonAnalyzer,
+    crate::{SystemState, SecurityAlert, AlertSeverity},
+};
+
+/// States kept for fitting the IsolationForest
+#[cfg(feature = "python")]
+const ENSEMBLE_WINDOW: usize = 3600;
+/// The IsolationForest is fit once this many states have been seen
+#[cfg(feature = "python")]
+const ENSEMBLE_TRAIN_SAMPLES: usize = 600;
+
+/// How the two detectors' verdicts are combined.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EnsembleMode {
+    /// Alert only when both detectors flag the state
+    #[default]
+    And,
+    /// Alert when either detector flags the state
+    Or,
+}
+
+/// Both detectors' opinions of one state, on a common 0–1 scale where 0.5 is
+/// each model's own decision boundary.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct EnsembleVote {
+    pub dbscan: f64,
+    /// `None` until the IsolationForest has been fit
+    pub isolation_forest: Option<f64>,
+    pub score: f64,
+    pub is_anomaly: bool,
+}
+
+/// DBSCAN scores are distances to the nearest centroid in units of the
+/// tolerance, so 1.0 (the cluster boundary) maps to 0.5.
+pub fn dbscan_probability(score: f64) -> f64 {
+    if !score.is_finite() {
+        return 1.0;
+    }
+    let score = score.max(0.0);
+    score / (1.0 + score)
+}
+
+/// IsolationForest's `decision_function` is negative for outliers and rarely
+/// leaves [-0.5, 0.5]; 0.0 (its threshold) maps to 0.5.
+pub fn isolation_forest_probability(decision: f64) -> f64 {
+    (0.5 - decision).clamp(0.0, 1.0)
+}
+
+/// One detector's verdict: its normalized score and whether it flagged the state.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Verdict {
+    pub score: f64,
+    pub is_anomaly: bool,
+}
+
+impl EnsembleMode {
+    /// Combines the verdicts. Until the IsolationForest is fit the DBSCAN
+    /// verdict stands alone in either mode.
+    pub fn combine(self, dbscan: Verdict, isolation_forest: Option<Verdict>) -> EnsembleVote {
+        let (score, is_anomaly) = match isolation_forest {
+            None => (dbscan.score, dbscan.is_anomaly),
+            Some(forest) => match self {
+                EnsembleMode::And => (dbscan.score.min(forest.score), dbscan.is_anomaly && forest.is_anomaly),
+                EnsembleMode::Or => (dbscan.score.max(forest.score), dbscan.is_anomaly || forest.is_anomaly),
+            },
+        };
+        EnsembleVote {
+            dbscan: dbscan.score,
+            isolation_forest: isolation_forest.map(|forest| forest.score),
+            score,
+            is_anomaly,
+        }
+    }
+}
+
+/// Runs the Python IsolationForest alongside the Rust DBSCAN detector and
+/// combines their verdicts. The two fail differently, so AND mode cuts false
+/// positives and OR mode catches more. Needs the `python` feature.
+#[cfg(feature = "python")]
+pub struct EnsembleDetector {
+    python: PythonAnalyzer,
+    mode: EnsembleMode,
+    window: VecDeque<SystemState>,
+    trained: bool,
+}
+
+#[cfg(feature = "python")]
+impl EnsembleDetector {
+    pub fn new(mode: EnsembleMode) -> Result<Self> {
+        Ok(Self {
+            python: PythonAnalyzer::new()?,
+            mode,
+            window: VecDeque::with_capacity(ENSEMBLE_WINDOW),
+            trained: false,
+        })
+    }
+
+    pub fn mode(&self) -> EnsembleMode {
+        self.mode
+    }
+
+    /// Scores `state` with the IsolationForest and combines it with DBSCAN's
+    /// score for the same state. `allowed_ports` must match the DBSCAN
+    /// detector's so both see the same features.
+    pub async fn vote(
+        &mut self,
+        state: &SystemState,
+        dbscan: &AnomalyScore,
+        allowed_ports: &[u16],
+    ) -> Result<EnsembleVote> {
+        if self.window.len() == ENSEMBLE_WINDOW {
+            self.window.pop_front();
+        }
+        self.window.push_back(state.clone());
+
+        if !self.trained && self.window.len() >= ENSEMBLE_TRAIN_SAMPLES {
+            let window: Vec<SystemState> = self.window.iter().cloned().collect();
+            self.python.train_model(&window, allowed_ports).await?;
+            self.trained = true;
+            info!("Fitted IsolationForest on {} states", window.len());
+        }
+
+        let forest = if self.trained {
+            self.python.analyze_state(std::slice::from_ref(state), allowed_ports).await?
+                .first()
+                .map(|(decision, is_anomaly)| Verdict {
+                    score: isolation_forest_probability(*decision),
+                    is_anomaly: *is_anomaly,
+                })
+        } else {
+            None
+        };
+
+        let dbscan = Verdict {
+            score: dbscan_probability(dbscan.score),
+            is_anomaly: dbscan.is_anomaly,
+        };
+        Ok(self.mode.combine(dbscan, forest))
+    }
+
+    pub fn alert(&self, vote: &EnsembleVote, state: &SystemState) -> SecurityAlert {
+        let forest = vote.isolation_forest
+            .map(|score| format!("{:.2}", score))
+            .unwrap_or_else(|| "untrained".to_string());
+        SecurityAlert {
+            timestamp: Utc::now(),
+            severity: AlertSeverity::Medium,
+            description: format!(
+                "Anomalous system behavior detected (score {:.2}; DBSCAN {:.2}, IsolationForest {})",
+                vote.score, vote.dbscan, forest
+            ),
+            source: ANOMALY_DETECTOR_SOURCE.to_string(),
+            recommendation: Some(anomaly_recommendation(state)),
+            count: 1,
+            would_enforce: false,
+            rule: None,
+            pid: None,
+            remote_ip: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_score_normalization() {
+        assert_eq!(dbscan_probability(1.0), 0.5);
+        assert_eq!(dbscan_probability(0.0), 0.0);
+        assert_eq!(dbscan_probability(f64::INFINITY), 1.0);
+        assert_eq!(isolation_forest_probability(0.0), 0.5);
+        assert_eq!(isolation_forest_probability(0.5), 0.0);
+        assert_eq!(isolation_forest_probability(-0.7), 1.0);
+    }
+
+    #[test]
+    fn test_combine_modes() {
+        let flagged = Verdict { score: 0.8, is_anomaly: true };
+        let normal = Verdict { score: 0.3, is_anomaly: false };
+
+        let and = EnsembleMode::And.combine(flagged, Some(normal));
+        assert!(!and.is_anomaly);
+        assert_eq!(and.score, 0.3);
+
+        let or = EnsembleMode::Or.combine(flagged, Some(normal));
+        assert!(or.is_anomaly);
+        assert_eq!(or.score, 0.8);
+
+        assert!(EnsembleMode::And.combine(flagged, Some(flagged)).is_anomaly);
+        assert!(EnsembleMode::And.combine(flagged, None).is_anomaly);
+        assert!(!EnsembleMode::Or.combine(normal, None).is_anomaly);
+    }
+}
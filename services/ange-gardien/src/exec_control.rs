@@ -0,0 +1,352 @@
+//! Application allowlisting on process execution.
+//!
+//! With the `endpoint-security` feature, an EndpointSecurity client subscribes to
+//! `ES_EVENT_TYPE_AUTH_EXEC` and answers every exec authorization request using
+//! the configured [`ExecPolicy`].
+//!
+//! **Risks:** in [`ExecControlMode::Enforce`] a binary missing from the allowlist
+//! is denied before it runs. An incomplete allowlist can break logins, updates,
+//! shells and recovery tooling, and a crashed or stalled client makes the kernel
+//! deny (or time out) pending requests. Always run in [`ExecControlMode::Audit`]
+//! first and review the alerts before enforcing. A global
+//! [`EnforcementMode::Monitor`] turns would-be denials into alerts marked
+//! `would_enforce`, whatever the mode here. The client requires the
+//! `com.apple.developer.endpoint-security.client` entitlement, root, and Full
+//! Disk Access for the hosting binary; without them `ExecGuard::start` fails.
+
+use anyhow::Result;
+use chrono::Utc;
+use serde::{Serialize, Deserialize};
+use std::path::Path;
+use tokio::sync::mpsc;
+use crate::{SecurityAlert, AlertSeverity};
+use crate::security::EnforcementMode;
+
+pub const EXEC_CONTROL_SOURCE: &str = "Exec Control";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ExecControlMode {
+    /// Allow every exec, alert on binaries outside the allowlist
+    Audit,
+    /// Deny execs of binaries outside the allowlist
+    Enforce,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExecDecision {
+    Allow,
+    AllowWithAlert,
+    Deny,
+    /// Would have been denied, but enforcement is in monitor mode
+    WouldDeny,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExecPolicy {
+    /// Exec control is opt-in and disabled unless explicitly turned on
+    pub enabled: bool,
+    pub mode: ExecControlMode,
+    /// Absolute binary paths, or directory prefixes ending in `/`
+    pub allowlist: Vec<String>,
+    /// Let Apple platform binaries through regardless of the allowlist
+    pub allow_platform_binaries: bool,
+}
+
+impl Default for ExecPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            mode: ExecControlMode::Audit,
+            allowlist: vec![
+                "/System/".to_string(),
+                "/usr/bin/".to_string(),
+                "/usr/sbin/".to_string(),
+                "/usr/libexec/".to_string(),
+                "/bin/".to_string(),
+                "/sbin/".to_string(),
+            ],
+            allow_platform_binaries: true,
+        }
+    }
+}
+
+impl ExecPolicy {
+    pub fn is_allowlisted(&self, path: &Path) -> bool {
+        let path = path.to_string_lossy();
+        self.allowlist.iter().any(|entry| {
+            if entry.ends_with('/') {
+                path.starts_with(entry.as_str())
+            } else {
+                path == entry.as_str()
+            }
+        })
+    }
+
+    pub fn evaluate(&self, path: &Path, is_platform_binary: bool, enforcement: EnforcementMode) -> ExecDecision {
+        if !self.enabled
+            || (self.allow_platform_binaries && is_platform_binary)
+            || self.is_allowlisted(path)
+        {
+            return ExecDecision::Allow;
+        }
+
+        match self.mode {
+            ExecControlMode::Audit => ExecDecision::AllowWithAlert,
+            ExecControlMode::Enforce => match enforcement {
+                EnforcementMode::Enforce => ExecDecision::Deny,
+                EnforcementMode::Monitor => ExecDecision::WouldDeny,
+            },
+        }
+    }
+
+    fn alert_for(&self, path: &Path, decision: ExecDecision) -> Option<SecurityAlert> {
+        let (severity, description) = match decision {
+            ExecDecision::Allow => return None,
+            ExecDecision::AllowWithAlert => (
+                AlertSeverity::Medium,
+                format!("Non-allowlisted binary executed: {}", path.display()),
+            ),
+            ExecDecision::Deny => (
+                AlertSeverity::High,
+                format!("Blocked execution of non-allowlisted binary: {}", path.display()),
+            ),
+            ExecDecision::WouldDeny => (
+                AlertSeverity::High,
+                format!("Would block execution of non-allowlisted binary: {}", path.display()),
+            ),
+        };
+
+        Some(SecurityAlert {
+            timestamp: Utc::now(),
+            severity,
+            description,
+            source: EXEC_CONTROL_SOURCE.to_string(),
+            recommendation: Some(format!(
+                "Add {} to the exec allowlist if it is expected",
+                path.display()
+            )),
+            count: 1,
+            would_enforce: decision == ExecDecision::WouldDeny,
+            rule: None,
+            pid: None,
+            remote_ip: None,
+        })
+    }
+}
+
+/// A running EndpointSecurity client. Dropping it unsubscribes and deletes the client.
+pub struct ExecGuard {
+    #[cfg(all(target_os = "macos", feature = "endpoint-security"))]
+    client: *mut ffi::EsClient,
+}
+
+// The raw client pointer is only used to tear down the client on drop
+unsafe impl Send for ExecGuard {}
+unsafe impl Sync for ExecGuard {}
+
+impl ExecGuard {
+    /// Starts the exec authorization client. Alerts for non-allowlisted executions
+    /// are delivered on the returned channel.
+    #[cfg(all(target_os = "macos", feature = "endpoint-security"))]
+    pub fn start(
+        policy: ExecPolicy,
+        enforcement: EnforcementMode,
+    ) -> Result<(Self, mpsc::UnboundedReceiver<SecurityAlert>)> {
+        use block::ConcreteBlock;
+        use std::ffi::CStr;
+
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        let handler = ConcreteBlock::new(move |client: *mut ffi::EsClient, message: *const ffi::EsMessage| {
+            unsafe {
+                let message = &*message;
+                if message.event_type != ffi::ES_EVENT_TYPE_AUTH_EXEC {
+                    return;
+                }
+
+                let target = &*message.event_exec_target;
+                let executable = &*target.executable;
+                let path = if executable.path.data.is_null() {
+                    String::new()
+                } else {
+                    CStr::from_ptr(executable.path.data).to_string_lossy().into_owned()
+                };
+                let path = Path::new(&path);
+
+                let decision = policy.evaluate(path, target.is_platform_binary, enforcement);
+                let result = match decision {
+                    ExecDecision::Deny => ffi::ES_AUTH_RESULT_DENY,
+                    _ => ffi::ES_AUTH_RESULT_ALLOW,
+                };
+                // Only cache allow verdicts so policy changes take effect for denied binaries
+                ffi::es_respond_auth_result(client, message, result, decision == ExecDecision::Allow);
+
+                if let Some(mut alert) = policy.alert_for(path, decision) {
+                    // The audit token's sixth word is the pid, which exec keeps
+                    alert.pid = Some(target.audit_token[5]);
+                    let _ = tx.send(alert);
+                }
+            }
+        })
+        .copy();
+
+        unsafe {
+            let mut client: *mut ffi::EsClient = std::ptr::null_mut();
+            let result = ffi::es_new_client(&mut client, &*handler as *const ffi::EsHandler);
+            if result != ffi::ES_NEW_CLIENT_RESULT_SUCCESS {
+                return Err(anyhow::anyhow!(
+                    "Failed to create EndpointSecurity client (error {}); check the entitlement, root and Full Disk Access",
+                    result
+                ));
+            }
+
+            let events = [ffi::ES_EVENT_TYPE_AUTH_EXEC];
+            if ffi::es_subscribe(client, events.as_ptr(), events.len() as u32) != ffi::ES_RETURN_SUCCESS {
+                ffi::es_delete_client(client);
+                return Err(anyhow::anyhow!("Failed to subscribe to exec authorization events"));
+            }
+
+            Ok((Self { client }, rx))
+        }
+    }
+
+    #[cfg(not(all(target_os = "macos", feature = "endpoint-security")))]
+    pub fn start(
+        _policy: ExecPolicy,
+        _enforcement: EnforcementMode,
+    ) -> Result<(Self, mpsc::UnboundedReceiver<SecurityAlert>)> {
+        Err(anyhow::anyhow!("Exec control requires macOS and the endpoint-security feature"))
+    }
+}
+
+#[cfg(all(target_os = "macos", feature = "endpoint-security"))]
+impl Drop for ExecGuard {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::es_unsubscribe_all(self.client);
+            ffi::es_delete_client(self.client);
+        }
+    }
+}
+
+/// Minimal bindings for the parts of EndpointSecurity.framework used here.
+#[cfg(all(target_os = "macos", feature = "endpoint-security"))]
+mod ffi {
+    use block::Block;
+    use std::os::raw::c_char;
+
+    pub const ES_EVENT_TYPE_AUTH_EXEC: u32 = 0;
+    pub const ES_AUTH_RESULT_ALLOW: u32 = 0;
+    pub const ES_AUTH_RESULT_DENY: u32 = 1;
+    pub const ES_NEW_CLIENT_RESULT_SUCCESS: u32 = 0;
+    pub const ES_RETURN_SUCCESS: u32 = 0;
+
+    #[repr(C)]
+    pub struct EsClient {
+        _private: [u8; 0],
+    }
+
+    #[repr(C)]
+    pub struct EsStringToken {
+        pub length: usize,
+        pub data: *const c_char,
+    }
+
+    #[repr(C)]
+    pub struct EsFile {
+        pub path: EsStringToken,
+        pub path_truncated: bool,
+    }
+
+    #[repr(C)]
+    pub struct EsProcess {
+        pub audit_token: [u32; 8],
+        pub ppid: i32,
+        pub original_ppid: i32,
+        pub group_id: i32,
+        pub session_id: i32,
+        pub codesigning_flags: u32,
+        pub is_platform_binary: bool,
+        pub is_es_client: bool,
+        pub cdhash: [u8; 20],
+        pub signing_id: EsStringToken,
+        pub team_id: EsStringToken,
+        pub executable: *const EsFile,
+    }
+
+    #[repr(C)]
+    pub struct EsMessage {
+        pub version: u32,
+        pub time: libc::timespec,
+        pub mach_time: u64,
+        pub deadline: u64,
+        pub process: *const EsProcess,
+        pub seq_num: u64,
+        pub action_type: u32,
+        pub action: [u8; 36],
+        pub event_type: u32,
+        // First member of es_event_exec_t; the rest of the event union is unused
+        pub event_exec_target: *const EsProcess,
+    }
+
+    pub type EsHandler = Block<(*mut EsClient, *const EsMessage), ()>;
+
+    #[link(name = "EndpointSecurity")]
+    extern "C" {
+        pub fn es_new_client(client: *mut *mut EsClient, handler: *const EsHandler) -> u32;
+        pub fn es_subscribe(client: *mut EsClient, events: *const u32, event_count: u32) -> u32;
+        pub fn es_unsubscribe_all(client: *mut EsClient) -> u32;
+        pub fn es_delete_client(client: *mut EsClient) -> u32;
+        pub fn es_respond_auth_result(
+            client: *mut EsClient,
+            message: *const EsMessage,
+            result: u32,
+            cache: bool,
+        ) -> u32;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disabled_policy_allows_everything() {
+        let policy = ExecPolicy::default();
+        assert_eq!(policy.evaluate(Path::new("/tmp/payload"), false, EnforcementMode::Enforce), ExecDecision::Allow);
+    }
+
+    #[test]
+    fn test_audit_and_enforce_decisions() {
+        let mut policy = ExecPolicy {
+            enabled: true,
+            ..ExecPolicy::default()
+        };
+        let enforce = EnforcementMode::Enforce;
+        assert_eq!(policy.evaluate(Path::new("/usr/bin/ssh"), false, enforce), ExecDecision::Allow);
+        assert_eq!(policy.evaluate(Path::new("/tmp/payload"), true, enforce), ExecDecision::Allow);
+        assert_eq!(policy.evaluate(Path::new("/tmp/payload"), false, enforce), ExecDecision::AllowWithAlert);
+
+        policy.mode = ExecControlMode::Enforce;
+        assert_eq!(policy.evaluate(Path::new("/tmp/payload"), false, enforce), ExecDecision::Deny);
+        assert!(policy.alert_for(Path::new("/tmp/payload"), ExecDecision::Deny).is_some());
+
+        let monitor = EnforcementMode::Monitor;
+        assert_eq!(policy.evaluate(Path::new("/tmp/payload"), false, monitor), ExecDecision::WouldDeny);
+        let alert = policy.alert_for(Path::new("/tmp/payload"), ExecDecision::WouldDeny).unwrap();
+        assert!(alert.would_enforce);
+    }
+
+    #[test]
+    fn test_allowlist_matches_exact_paths_and_prefixes() {
+        let policy = ExecPolicy {
+            enabled: true,
+            allowlist: vec!["/opt/app/bin/server".to_string(), "/Applications/".to_string()],
+            ..ExecPolicy::default()
+        };
+        assert!(policy.is_allowlisted(Path::new("/opt/app/bin/server")));
+        assert!(!policy.is_allowlisted(Path::new("/opt/app/bin/server-helper")));
+        assert!(policy.is_allowlisted(Path::new("/Applications/Safari.app/Contents/MacOS/Safari")));
+    }
+}
@@ -0,0 +1,195 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use std::io::{BufWriter, Write};
+use crate::store::StateStore;
+
+const STATE_HEADER: [&str; 7] = [
+    "timestamp",
+    "cpu",
+    "memory",
+    "disk",
+    "bytes_sent",
+    "bytes_received",
+    "process_count",
+];
+
+const ALERT_HEADER: [&str; 7] = [
+    "timestamp",
+    "severity",
+    "source",
+    "description",
+    "recommendation",
+    "count",
+    "would_enforce",
+];
+
+/// Writes states recorded since `since` as CSV, one row per state, and
+/// returns how many rows were written.
+pub(crate) async fn states_csv<W: Write + Send>(
+    db: &dyn StateStore,
+    writer: W,
+    since: DateTime<Utc>,
+) -> Result<usize> {
+    let mut csv = csv::Writer::from_writer(writer);
+    csv.write_record(STATE_HEADER)?;
+
+    let mut rows = 0;
+    db.for_each_state_since(since, &mut |state| {
+        csv.write_record([
+            state.timestamp.to_rfc3339(),
+            state.cpu_usage.to_string(),
+            state.memory_usage.to_string(),
+            state.disk_usage.to_string(),
+            state.network_stats.bytes_sent.to_string(),
+            state.network_stats.bytes_received.to_string(),
+            state.active_processes.len().to_string(),
+        ])?;
+        rows += 1;
+        Ok(())
+    }).await?;
+
+    csv.flush()?;
+    Ok(rows)
+}
+
+/// Writes alerts raised since `since` as CSV and returns how many rows were
+/// written.
+pub(crate) async fn alerts_csv<W: Write + Send>(
+    db: &dyn StateStore,
+    writer: W,
+    since: DateTime<Utc>,
+) -> Result<usize> {
+    let mut csv = csv::Writer::from_writer(writer);
+    csv.write_record(ALERT_HEADER)?;
+
+    let mut rows = 0;
+    db.for_each_alert_since(since, &mut |alert| {
+        csv.write_record([
+            alert.timestamp.to_rfc3339(),
+            alert.severity.to_string(),
+            alert.source,
+            alert.description,
+            alert.recommendation.unwrap_or_default(),
+            alert.count.to_string(),
+            alert.would_enforce.to_string(),
+        ])?;
+        rows += 1;
+        Ok(())
+    }).await?;
+
+    csv.flush()?;
+    Ok(rows)
+}
+
+/// Writes states recorded since `since` as JSON Lines, one full `SystemState`
+/// per line, and returns how many lines were written.
+pub(crate) async fn states_jsonl<W: Write + Send>(
+    db: &dyn StateStore,
+    writer: W,
+    since: DateTime<Utc>,
+) -> Result<usize> {
+    let mut out = BufWriter::new(writer);
+
+    let mut lines = 0;
+    db.for_each_state_since(since, &mut |state| {
+        serde_json::to_writer(&mut out, &state)?;
+        out.write_all(b"\n")?;
+        lines += 1;
+        Ok(())
+    }).await?;
+
+    out.flush()?;
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::InMemoryStore;
+    use crate::{AlertSeverity, SecurityAlert, SystemState};
+
+    #[tokio::test]
+    async fn test_csv_export() {
+        let store = InMemoryStore::new();
+        let timestamp = DateTime::parse_from_rfc3339("2024-03-01T12:00:00Z").unwrap().with_timezone(&Utc);
+        let mut state = SystemState {
+            timestamp,
+            cpu_usage: 12.5,
+            memory_usage: 40.0,
+            disk_usage: 70.0,
+            security_alerts: vec![SecurityAlert {
+                timestamp,
+                severity: AlertSeverity::High,
+                description: "Port scan, from 10.0.0.1".to_string(),
+                source: "Network".to_string(),
+                recommendation: None,
+                count: 2,
+                would_enforce: false,
+                rule: None,
+                pid: None,
+                remote_ip: None,
+            }],
+            ..Default::default()
+        };
+        state.network_stats.bytes_sent = 100;
+        store.store_state(&state).await.unwrap();
+
+        let since = timestamp - chrono::Duration::hours(1);
+        let mut out = Vec::new();
+        assert_eq!(states_csv(&store, &mut out, since).await.unwrap(), 1);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "timestamp,cpu,memory,disk,bytes_sent,bytes_received,process_count\n\
+             2024-03-01T12:00:00+00:00,12.5,40,70,100,0,0\n"
+        );
+
+        let mut out = Vec::new();
+        assert_eq!(alerts_csv(&store, &mut out, since).await.unwrap(), 1);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "timestamp,severity,source,description,recommendation,count,would_enforce\n\
+             2024-03-01T12:00:00+00:00,High,Network,\"Port scan, from 10.0.0.1\",,2,false\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_jsonl_export() {
+        let store = InMemoryStore::new();
+        let timestamp = DateTime::parse_from_rfc3339("2024-03-01T12:00:00Z").unwrap().with_timezone(&Utc);
+        let mut state = SystemState {
+            timestamp,
+            cpu_usage: 12.5,
+            memory_usage: 40.0,
+            disk_usage: 70.0,
+            security_alerts: vec![SecurityAlert {
+                timestamp,
+                severity: AlertSeverity::High,
+                description: "Port scan, from 10.0.0.1".to_string(),
+                source: "Network".to_string(),
+                recommendation: None,
+                count: 2,
+                would_enforce: false,
+                rule: None,
+                pid: None,
+                remote_ip: None,
+            }],
+            ..Default::default()
+        };
+        store.store_state(&state).await.unwrap();
+        state.cpu_usage = 20.0;
+        state.timestamp = timestamp + chrono::Duration::seconds(1);
+        store.store_state(&state).await.unwrap();
+
+        let since = timestamp - chrono::Duration::hours(1);
+        let mut out = Vec::new();
+        assert_eq!(states_jsonl(&store, &mut out, since).await.unwrap(), 2);
+
+        let lines: Vec<SystemState> = String::from_utf8(out).unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1].cpu_usage, 20.0);
+        assert_eq!(lines[1].security_alerts[0].description, "Port scan, from 10.0.0.1");
+    }
+}
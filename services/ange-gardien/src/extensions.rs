@@ -0,0 +1,232 @@
+use anyhow::Result;
+use chrono::Utc;
+use serde::{Serialize, Deserialize};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use crate::codesign::{self, SigningInfo};
+use crate::{SecurityAlert, AlertSeverity};
+use tracing::{debug, warn};
+
+pub const EXTENSION_AUDIT_SOURCE: &str = "Extension Audit";
+
+/// Apple's own kernel and system extensions are platform binaries signed by
+/// "Software Signing" and carry no team identifier; they are reported under this ID.
+pub const APPLE_TEAM_ID: &str = "apple";
+
+/// Satisfied only by Apple's platform binaries
+const APPLE_REQUIREMENT: &str = "anchor apple";
+/// Satisfied by anything signed through an Apple-issued certificate, e.g. Developer ID
+const DEVELOPER_REQUIREMENT: &str = "anchor apple generic";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ExtensionKind {
+    Kernel,
+    System,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoadedExtension {
+    pub kind: ExtensionKind,
+    pub bundle_id: String,
+    /// `None` when the extension is unsigned or the signature could not be read
+    pub team_id: Option<String>,
+}
+
+/// Enumerates loaded kernel extensions (`kextstat`) and system extensions
+/// (`systemextensionsctl`).
+pub fn list_loaded_extensions() -> Result<Vec<LoadedExtension>> {
+    let mut extensions = Vec::new();
+
+    match run("kextstat", &["-l"]) {
+        Ok(output) => {
+            for bundle_id in parse_kextstat(&output) {
+                let team_id = kext_path(&bundle_id)
+                    .and_then(|path| signer_team(&path, codesign::verify));
+                debug!("Kernel extension {} signed by {:?}", bundle_id, team_id);
+                extensions.push(LoadedExtension {
+                    kind: ExtensionKind::Kernel,
+                    bundle_id,
+                    team_id,
+                });
+            }
+        }
+        Err(e) => warn!("Failed to list kernel extensions: {}", e),
+    }
+
+    match run("systemextensionsctl", &["list"]) {
+        Ok(output) => extensions.extend(parse_systemextensionsctl(&output)),
+        Err(e) => warn!("Failed to list system extensions: {}", e),
+    }
+
+    Ok(extensions)
+}
+
+/// Returns a `Critical` alert for every extension not signed by an allowed team.
+pub fn audit_extensions(extensions: &[LoadedExtension], allowed_teams: &[String]) -> Vec<SecurityAlert> {
+    extensions.iter()
+        .filter(|extension| match &extension.team_id {
+            Some(team) => !allowed_teams.iter().any(|allowed| allowed == team),
+            None => true,
+        })
+        .map(|extension| {
+            let kind = match extension.kind {
+                ExtensionKind::Kernel => "kernel extension",
+                ExtensionKind::System => "system extension",
+            };
+            let signer = match &extension.team_id {
+                Some(team) => format!("team {}", team),
+                None => "unsigned".to_string(),
+            };
+
+            SecurityAlert {
+                timestamp: Utc::now(),
+                severity: AlertSeverity::Critical,
+                description: format!(
+                    "Untrusted {} loaded: {} ({})",
+                    kind,
+                    extension.bundle_id,
+                    signer
+                ),
+                source: EXTENSION_AUDIT_SOURCE.to_string(),
+                recommendation: Some(format!(
+                    "Verify {} is expected; add its team ID to the allowlist or remove it",
+                    extension.bundle_id
+                )),
+                count: 1,
+                would_enforce: false,
+                rule: None,
+                pid: None,
+                remote_ip: None,
+            }
+        })
+        .collect()
+}
+
+fn run(program: &str, args: &[&str]) -> Result<String> {
+    let output = Command::new(program).args(args).output()?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("{} exited with {}", program, output.status));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn kext_path(bundle_id: &str) -> Option<PathBuf> {
+    let output = run("kextfind", &["-b", bundle_id]).ok()?;
+    output.lines().next().map(|line| PathBuf::from(line.trim()))
+}
+
+/// The team whose valid signature the bundle at `path` carries. Only a
+/// signature chaining to Apple's platform anchor counts as Apple's; the
+/// bundle id is never trusted, since anyone can name a kext `com.apple.*`.
+fn signer_team(path: &Path, verify: impl Fn(&Path, &str) -> Result<SigningInfo>) -> Option<String> {
+    if verify(path, APPLE_REQUIREMENT).is_ok() {
+        return Some(APPLE_TEAM_ID.to_string());
+    }
+    verify(path, DEVELOPER_REQUIREMENT).ok()?.team_id
+}
+
+fn parse_kextstat(output: &str) -> Vec<String> {
+    // Index Refs Address Size Wired Name (Version) UUID <Linked Against>
+    output.lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.first()?.parse::<u32>().is_err() {
+                return None;
+            }
+            fields.get(5).map(|name| name.to_string())
+        })
+        .collect()
+}
+
+fn parse_systemextensionsctl(output: &str) -> Vec<LoadedExtension> {
+    // enabled active teamID bundleID (version) name [state]
+    output.lines()
+        .filter(|line| line.contains("[activated"))
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split('\t').map(str::trim).filter(|f| !f.is_empty()).collect();
+            let team_index = fields.iter().position(|f| *f != "*")?;
+            let team = fields.get(team_index)?;
+            let bundle_id = fields.get(team_index + 1)?.split_whitespace().next()?;
+
+            Some(LoadedExtension {
+                kind: ExtensionKind::System,
+                bundle_id: bundle_id.to_string(),
+                team_id: if *team == "-" { None } else { Some(team.to_string()) },
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_kextstat() {
+        let output = "Index Refs Address            Size       Wired      Name (Version) UUID <Linked Against>\n\
+                      1  145 0                  0          0          com.apple.kpi.bsd (21.6.0) 1A2B <>\n\
+                      201    0 0xffffff7f83d5e000 0x5000     0x5000     com.vendor.driver (1.0) 3C4D <5 3>\n";
+        assert_eq!(parse_kextstat(output), vec!["com.apple.kpi.bsd", "com.vendor.driver"]);
+    }
+
+    #[test]
+    fn test_parse_systemextensionsctl() {
+        let output = "1 extension(s)\n\
+                      --- com.apple.system_extension.network_extension\n\
+                      enabled\tactive\tteamID\tbundleID (version)\tname\t[state]\n\
+                      *\t*\tABCDE12345\tcom.vendor.filter (1.2/3)\tVendor Filter\t[activated enabled]\n";
+        let extensions = parse_systemextensionsctl(output);
+        assert_eq!(extensions.len(), 1);
+        assert_eq!(extensions[0].bundle_id, "com.vendor.filter");
+        assert_eq!(extensions[0].team_id.as_deref(), Some("ABCDE12345"));
+    }
+
+    #[test]
+    fn test_audit_extensions() {
+        let extensions = vec![
+            LoadedExtension {
+                kind: ExtensionKind::Kernel,
+                bundle_id: "com.apple.kpi.bsd".to_string(),
+                team_id: Some(APPLE_TEAM_ID.to_string()),
+            },
+            LoadedExtension {
+                kind: ExtensionKind::Kernel,
+                bundle_id: "com.unknown.rootkit".to_string(),
+                team_id: None,
+            },
+        ];
+        let alerts = audit_extensions(&extensions, &[APPLE_TEAM_ID.to_string()]);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].severity, AlertSeverity::Critical);
+        assert!(alerts[0].description.contains("com.unknown.rootkit"));
+    }
+
+    #[test]
+    fn test_apple_bundle_id_needs_apple_signature() {
+        // Signed with a Developer ID, but not by Apple
+        let verify = |_: &Path, requirement: &str| match requirement {
+            DEVELOPER_REQUIREMENT => Ok(SigningInfo {
+                identifier: Some("com.apple.evil".to_string()),
+                team_id: Some("EVIL123456".to_string()),
+                authority: Some("Developer ID Application: Evil Corp".to_string()),
+            }),
+            _ => Err(anyhow::anyhow!("not signed by an allowed authority")),
+        };
+        let path = Path::new("/Library/Extensions/evil.kext");
+        let extension = LoadedExtension {
+            kind: ExtensionKind::Kernel,
+            bundle_id: "com.apple.evil".to_string(),
+            team_id: signer_team(path, verify),
+        };
+        assert_eq!(extension.team_id.as_deref(), Some("EVIL123456"));
+
+        let alerts = audit_extensions(&[extension], &[APPLE_TEAM_ID.to_string()]);
+        assert_eq!(alerts.len(), 1);
+        assert!(alerts[0].description.contains("com.apple.evil"));
+
+        let unsigned = |_: &Path, _: &str| -> Result<SigningInfo> { Err(anyhow::anyhow!("not signed")) };
+        assert_eq!(signer_team(path, unsigned), None);
+        let apple = |_: &Path, _: &str| -> Result<SigningInfo> { Ok(SigningInfo::default()) };
+        assert_eq!(signer_team(path, apple).as_deref(), Some(APPLE_TEAM_ID));
+    }
+}
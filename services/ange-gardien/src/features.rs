@@ -0,0 +1,140 @@
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+use crate::network::ConnectionState;
+use crate::SystemState;
+
+/// Length of every feature vector fed to the anomaly detectors.
+pub const FEATURE_COUNT: usize = 10;
+
+/// Order of the features in each vector. A saved IsolationForest is only valid
+/// for this exact layout: append new features rather than reordering them.
+/// Models saved with a different `FEATURE_COUNT` are discarded on load.
+pub const FEATURE_NAMES: [&str; FEATURE_COUNT] = [
+    "cpu_usage",
+    "memory_usage",
+    "disk_usage",
+    "bytes_sent",
+    "bytes_received",
+    "process_count",
+    "unique_remote_ips",
+    "disallowed_port_connections",
+    "new_processes_per_min",
+    "load_average",
+];
+
+/// Processes started this long before a state count towards its spawn rate
+const NEW_PROCESS_WINDOW_SECS: i64 = 60;
+
+/// Feature vector for one state, in `FEATURE_NAMES` order. Connections to
+/// ports outside `allowed_ports` are counted as disallowed.
+pub fn state_features(state: &SystemState, allowed_ports: &[u16]) -> [f64; FEATURE_COUNT] {
+    let outbound: Vec<SocketAddr> = state.network_stats.connections.iter()
+        .filter(|conn| conn.state != ConnectionState::Listen)
+        .filter_map(|conn| conn.remote_socket_addr())
+        .filter(|addr| !addr.ip().is_unspecified() && addr.port() != 0)
+        .collect();
+
+    let unique_ips: HashSet<IpAddr> = outbound.iter().map(|addr| addr.ip()).collect();
+    let disallowed = outbound.iter()
+        .filter(|addr| !allowed_ports.contains(&addr.port()))
+        .count();
+
+    let window_start = state.timestamp - chrono::Duration::seconds(NEW_PROCESS_WINDOW_SECS);
+    let new_processes = state.active_processes.iter()
+        .filter(|process| process.start_time > window_start && process.start_time <= state.timestamp)
+        .count();
+
+    [
+        state.cpu_usage as f64,
+        state.memory_usage as f64,
+        state.disk_usage as f64,
+        state.network_stats.bytes_sent as f64,
+        state.network_stats.bytes_received as f64,
+        state.active_processes.len() as f64,
+        unique_ips.len() as f64,
+        disallowed as f64,
+        new_processes as f64 * 60.0 / NEW_PROCESS_WINDOW_SECS as f64,
+        state.system_metrics.as_ref().map_or(0.0, |metrics| metrics.load_average),
+    ]
+}
+
+/// Row-major feature matrix with `FEATURE_COUNT` columns, one row per state.
+pub fn feature_matrix<'a>(
+    states: impl IntoIterator<Item = &'a SystemState>,
+    allowed_ports: &[u16],
+) -> Vec<f64> {
+    states.into_iter()
+        .flat_map(|state| state_features(state, allowed_ports))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{ConnectionInfo, Protocol};
+    use crate::{NetworkStats, ProcessInfo};
+    use chrono::{DateTime, Duration, Utc};
+
+    fn connection(remote: &str, state: ConnectionState) -> ConnectionInfo {
+        ConnectionInfo {
+            local_addr: "192.168.1.2:50000".to_string(),
+            remote_addr: remote.to_string(),
+            protocol: Protocol::TCP,
+            state,
+            process_id: None,
+            dns_name: None,
+            country: None,
+            asn: None,
+            bytes_sent: 0,
+            bytes_received: 0,
+            last_seen: Utc::now(),
+        }
+    }
+
+    fn process(pid: u32, start_time: DateTime<Utc>) -> ProcessInfo {
+        ProcessInfo {
+            pid,
+            ppid: 1,
+            name: "worker".to_string(),
+            cpu_usage: 0.0,
+            memory_usage: 0.0,
+            threads: 1,
+            start_time,
+            command: "worker".to_string(),
+            disk_bytes_read: 0,
+            disk_bytes_written: 0,
+        }
+    }
+
+    #[test]
+    fn test_state_features() {
+        let now = Utc::now();
+        let state = SystemState {
+            timestamp: now,
+            cpu_usage: 10.0,
+            memory_usage: 20.0,
+            disk_usage: 30.0,
+            network_stats: NetworkStats {
+                bytes_sent: 100,
+                bytes_received: 200,
+                connections: vec![
+                    connection("93.184.216.34:443", ConnectionState::Established),
+                    connection("93.184.216.34:4444", ConnectionState::Established),
+                    connection("[2001:db8::1]:443", ConnectionState::Established),
+                    connection("0.0.0.0:0", ConnectionState::Listen),
+                ],
+                ..Default::default()
+            },
+            active_processes: vec![
+                process(10, now - Duration::hours(1)),
+                process(11, now - Duration::seconds(5)),
+                process(12, now - Duration::seconds(30)),
+            ],
+            ..Default::default()
+        };
+
+        let features = state_features(&state, &[443]);
+        assert_eq!(features, [10.0, 20.0, 30.0, 100.0, 200.0, 3.0, 2.0, 1.0, 2.0, 0.0]);
+        assert_eq!(feature_matrix([&state, &state], &[443]).len(), 2 * FEATURE_COUNT);
+    }
+}
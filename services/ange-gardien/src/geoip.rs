@@ -0,0 +1,146 @@
+use serde::{Serialize, Deserialize};
+use std::path::PathBuf;
+#[cfg(feature = "geoip")]
+use {
+    lru::LruCache,
+    maxminddb::{geoip2, Reader},
+    std::net::IpAddr,
+    std::num::NonZeroUsize,
+    std::path::Path,
+    std::sync::{Mutex, OnceLock},
+    tracing::{info, warn},
+    crate::network::ConnectionInfo,
+};
+
+#[cfg(feature = "geoip")]
+const CACHE_CAPACITY: usize = 4096;
+
+/// Local MaxMind databases used to enrich connections. Either may be unset;
+/// with both unset enrichment is skipped.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GeoIpConfig {
+    /// GeoLite2-Country (or City) database
+    pub country_db: Option<PathBuf>,
+    /// GeoLite2-ASN database
+    pub asn_db: Option<PathBuf>,
+}
+
+impl GeoIpConfig {
+    pub fn is_configured(&self) -> bool {
+        self.country_db.is_some() || self.asn_db.is_some()
+    }
+}
+
+#[cfg(feature = "geoip")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GeoInfo {
+    /// ISO 3166-1 alpha-2 country code
+    pub country: Option<String>,
+    pub asn: Option<u32>,
+}
+
+#[cfg(feature = "geoip")]
+struct Readers {
+    country: Option<Reader<Vec<u8>>>,
+    asn: Option<Reader<Vec<u8>>>,
+}
+
+/// Country and ASN lookups for remote addresses. The databases are opened on
+/// first use and answers are cached per address. Needs the `geoip` feature.
+#[cfg(feature = "geoip")]
+pub struct GeoIp {
+    config: GeoIpConfig,
+    readers: OnceLock<Readers>,
+    cache: Mutex<LruCache<IpAddr, GeoInfo>>,
+}
+
+#[cfg(feature = "geoip")]
+impl GeoIp {
+    /// `None` when no database is configured.
+    pub fn from_config(config: &GeoIpConfig) -> Option<Self> {
+        if !config.is_configured() {
+            return None;
+        }
+        Some(Self {
+            config: config.clone(),
+            readers: OnceLock::new(),
+            cache: Mutex::new(LruCache::new(NonZeroUsize::new(CACHE_CAPACITY).unwrap())),
+        })
+    }
+
+    pub fn lookup(&self, ip: IpAddr) -> GeoInfo {
+        if ip.is_loopback() || ip.is_unspecified() {
+            return GeoInfo::default();
+        }
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(info) = cache.get(&ip) {
+            return info.clone();
+        }
+
+        let readers = self.readers();
+        let country = readers.country.as_ref()
+            .and_then(|reader| reader.lookup::<geoip2::Country>(ip).ok())
+            .and_then(|record| record.country)
+            .and_then(|country| country.iso_code)
+            .map(str::to_string);
+        let asn = readers.asn.as_ref()
+            .and_then(|reader| reader.lookup::<geoip2::Asn>(ip).ok())
+            .and_then(|record| record.autonomous_system_number);
+
+        let info = GeoInfo { country, asn };
+        cache.put(ip, info.clone());
+        info
+    }
+
+    /// Fills in the country and ASN of `connection`'s remote end.
+    pub fn enrich(&self, connection: &mut ConnectionInfo) {
+        if connection.country.is_some() || connection.asn.is_some() {
+            return;
+        }
+        if let Some(remote) = connection.remote_socket_addr() {
+            let info = self.lookup(remote.ip());
+            connection.country = info.country;
+            connection.asn = info.asn;
+        }
+    }
+
+    fn readers(&self) -> &Readers {
+        self.readers.get_or_init(|| Readers {
+            country: self.config.country_db.as_deref().and_then(open),
+            asn: self.config.asn_db.as_deref().and_then(open),
+        })
+    }
+}
+
+/// A database that can't be opened disables its half of the enrichment.
+#[cfg(feature = "geoip")]
+fn open(path: &Path) -> Option<Reader<Vec<u8>>> {
+    match Reader::open_readfile(path) {
+        Ok(reader) => {
+            info!("Opened GeoIP database {}", path.display());
+            Some(reader)
+        }
+        Err(e) => {
+            warn!("Failed to open GeoIP database {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+#[cfg(all(test, feature = "geoip"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unconfigured_and_missing_databases() {
+        assert!(GeoIp::from_config(&GeoIpConfig::default()).is_none());
+
+        let geoip = GeoIp::from_config(&GeoIpConfig {
+            country_db: Some(PathBuf::from("/nonexistent/GeoLite2-Country.mmdb")),
+            asn_db: None,
+        }).unwrap();
+        assert_eq!(geoip.lookup("203.0.113.9".parse().unwrap()), GeoInfo::default());
+        assert_eq!(geoip.cache.lock().unwrap().len(), 1);
+    }
+}
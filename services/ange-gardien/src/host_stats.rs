@@ -0,0 +1,245 @@
+use anyhow::Result;
+use serde::{Serialize, Deserialize};
+use std::time::Instant;
+
+/// Cumulative kernel counters at one point in time. Rates come from diffing two
+/// samples, so callers keep the previous one around.
+#[derive(Debug, Clone, Copy)]
+pub struct KernelCounters {
+    pub at: Instant,
+    /// Pages moved to or from backing store since boot
+    pub paging: u64,
+    pub swap_ins: u64,
+    pub swap_outs: u64,
+    /// Pages handed to the memory compressor since boot
+    pub compressions: u64,
+    /// Memory held by the compressor right now
+    pub compressed_bytes: u64,
+    pub context_switches: u64,
+    pub interrupts: u64,
+}
+
+/// Per-second rates between two `KernelCounters` samples.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct KernelRates {
+    pub io_wait: f64,
+    pub swap_ins: f64,
+    pub swap_outs: f64,
+    pub compressions: f64,
+    pub context_switches: u64,
+    pub interrupts: u64,
+}
+
+/// The kernel's own verdict on memory, from
+/// `kern.memorystatus_vm_pressure_level`. On macOS this, not percent used,
+/// says whether the machine is short of memory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MemoryPressure {
+    #[default]
+    Normal,
+    Warn,
+    Critical,
+}
+
+impl std::fmt::Display for MemoryPressure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MemoryPressure::Normal => write!(f, "normal"),
+            MemoryPressure::Warn => write!(f, "warn"),
+            MemoryPressure::Critical => write!(f, "critical"),
+        }
+    }
+}
+
+impl MemoryPressure {
+    /// Reads the current level; unknown levels count as normal.
+    #[cfg(target_os = "macos")]
+    pub fn sample() -> Result<Self> {
+        // DISPATCH_MEMORYPRESSURE_WARN and _CRITICAL
+        Ok(match ffi::sysctl_int("kern.memorystatus_vm_pressure_level")? {
+            4 => MemoryPressure::Critical,
+            2 => MemoryPressure::Warn,
+            _ => MemoryPressure::Normal,
+        })
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    pub fn sample() -> Result<Self> {
+        Err(anyhow::anyhow!("Memory pressure is only available on macOS"))
+    }
+}
+
+impl KernelCounters {
+    /// Reads the counters from the kernel.
+    ///
+    /// Darwin has no iowait CPU state, so `paging` (page-ins, page-outs, swap-ins
+    /// and swap-outs from `host_statistics64`) stands in for it: heavy paging is
+    /// what disk thrashing looks like on macOS. Context switches are summed from
+    /// every visible process's task info. The kernel exposes no host-wide
+    /// interrupt counter, so `interrupts` stays 0 on macOS.
+    #[cfg(target_os = "macos")]
+    pub fn sample() -> Result<Self> {
+        let vm = ffi::vm_statistics()?;
+        let paging = vm.pageins + vm.pageouts + vm.swapins + vm.swapouts;
+
+        Ok(Self {
+            at: Instant::now(),
+            paging,
+            swap_ins: vm.swapins,
+            swap_outs: vm.swapouts,
+            compressions: vm.compressions,
+            compressed_bytes: vm.compressor_page_count as u64 * ffi::page_size(),
+            context_switches: crate::procinfo::total_context_switches()?,
+            interrupts: 0,
+        })
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    pub fn sample() -> Result<Self> {
+        Err(anyhow::anyhow!("Kernel counters are only available on macOS"))
+    }
+
+    pub fn rates_since(&self, previous: &KernelCounters) -> KernelRates {
+        let elapsed = self.at.duration_since(previous.at).as_secs_f64();
+        if elapsed <= 0.0 {
+            return KernelRates::default();
+        }
+
+        // Counters summed over processes shrink when processes exit
+        let rate = |current: u64, previous: u64| current.saturating_sub(previous) as f64 / elapsed;
+
+        KernelRates {
+            io_wait: rate(self.paging, previous.paging),
+            swap_ins: rate(self.swap_ins, previous.swap_ins),
+            swap_outs: rate(self.swap_outs, previous.swap_outs),
+            compressions: rate(self.compressions, previous.compressions),
+            context_switches: rate(self.context_switches, previous.context_switches) as u64,
+            interrupts: rate(self.interrupts, previous.interrupts) as u64,
+        }
+    }
+}
+
+/// Minimal bindings for `host_statistics64` and the sysctls used above.
+#[cfg(target_os = "macos")]
+mod ffi {
+    use anyhow::Result;
+
+    const HOST_VM_INFO64: i32 = 4;
+    const KERN_SUCCESS: i32 = 0;
+
+    // vm_statistics64 is declared under `#pragma pack(4)`
+    #[repr(C, packed(4))]
+    #[derive(Default, Clone, Copy)]
+    pub struct VmStatistics64 {
+        pub free_count: u32,
+        pub active_count: u32,
+        pub inactive_count: u32,
+        pub wire_count: u32,
+        pub zero_fill_count: u64,
+        pub reactivations: u64,
+        pub pageins: u64,
+        pub pageouts: u64,
+        pub faults: u64,
+        pub cow_faults: u64,
+        pub lookups: u64,
+        pub hits: u64,
+        pub purges: u64,
+        pub purgeable_count: u32,
+        pub speculative_count: u32,
+        pub decompressions: u64,
+        pub compressions: u64,
+        pub swapins: u64,
+        pub swapouts: u64,
+        pub compressor_page_count: u32,
+        pub throttled_count: u32,
+        pub external_page_count: u32,
+        pub internal_page_count: u32,
+        pub total_uncompressed_pages_in_compressor: u64,
+    }
+
+    extern "C" {
+        fn mach_host_self() -> u32;
+        fn host_statistics64(host: u32, flavor: i32, info: *mut i32, count: *mut u32) -> i32;
+    }
+
+    pub fn vm_statistics() -> Result<VmStatistics64> {
+        let mut stats = VmStatistics64::default();
+        let mut count = (std::mem::size_of::<VmStatistics64>() / std::mem::size_of::<i32>()) as u32;
+
+        let kr = unsafe {
+            host_statistics64(
+                mach_host_self(),
+                HOST_VM_INFO64,
+                &mut stats as *mut _ as *mut i32,
+                &mut count,
+            )
+        };
+        if kr != KERN_SUCCESS {
+            return Err(anyhow::anyhow!("host_statistics64 failed with {}", kr));
+        }
+
+        Ok(stats)
+    }
+
+    pub fn page_size() -> u64 {
+        unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(0) as u64
+    }
+
+    pub fn sysctl_int(name: &str) -> Result<i32> {
+        let name = std::ffi::CString::new(name)?;
+        let mut value: i32 = 0;
+        let mut size = std::mem::size_of::<i32>();
+        let rc = unsafe {
+            libc::sysctlbyname(
+                name.as_ptr(),
+                &mut value as *mut i32 as *mut libc::c_void,
+                &mut size,
+                std::ptr::null_mut(),
+                0,
+            )
+        };
+        if rc != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_rates_since() {
+        let previous = KernelCounters {
+            at: Instant::now(),
+            paging: 100,
+            swap_ins: 10,
+            swap_outs: 20,
+            compressions: 500,
+            compressed_bytes: 4096,
+            context_switches: 1_000,
+            interrupts: 0,
+        };
+        let current = KernelCounters {
+            at: previous.at + Duration::from_secs(2),
+            paging: 300,
+            swap_ins: 30,
+            swap_outs: 20,
+            compressions: 700,
+            compressed_bytes: 8192,
+            context_switches: 900, // a busy process exited
+            interrupts: 0,
+        };
+
+        let rates = current.rates_since(&previous);
+        assert_eq!(rates.io_wait, 100.0);
+        assert_eq!(rates.swap_ins, 10.0);
+        assert_eq!(rates.swap_outs, 0.0);
+        assert_eq!(rates.compressions, 100.0);
+        assert_eq!(rates.context_switches, 0);
+        assert_eq!(current.rates_since(&current), KernelRates::default());
+    }
+}
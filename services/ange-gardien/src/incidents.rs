@@ -0,0 +1,239 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Serialize, Deserialize};
+use std::collections::HashSet;
+use std::net::IpAddr;
+use crate::{SecurityAlert, AlertSeverity};
+
+/// Related alerts raised close together, reviewed as one event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Incident {
+    pub id: String,
+    /// Oldest first
+    pub alerts: Vec<SecurityAlert>,
+    /// The highest severity among the alerts
+    pub severity: AlertSeverity,
+    pub started_at: DateTime<Utc>,
+}
+
+impl Incident {
+    fn new(id: String, alert: SecurityAlert) -> Self {
+        Self {
+            id,
+            severity: alert.severity,
+            started_at: alert.timestamp,
+            alerts: vec![alert],
+        }
+    }
+
+    fn push(&mut self, alert: SecurityAlert) {
+        self.severity = self.severity.max(alert.severity);
+        self.alerts.push(alert);
+    }
+}
+
+/// Processes and hosts alerts are about, as set by whatever raised them.
+/// Pids or addresses that only appear in the text, such as the busiest
+/// process named in an anomaly's recommendation, don't count.
+#[derive(Debug, Default)]
+struct Subjects {
+    pids: HashSet<u32>,
+    ips: HashSet<IpAddr>,
+}
+
+impl Subjects {
+    fn of(alert: &SecurityAlert) -> Self {
+        Self {
+            pids: alert.pid.into_iter().collect(),
+            ips: alert.remote_ip.into_iter().collect(),
+        }
+    }
+
+    fn shares(&self, other: &Subjects) -> bool {
+        !self.pids.is_disjoint(&other.pids) || !self.ips.is_disjoint(&other.ips)
+    }
+
+    /// Both name processes, or both name hosts, and none of them match
+    fn conflicts(&self, other: &Subjects) -> bool {
+        let pids_differ = !self.pids.is_empty() && !other.pids.is_empty() && self.pids.is_disjoint(&other.pids);
+        let ips_differ = !self.ips.is_empty() && !other.ips.is_empty() && self.ips.is_disjoint(&other.ips);
+        pids_differ || ips_differ
+    }
+
+    fn extend(&mut self, other: Subjects) {
+        self.pids.extend(other.pids);
+        self.ips.extend(other.ips);
+    }
+}
+
+struct OpenIncident {
+    incident: Incident,
+    subjects: Subjects,
+    last_alert_at: DateTime<Utc>,
+}
+
+/// Groups alerts into incidents. An alert joins the latest open incident that
+/// shares a pid or remote IP with it, or otherwise one it doesn't contradict,
+/// as long as the incident's last alert is within the window. So a process
+/// from /tmp, a connection to an unknown host and a CPU spike within seconds
+/// become one incident, while two unrelated processes misbehaving stay apart.
+pub struct IncidentCorrelator {
+    window: Duration,
+    open: Vec<OpenIncident>,
+    /// Disambiguates incidents started in the same millisecond
+    sequence: u64,
+}
+
+impl IncidentCorrelator {
+    pub fn new(window: std::time::Duration) -> Self {
+        Self {
+            window: Duration::from_std(window).unwrap_or(Duration::seconds(30)),
+            open: Vec::new(),
+            sequence: 0,
+        }
+    }
+
+    /// Files `alerts` into incidents and returns every incident they created
+    /// or extended, for persisting.
+    pub fn correlate(&mut self, alerts: &[SecurityAlert]) -> Vec<Incident> {
+        let Some(latest) = alerts.iter().map(|alert| alert.timestamp).max() else {
+            return Vec::new();
+        };
+        let window = self.window;
+        self.open.retain(|open| latest - open.last_alert_at <= window);
+
+        let mut touched = Vec::new();
+        let mut alerts = alerts.to_vec();
+        alerts.sort_by_key(|alert| alert.timestamp);
+        for alert in alerts {
+            let subjects = Subjects::of(&alert);
+            let index = match self.find(&alert, &subjects) {
+                Some(index) => {
+                    let open = &mut self.open[index];
+                    open.last_alert_at = open.last_alert_at.max(alert.timestamp);
+                    open.subjects.extend(subjects);
+                    open.incident.push(alert);
+                    index
+                }
+                None => {
+                    self.sequence += 1;
+                    let id = format!("{}-{}", alert.timestamp.format("%Y%m%d%H%M%S%3f"), self.sequence);
+                    self.open.push(OpenIncident {
+                        last_alert_at: alert.timestamp,
+                        incident: Incident::new(id, alert),
+                        subjects,
+                    });
+                    self.open.len() - 1
+                }
+            };
+            if !touched.contains(&index) {
+                touched.push(index);
+            }
+        }
+
+        touched.into_iter().map(|index| self.open[index].incident.clone()).collect()
+    }
+
+    fn find(&self, alert: &SecurityAlert, subjects: &Subjects) -> Option<usize> {
+        let candidates = || self.open.iter().enumerate().rev()
+            .filter(move |(_, open)| (alert.timestamp - open.last_alert_at).abs() <= self.window);
+        candidates()
+            .find(|(_, open)| open.subjects.shares(subjects))
+            .or_else(|| candidates().find(|(_, open)| !open.subjects.conflicts(subjects)))
+            .map(|(index, _)| index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alert(seconds: i64, severity: AlertSeverity, description: &str) -> SecurityAlert {
+        SecurityAlert {
+            timestamp: DateTime::from_timestamp(1_700_000_000 + seconds, 0).unwrap(),
+            severity,
+            description: description.to_string(),
+            source: "test".to_string(),
+            recommendation: None,
+            count: 1,
+            would_enforce: false,
+            rule: None,
+            pid: None,
+            remote_ip: None,
+        }
+    }
+
+    #[test]
+    fn test_related_alerts_form_one_incident() {
+        let mut correlator = IncidentCorrelator::new(std::time::Duration::from_secs(30));
+
+        let first = correlator.correlate(&[
+            SecurityAlert {
+                pid: Some(4242),
+                ..alert(0, AlertSeverity::Medium, "Process dropper (PID: 4242) is running from /tmp/dropper")
+            },
+            SecurityAlert {
+                remote_ip: Some("203.0.113.9".parse().unwrap()),
+                ..alert(5, AlertSeverity::High, "Unauthorized network connection to port 4444 (203.0.113.9:4444)")
+            },
+        ]);
+        assert_eq!(first.len(), 1);
+
+        // Unrelated process, but the CPU spike fits the open incident
+        let second = correlator.correlate(&[
+            alert(12, AlertSeverity::Low, "CPU usage too high: 97.0% (max: 90.0%)"),
+            SecurityAlert {
+                pid: Some(77),
+                ..alert(15, AlertSeverity::Medium, "Process backupd (PID: 77) memory usage too high")
+            },
+        ]);
+        assert_eq!(second.len(), 2);
+        let incident = second.iter().find(|i| i.id == first[0].id).unwrap();
+        assert_eq!(incident.alerts.len(), 3);
+        assert_eq!(incident.severity, AlertSeverity::High);
+        assert_eq!(incident.started_at, first[0].started_at);
+
+        // A quiet spell closes the incident, even for the same pid
+        let later = correlator.correlate(&[SecurityAlert {
+            pid: Some(4242),
+            ..alert(120, AlertSeverity::Low, "Suspicious process detected: dropper (PID: 4242)")
+        }]);
+        assert_eq!(later.len(), 1);
+        assert_ne!(later[0].id, first[0].id);
+    }
+
+    #[test]
+    fn test_subjects_from_alert_fields() {
+        let subjects = Subjects::of(&SecurityAlert {
+            recommendation: Some("Investigate PID 12; consider `kill -9 12`".to_string()),
+            pid: Some(12),
+            remote_ip: Some("2001:db8::1".parse().unwrap()),
+            ..alert(0, AlertSeverity::Low, "Connection to 2001:db8::1 outside the allowed networks ([2001:db8::1]:443)")
+        });
+        assert_eq!(subjects.pids, HashSet::from([12]));
+        assert_eq!(subjects.ips, HashSet::from(["2001:db8::1".parse::<IpAddr>().unwrap()]));
+    }
+
+    #[test]
+    fn test_pids_in_text_dont_correlate() {
+        let mut correlator = IncidentCorrelator::new(std::time::Duration::from_secs(30));
+        let incidents = correlator.correlate(&[
+            SecurityAlert {
+                pid: Some(77),
+                ..alert(0, AlertSeverity::Medium, "Process backupd (PID: 77) memory usage too high")
+            },
+            SecurityAlert {
+                pid: Some(4242),
+                ..alert(5, AlertSeverity::High, "Process dropper (PID: 4242) is running from /tmp/dropper")
+            },
+            // Names the busiest process, which is no reason to tie it to backupd
+            SecurityAlert {
+                recommendation: Some("Busiest process: backupd (PID 77)".to_string()),
+                ..alert(10, AlertSeverity::Medium, "Anomalous system behavior detected")
+            },
+        ]);
+
+        assert_eq!(incidents.len(), 2);
+        let dropper = incidents.iter().find(|i| i.alerts[0].pid == Some(4242)).unwrap();
+        assert_eq!(dropper.alerts.len(), 2);
+    }
+}
@@ -1,45 +1,129 @@
-use std::{sync::Arc, time::Duration};
-use tokio::sync::RwLock;
+use std::{collections::HashMap, net::IpAddr, path::Path, sync::Arc, time::{Duration, Instant}};
+use tokio::sync::{Mutex, RwLock};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
 use anyhow::Result;
 use serde::{Serialize, Deserialize};
 use chrono::{DateTime, Utc};
-use log::{info, warn, error};
+use tracing::{field, info, info_span, warn, error, Span};
 
+mod builder;
 mod monitor;
+mod alerting;
 mod database;
+mod store;
+#[cfg(feature = "postgres")]
+mod postgres;
 mod network;
+mod dns;
+mod geoip;
+mod procinfo;
+mod host_stats;
 mod analysis;
+mod features;
+mod incidents;
 mod security;
+mod codesign;
+mod exec_control;
+#[cfg(feature = "python")]
 mod python;
+mod ensemble;
 mod time;
+mod container;
+mod bundle;
+mod export;
+mod extensions;
+mod telemetry;
+mod config;
+mod metrics;
+#[cfg(feature = "api")]
+mod api;
 
-pub use analysis::AnomalyDetector;
-pub use database::Database;
-pub use monitor::SystemMonitor;
-pub use network::{NetworkMonitor, NetworkStats, ConnectionInfo};
-pub use python::PythonRuntime;
-pub use security::SecurityManager;
+pub use config::{Config, ApiConfig};
+pub use builder::AngeGardienBuilder;
+pub use alerting::{
+    AlertSink, AlertDispatcher, AlertingConfig, SyslogConfig, SyslogTarget, SyslogSink,
+    WebhookConfig, WebhookSink,
+    RateLimitedSink, RateLimitConfig,
+};
+pub use analysis::{Analyzer, AnalysisRule, AnomalyDetector, DetectorSnapshot, ClusterSummary, AnomalyScore, FeatureScaler, BaselineModel, TrainingReport};
+pub use features::{FEATURE_COUNT, FEATURE_NAMES};
+pub use incidents::{Incident, IncidentCorrelator};
+pub use codesign::SigningInfo;
+pub use exec_control::{ExecPolicy, ExecControlMode, ExecDecision};
+pub use container::{ContainerMode, Environment, detect_environment};
+pub use bundle::{DiagnosticBundle, RedactionOptions};
+pub use extensions::{LoadedExtension, ExtensionKind};
+pub use database::{Database, Metric, Distribution, SystemStatistics, SeverityCounts, CleanupReport, UsageSample};
+pub use store::{StateStore, InMemoryStore};
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresStore;
+pub use monitor::{SystemMonitor, ProcessHistory, DiskUsage, ThreadInfo};
+pub use host_stats::MemoryPressure;
+pub use network::{NetworkMonitor, NetworkStats, ConnectionInfo, FanOutConfig, BeaconConfig, CaptureMode, IcmpConfig, IcmpCount};
+pub use geoip::GeoIpConfig;
+#[cfg(feature = "geoip")]
+pub use geoip::{GeoIp, GeoInfo};
+#[cfg(feature = "python")]
+pub use python::PythonAnalyzer;
+#[cfg(feature = "python")]
+pub use ensemble::EnsembleDetector;
+pub use ensemble::{EnsembleMode, EnsembleVote};
+pub use security::{SecurityManager, SecurityPolicies, ProcessLimits, ProcessMatchMode, PortRange, EnforcementMode, LivenessReport, PolicyViolation, DEFAULT_SERVICE_USER, create_service_user, file_hash};
 pub use time::{TimeStamp, utils as time_utils};
+pub use telemetry::{init_logging, LogFormat};
+#[cfg(feature = "otel")]
+pub use telemetry::shutdown_otel;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use telemetry::{timed, CycleTimings};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct SystemState {
     pub timestamp: DateTime<Utc>,
     pub cpu_usage: f32,
+    /// Usage of each logical core, so one pegged core shows even when the
+    /// average looks fine
+    #[serde(default)]
+    pub per_core_cpu: Vec<f32>,
     pub memory_usage: f32,
+    /// Average over `disks`, kept for consumers of the single figure
     pub disk_usage: f32,
+    #[serde(default)]
+    pub disks: Vec<DiskUsage>,
     pub network_stats: NetworkStats,
     pub active_processes: Vec<ProcessInfo>,
     pub security_alerts: Vec<SecurityAlert>,
     pub system_metrics: Option<SystemMetrics>,
+    /// Time the collection phases of the monitoring cycle took: gathering
+    /// metrics, connections and processes, and analysing them. Storing the
+    /// state, policy checks, alert delivery and incident filing come after
+    /// and aren't counted; the `monitor_cycle` span covers the whole cycle.
+    #[serde(default)]
+    pub collection_duration_ms: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProcessInfo {
     pub pid: u32,
+    /// Parent pid; 0 when the process has no parent
+    #[serde(default)]
+    pub ppid: u32,
     pub name: String,
     pub cpu_usage: f32,
+    /// Resident memory as a percentage (0-100) of total physical memory
     pub memory_usage: f32,
     pub threads: u32,
+    #[serde(default)]
+    pub start_time: DateTime<Utc>,
+    /// Full command line, arguments joined by spaces
+    #[serde(default)]
+    pub command: String,
+    /// Cumulative bytes read from disk, from rusage; 0 when rusage is unavailable
+    #[serde(default)]
+    pub disk_bytes_read: u64,
+    /// Cumulative bytes written to disk, from rusage; 0 when rusage is unavailable
+    #[serde(default)]
+    pub disk_bytes_written: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -49,9 +133,41 @@ pub struct SecurityAlert {
     pub description: String,
     pub source: String,
     pub recommendation: Option<String>,
+    /// Occurrences folded into this alert by deduplication
+    #[serde(default = "default_alert_count")]
+    pub count: u32,
+    /// Raised in monitor mode for an active response that was held back
+    #[serde(default)]
+    pub would_enforce: bool,
+    /// Identifies the condition behind the alert (e.g. `cpu`, `process_cpu:1234`)
+    /// when the description carries readings that change between checks.
+    /// Deduplication matches on it instead of the description.
+    #[serde(default)]
+    pub rule: Option<String>,
+    /// The process the alert is about, for correlating it with others
+    #[serde(default)]
+    pub pid: Option<u32>,
+    /// The remote host the alert is about, for correlating it with others
+    #[serde(default)]
+    pub remote_ip: Option<IpAddr>,
+}
+
+fn default_alert_count() -> u32 {
+    1
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+impl std::fmt::Display for SecurityAlert {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.count > 1 {
+            write!(f, "{} (x{})", self.description, self.count)
+        } else {
+            write!(f, "{}", self.description)
+        }
+    }
+}
+
+/// Ordered by urgency, so `severity >= AlertSeverity::High` selects High and Critical.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum AlertSeverity {
     Low,
     Medium,
@@ -59,12 +175,74 @@ pub enum AlertSeverity {
     Critical,
 }
 
+impl AlertSeverity {
+    pub const ALL: [AlertSeverity; 4] = [
+        AlertSeverity::Low,
+        AlertSeverity::Medium,
+        AlertSeverity::High,
+        AlertSeverity::Critical,
+    ];
+
+    /// This severity and every one above it.
+    pub fn and_above(self) -> impl Iterator<Item = AlertSeverity> {
+        Self::ALL.into_iter().filter(move |severity| *severity >= self)
+    }
+}
+
+impl std::fmt::Display for AlertSeverity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            AlertSeverity::Low => "Low",
+            AlertSeverity::Medium => "Medium",
+            AlertSeverity::High => "High",
+            AlertSeverity::Critical => "Critical",
+        })
+    }
+}
+
+impl std::str::FromStr for AlertSeverity {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::ALL.into_iter()
+            .find(|severity| severity.to_string().eq_ignore_ascii_case(s.trim()))
+            .ok_or_else(|| anyhow::anyhow!("Unknown alert severity: {}", s))
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SystemMetrics {
+    pub cpu_count: usize,
+    pub physical_cpu_count: usize,
+    /// When the process list was last refreshed
+    pub last_update: DateTime<Utc>,
+    /// Seconds since boot
+    pub uptime: u64,
+    /// When the machine last booted; a change between states means it restarted
+    #[serde(default)]
+    pub boot_time: Option<DateTime<Utc>>,
     pub load_average: f64,
+    /// Darwin has no iowait CPU state; on macOS this is pages moved to or from
+    /// backing store per second, the kernel's signal for disk thrashing
     pub io_wait: f64,
+    /// Per second, across all visible processes
     pub context_switches: u64,
+    /// Per second; macOS exposes no host-wide counter, so this is 0 there
     pub interrupts: u64,
+    /// Pages swapped in from disk per second
+    #[serde(default)]
+    pub swap_ins: f64,
+    /// Pages swapped out to disk per second
+    #[serde(default)]
+    pub swap_outs: f64,
+    /// Pages compressed per second by the memory compressor
+    #[serde(default)]
+    pub compressions: f64,
+    /// Memory currently held by the compressor
+    #[serde(default)]
+    pub compressed_bytes: u64,
+    #[serde(default)]
+    pub memory_pressure: MemoryPressure,
 }
 
 impl Default for NetworkStats {
@@ -74,6 +252,12 @@ impl Default for NetworkStats {
             bytes_received: 0,
             connections: Vec::new(),
             suspicious_activity: Vec::new(),
+            bytes_sent_per_sec: 0.0,
+            bytes_received_per_sec: 0.0,
+            dns_cache_hits: 0,
+            dns_cache_misses: 0,
+            icmp_packets: 0,
+            icmp_types: Vec::new(),
         }
     }
 }
@@ -81,146 +265,660 @@ impl Default for NetworkStats {
 impl Default for SystemMetrics {
     fn default() -> Self {
         Self {
+            cpu_count: 0,
+            physical_cpu_count: 0,
+            last_update: Utc::now(),
+            uptime: 0,
+            boot_time: None,
             load_average: 0.0,
             io_wait: 0.0,
             context_switches: 0,
             interrupts: 0,
+            swap_ins: 0.0,
+            swap_outs: 0.0,
+            compressions: 0.0,
+            compressed_bytes: 0,
+            memory_pressure: MemoryPressure::Normal,
         }
     }
 }
 
 pub struct AngeGardien {
     state: Arc<RwLock<SystemState>>,
-    db: Arc<database::Database>,
+    db: Arc<dyn StateStore>,
+    monitor: Arc<monitor::SystemMonitor>,
+    /// None when built with `disable_network`
+    network_monitor: Option<Arc<network::NetworkMonitor>>,
+    analyzer: Arc<analysis::Analyzer>,
+    security: Arc<security::SecurityManager>,
+    alert_dispatcher: Arc<alerting::AlertDispatcher>,
+    incidents: Arc<Mutex<incidents::IncidentCorrelator>>,
+    config: Config,
+    redaction: RedactionOptions,
+    poll_interval: Arc<RwLock<Duration>>,
+    shutdown: CancellationToken,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+/// What the monitoring cycle reads and updates, cloned out of `AngeGardien`
+/// for the polling task.
+struct CycleHandles {
+    state: Arc<RwLock<SystemState>>,
+    db: Arc<dyn StateStore>,
     monitor: Arc<monitor::SystemMonitor>,
-    network_monitor: Arc<network::NetworkMonitor>,
+    network_monitor: Option<Arc<network::NetworkMonitor>>,
     analyzer: Arc<analysis::Analyzer>,
     security: Arc<security::SecurityManager>,
+    alert_dispatcher: Arc<alerting::AlertDispatcher>,
+    alert_config: alerting::AlertingConfig,
+    incidents: Arc<Mutex<incidents::IncidentCorrelator>>,
 }
 
 impl AngeGardien {
-    pub async fn new() -> Result<Self> {
-        let db = Arc::new(database::Database::new()?);
-        let monitor = Arc::new(monitor::SystemMonitor::new());
-        let network_monitor = Arc::new(network::NetworkMonitor::new()?);
-        let analyzer = Arc::new(analysis::Analyzer::new());
-        let security = Arc::new(security::SecurityManager::new());
-
-        let initial_state = SystemState {
-            timestamp: Utc::now(),
-            cpu_usage: 0.0,
-            memory_usage: 0.0,
-            disk_usage: 0.0,
-            network_stats: NetworkStats {
-                bytes_sent: 0,
-                bytes_received: 0,
-                connections: Vec::new(),
-                suspicious_activity: Vec::new(),
-            },
-            active_processes: Vec::new(),
-            security_alerts: Vec::new(),
-            system_metrics: None,
+    pub async fn new(config: Option<Config>) -> Result<Self> {
+        AngeGardienBuilder::new().with_config(config.unwrap_or_default()).build().await
+    }
+
+    /// Like `new`, but persists to `db` instead of the store named in the
+    /// config, e.g. an `InMemoryStore` in tests.
+    pub async fn with_store(config: Config, db: Arc<dyn StateStore>) -> Result<Self> {
+        AngeGardienBuilder::new().with_config(config).with_store(db).build().await
+    }
+
+    /// Fits the anomaly detectors on the last `days` of stored states, so
+    /// detection starts warm on a new machine. The DBSCAN detector is saved to
+    /// `anomaly_model_path`; with the `python` feature the IsolationForest is
+    /// fitted on the same states and saves itself.
+    pub async fn train(config: &Config, days: i64) -> Result<analysis::TrainingReport> {
+        let db = Self::open_store(config)?;
+        let states = db.get_system_states_since(Utc::now() - chrono::Duration::days(days)).await?;
+        if states.len() < 10 {
+            return Err(anyhow::anyhow!(
+                "Only {} states recorded in the last {} days; at least 10 are needed to train",
+                states.len(),
+                days
+            ));
+        }
+        let allowed_ports = config.security_policies()?.allowed_ports().to_vec();
+
+        let mut detector = analysis::AnomalyDetector::new();
+        detector.set_allowed_ports(&allowed_ports);
+        for state in db.get_anomaly_feedback().await? {
+            detector.record_false_positive(state);
+        }
+        detector.fit(&states);
+        match &config.anomaly_model_path {
+            Some(path) => detector.save_model(path)?,
+            None => warn!("anomaly_model_path is unset, so the trained DBSCAN model will not be saved"),
+        }
+
+        #[cfg(feature = "python")]
+        let isolation_forest = {
+            python::PythonAnalyzer::new()?.train_model(&states, &allowed_ports).await?;
+            true
         };
+        #[cfg(not(feature = "python"))]
+        let isolation_forest = false;
 
-        Ok(Self {
-            state: Arc::new(RwLock::new(initial_state)),
-            db,
-            monitor,
-            network_monitor,
-            analyzer,
-            security,
+        Ok(analysis::TrainingReport {
+            states: states.len(),
+            dbscan: detector.snapshot(),
+            isolation_forest,
         })
     }
 
+    fn open_store(config: &Config) -> Result<Arc<dyn StateStore>> {
+        if let Some(url) = &config.database_url {
+            #[cfg(feature = "postgres")]
+            return Ok(Arc::new(postgres::PostgresStore::new(url)?));
+            #[cfg(not(feature = "postgres"))]
+            return Err(anyhow::anyhow!(
+                "database_url {} needs the postgres feature, which this build lacks",
+                url
+            ));
+        }
+
+        let database = match &config.database_path {
+            Some(path) => database::Database::with_path(path)?,
+            None => database::Database::new()?,
+        };
+        Ok(Arc::new(database.with_write_batch(config.write_batch_size, config.write_flush_interval())))
+    }
+
+    pub async fn poll_interval(&self) -> Duration {
+        *self.poll_interval.read().await
+    }
+
+    /// Changes the collection cadence; the running loop picks it up on its next tick.
+    pub async fn set_poll_interval(&self, interval: Duration) {
+        *self.poll_interval.write().await = interval;
+    }
+
+    pub fn set_redaction(&mut self, redaction: RedactionOptions) {
+        self.redaction = redaction;
+    }
+
+    /// Re-reads the config file at `config_path` and applies its security
+    /// policies (from `security_policy_file` if set, otherwise the inline
+    /// table). On any error the running policies are left untouched.
+    pub fn reload_policies<P: AsRef<Path>>(&self, config_path: P) -> Result<()> {
+        let config = Config::from_path(config_path)?;
+        match &config.security_policy_file {
+            Some(path) => self.security.reload_policies(path),
+            None => self.security.set_policies(config.security_policies()?),
+        }
+    }
+
+    /// Registers an extra destination for new alerts, in addition to the configured ones.
+    pub async fn add_alert_sink(&self, sink: Arc<dyn AlertSink>) {
+        self.alert_dispatcher.add_sink(sink).await;
+    }
+
+    /// Adds a detection rule, run on every state from the next cycle on.
+    pub fn register_rule(&self, rule: Box<dyn AnalysisRule>) {
+        self.analyzer.register_rule(rule);
+    }
+
     pub async fn start(&self) -> Result<()> {
         info!("Starting Ange Gardien monitoring service...");
         
-        let state = Arc::clone(&self.state);
-        let db = Arc::clone(&self.db);
-        let monitor = Arc::clone(&self.monitor);
-        let network_monitor = Arc::clone(&self.network_monitor);
-        let analyzer = Arc::clone(&self.analyzer);
-        let security = Arc::clone(&self.security);
+        let handles = CycleHandles {
+            state: Arc::clone(&self.state),
+            db: Arc::clone(&self.db),
+            monitor: Arc::clone(&self.monitor),
+            network_monitor: self.network_monitor.clone(),
+            analyzer: Arc::clone(&self.analyzer),
+            security: Arc::clone(&self.security),
+            alert_dispatcher: Arc::clone(&self.alert_dispatcher),
+            alert_config: self.config.alerting.clone(),
+            incidents: Arc::clone(&self.incidents),
+        };
+        let poll_interval = Arc::clone(&self.poll_interval);
+
+        // Capture needs BPF access, so open it before dropping privileges.
+        // Without it the connection checks see nothing, but the rest of the
+        // monitoring still runs
+        if let Some(network_monitor) = &self.network_monitor {
+            if let Err(e) = network_monitor.start_monitoring().await {
+                warn!("Network monitoring degraded, no connections will be tracked: {}", e);
+            }
+        }
 
         // Drop privileges after initialization
-        if let Err(e) = security::drop_privileges() {
+        if self.config.enforcement_mode == security::EnforcementMode::Monitor {
+            warn!("Monitor mode: no active responses will fire; keeping current privileges");
+        } else if let Err(e) = security::drop_privileges(&self.config.service_user) {
             error!("Failed to drop privileges: {}", e);
-            return Err(anyhow::anyhow!("Failed to drop privileges"));
+            return Err(anyhow::anyhow!("Failed to drop privileges: {}", e));
         }
 
-        tokio::spawn(async move {
+        let mut tasks = self.tasks.lock().await;
+
+        // Extensions change rarely, so audit them on their own slower schedule
+        let extension_state = Arc::clone(&self.state);
+        let extension_security = Arc::clone(&self.security);
+        let extension_dispatcher = Arc::clone(&self.alert_dispatcher);
+        let extension_alert_config = self.config.alerting.clone();
+        let extension_incidents = Arc::clone(&self.incidents);
+        let extension_db = Arc::clone(&self.db);
+        let extension_shutdown = self.shutdown.clone();
+        tasks.push(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(extension_security.extension_check_interval());
             loop {
-                if let Err(e) = Self::update_system_state(
-                    &state,
-                    &db,
-                    &monitor,
-                    &network_monitor,
-                    &analyzer,
-                    &security,
-                ).await {
-                    error!("Error updating system state: {}", e);
+                tokio::select! {
+                    _ = extension_shutdown.cancelled() => break,
+                    _ = interval.tick() => {}
+                }
+                match extension_security.check_extensions().await {
+                    Ok(alerts) => {
+                        let fresh = extension_alert_config.record(
+                            &mut extension_state.write().await.security_alerts,
+                            alerts,
+                        );
+                        extension_dispatcher.dispatch(&fresh).await;
+                        if let Err(e) = Self::file_incidents(&extension_incidents, &extension_db, &fresh).await {
+                            error!("Error storing incidents: {}", e);
+                        }
+                    }
+                    Err(e) => error!("Error auditing extensions: {}", e),
                 }
-                tokio::time::sleep(Duration::from_secs(1)).await;
             }
-        });
+        }));
+
+        // Rate-limited sinks only report what they suppressed when flushed, so
+        // summaries go out even after an alert storm stops
+        let summary_dispatcher = Arc::clone(&self.alert_dispatcher);
+        let summary_interval = self.config.alerting.summary_interval();
+        let summary_shutdown = self.shutdown.clone();
+        tasks.push(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(summary_interval);
+            loop {
+                tokio::select! {
+                    _ = summary_shutdown.cancelled() => break,
+                    _ = interval.tick() => {}
+                }
+                summary_dispatcher.flush().await;
+            }
+        }));
+
+        // Refit the hour-of-day baseline as history accumulates
+        let baseline_db = Arc::clone(&self.db);
+        let baseline_analyzer = Arc::clone(&self.analyzer);
+        let baseline_shutdown = self.shutdown.clone();
+        tasks.push(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(3600));
+            // The first tick fires immediately and `new` has just fitted it
+            interval.tick().await;
+            loop {
+                tokio::select! {
+                    _ = baseline_shutdown.cancelled() => break,
+                    _ = interval.tick() => {}
+                }
+                if let Err(e) = baseline_analyzer.update_baseline(&*baseline_db, analysis::BASELINE_DAYS).await {
+                    error!("Error updating hourly baseline: {}", e);
+                }
+            }
+        }));
+
+        // Down-sample old states and enforce the retention window so the
+        // store doesn't grow without bound
+        let cleanup_db = Arc::clone(&self.db);
+        let retention = self.config.retention();
+        let rollup_after = self.config.rollup_after();
+        let cleanup_interval = self.config.cleanup_interval();
+        let cleanup_shutdown = self.shutdown.clone();
+        tasks.push(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(cleanup_interval);
+            loop {
+                tokio::select! {
+                    _ = cleanup_shutdown.cancelled() => break,
+                    _ = interval.tick() => {}
+                }
+                match cleanup_db.rollup_old_states(Utc::now() - rollup_after).await {
+                    Ok(rolled_up) if rolled_up > 0 => {
+                        info!("Rolled {} states up into one-minute buckets", rolled_up)
+                    }
+                    Ok(_) => {}
+                    Err(e) => error!("Error rolling up old states: {}", e),
+                }
+                match cleanup_db.cleanup_old_records(Utc::now() - retention).await {
+                    Ok(report) => info!(
+                        "Retention cleanup deleted {} states and {} alerts, reclaimed {} bytes",
+                        report.states_deleted,
+                        report.alerts_deleted,
+                        report.bytes_reclaimed
+                    ),
+                    Err(e) => error!("Error cleaning up old records: {}", e),
+                }
+            }
+        }));
+
+        // Exec allowlisting is opt-in; a failure to start it must not stop monitoring
+        let exec_policy = self.security.exec_policy();
+        if exec_policy.enabled {
+            match exec_control::ExecGuard::start(exec_policy, self.config.enforcement_mode) {
+                Ok((guard, mut exec_alerts)) => {
+                    let state = Arc::clone(&self.state);
+                    let exec_dispatcher = Arc::clone(&self.alert_dispatcher);
+                    let exec_alert_config = self.config.alerting.clone();
+                    let exec_incidents = Arc::clone(&self.incidents);
+                    let exec_db = Arc::clone(&self.db);
+                    let exec_shutdown = self.shutdown.clone();
+                    tasks.push(tokio::spawn(async move {
+                        // Keep the EndpointSecurity client alive for as long as alerts flow
+                        let _guard = guard;
+                        loop {
+                            let alert = tokio::select! {
+                                _ = exec_shutdown.cancelled() => break,
+                                alert = exec_alerts.recv() => match alert {
+                                    Some(alert) => alert,
+                                    None => break,
+                                },
+                            };
+                            let fresh = exec_alert_config.record(
+                                &mut state.write().await.security_alerts,
+                                vec![alert],
+                            );
+                            exec_dispatcher.dispatch(&fresh).await;
+                            if let Err(e) = Self::file_incidents(&exec_incidents, &exec_db, &fresh).await {
+                                error!("Error storing incidents: {}", e);
+                            }
+                        }
+                    }));
+                }
+                Err(e) => error!("Failed to start exec control: {}", e),
+            }
+        }
+
+        #[cfg(feature = "api")]
+        if self.config.api.enabled {
+            let api_state = api::ApiState {
+                state: Arc::clone(&self.state),
+                db: Arc::clone(&self.db),
+                analyzer: Arc::clone(&self.analyzer),
+                monitor: Arc::clone(&self.monitor),
+            };
+            let bind = self.config.api.bind;
+            let api_shutdown = self.shutdown.clone();
+            tasks.push(tokio::spawn(async move {
+                if let Err(e) = api::serve(api_state, bind, api_shutdown).await {
+                    error!("HTTP API stopped: {}", e);
+                }
+            }));
+        }
+
+        let shutdown = self.shutdown.clone();
+        tasks.push(tokio::spawn(async move {
+            loop {
+                let result = Self::update_system_state(&handles).await;
+                let interval = *poll_interval.read().await;
+                match result {
+                    Ok(timings) if timings.total > interval => {
+                        let (phase, elapsed) = timings.slowest();
+                        warn!(
+                            "Monitoring cycle took {}ms, over the {}ms poll interval; slowest phase was {} at {}ms",
+                            timings.total.as_millis(),
+                            interval.as_millis(),
+                            phase,
+                            elapsed.as_millis()
+                        );
+                    }
+                    Ok(_) => {}
+                    Err(e) => error!("Error updating system state: {}", e),
+                }
+                if shutdown.is_cancelled() {
+                    break;
+                }
+                tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    _ = tokio::time::sleep(interval) => {}
+                }
+            }
+        }));
 
         Ok(())
     }
 
-    async fn update_system_state(
-        state: &Arc<RwLock<SystemState>>,
-        db: &Arc<database::Database>,
-        monitor: &Arc<monitor::SystemMonitor>,
-        network_monitor: &Arc<network::NetworkMonitor>,
-        analyzer: &Arc<analysis::Analyzer>,
-        security: &Arc<security::SecurityManager>,
-    ) -> Result<()> {
+    /// Stops the background tasks, waits for them to exit and stores the final state.
+    pub async fn stop(&self) -> Result<()> {
+        info!("Stopping Ange Gardien monitoring service...");
+        self.shutdown.cancel();
+        if let Some(network_monitor) = &self.network_monitor {
+            network_monitor.stop_monitoring();
+        }
+
+        let tasks: Vec<JoinHandle<()>> = self.tasks.lock().await.drain(..).collect();
+        for task in tasks {
+            if let Err(e) = task.await {
+                error!("Monitoring task failed during shutdown: {}", e);
+            }
+        }
+
+        let final_state = self.state.read().await.clone();
+        self.db.store_state(&final_state).await?;
+        self.db.flush().await?;
+
+        if let Some(path) = &self.config.anomaly_model_path {
+            self.analyzer.save_model(path).await?;
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "monitor_cycle",
+        skip_all,
+        fields(processes = field::Empty, connections = field::Empty, alerts = field::Empty)
+    )]
+    async fn update_system_state(handles: &CycleHandles) -> Result<CycleTimings> {
+        let CycleHandles {
+            state,
+            db,
+            monitor,
+            network_monitor,
+            analyzer,
+            security,
+            alert_dispatcher,
+            alert_config,
+            incidents,
+        } = handles;
+        let cycle = Span::current();
+        let started = Instant::now();
+        let mut timings = CycleTimings::default();
         let mut current_state = state.write().await;
         
         // Update system metrics
         current_state.timestamp = Utc::now();
-        current_state.cpu_usage = monitor.get_cpu_usage().await?;
-        current_state.memory_usage = monitor.get_memory_usage().await?;
-        current_state.disk_usage = monitor.get_disk_usage().await?;
-        
-        // Get detailed system metrics
-        current_state.system_metrics = Some(monitor.get_system_metrics().await?);
+        let (cpu_usage, per_core_cpu, memory_usage, disk_usage, disks, system_metrics) = timed(
+            &mut timings.metrics,
+            info_span!("metrics", duration_ms = field::Empty),
+            async {
+                Ok((
+                    monitor.get_cpu_usage().await?,
+                    monitor.get_per_core_usage().await?,
+                    monitor.get_memory_usage().await?,
+                    monitor.get_disk_usage().await?,
+                    monitor.get_disk_usage_per_mount().await?,
+                    // Get detailed system metrics
+                    monitor.get_system_metrics().await?,
+                ))
+            },
+        ).await?;
+        current_state.cpu_usage = cpu_usage;
+        current_state.per_core_cpu = per_core_cpu;
+        current_state.memory_usage = memory_usage;
+        current_state.disk_usage = disk_usage;
+        current_state.disks = disks;
+        current_state.system_metrics = Some(system_metrics);
         
         // Update network statistics
-        let network_stats = network_monitor.get_stats().await?;
-        current_state.network_stats = network_stats;
+        if let Some(network_monitor) = network_monitor {
+            let network_span = info_span!("network", duration_ms = field::Empty, connections = field::Empty);
+            current_state.network_stats = timed(&mut timings.network, network_span.clone(), network_monitor.get_stats()).await?;
+            network_span.record("connections", current_state.network_stats.connections.len());
+            cycle.record("connections", current_state.network_stats.connections.len());
+            current_state.network_stats.suspicious_activity = network_monitor
+                .check_suspicious_activity(&security.policies())
+                .await?;
+        }
         
         // Update process information using the thread pool
-        current_state.active_processes = monitor.get_process_list().await?;
+        let process_span = info_span!("processes", duration_ms = field::Empty, count = field::Empty);
+        current_state.active_processes = timed(&mut timings.processes, process_span.clone(), monitor.get_process_list()).await?;
+        process_span.record("count", current_state.active_processes.len());
+        cycle.record("processes", current_state.active_processes.len());
         
         // Analyze current state for security threats
-        let alerts = analyzer.analyze_state(&current_state).await?;
-        current_state.security_alerts.extend(alerts);
+        let analysis_span = info_span!("analysis", duration_ms = field::Empty, alerts = field::Empty);
+        analyzer.set_allowed_ports(security.policies().allowed_ports()).await;
+        analyzer.set_server_processes(security.policies().server_processes()).await;
+        if let Some(network_monitor) = network_monitor {
+            // For the rule flagging listeners that appeared since the baseline
+            match network_monitor.get_listening_ports().await {
+                Ok(listeners) => analyzer.set_listeners(listeners).await,
+                Err(e) => warn!("Failed to list listening ports: {}", e),
+            }
+        }
+        let alerts = timed(&mut timings.analysis, analysis_span.clone(), analyzer.analyze_state(&current_state)).await?;
+        analysis_span.record("alerts", alerts.len());
+        let mut new_alerts = alert_config.record(&mut current_state.security_alerts, alerts);
+
+        if let Some(network_monitor) = network_monitor {
+            // Flag processes spraying connections across many ports or hosts
+            let fan_out_alerts = network_monitor.check_fan_out().await;
+            new_alerts.extend(alert_config.record(&mut current_state.security_alerts, fan_out_alerts));
+
+            // Flag endpoints reconnected to on a steady period
+            let beacon_alerts = network_monitor.check_beaconing().await;
+            new_alerts.extend(alert_config.record(&mut current_state.security_alerts, beacon_alerts));
+
+            // Flag ping floods and echo payloads carrying data
+            let icmp_alerts = network_monitor.check_icmp().await;
+            new_alerts.extend(alert_config.record(&mut current_state.security_alerts, icmp_alerts));
+        }
         
-        // Store state in database
-        db.store_state(&current_state).await?;
+        // Store state in database, stamped with the collection phases only since
+        // the rest of the cycle hasn't run yet
+        current_state.collection_duration_ms = started.elapsed().as_millis() as u64;
+        timed(
+            &mut timings.storage,
+            info_span!("storage", duration_ms = field::Empty),
+            db.store_state(&current_state),
+        ).await?;
         
-        // Check security policies
-        if let Some(violation) = security.check_policies(&current_state).await? {
-            warn!("Security policy violation detected: {:?}", violation);
-            current_state.security_alerts.push(SecurityAlert {
-                timestamp: Utc::now(),
-                severity: AlertSeverity::High,
-                description: violation,
-                source: "Security Policy Check".to_string(),
-                recommendation: None,
+        let security_span = info_span!("security", duration_ms = field::Empty, alerts = field::Empty);
+        let (violation, liveness, terminations) = timed(&mut timings.security, security_span.clone(), async {
+            Ok((
+                // Check security policies
+                security.check_policies(&current_state).await?,
+                // Check that expected critical services are still running
+                security.check_service_liveness(&current_state).await?,
+                // Kill suspicious processes if auto-termination is enabled
+                security.enforce_suspicious_processes(&current_state).await,
+            ))
+        }).await?;
+        security_span.record(
+            "alerts",
+            liveness.alerts.len() + terminations.len() + violation.as_ref().map_or(0, |v| v.len()),
+        );
+
+        if let Some(violation) = violation {
+            warn!("Security policy violation detected: {:?}", violation.description());
+            new_alerts.extend(alert_config.record(&mut current_state.security_alerts, violation.alerts()));
+        }
+
+        if !liveness.recovered.is_empty() {
+            let cleared: Vec<String> = liveness.recovered.iter()
+                .map(|service| security::SecurityManager::liveness_description(service))
+                .collect();
+            current_state.security_alerts.retain(|alert| {
+                alert.source != security::SERVICE_LIVENESS_SOURCE || !cleared.contains(&alert.description)
             });
         }
+        new_alerts.extend(alert_config.record(&mut current_state.security_alerts, liveness.alerts));
+        new_alerts.extend(alert_config.record(&mut current_state.security_alerts, terminations));
+        cycle.record("alerts", current_state.security_alerts.len());
+        drop(current_state);
 
+        // Deliver after releasing the state lock so slow sinks don't stall readers
+        alert_dispatcher.dispatch(&new_alerts).await;
+
+        Self::file_incidents(incidents, db, &new_alerts).await?;
+
+        timings.total = started.elapsed();
+        timings.log();
+        Ok(timings)
+    }
+
+    /// Files fresh alerts with related recent ones and stores every incident
+    /// they created or extended.
+    async fn file_incidents(
+        incidents: &Mutex<incidents::IncidentCorrelator>,
+        db: &Arc<dyn StateStore>,
+        alerts: &[SecurityAlert],
+    ) -> Result<()> {
+        let touched = incidents.lock().await.correlate(alerts);
+        for incident in &touched {
+            db.store_incident(incident).await?;
+        }
         Ok(())
     }
 
+    pub async fn get_service_status(&self) -> HashMap<String, bool> {
+        self.security.get_service_status().await
+    }
+
+    /// The last hour of CPU and memory samples for `pid`, if it has been seen.
+    pub async fn get_process_history(&self, pid: u32) -> Option<ProcessHistory> {
+        self.monitor.get_process_history(pid).await
+    }
+
     pub async fn get_current_state(&self) -> Result<SystemState> {
         Ok(self.state.read().await.clone())
     }
 
+    /// Current state in the Prometheus text exposition format.
+    pub async fn render_metrics(&self) -> String {
+        metrics::render(&*self.state.read().await)
+    }
+
+    pub async fn get_detector_snapshot(&self) -> Result<DetectorSnapshot> {
+        Ok(self.analyzer.snapshot().await)
+    }
+
+    pub async fn capture_diagnostic_bundle(&self, path: &Path) -> Result<()> {
+        let state = self.get_current_state().await?;
+        let recent_alerts = self.db.get_alerts_since(Utc::now() - chrono::Duration::hours(24), None).await?;
+        let connections = match &self.network_monitor {
+            Some(network_monitor) => network_monitor.get_active_connections().await?,
+            None => Vec::new(),
+        };
+
+        let bundle = DiagnosticBundle::new(
+            self.config.clone(),
+            state,
+            recent_alerts,
+            connections,
+            self.get_service_status().await,
+        ).redact(&self.redaction);
+
+        bundle.write_to(path)?;
+        info!("Diagnostic bundle written to {}", path.display());
+        Ok(())
+    }
+
+    /// Marks an anomaly alert as a false positive. The state that triggered it is
+    /// stored and folded into the detector's normal set on its next retrain.
+    pub async fn mark_false_positive(&self, alert: &SecurityAlert) -> Result<()> {
+        if alert.source != analysis::ANOMALY_DETECTOR_SOURCE {
+            return Err(anyhow::anyhow!("Only anomaly detector alerts can be marked as false positives"));
+        }
+
+        let state = self.db.get_state_at(alert.timestamp).await?
+            .ok_or_else(|| anyhow::anyhow!("No stored state found for alert at {}", alert.timestamp))?;
+
+        self.db.store_anomaly_feedback(&state).await?;
+        self.analyzer.record_false_positive(state).await;
+        info!("Recorded false positive feedback for anomaly at {}", alert.timestamp);
+        Ok(())
+    }
+
     pub async fn get_alerts(&self, since: DateTime<Utc>) -> Result<Vec<SecurityAlert>> {
-        self.db.get_alerts_since(since).await
+        self.db.get_alerts_since(since, None).await
+    }
+
+    /// Incidents started after `since`, newest first, each grouping alerts
+    /// raised together about the same processes or hosts.
+    pub async fn get_incidents_since(&self, since: DateTime<Utc>) -> Result<Vec<Incident>> {
+        self.db.get_incidents_since(since).await
+    }
+
+    /// Streams states recorded since `since` to `writer` as CSV (timestamp,
+    /// cpu, memory, disk, bytes_sent, bytes_received, process_count). Returns
+    /// the number of rows written.
+    pub async fn export_csv<W: std::io::Write + Send>(&self, writer: W, since: DateTime<Utc>) -> Result<usize> {
+        export::states_csv(self.db.as_ref(), writer, since).await
+    }
+
+    /// Streams states recorded since `since` to `writer` as JSON Lines. Unlike
+    /// the CSV export this keeps connections and per-process detail.
+    pub async fn export_jsonl<W: std::io::Write + Send>(&self, writer: W, since: DateTime<Utc>) -> Result<usize> {
+        export::states_jsonl(self.db.as_ref(), writer, since).await
+    }
+
+    /// Streams alerts raised since `since` to `writer` as CSV.
+    pub async fn export_alerts_csv<W: std::io::Write + Send>(&self, writer: W, since: DateTime<Utc>) -> Result<usize> {
+        export::alerts_csv(self.db.as_ref(), writer, since).await
+    }
+
+    /// Like `get_alerts`, but only alerts at or above `min_severity`; the
+    /// filter runs in the store rather than over loaded rows.
+    pub async fn get_alerts_at_least(
+        &self,
+        since: DateTime<Utc>,
+        min_severity: AlertSeverity,
+    ) -> Result<Vec<SecurityAlert>> {
+        self.db.get_alerts_since(since, Some(min_severity)).await
     }
 }
 
@@ -229,16 +927,201 @@ mod tests {
     use super::*;
     use tokio_test;
 
+    async fn in_memory(config: Config) -> Result<AngeGardien> {
+        AngeGardien::with_store(config, Arc::new(InMemoryStore::new())).await
+    }
+
     #[tokio::test]
     async fn test_ange_gardien_creation() {
-        let guardian = AngeGardien::new().await;
+        let guardian = in_memory(Config::default()).await;
         assert!(guardian.is_ok());
     }
 
     #[tokio::test]
     async fn test_system_state_update() {
-        let guardian = AngeGardien::new().await.unwrap();
+        let guardian = in_memory(Config::default()).await.unwrap();
         let initial_state = guardian.get_current_state().await.unwrap();
         assert_eq!(initial_state.active_processes.len(), 0);
     }
-} 
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_start_and_stop() {
+        let guardian = in_memory(Config::default()).await.unwrap();
+        guardian.start().await.unwrap();
+        guardian.stop().await.unwrap();
+        assert!(guardian.tasks.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_builder_overrides() {
+        let policies: SecurityPolicies = serde_json::from_str(r#"{ "allowed_ports": [22] }"#).unwrap();
+        let guardian = AngeGardienBuilder::new()
+            .with_store(Arc::new(InMemoryStore::new()))
+            .with_policies(policies)
+            .with_poll_interval(Duration::from_millis(500))
+            .disable_network()
+            .disable_python()
+            .build()
+            .await
+            .unwrap();
+
+        assert_eq!(guardian.poll_interval().await, Duration::from_millis(500));
+        assert_eq!(guardian.security.policies().allowed_ports(), &[22]);
+        assert!(guardian.network_monitor.is_none());
+        guardian.start().await.unwrap();
+        guardian.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_custom_rules_reach_the_analyzer() {
+        struct Named(&'static str);
+
+        impl AnalysisRule for Named {
+            fn evaluate(&self, state: &SystemState, _history: &[SystemState]) -> Vec<SecurityAlert> {
+                vec![SecurityAlert {
+                    timestamp: state.timestamp,
+                    severity: AlertSeverity::Low,
+                    description: format!("{} ran", self.0),
+                    source: self.0.to_string(),
+                    recommendation: None,
+                    count: 1,
+                    would_enforce: false,
+                    rule: None,
+                    pid: None,
+                    remote_ip: None,
+                }]
+            }
+        }
+
+        let guardian = AngeGardienBuilder::new()
+            .with_store(Arc::new(InMemoryStore::new()))
+            .with_rule(Box::new(Named("builder rule")))
+            .disable_network()
+            .disable_python()
+            .build()
+            .await
+            .unwrap();
+        guardian.register_rule(Box::new(Named("late rule")));
+
+        let state = guardian.get_current_state().await.unwrap();
+        let alerts = guardian.analyzer.analyze_state(&state).await.unwrap();
+        let sources: Vec<&str> = alerts.iter().map(|a| a.source.as_str()).collect();
+        assert!(sources.contains(&"builder rule"));
+        assert!(sources.contains(&"late rule"));
+    }
+
+    #[tokio::test]
+    async fn test_poll_interval_configurable() {
+        let config = Config {
+            poll_interval_ms: 250,
+            ..Config::default()
+        };
+        let guardian = in_memory(config).await.unwrap();
+        assert_eq!(guardian.poll_interval().await, Duration::from_millis(250));
+
+        guardian.set_poll_interval(Duration::from_millis(100)).await;
+        assert_eq!(guardian.poll_interval().await, Duration::from_millis(100));
+    }
+
+    #[cfg(not(feature = "python"))]
+    #[tokio::test]
+    async fn test_train_from_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            database_path: Some(dir.path().join("states.db")),
+            anomaly_model_path: Some(dir.path().join("model.json")),
+            ..Config::default()
+        };
+
+        let db = database::Database::with_path(config.database_path.as_ref().unwrap()).unwrap();
+        let mut state = in_memory(Config::default()).await.unwrap().get_current_state().await.unwrap();
+        for i in 0..20 {
+            state.timestamp = Utc::now() - chrono::Duration::minutes(i);
+            state.cpu_usage = 10.0 + (i % 3) as f32;
+            db.store_state(&state).await.unwrap();
+        }
+        db.flush().await.unwrap();
+
+        let report = AngeGardien::train(&config, 1).await.unwrap();
+        assert_eq!(report.states, 20);
+        assert!(report.dbscan.trained);
+        assert!(!report.isolation_forest);
+        assert!(config.anomaly_model_path.as_ref().unwrap().exists());
+
+        assert!(AngeGardien::train(&Config { anomaly_model_path: None, ..config }, 0).await.is_err());
+    }
+
+    #[test]
+    fn test_alert_severity_string_form_and_order() {
+        for severity in AlertSeverity::ALL {
+            assert_eq!(severity.to_string().parse::<AlertSeverity>().unwrap(), severity);
+        }
+        assert_eq!("critical".parse::<AlertSeverity>().unwrap(), AlertSeverity::Critical);
+        assert_eq!(" HIGH ".parse::<AlertSeverity>().unwrap(), AlertSeverity::High);
+        assert!("urgent".parse::<AlertSeverity>().is_err());
+
+        assert!(AlertSeverity::Critical > AlertSeverity::High);
+        assert!(AlertSeverity::Medium > AlertSeverity::Low);
+        assert_eq!(
+            AlertSeverity::High.and_above().collect::<Vec<_>>(),
+            vec![AlertSeverity::High, AlertSeverity::Critical]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_alerts_at_least() {
+        let guardian = in_memory(Config::default()).await.unwrap();
+        let alert = |severity| SecurityAlert {
+            timestamp: Utc::now(),
+            severity,
+            description: format!("{} alert", severity),
+            source: "test".to_string(),
+            recommendation: None,
+            count: 1,
+            would_enforce: false,
+            rule: None,
+            pid: None,
+            remote_ip: None,
+        };
+        let mut state = guardian.get_current_state().await.unwrap();
+        state.security_alerts = vec![
+            alert(AlertSeverity::Low),
+            alert(AlertSeverity::Medium),
+            alert(AlertSeverity::High),
+            alert(AlertSeverity::Critical),
+        ];
+        guardian.db.store_state(&state).await.unwrap();
+
+        let since = Utc::now() - chrono::Duration::hours(1);
+        assert_eq!(guardian.get_alerts(since).await.unwrap().len(), 4);
+        let paged = guardian.get_alerts_at_least(since, AlertSeverity::High).await.unwrap();
+        assert!(paged.iter().all(|a| a.severity >= AlertSeverity::High));
+        assert_eq!(paged.len(), 2);
+    }
+    #[tokio::test]
+    async fn test_exec_alerts_join_incidents() {
+        let guardian = in_memory(Config::default()).await.unwrap();
+        let alert = |source: &str, description: &str| SecurityAlert {
+            timestamp: Utc::now(),
+            severity: AlertSeverity::High,
+            description: description.to_string(),
+            source: source.to_string(),
+            recommendation: None,
+            count: 1,
+            would_enforce: false,
+            rule: None,
+            pid: Some(4242),
+            remote_ip: None,
+        };
+
+        // Raised by the exec guard's task, outside the monitoring cycle
+        let exec = alert(exec_control::EXEC_CONTROL_SOURCE, "Non-allowlisted binary executed: /tmp/dropper");
+        AngeGardien::file_incidents(&guardian.incidents, &guardian.db, &[exec]).await.unwrap();
+        let policy = alert(security::POLICY_CHECK_SOURCE, "Process dropper (PID: 4242) is running from /tmp/dropper");
+        AngeGardien::file_incidents(&guardian.incidents, &guardian.db, &[policy]).await.unwrap();
+
+        let incidents = guardian.db.get_incidents_since(Utc::now() - chrono::Duration::hours(1)).await.unwrap();
+        assert_eq!(incidents.len(), 1);
+        assert_eq!(incidents[0].alerts.len(), 2);
+    }
+}
@@ -1,6 +1,6 @@
-use ange_gardien::AngeGardien;
-use clap::Parser;
-use log::{info, error};
+use ange_gardien::{AngeGardien, Config, LogFormat, RedactionOptions, FEATURE_NAMES, create_service_user};
+use clap::{Parser, Subcommand};
+use tracing::{info, warn, error};
 use std::path::PathBuf;
 use anyhow::Result;
 
@@ -18,6 +18,38 @@ struct Args {
     /// Specify log level (error, warn, info, debug, trace)
     #[arg(short, long, default_value = "info")]
     log_level: String,
+
+    /// Log output format (text, json)
+    #[arg(long, default_value = "text")]
+    log_format: LogFormat,
+
+    /// Capture a diagnostic bundle to FILE and exit
+    #[arg(long, value_name = "FILE")]
+    bundle: Option<PathBuf>,
+
+    /// Redact network addresses and process names in the diagnostic bundle
+    #[arg(long, requires = "bundle")]
+    redact: bool,
+
+    /// Export tracing spans to an OTLP collector (e.g. http://localhost:4317)
+    #[cfg(feature = "otel")]
+    #[arg(long, value_name = "URL")]
+    otlp_endpoint: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Fit the anomaly models on stored history and save them
+    Train {
+        /// Days of history to train on
+        #[arg(long, default_value_t = 7)]
+        days: i64,
+    },
+    /// Create the unprivileged account named by service_user (needs root)
+    CreateUser,
 }
 
 #[tokio::main]
@@ -25,19 +57,92 @@ async fn main() -> Result<()> {
     let args = Args::parse();
 
     // Initialize logging
-    env_logger::Builder::from_env(env_logger::Env::default())
-        .filter_level(args.log_level.parse().unwrap_or(log::LevelFilter::Info))
-        .init();
+    #[cfg(feature = "otel")]
+    let otlp_endpoint = args.otlp_endpoint.as_deref();
+    #[cfg(not(feature = "otel"))]
+    let otlp_endpoint = None;
+    ange_gardien::init_logging(&args.log_level, args.log_format, otlp_endpoint)?;
+    if let Some(endpoint) = otlp_endpoint {
+        info!("Exporting traces to {}", endpoint);
+    }
+
+    let config = match &args.config {
+        Some(path) => Some(Config::from_path(path)?),
+        None => None,
+    };
+
+    if let Some(Command::CreateUser) = args.command {
+        let user = config.unwrap_or_default().service_user;
+        let (uid, gid) = create_service_user(&user)?;
+        println!("Service user {} has uid {} and gid {}", user, uid, gid);
+        return Ok(());
+    }
+
+    if let Some(Command::Train { days }) = args.command {
+        let report = AngeGardien::train(&config.unwrap_or_default(), days).await?;
+        let dbscan = &report.dbscan;
+        println!("Loaded {} states from the last {} days", report.states, days);
+        println!(
+            "DBSCAN: fit on {} samples, {} clusters, {} noise points",
+            dbscan.sample_count,
+            dbscan.clusters.len(),
+            dbscan.noise_points
+        );
+        if let Some(scaler) = &dbscan.scaler {
+            for ((name, mean), std_dev) in FEATURE_NAMES.iter().zip(&scaler.mean).zip(&scaler.std_dev) {
+                println!("  {:<28} mean {:>14.2}  std dev {:>14.2}", name, mean, std_dev);
+            }
+        }
+        if report.isolation_forest {
+            println!("IsolationForest: fit on {} samples", report.states);
+        }
+        return Ok(());
+    }
 
     info!("Starting Ange Gardien monitoring system...");
 
     // Create and start the guardian
-    let guardian = AngeGardien::new().await?;
+    let mut guardian = AngeGardien::new(config).await?;
+
+    if let Some(path) = args.bundle {
+        if args.redact {
+            guardian.set_redaction(RedactionOptions::all());
+        }
+        guardian.start().await?;
+
+        // Let a couple of collection cycles populate the state first
+        tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+        guardian.capture_diagnostic_bundle(&path).await?;
+        guardian.stop().await?;
+        return Ok(());
+    }
+
     guardian.start().await?;
 
-    // Keep the main thread running
-    tokio::signal::ctrl_c().await?;
+    // Run until interrupted; SIGHUP reloads the security policies
+    let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+    loop {
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => {
+                result?;
+                break;
+            }
+            _ = hangup.recv() => match &args.config {
+                Some(path) => {
+                    info!("Received SIGHUP, reloading policies from {}", path.display());
+                    if let Err(e) = guardian.reload_policies(path) {
+                        error!("Policy reload failed, keeping current policies: {}", e);
+                    }
+                }
+                None => warn!("Received SIGHUP but no config file was given, nothing to reload"),
+            },
+        }
+    }
     info!("Shutting down Ange Gardien...");
+    guardian.stop().await?;
+
+    #[cfg(feature = "otel")]
+    ange_gardien::shutdown_otel();
 
     Ok(())
 }
@@ -0,0 +1,147 @@
+use std::fmt::Write;
+use crate::{SystemState, AlertSeverity};
+
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Renders the state in the Prometheus text exposition format.
+pub fn render(state: &SystemState) -> String {
+    let mut out = String::new();
+
+    gauge(&mut out, "ange_cpu_usage_percent", "Global CPU usage", state.cpu_usage as f64);
+    gauge(&mut out, "ange_memory_usage_percent", "Memory in use", state.memory_usage as f64);
+    gauge(&mut out, "ange_disk_usage_percent", "Average disk usage across mounts", state.disk_usage as f64);
+    gauge(&mut out, "ange_processes", "Number of active processes", state.active_processes.len() as f64);
+    gauge(
+        &mut out,
+        "ange_process_threads",
+        "Threads across all active processes",
+        state.active_processes.iter().map(|p| p.threads as f64).sum(),
+    );
+    gauge(
+        &mut out,
+        "ange_network_connections",
+        "Tracked network connections",
+        state.network_stats.connections.len() as f64,
+    );
+    counter(&mut out, "ange_network_bytes_sent_total", "Bytes sent", state.network_stats.bytes_sent as f64);
+    counter(
+        &mut out,
+        "ange_network_bytes_received_total",
+        "Bytes received",
+        state.network_stats.bytes_received as f64,
+    );
+    gauge(
+        &mut out,
+        "ange_network_bytes_sent_per_second",
+        "Send throughput since the previous sample",
+        state.network_stats.bytes_sent_per_sec,
+    );
+    gauge(
+        &mut out,
+        "ange_network_bytes_received_per_second",
+        "Receive throughput since the previous sample",
+        state.network_stats.bytes_received_per_sec,
+    );
+    counter(
+        &mut out,
+        "ange_dns_cache_hits_total",
+        "Reverse DNS lookups served from the cache",
+        state.network_stats.dns_cache_hits as f64,
+    );
+    counter(
+        &mut out,
+        "ange_dns_cache_misses_total",
+        "Reverse DNS lookups that needed a query",
+        state.network_stats.dns_cache_misses as f64,
+    );
+    counter(
+        &mut out,
+        "ange_icmp_packets_total",
+        "ICMP packets captured",
+        state.network_stats.icmp_packets as f64,
+    );
+    gauge(
+        &mut out,
+        "ange_collection_duration_ms",
+        "Time the collection and analysis phases of the last monitoring cycle took, excluding storage, policy checks and alert delivery",
+        state.collection_duration_ms as f64,
+    );
+
+    let _ = writeln!(out, "# HELP ange_security_alerts_total Security alerts in the live state");
+    let _ = writeln!(out, "# TYPE ange_security_alerts_total counter");
+    for (severity, label) in [
+        (AlertSeverity::Low, "low"),
+        (AlertSeverity::Medium, "medium"),
+        (AlertSeverity::High, "high"),
+        (AlertSeverity::Critical, "critical"),
+    ] {
+        let count = state.security_alerts.iter().filter(|a| a.severity == severity).count();
+        let _ = writeln!(out, "ange_security_alerts_total{{severity=\"{}\"}} {}", label, count);
+    }
+
+    out
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: f64) {
+    metric(out, name, help, "gauge", value);
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: f64) {
+    metric(out, name, help, "counter", value);
+}
+
+fn metric(out: &mut String, name: &str, help: &str, kind: &str, value: f64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{NetworkStats, SecurityAlert};
+    use chrono::Utc;
+
+    #[test]
+    fn test_render_metrics() {
+        let state = SystemState {
+            timestamp: Utc::now(),
+            cpu_usage: 42.5,
+            memory_usage: 60.0,
+            disk_usage: 70.0,
+            network_stats: NetworkStats {
+                bytes_sent: 1024,
+                bytes_received: 2048,
+                connections: vec![],
+                suspicious_activity: vec![],
+                bytes_sent_per_sec: 0.0,
+                bytes_received_per_sec: 0.0,
+                dns_cache_hits: 0,
+                dns_cache_misses: 0,
+                icmp_packets: 0,
+                icmp_types: Vec::new(),
+            },
+            security_alerts: vec![SecurityAlert {
+                timestamp: Utc::now(),
+                severity: AlertSeverity::High,
+                description: "test".to_string(),
+                source: "test".to_string(),
+                recommendation: None,
+                count: 1,
+                would_enforce: false,
+                rule: None,
+                pid: None,
+                remote_ip: None,
+            }],
+            collection_duration_ms: 250,
+            ..Default::default()
+        };
+
+        let text = render(&state);
+        assert!(text.contains("# TYPE ange_cpu_usage_percent gauge\nange_cpu_usage_percent 42.5\n"));
+        assert!(text.contains("ange_network_bytes_sent_total 1024\n"));
+        assert!(text.contains("ange_collection_duration_ms 250\n"));
+        assert!(text.contains("ange_security_alerts_total{severity=\"high\"} 1\n"));
+        assert!(text.contains("ange_security_alerts_total{severity=\"low\"} 0\n"));
+    }
+}
@@ -1,80 +1,166 @@
 use anyhow::Result;
-use sysinfo::{System, SystemExt, ProcessExt, CpuExt};
+use sysinfo::{Disk, DiskExt, System, SystemExt, PidExt, ProcessExt, CpuExt};
 use chrono::{DateTime, Utc};
 use crate::ProcessInfo;
-use log::{info, warn};
+use serde::{Serialize, Deserialize};
+use tracing::{info, warn};
 use std::sync::Arc;
 use tokio::sync::RwLock;
-use time::OffsetDateTime;
 use num_cpus;
 use threadpool::ThreadPool;
-use darwin_libproc::pid_rusage;
-use mach::{kern_return, message, port, traps, vm_types};
+use libproc::libproc::pid_rusage::{pidrusage, RUsageInfoV2};
+use mach::{kern_return, mach_port, mach_types, message, port, task, traps, vm, vm_types};
 use core_foundation::{
     base::TCFType,
     dictionary::CFDictionary,
     string::CFString,
     number::CFNumber,
 };
-use std::collections::HashMap;
-use std::time::Duration;
-use serde::{Serialize, Deserialize};
-use crate::{SystemState, NetworkStats};
-
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use crate::{SystemState, SystemMetrics, NetworkStats};
+use crate::container::{CgroupV2, ContainerMode, CpuSample};
+use crate::host_stats::{KernelCounters, MemoryPressure};
+use crate::procinfo;
+
+/// Collects host metrics through sysinfo.
+///
+/// sysinfo measures CPU usage between two refreshes at least
+/// `MINIMUM_CPU_UPDATE_INTERVAL` apart, so a reading taken straight after
+/// construction would be 0 or garbage. The first CPU reading therefore waits
+/// out the rest of that interval; later ones never refresh more often than it
+/// allows.
 pub struct SystemMonitor {
     sys: Arc<RwLock<System>>,
     thread_pool: ThreadPool,
-    last_update: Arc<RwLock<OffsetDateTime>>,
-    process_history: Arc<RwLock<HashMap<u32, ProcessHistory>>>,
+    last_update: Arc<RwLock<DateTime<Utc>>>,
+    /// Per-pid `(timestamp, cpu, memory)` samples, oldest at the front
+    process_history: Arc<RwLock<HashMap<u32, VecDeque<(DateTime<Utc>, f32, f32)>>>>,
+    cgroup: Option<CgroupV2>,
+    last_cgroup_cpu: Arc<RwLock<Option<CpuSample>>>,
+    last_kernel_counters: Arc<RwLock<Option<KernelCounters>>>,
+    last_cpu_refresh: Arc<RwLock<CpuRefresh>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CpuRefresh {
+    at: Instant,
+    /// Whether a refresh has happened a full interval after a previous one,
+    /// i.e. CPU usage figures are meaningful
+    warm: bool,
+}
+
+/// Up to an hour of per-process samples, oldest first.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProcessHistory {
+    pub pid: u32,
+    /// `(timestamp, cpu usage %, memory usage %)`
+    pub samples: Vec<(DateTime<Utc>, f32, f32)>,
+}
+
+/// Space used on one mounted filesystem.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DiskUsage {
+    pub mount_point: String,
+    /// e.g. `apfs`, `hfs`, `smbfs`
+    pub file_system: String,
+    pub total_bytes: u64,
+    pub used_bytes: u64,
+    pub usage_percent: f32,
 }
 
-#[derive(Clone, Debug)]
-struct ProcessHistory {
-    cpu_usage: Vec<f32>,
-    memory_usage: Vec<u64>,
-    timestamp: Vec<DateTime<Utc>>,
+/// Filesystems without real storage behind them, never counted as disks
+const PSEUDO_FILE_SYSTEMS: [&str; 5] = ["devfs", "autofs", "devtmpfs", "proc", "sysfs"];
+
+impl DiskUsage {
+    fn of(disk: &Disk) -> Option<Self> {
+        Self::from_space(
+            disk.mount_point().display().to_string(),
+            String::from_utf8_lossy(disk.file_system()).into_owned(),
+            disk.total_space(),
+            disk.available_space(),
+        )
+    }
+
+    /// `None` for pseudo-filesystems and zero-sized disks, which have no
+    /// meaningful usage. Network and union filesystems can report more
+    /// available than total space, so usage is clamped to 0-100%.
+    fn from_space(mount_point: String, file_system: String, total_bytes: u64, available_bytes: u64) -> Option<Self> {
+        if total_bytes == 0 || PSEUDO_FILE_SYSTEMS.contains(&file_system.as_str()) {
+            return None;
+        }
+        let used_bytes = total_bytes.saturating_sub(available_bytes);
+        Some(Self {
+            mount_point,
+            file_system,
+            total_bytes,
+            used_bytes,
+            usage_percent: (used_bytes as f64 / total_bytes as f64 * 100.0).clamp(0.0, 100.0) as f32,
+        })
+    }
+}
+
+/// Mean usage across `disks`; 0 when there are none (e.g. in containers), so
+/// no NaN reaches the store or the policy checks.
+fn average_disk_usage(disks: &[DiskUsage]) -> f32 {
+    if disks.is_empty() {
+        return 0.0;
+    }
+    disks.iter().map(|disk| disk.usage_percent).sum::<f32>() / disks.len() as f32
 }
 
 impl SystemMonitor {
     pub fn new() -> Self {
+        Self::with_container_mode(ContainerMode::Auto)
+    }
+
+    pub fn with_container_mode(mode: ContainerMode) -> Self {
         let mut sys = System::new_all();
         sys.refresh_all();
         
         // Create a thread pool with number of threads equal to CPU cores
         let num_threads = num_cpus::get();
         let thread_pool = ThreadPool::new(num_threads);
+
+        let cgroup = CgroupV2::for_mode(mode);
+        // Baseline for the first cgroup reading, which would otherwise be 0
+        let first_cgroup_cpu = cgroup.as_ref().and_then(|cgroup| cgroup.cpu_sample().ok());
         
         Self {
             sys: Arc::new(RwLock::new(sys)),
             thread_pool,
-            last_update: Arc::new(RwLock::new(OffsetDateTime::now_utc())),
+            last_update: Arc::new(RwLock::new(Utc::now())),
             process_history: Arc::new(RwLock::new(HashMap::new())),
+            cgroup,
+            last_cgroup_cpu: Arc::new(RwLock::new(first_cgroup_cpu)),
+            last_kernel_counters: Arc::new(RwLock::new(None)),
+            last_cpu_refresh: Arc::new(RwLock::new(CpuRefresh { at: Instant::now(), warm: false })),
         }
     }
 
+    /// Whether resource percentages are relative to cgroup limits rather than the host.
+    pub fn is_cgroup_aware(&self) -> bool {
+        self.cgroup.is_some()
+    }
+
     pub async fn get_system_state(&self) -> Result<SystemState> {
         let mut sys = self.sys.write().await;
-        sys.refresh_all();
+        // Not refresh_all: an unconditional CPU refresh could land right
+        // after another caller's and yield a meaningless sample
+        sys.refresh_memory();
+        sys.refresh_disks_list();
+        sys.refresh_processes();
+        self.refresh_cpu(&mut sys).await;
 
         let cpu_usage = sys.global_cpu_info().cpu_usage().min(100.0) as f32;
+        let per_core_cpu = sys.cpus().iter().map(|cpu| cpu.cpu_usage().min(100.0)).collect();
         
         let total_memory = sys.total_memory().max(1) as f32;  // Prevent division by zero
         let used_memory = (sys.total_memory() - sys.available_memory()) as f32;
         let memory_usage = (used_memory / total_memory * 100.0).min(100.0);
 
-        let mut disk_usage = 0.0;
-        let mut disk_count = 0;
-        for disk in sys.disks() {
-            let total = disk.total_space() as f32;
-            if total > 0.0 {  // Skip zero-sized disks
-                let used = (disk.total_space() - disk.available_space()) as f32;
-                disk_usage += (used / total * 100.0).min(100.0);
-                disk_count += 1;
-            }
-        }
-        if disk_count > 0 {
-            disk_usage /= disk_count as f32;
-        }
+        let disks: Vec<DiskUsage> = sys.disks().iter().filter_map(DiskUsage::of).collect();
+        let disk_usage = average_disk_usage(&disks);
 
         let mut active_processes = Vec::new();
         for (pid, process) in sys.processes() {
@@ -88,10 +174,16 @@ impl SystemMonitor {
             
             let process_info = ProcessInfo {
                 pid: pid.as_u32(),
+                ppid: process.parent().map(|parent| parent.as_u32()).unwrap_or(0),
                 name: process.name().to_string(),
                 cpu_usage: process.cpu_usage().min(100.0) as f32,
                 memory_usage: memory_percentage,
-                threads: process.thread_count().max(1) as u32,  // Ensure at least 1 thread
+                threads: procinfo::thread_count(pid.as_u32()).unwrap_or(1).max(1),  // Ensure at least 1 thread
+                start_time: DateTime::from_timestamp(process.start_time() as i64, 0)
+                    .unwrap_or_else(|| Utc::now()),
+                command: process.cmd().join(" "),
+                disk_bytes_read: 0,
+                disk_bytes_written: 0,
             };
             active_processes.push(process_info);
         }
@@ -99,167 +191,217 @@ impl SystemMonitor {
         Ok(SystemState {
             timestamp: chrono::Utc::now(),
             cpu_usage,
+            per_core_cpu,
             memory_usage,
             disk_usage,
+            disks,
             network_stats: NetworkStats::default(),
             active_processes,
             security_alerts: Vec::new(),
             system_metrics: None,
+            collection_duration_ms: 0,
         })
     }
 
     pub async fn get_cpu_usage(&self) -> Result<f32> {
+        if let Some(cgroup) = &self.cgroup {
+            return self.get_cgroup_cpu_usage(cgroup).await;
+        }
+
         let mut sys = self.sys.write().await;
-        sys.refresh_cpu();
+        self.refresh_cpu(&mut sys).await;
         
         let cpu_usage = sys.global_cpu_info().cpu_usage();
         Ok(cpu_usage)
     }
 
+    /// Usage of each logical core, in the order sysinfo lists them.
+    pub async fn get_per_core_usage(&self) -> Result<Vec<f32>> {
+        let mut sys = self.sys.write().await;
+        self.refresh_cpu(&mut sys).await;
+        Ok(sys.cpus().iter().map(|cpu| cpu.cpu_usage().min(100.0)).collect())
+    }
+
+    /// CPU usage is measured between refreshes, so refreshing again right
+    /// after a previous caller did would report a meaningless sample. Only
+    /// refresh once `MINIMUM_CPU_UPDATE_INTERVAL` has passed, waiting out the
+    /// remainder when no meaningful sample exists yet.
+    async fn refresh_cpu(&self, sys: &mut System) {
+        let mut last = self.last_cpu_refresh.write().await;
+        let elapsed = last.at.elapsed();
+        if elapsed < System::MINIMUM_CPU_UPDATE_INTERVAL {
+            if last.warm {
+                return;
+            }
+            tokio::time::sleep(System::MINIMUM_CPU_UPDATE_INTERVAL - elapsed).await;
+        }
+        sys.refresh_cpu();
+        *last = CpuRefresh { at: Instant::now(), warm: true };
+    }
+
     pub async fn get_memory_usage(&self) -> Result<f32> {
         let sys = self.sys.read().await;
+
+        if let Some(cgroup) = &self.cgroup {
+            // An unlimited cgroup can use all of the host's memory
+            let limit = cgroup.memory_limit()?.unwrap_or_else(|| sys.total_memory()).max(1);
+            return Ok((cgroup.memory_current()? as f32 / limit as f32 * 100.0).min(100.0));
+        }
+
         let total_memory = sys.total_memory() as f32;
         let used_memory = sys.used_memory() as f32;
         
         Ok((used_memory / total_memory) * 100.0)
     }
 
-    pub async fn get_disk_usage(&self) -> Result<f32> {
-        let sys = self.sys.read().await;
-        let mut total_usage = 0.0;
-        let mut disk_count = 0;
-
-        for disk in sys.disks() {
-            let total = disk.total_space() as f32;
-            let used = (disk.total_space() - disk.available_space()) as f32;
-            total_usage += (used / total) * 100.0;
-            disk_count += 1;
-        }
+    async fn get_cgroup_cpu_usage(&self, cgroup: &CgroupV2) -> Result<f32> {
+        let current = cgroup.cpu_sample()?;
+        let mut last = self.last_cgroup_cpu.write().await;
+
+        // CPU usage needs two samples; report 0 until we have a previous one
+        let usage = match last.as_ref() {
+            Some(previous) => cgroup.cpu_usage_percent(previous, &current, num_cpus::get())?,
+            None => 0.0,
+        };
+        *last = Some(current);
 
-        Ok(total_usage / disk_count as f32)
+        Ok(usage)
     }
 
-    pub async fn get_process_list(&self) -> Result<Vec<ProcessInfo>> {
-        let sys = self.sys.read().await;
-        let mut processes = Vec::new();
-        let (tx, rx) = std::sync::mpsc::channel();
+    pub async fn get_disk_usage(&self) -> Result<f32> {
+        Ok(average_disk_usage(&self.get_disk_usage_per_mount().await?))
+    }
 
-        for (pid, process) in sys.processes() {
-            let tx = tx.clone();
-            let process_name = process.name().to_string();
-            let process_cpu = process.cpu_usage();
-            let process_memory = process.memory();
-            let process_threads = process.thread_count();
-            let process_cmd = process.cmd().join(" ");
-            let process_start = process.start_time();
-
-            self.thread_pool.execute(move || {
-                // Get macOS-specific process information using libproc
-                if let Ok(rusage) = pid_rusage::pidrusage(*pid) {
-                    let process_info = ProcessInfo {
-                        pid: *pid,
-                        name: process_name,
-                        cpu_usage: process_cpu,
-                        memory_usage: process_memory,
-                        threads: process_threads,
-                        start_time: DateTime::from_timestamp(
-                            process_start as i64,
-                            0
-                        ).unwrap_or_else(|| Utc::now()),
-                        command: process_cmd,
-                    };
+    /// Usage of each mounted filesystem, so one full volume isn't hidden by
+    /// the average.
+    pub async fn get_disk_usage_per_mount(&self) -> Result<Vec<DiskUsage>> {
+        let sys = self.sys.read().await;
+        Ok(sys.disks().iter().filter_map(DiskUsage::of).collect())
+    }
 
+    pub async fn get_process_list(&self) -> Result<Vec<ProcessInfo>> {
+        // Copy what's needed out of sysinfo so the lock isn't held during the
+        // rusage and thread lookups
+        let snapshot: Vec<ProcessInfo> = {
+            let sys = self.sys.read().await;
+            let total_memory = sys.total_memory().max(1) as f32;
+            sys.processes().iter().map(|(pid, process)| ProcessInfo {
+                pid: pid.as_u32(),
+                ppid: process.parent().map(|parent| parent.as_u32()).unwrap_or(0),
+                name: process.name().to_string(),
+                cpu_usage: process.cpu_usage(),
+                memory_usage: (process.memory() as f32 / total_memory * 100.0).min(100.0),
+                threads: 0,
+                start_time: DateTime::from_timestamp(process.start_time() as i64, 0)
+                    .unwrap_or_else(|| Utc::now()),
+                command: process.cmd().join(" "),
+                disk_bytes_read: 0,
+                disk_bytes_written: 0,
+            }).collect()
+        };
+
+        // The lookups are blocking syscalls: fan them out on the pool and wait
+        // for them on a blocking thread rather than a runtime worker
+        let thread_pool = self.thread_pool.clone();
+        let mut processes = tokio::task::spawn_blocking(move || {
+            let (tx, rx) = std::sync::mpsc::channel();
+            for mut process_info in snapshot {
+                let tx = tx.clone();
+                thread_pool.execute(move || {
+                    // Get macOS-specific process information using libproc; rusage is
+                    // denied for other users' processes without root, so I/O reads 0 there
+                    if let Ok(rusage) = pidrusage::<RUsageInfoV2>(process_info.pid as i32) {
+                        process_info.disk_bytes_read = rusage.ri_diskio_bytesread;
+                        process_info.disk_bytes_written = rusage.ri_diskio_byteswritten;
+                    }
+                    process_info.threads = procinfo::thread_count(process_info.pid).unwrap_or(0);
                     let _ = tx.send(process_info);
-                }
-            });
-        }
-
-        drop(tx);
-
-        // Collect results from the thread pool
-        for process_info in rx.iter() {
-            processes.push(process_info);
-        }
+                });
+            }
+            drop(tx);
+            rx.iter().collect::<Vec<ProcessInfo>>()
+        }).await?;
 
         // Update process history
         let mut history = self.process_history.write().await;
         let current_time = Utc::now();
 
         for process in &processes {
-            let history_entry = history.entry(process.pid).or_insert_with(|| ProcessHistory {
-                cpu_usage: Vec::new(),
-                memory_usage: Vec::new(),
-                timestamp: Vec::new(),
-            });
+            let samples = history.entry(process.pid).or_default();
 
             // Keep last hour of data (3600 seconds)
-            while !history_entry.timestamp.is_empty() && 
-                  (current_time - history_entry.timestamp[0]).num_seconds() > 3600 {
-                history_entry.cpu_usage.remove(0);
-                history_entry.memory_usage.remove(0);
-                history_entry.timestamp.remove(0);
+            while samples.front().is_some_and(|(at, _, _)| (current_time - *at).num_seconds() > 3600) {
+                samples.pop_front();
             }
 
-            history_entry.cpu_usage.push(process.cpu_usage);
-            history_entry.memory_usage.push(process.memory_usage);
-            history_entry.timestamp.push(current_time);
+            samples.push_back((current_time, process.cpu_usage, process.memory_usage));
         }
 
         // Update last update time
-        *self.last_update.write().await = OffsetDateTime::now_utc();
+        *self.last_update.write().await = Utc::now();
 
         // Sort by CPU usage for quick identification of resource-intensive processes
-        processes.sort_by(|a, b| b.cpu_usage.partial_cmp(&a.cpu_usage).unwrap());
+        sort_by_cpu_desc(&mut processes);
 
         Ok(processes)
     }
 
-    pub async fn get_thread_info(&self) -> Result<Vec<ThreadInfo>> {
+    /// CPU usage and accumulated run time of each thread of `pid`, to find
+    /// the hot thread in a busy multithreaded process. Threads of other
+    /// processes need root; hardened and platform binaries refuse even then.
+    pub async fn get_thread_info(&self, pid: u32) -> Result<Vec<ThreadInfo>> {
         unsafe {
-            let task = traps::mach_task_self();
-            let mut thread_list: port::mach_port_array_t = std::ptr::null_mut();
-            let mut thread_count: mach_msg_type_number_t = 0;
-
-            let kr = traps::task_threads(
-                task,
-                &mut thread_list,
-                &mut thread_count,
-            );
+            let own_task = traps::mach_task_self();
+            let task = if pid == std::process::id() {
+                own_task
+            } else {
+                let mut task: port::mach_port_t = port::MACH_PORT_NULL;
+                let kr = traps::task_for_pid(own_task, pid as i32, &mut task);
+                if kr != kern_return::KERN_SUCCESS {
+                    return Err(anyhow::anyhow!("task_for_pid({}) failed with {}; root is required", pid, kr));
+                }
+                task
+            };
 
+            let mut thread_list: mach_types::thread_act_array_t = std::ptr::null_mut();
+            let mut thread_count: message::mach_msg_type_number_t = 0;
+            let kr = task::task_threads(task, &mut thread_list, &mut thread_count);
             if kr != kern_return::KERN_SUCCESS {
-                return Err(anyhow::anyhow!("Failed to get task threads"));
+                if task != own_task {
+                    mach_port::mach_port_deallocate(own_task, task);
+                }
+                return Err(anyhow::anyhow!("Failed to list the threads of PID {} ({})", pid, kr));
             }
 
-            let threads = std::slice::from_raw_parts(thread_list, thread_count as usize)
-                .iter()
+            let ports = std::slice::from_raw_parts(thread_list, thread_count as usize);
+            let threads = ports.iter()
                 .filter_map(|&thread| {
-                    let mut thread_info = thread_basic_info::default();
-                    let mut count = THREAD_BASIC_INFO_COUNT;
-
-                    let kr = thread_info(
-                        thread,
-                        THREAD_BASIC_INFO,
-                        &mut thread_info as *mut _ as thread_info_t,
-                        &mut count,
-                    );
-
-                    if kr == kern_return::KERN_SUCCESS {
-                        Some(ThreadInfo {
-                            cpu_usage: thread_info.cpu_usage as f32 / TH_USAGE_SCALE as f32,
-                            run_time: thread_info.user_time.seconds as f64
-                                + thread_info.system_time.seconds as f64,
-                        })
-                    } else {
-                        None
-                    }
+                    let info = thread_ffi::basic_info(thread);
+                    let thread_id = thread_ffi::thread_id(thread);
+                    mach_port::mach_port_deallocate(own_task, thread);
+                    let (basic, thread_id) = (info?, thread_id?);
+
+                    let seconds = |time: thread_ffi::TimeValue| {
+                        time.seconds as f64 + time.microseconds as f64 / 1_000_000.0
+                    };
+                    Some(ThreadInfo {
+                        thread_id,
+                        cpu_usage: basic.cpu_usage as f32 / thread_ffi::TH_USAGE_SCALE as f32 * 100.0,
+                        user_time: seconds(basic.user_time),
+                        system_time: seconds(basic.system_time),
+                        run_time: seconds(basic.user_time) + seconds(basic.system_time),
+                    })
                 })
                 .collect();
 
-            vm_deallocate(
-                task,
-                thread_list as vm_address_t,
-                (thread_count as usize * std::mem::size_of::<mach_port_t>()) as vm_size_t,
+            vm::mach_vm_deallocate(
+                own_task,
+                thread_list as vm_types::mach_vm_address_t,
+                std::mem::size_of_val(ports) as vm_types::mach_vm_size_t,
             );
+            if task != own_task {
+                mach_port::mach_port_deallocate(own_task, task);
+            }
 
             Ok(threads)
         }
@@ -267,7 +409,7 @@ impl SystemMonitor {
 
     pub async fn monitor_process_creation(&self) -> Result<()> {
         let sys = self.sys.read().await;
-        let current_processes: Vec<u32> = sys.processes().keys().cloned().collect();
+        let current_processes: Vec<u32> = sys.processes().keys().map(|pid| pid.as_u32()).collect();
         
         info!("Monitoring {} processes", current_processes.len());
         
@@ -278,35 +420,161 @@ impl SystemMonitor {
         let sys = self.sys.read().await;
         let num_physical_cores = num_cpus::get_physical();
         let num_logical_cores = num_cpus::get();
-        
+
+        // Rates need two samples; they read 0 until the second call
+        let mut last_counters = self.last_kernel_counters.write().await;
+        let (rates, compressed_bytes) = match KernelCounters::sample() {
+            Ok(current) => {
+                let rates = last_counters.as_ref()
+                    .map(|previous| current.rates_since(previous))
+                    .unwrap_or_default();
+                *last_counters = Some(current);
+                (rates, current.compressed_bytes)
+            }
+            Err(e) => {
+                warn!("Failed to sample kernel counters: {}", e);
+                Default::default()
+            }
+        };
+        let memory_pressure = MemoryPressure::sample().unwrap_or_else(|e| {
+            warn!("Failed to read memory pressure: {}", e);
+            MemoryPressure::Normal
+        });
+
         Ok(SystemMetrics {
             cpu_count: num_logical_cores,
             physical_cpu_count: num_physical_cores,
             last_update: *self.last_update.read().await,
             uptime: sys.uptime(),
+            boot_time: DateTime::from_timestamp(sys.boot_time() as i64, 0),
             load_average: sys.load_average().one,
+            io_wait: rates.io_wait,
+            context_switches: rates.context_switches,
+            interrupts: rates.interrupts,
+            swap_ins: rates.swap_ins,
+            swap_outs: rates.swap_outs,
+            compressions: rates.compressions,
+            compressed_bytes,
+            memory_pressure,
         })
     }
 
+    /// Maps each pid to its child pids, as of the last process refresh.
+    pub async fn get_process_tree(&self) -> Result<HashMap<u32, Vec<u32>>> {
+        let sys = self.sys.read().await;
+        Ok(build_process_tree(sys.processes().iter().map(|(pid, process)| {
+            (pid.as_u32(), process.parent().map(|parent| parent.as_u32()).unwrap_or(0))
+        })))
+    }
+
     pub async fn get_process_history(&self, pid: u32) -> Option<ProcessHistory> {
         let history = self.process_history.read().await;
-        history.get(&pid).cloned()
+        history.get(&pid).map(|samples| ProcessHistory {
+            pid,
+            samples: samples.iter().copied().collect(),
+        })
     }
 }
 
+/// Busiest first. sysinfo occasionally reports NaN usage; those go last
+/// instead of panicking the comparison.
+fn sort_by_cpu_desc(processes: &mut [ProcessInfo]) {
+    processes.sort_by(|a, b| {
+        a.cpu_usage.is_nan().cmp(&b.cpu_usage.is_nan())
+            .then_with(|| b.cpu_usage.total_cmp(&a.cpu_usage))
+    });
+}
+
+/// Builds a parent -> children map from `(pid, ppid)` pairs. Processes whose
+/// parent isn't in the snapshot (it already exited, or they have none) are
+/// listed as children of 0, so every process appears exactly once.
+pub fn build_process_tree(processes: impl IntoIterator<Item = (u32, u32)>) -> HashMap<u32, Vec<u32>> {
+    let processes: Vec<(u32, u32)> = processes.into_iter().collect();
+    let pids: std::collections::HashSet<u32> = processes.iter().map(|(pid, _)| *pid).collect();
+
+    let mut tree: HashMap<u32, Vec<u32>> = HashMap::new();
+    for (pid, ppid) in processes {
+        let parent = if ppid != pid && pids.contains(&ppid) { ppid } else { 0 };
+        tree.entry(parent).or_default().push(pid);
+    }
+    for children in tree.values_mut() {
+        children.sort_unstable();
+    }
+
+    tree
+}
+
+/// One thread of a process, from `thread_info`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct SystemMetrics {
-    pub cpu_count: usize,
-    pub physical_cpu_count: usize,
-    pub last_update: OffsetDateTime,
-    pub uptime: u64,
-    pub load_average: f64,
+pub struct ThreadInfo {
+    /// System-wide unique id, as shown by Instruments and `spindump`
+    pub thread_id: u64,
+    /// Recent CPU usage, percent of one core
+    pub cpu_usage: f32,
+    /// Seconds spent in user mode
+    pub user_time: f64,
+    /// Seconds spent in the kernel
+    pub system_time: f64,
+    /// User plus system seconds
+    pub run_time: f64,
 }
 
-#[derive(Debug)]
-struct ThreadInfo {
-    cpu_usage: f32,
-    run_time: f64,
+/// Minimal bindings for `thread_info`, which the mach crate doesn't cover.
+mod thread_ffi {
+    use mach::kern_return::KERN_SUCCESS;
+    use mach::port::mach_port_t;
+
+    const THREAD_BASIC_INFO: i32 = 3;
+    const THREAD_IDENTIFIER_INFO: i32 = 4;
+    pub const TH_USAGE_SCALE: i32 = 1000;
+
+    #[repr(C)]
+    #[derive(Debug, Default, Clone, Copy)]
+    pub struct TimeValue {
+        pub seconds: i32,
+        pub microseconds: i32,
+    }
+
+    #[repr(C)]
+    #[derive(Debug, Default, Clone, Copy)]
+    pub struct ThreadBasicInfo {
+        pub user_time: TimeValue,
+        pub system_time: TimeValue,
+        /// Scaled by `TH_USAGE_SCALE`
+        pub cpu_usage: i32,
+        pub policy: i32,
+        pub run_state: i32,
+        pub flags: i32,
+        pub suspend_count: i32,
+        pub sleep_time: i32,
+    }
+
+    #[repr(C)]
+    #[derive(Debug, Default, Clone, Copy)]
+    struct ThreadIdentifierInfo {
+        thread_id: u64,
+        thread_handle: u64,
+        dispatch_qaddr: u64,
+    }
+
+    extern "C" {
+        fn thread_info(thread: mach_port_t, flavor: i32, info: *mut i32, count: *mut u32) -> i32;
+    }
+
+    unsafe fn query<T: Default>(thread: mach_port_t, flavor: i32) -> Option<T> {
+        let mut info = T::default();
+        let mut count = (std::mem::size_of::<T>() / std::mem::size_of::<i32>()) as u32;
+        let kr = thread_info(thread, flavor, &mut info as *mut T as *mut i32, &mut count);
+        (kr == KERN_SUCCESS).then_some(info)
+    }
+
+    pub unsafe fn basic_info(thread: mach_port_t) -> Option<ThreadBasicInfo> {
+        query(thread, THREAD_BASIC_INFO)
+    }
+
+    pub unsafe fn thread_id(thread: mach_port_t) -> Option<u64> {
+        query::<ThreadIdentifierInfo>(thread, THREAD_IDENTIFIER_INFO).map(|info| info.thread_id)
+    }
 }
 
 #[cfg(test)]
@@ -322,6 +590,36 @@ mod tests {
         assert!(usage.unwrap() >= 0.0);
     }
 
+    #[tokio::test]
+    async fn test_first_cpu_reading_waits_for_warm_up() {
+        let monitor = SystemMonitor::new();
+        let start = Instant::now();
+        monitor.get_cpu_usage().await.unwrap();
+        assert!(start.elapsed() + Duration::from_millis(5) >= System::MINIMUM_CPU_UPDATE_INTERVAL);
+        assert!(monitor.last_cpu_refresh.read().await.warm);
+
+        // Warm readings inside the interval reuse the last sample
+        let start = Instant::now();
+        monitor.get_per_core_usage().await.unwrap();
+        assert!(start.elapsed() < System::MINIMUM_CPU_UPDATE_INTERVAL);
+    }
+
+    #[tokio::test]
+    async fn test_per_core_usage() {
+        let monitor = SystemMonitor::new();
+        let cores = monitor.get_per_core_usage().await.unwrap();
+        assert_eq!(cores.len(), num_cpus::get());
+        assert!(cores.iter().all(|usage| (0.0..=100.0).contains(usage)));
+    }
+
+    #[tokio::test]
+    async fn test_thread_info_of_own_process() {
+        let monitor = SystemMonitor::new();
+        let threads = monitor.get_thread_info(std::process::id()).await.unwrap();
+        assert!(!threads.is_empty());
+        assert!(threads.iter().all(|thread| thread.thread_id != 0 && thread.run_time >= 0.0));
+    }
+
     #[tokio::test]
     async fn test_memory_usage() {
         let monitor = SystemMonitor::new();
@@ -337,4 +635,99 @@ mod tests {
         assert!(processes.is_ok());
         assert!(!processes.unwrap().is_empty());
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_average_disk_usage_without_disks() {
+        assert_eq!(average_disk_usage(&[]), 0.0);
+
+        let disk = |usage_percent| DiskUsage {
+            mount_point: "/".to_string(),
+            file_system: "apfs".to_string(),
+            total_bytes: 100,
+            used_bytes: 0,
+            usage_percent,
+        };
+        assert_eq!(average_disk_usage(&[disk(20.0), disk(60.0)]), 40.0);
+    }
+
+    #[test]
+    fn test_disk_usage_skips_pseudo_filesystems_and_clamps() {
+        let usage = |file_system: &str, total, available| {
+            DiskUsage::from_space("/mnt".to_string(), file_system.to_string(), total, available)
+                .map(|disk| disk.usage_percent)
+        };
+        assert_eq!(usage("apfs", 200, 50), Some(75.0));
+        assert_eq!(usage("devfs", 200, 50), None);
+        assert_eq!(usage("autofs", 200, 0), None);
+        assert_eq!(usage("apfs", 0, 0), None);
+        // A network share reporting more free space than its size
+        assert_eq!(usage("smbfs", 100, 140), Some(0.0));
+    }
+
+    #[tokio::test]
+    async fn test_process_list_pids_match_live_processes() {
+        let mut child = std::process::Command::new("sleep").arg("30").spawn().unwrap();
+        let monitor = SystemMonitor::new();
+
+        let processes = monitor.get_process_list().await.unwrap();
+        let pids: std::collections::HashSet<u32> = processes.iter().map(|p| p.pid).collect();
+        let live: std::collections::HashSet<u32> = monitor.sys.read().await
+            .processes()
+            .keys()
+            .map(|pid| pid.as_u32())
+            .collect();
+
+        assert_eq!(pids.len(), processes.len());
+        assert_eq!(pids, live);
+        assert!(pids.contains(&std::process::id()));
+        let sleeper = processes.iter().find(|p| p.pid == child.id()).unwrap();
+        assert_eq!(sleeper.name, "sleep");
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn test_sort_by_cpu_puts_nan_last() {
+        let process = |pid: u32, cpu_usage: f32| ProcessInfo {
+            pid,
+            ppid: 1,
+            name: "worker".to_string(),
+            cpu_usage,
+            memory_usage: 0.0,
+            threads: 1,
+            start_time: Utc::now(),
+            command: String::new(),
+            disk_bytes_read: 0,
+            disk_bytes_written: 0,
+        };
+        let mut processes = vec![process(1, 5.0), process(2, f32::NAN), process(3, 80.0), process(4, 0.0)];
+        sort_by_cpu_desc(&mut processes);
+        let pids: Vec<u32> = processes.iter().map(|p| p.pid).collect();
+        assert_eq!(pids, vec![3, 1, 4, 2]);
+    }
+
+    #[test]
+    fn test_build_process_tree() {
+        // 40's parent 30 has already exited
+        let tree = build_process_tree(vec![(1, 0), (10, 1), (20, 10), (21, 10), (40, 30)]);
+        assert_eq!(tree.get(&0), Some(&vec![1, 40]));
+        assert_eq!(tree.get(&1), Some(&vec![10]));
+        assert_eq!(tree.get(&10), Some(&vec![20, 21]));
+        assert!(!tree.contains_key(&30));
+    }
+
+    #[tokio::test]
+    async fn test_process_history() {
+        let monitor = SystemMonitor::new();
+        let processes = monitor.get_process_list().await.unwrap();
+        let pid = processes[0].pid;
+        monitor.get_process_list().await.unwrap();
+
+        let history = monitor.get_process_history(pid).await.unwrap();
+        assert_eq!(history.pid, pid);
+        assert!(!history.samples.is_empty());
+        assert!(history.samples.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+        assert!(monitor.get_process_history(u32::MAX).await.is_none());
+    }
+}
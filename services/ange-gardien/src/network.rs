@@ -1,28 +1,475 @@
 use anyhow::Result;
-use pnet::datalink::{self, NetworkInterface};
+use pnet::datalink::{self, MacAddr, NetworkInterface};
 use pnet::packet::ethernet::{EthernetPacket, EtherTypes};
+use pnet::packet::icmp::IcmpPacket;
+use pnet::packet::icmpv6::Icmpv6Packet;
 use pnet::packet::ip::IpNextHeaderProtocols;
+use pnet::packet::ip::IpNextHeaderProtocol;
 use pnet::packet::ipv4::Ipv4Packet;
+use pnet::packet::ipv6::Ipv6Packet;
 use pnet::packet::tcp::TcpPacket;
 use pnet::packet::udp::UdpPacket;
 use pnet::packet::Packet;
-use std::collections::HashMap;
-use std::net::IpAddr;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
-use tokio::sync::RwLock;
-use trust_dns_resolver::Resolver;
-use trust_dns_resolver::config::*;
-use crate::NetworkStats;
-use log::{info, warn};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, RwLock, Semaphore};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, info_span, warn};
+use serde::{Serialize, Deserialize};
+use crate::dns::ReverseDns;
+use crate::procinfo;
+use crate::{AlertSeverity, SecurityAlert};
+use crate::security::SecurityPolicies;
+use chrono::{DateTime, Utc};
+#[cfg(feature = "geoip")]
+use crate::geoip::GeoIp;
+
+const SOCKET_OWNER_REFRESH: Duration = Duration::from_secs(1);
+/// How long a capture thread blocks waiting for a packet before checking
+/// whether it should stop
+const CAPTURE_READ_TIMEOUT: Duration = Duration::from_secs(1);
+/// How often idle and closed connections are swept from the map
+const CONNECTION_SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+/// How long a closed connection stays listed, so at least one collection
+/// cycle reports it
+const CLOSED_CONNECTION_LINGER: Duration = Duration::from_secs(30);
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_ACK: u8 = 0x10;
+const DNS_CONCURRENCY: usize = 16;
+/// Start of the IANA dynamic/private port range
+pub const EPHEMERAL_PORT_START: u16 = 49152;
+pub const FAN_OUT_SOURCE: &str = "Connection Fan-out";
+pub const BEACON_SOURCE: &str = "Connection Beaconing";
+pub const ICMP_SOURCE: &str = "ICMP Activity";
+
+/// Limits on how many endpoints one process may contact in a short window.
+/// Port scanners and some C2 implants spray connections well past them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FanOutConfig {
+    /// Sliding window the counts cover
+    pub window_secs: u64,
+    /// Distinct remote ports one process may contact within the window
+    pub max_remote_ports: usize,
+    /// Distinct remote IPs one process may contact within the window
+    pub max_remote_ips: usize,
+}
+
+impl Default for FanOutConfig {
+    fn default() -> Self {
+        Self {
+            window_secs: 60,
+            max_remote_ports: 25,
+            max_remote_ips: 100,
+        }
+    }
+}
+
+impl FanOutConfig {
+    pub fn window(&self) -> Duration {
+        Duration::from_secs(self.window_secs)
+    }
+}
+
+/// ICMP volume above which traffic is flagged as a flood or a tunnel. Echo
+/// payloads are counted on their own: `ping` sends 56 bytes, so sustained
+/// kilobytes per second of echo data usually means something rides in them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IcmpConfig {
+    /// ICMP packets per second, in either direction
+    pub max_packets_per_sec: f64,
+    /// Echo request and reply payload bytes per second
+    pub max_echo_bytes_per_sec: f64,
+}
+
+impl Default for IcmpConfig {
+    fn default() -> Self {
+        Self {
+            max_packets_per_sec: 50.0,
+            max_echo_bytes_per_sec: 10_000.0,
+        }
+    }
+}
+
+/// Packets of one ICMP type and code seen since startup.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IcmpCount {
+    /// ICMPv6 types are 128 and up for informational messages, so the two
+    /// families don't collide except on error types
+    pub icmp_type: u8,
+    pub code: u8,
+    pub packets: u64,
+}
+
+/// Cumulative ICMP counters, and where they stood at the previous check.
+#[derive(Default)]
+struct IcmpActivity {
+    counts: HashMap<(u8, u8), u64>,
+    packets: u64,
+    echo_bytes: u64,
+    last_check: Option<(Instant, u64, u64)>,
+}
+
+impl IcmpActivity {
+    fn record(&mut self, icmp_type: u8, code: u8, echo: bool, payload_len: usize) {
+        *self.counts.entry((icmp_type, code)).or_insert(0) += 1;
+        self.packets += 1;
+        if echo {
+            self.echo_bytes += payload_len as u64;
+        }
+    }
+
+    fn counts(&self) -> Vec<IcmpCount> {
+        let mut counts: Vec<IcmpCount> = self.counts.iter()
+            .map(|(&(icmp_type, code), &packets)| IcmpCount { icmp_type, code, packets })
+            .collect();
+        counts.sort_by_key(|count| (count.icmp_type, count.code));
+        counts
+    }
+
+    /// Packets and echo payload bytes per second since the previous call;
+    /// None on the first.
+    fn rates(&mut self, now: Instant) -> Option<(f64, f64)> {
+        let previous = self.last_check.replace((now, self.packets, self.echo_bytes));
+        let (at, packets, echo_bytes) = previous?;
+        let elapsed = now.duration_since(at).as_secs_f64();
+        if elapsed <= 0.0 {
+            return None;
+        }
+        Some((
+            (self.packets - packets) as f64 / elapsed,
+            (self.echo_bytes - echo_bytes) as f64 / elapsed,
+        ))
+    }
+}
+
+/// When each process was first seen talking to each remote endpoint.
+#[derive(Default)]
+struct FanOut {
+    first_seen: HashMap<(u32, SocketAddr), Instant>,
+    /// Pids already alerted on, so one burst raises one alert per window
+    reported: HashMap<u32, Instant>,
+}
+
+impl FanOut {
+    /// Records the current `(pid, remote)` pairs and returns each pid that
+    /// crossed a limit, with its distinct remote port and IP counts.
+    fn observe(
+        &mut self,
+        endpoints: impl IntoIterator<Item = (u32, SocketAddr)>,
+        config: &FanOutConfig,
+        now: Instant,
+    ) -> Vec<(u32, usize, usize)> {
+        let window = config.window();
+        let mut current = HashSet::new();
+        for endpoint in endpoints {
+            self.first_seen.entry(endpoint).or_insert(now);
+            current.insert(endpoint);
+        }
+        self.first_seen.retain(|endpoint, seen| current.contains(endpoint) || now.duration_since(*seen) < window);
+        self.reported.retain(|_, at| now.duration_since(*at) < window);
+
+        let mut recent: HashMap<u32, (HashSet<u16>, HashSet<IpAddr>)> = HashMap::new();
+        for ((pid, remote), seen) in &self.first_seen {
+            if now.duration_since(*seen) < window {
+                let (ports, ips) = recent.entry(*pid).or_default();
+                ports.insert(remote.port());
+                ips.insert(remote.ip());
+            }
+        }
+
+        let mut flagged: Vec<(u32, usize, usize)> = recent.into_iter()
+            .map(|(pid, (ports, ips))| (pid, ports.len(), ips.len()))
+            .filter(|(_, ports, ips)| *ports > config.max_remote_ports || *ips > config.max_remote_ips)
+            .filter(|(pid, _, _)| !self.reported.contains_key(pid))
+            .collect();
+        flagged.sort();
+        for (pid, _, _) in &flagged {
+            self.reported.insert(*pid, now);
+        }
+        flagged
+    }
+}
+
+/// Thresholds for flagging remote endpoints that are reconnected to at a
+/// suspiciously steady rate, as C2 implants checking in tend to be.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BeaconConfig {
+    /// Inter-arrival intervals needed before an endpoint is judged
+    pub min_samples: usize,
+    /// Largest standard deviation of the intervals, as a fraction of their
+    /// mean, still considered regular
+    pub max_jitter: f64,
+    /// Shorter periods are ignored; bursts of reconnects are not beacons
+    pub min_period_secs: u64,
+    /// Endpoints not reconnected to for this long are forgotten
+    pub max_period_secs: u64,
+}
+
+impl Default for BeaconConfig {
+    fn default() -> Self {
+        Self {
+            min_samples: 6,
+            max_jitter: 0.1,
+            min_period_secs: 10,
+            max_period_secs: 3600,
+        }
+    }
+}
+
+/// A remote endpoint reconnected to at a steady rate.
+#[derive(Debug, Clone, PartialEq)]
+struct Beacon {
+    remote: SocketAddr,
+    period: Duration,
+    /// Standard deviation of the intervals over their mean
+    jitter: f64,
+    samples: usize,
+}
+
+/// When connections to each remote endpoint were established.
+#[derive(Default)]
+struct Beacons {
+    /// Connection keys present at the previous observation
+    seen: HashSet<String>,
+    arrivals: HashMap<SocketAddr, VecDeque<Instant>>,
+    /// Endpoints already alerted on
+    reported: HashSet<SocketAddr>,
+}
+
+impl Beacons {
+    /// Records the connections not present at the previous call as
+    /// established at `now` and returns the endpoints whose recent
+    /// establishments became regular enough to flag. Several connections to
+    /// one endpoint within a call count once.
+    fn observe(
+        &mut self,
+        connections: impl IntoIterator<Item = (String, SocketAddr)>,
+        config: &BeaconConfig,
+        now: Instant,
+    ) -> Vec<Beacon> {
+        let mut current = HashSet::new();
+        let mut established = HashSet::new();
+        for (key, remote) in connections {
+            if !self.seen.contains(&key) {
+                established.insert(remote);
+            }
+            current.insert(key);
+        }
+        self.seen = current;
+
+        for remote in &established {
+            let arrivals = self.arrivals.entry(*remote).or_default();
+            arrivals.push_back(now);
+            while arrivals.len() > config.min_samples + 1 {
+                arrivals.pop_front();
+            }
+        }
+        let max_period = Duration::from_secs(config.max_period_secs);
+        self.arrivals.retain(|_, arrivals| {
+            arrivals.back().is_some_and(|last| now.duration_since(*last) <= max_period)
+        });
+        let arrivals = &self.arrivals;
+        self.reported.retain(|remote| arrivals.contains_key(remote));
+
+        let mut flagged = Vec::new();
+        for remote in established {
+            if self.reported.contains(&remote) {
+                continue;
+            }
+            let arrivals = &self.arrivals[&remote];
+            if config.min_samples == 0 || arrivals.len() <= config.min_samples {
+                continue;
+            }
+            let intervals: Vec<f64> = arrivals.iter().zip(arrivals.iter().skip(1))
+                .map(|(earlier, later)| later.duration_since(*earlier).as_secs_f64())
+                .collect();
+            let mean = intervals.iter().sum::<f64>() / intervals.len() as f64;
+            if mean < config.min_period_secs as f64 || mean <= 0.0 {
+                continue;
+            }
+            let variance = intervals.iter().map(|i| (i - mean).powi(2)).sum::<f64>() / intervals.len() as f64;
+            let jitter = variance.sqrt() / mean;
+            if jitter <= config.max_jitter {
+                self.reported.insert(remote);
+                flagged.push(Beacon {
+                    remote,
+                    period: Duration::from_secs_f64(mean),
+                    jitter,
+                    samples: intervals.len(),
+                });
+            }
+        }
+        flagged.sort_by_key(|beacon| beacon.remote);
+        flagged
+    }
+}
 
 pub struct NetworkMonitor {
     interfaces: Vec<NetworkInterface>,
     stats: Arc<RwLock<NetworkStats>>,
     connections: Arc<RwLock<HashMap<String, ConnectionInfo>>>,
-    resolver: Arc<Resolver>,
+    dns: Arc<ReverseDns>,
+    socket_owners: Arc<RwLock<SocketOwners>>,
+    last_sample: Arc<RwLock<Option<ThroughputSample>>>,
+    fan_out: Arc<std::sync::Mutex<FanOut>>,
+    fan_out_config: FanOutConfig,
+    beacons: Arc<std::sync::Mutex<Beacons>>,
+    beacon_config: BeaconConfig,
+    icmp: Arc<std::sync::Mutex<IcmpActivity>>,
+    icmp_config: IcmpConfig,
+    /// Interface names to capture on; every interface when None
+    capture_interfaces: Option<Vec<String>>,
+    /// Interface names never captured on, even if allowlisted
+    exclude_interfaces: Vec<String>,
+    /// pcap filter expression installed in the kernel on each capture
+    capture_filter: Option<String>,
+    #[cfg(feature = "geoip")]
+    geoip: Option<Arc<GeoIp>>,
+    shutdown: CancellationToken,
+    capture_mode: Arc<std::sync::Mutex<CaptureMode>>,
+    /// Connections idle for longer are dropped from the map
+    connection_ttl: Duration,
+    /// When the connection table was last rebuilt in `ProcInfo` mode
+    last_socket_poll: Arc<RwLock<Option<Instant>>>,
+}
+
+/// Byte counters at the previous `get_stats` call, used to derive rates.
+#[derive(Debug, Clone, Copy)]
+struct ThroughputSample {
+    bytes_sent: u64,
+    bytes_received: u64,
+    at: Instant,
+}
+
+/// Where a capture thread records the packets it reads, shared with the
+/// monitor and every other capture thread.
+struct PacketSinks<'a> {
+    stats: &'a Arc<RwLock<NetworkStats>>,
+    connections: &'a Arc<RwLock<HashMap<String, ConnectionInfo>>>,
+    dns: &'a ReverseDns,
+    socket_owners: &'a RwLock<SocketOwners>,
+    icmp: &'a std::sync::Mutex<IcmpActivity>,
+}
+
+/// Addresses of the interface a capture thread reads from, to tell the
+/// packets it sent from those it received.
+#[derive(Debug, Clone, Default)]
+struct LocalAddresses {
+    mac: Option<MacAddr>,
+    ips: HashSet<IpAddr>,
+}
+
+impl LocalAddresses {
+    fn of(interface: &NetworkInterface) -> Self {
+        Self {
+            mac: interface.mac,
+            ips: interface.ips.iter().map(|network| network.ip()).collect(),
+        }
+    }
+
+    /// Whether the packet originates here: by source IP for IP packets, by
+    /// source MAC otherwise (e.g. ARP).
+    fn is_outbound(&self, ethernet: &EthernetPacket) -> bool {
+        let source = match ethernet.get_ethertype() {
+            EtherTypes::Ipv4 => Ipv4Packet::new(ethernet.payload()).map(|ip| IpAddr::V4(ip.get_source())),
+            EtherTypes::Ipv6 => Ipv6Packet::new(ethernet.payload()).map(|ip| IpAddr::V6(ip.get_source())),
+            _ => None,
+        };
+        match source {
+            Some(ip) => self.ips.contains(&ip),
+            None => self.mac == Some(ethernet.get_source()),
+        }
+    }
+}
+
+/// Where connection data comes from, as settled by `start_monitoring`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureMode {
+    /// Packets are captured from BPF, so byte counts are available
+    Raw,
+    /// BPF is unavailable; connections are read from process socket tables,
+    /// which needs no privileges but carries no byte counts
+    ProcInfo,
+    /// Nothing is collected
+    Disabled,
+}
+
+impl Default for CaptureMode {
+    fn default() -> Self {
+        CaptureMode::Disabled
+    }
 }
 
-#[derive(Debug, Clone)]
+/// Maps local ports to the pid owning the socket, rebuilt from the process
+/// file descriptor tables at most once per `SOCKET_OWNER_REFRESH`.
+#[derive(Default)]
+struct SocketOwners {
+    owners: HashMap<(Protocol, u16), u32>,
+    refreshed_at: Option<Instant>,
+}
+
+impl SocketOwners {
+    async fn lookup(owners: &RwLock<SocketOwners>, protocol: &Protocol, ports: [u16; 2]) -> Option<u32> {
+        let find = |cache: &SocketOwners| {
+            ports.iter().find_map(|port| cache.owners.get(&(protocol.clone(), *port)).copied())
+        };
+
+        if let Some(pid) = find(&*owners.read().await) {
+            return Some(pid);
+        }
+
+        let mut cache = owners.write().await;
+        let stale = cache.refreshed_at.map_or(true, |at| at.elapsed() >= SOCKET_OWNER_REFRESH);
+        if stale {
+            match tokio::task::spawn_blocking(procinfo::list_sockets).await {
+                Ok(Ok(sockets)) => {
+                    cache.owners = sockets.into_iter()
+                        .map(|socket| ((socket.protocol, socket.local.port()), socket.pid))
+                        .collect();
+                }
+                Ok(Err(e)) => warn!("Failed to enumerate process sockets: {}", e),
+                Err(e) => warn!("Socket enumeration task failed: {}", e),
+            }
+            cache.refreshed_at = Some(Instant::now());
+        }
+
+        find(&*cache)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkStats {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub connections: Vec<ConnectionInfo>,
+    pub suspicious_activity: Vec<String>,
+    #[serde(default)]
+    pub bytes_sent_per_sec: f64,
+    #[serde(default)]
+    pub bytes_received_per_sec: f64,
+    /// Reverse DNS cache lookups answered from the cache since startup
+    #[serde(default)]
+    pub dns_cache_hits: u64,
+    /// Reverse DNS cache lookups that needed a query since startup
+    #[serde(default)]
+    pub dns_cache_misses: u64,
+    /// ICMP packets captured since startup
+    #[serde(default)]
+    pub icmp_packets: u64,
+    /// ICMP packets since startup by type and code
+    #[serde(default)]
+    pub icmp_types: Vec<IcmpCount>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConnectionInfo {
     pub local_addr: String,
     pub remote_addr: String,
@@ -30,9 +477,43 @@ pub struct ConnectionInfo {
     pub state: ConnectionState,
     pub process_id: Option<u32>,
     pub dns_name: Option<String>,
+    /// ISO country code of the remote end, when GeoIP enrichment is enabled
+    #[serde(default)]
+    pub country: Option<String>,
+    /// Autonomous system number of the remote end, when GeoIP enrichment is enabled
+    #[serde(default)]
+    pub asn: Option<u32>,
+    /// Payload bytes sent to the remote end since the connection was first seen
+    #[serde(default)]
+    pub bytes_sent: u64,
+    /// Payload bytes received from the remote end since the connection was first seen
+    #[serde(default)]
+    pub bytes_received: u64,
+    /// When a packet of this connection was last seen
+    #[serde(default = "Utc::now")]
+    pub last_seen: DateTime<Utc>,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+impl ConnectionInfo {
+    fn add_bytes(&mut self, length: u64, outbound: bool) {
+        if outbound {
+            self.bytes_sent += length;
+        } else {
+            self.bytes_received += length;
+        }
+    }
+
+    /// The remote end as an address. Accepts `SocketAddr` formatting
+    /// (`1.2.3.4:80`, `[::1]:80`) as well as an unbracketed `::1:80`.
+    pub fn remote_socket_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr.parse().ok().or_else(|| {
+            let (host, port) = self.remote_addr.rsplit_once(':')?;
+            Some(SocketAddr::new(host.parse().ok()?, port.parse().ok()?))
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Protocol {
     TCP,
     UDP,
@@ -40,19 +521,54 @@ pub enum Protocol {
     Other(u8),
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum ConnectionState {
+    /// A SYN went out with no reply yet
+    SynSent,
+    /// The SYN was answered with a SYN-ACK; waiting for the final ACK
+    SynReceived,
     Established,
+    /// One side has sent a FIN
+    FinWait,
     Listen,
     Closed,
     Unknown,
 }
 
+impl ConnectionState {
+    /// The state after a packet with TCP `flags`, as seen by an observer of
+    /// both directions. A flow first seen mid-stream (no SYN) is taken as
+    /// established; a SYN on a closed flow is a new connection reusing the
+    /// ports.
+    fn after_tcp_flags(&self, flags: u8) -> ConnectionState {
+        use ConnectionState::*;
+
+        if flags & TCP_RST != 0 {
+            return Closed;
+        }
+        if flags & TCP_SYN != 0 {
+            return if flags & TCP_ACK != 0 { SynReceived } else { SynSent };
+        }
+        if flags & TCP_FIN != 0 {
+            return match self {
+                // The second FIN; the final ACK adds nothing worth tracking
+                FinWait | Closed => Closed,
+                _ => FinWait,
+            };
+        }
+        match self {
+            SynReceived | Unknown | Listen => Established,
+            // An ACK alone can't tell a retransmitted SYN from the handshake
+            SynSent => SynSent,
+            state => state.clone(),
+        }
+    }
+}
+
 impl NetworkMonitor {
     pub fn new() -> Result<Self> {
         let interfaces = datalink::interfaces();
-        let resolver = Arc::new(Resolver::new(ResolverConfig::default(), ResolverOpts::default())?);
-        
+
         Ok(Self {
             interfaces,
             stats: Arc::new(RwLock::new(NetworkStats {
@@ -60,200 +576,623 @@ impl NetworkMonitor {
                 bytes_received: 0,
                 connections: Vec::new(),
                 suspicious_activity: Vec::new(),
+                bytes_sent_per_sec: 0.0,
+                bytes_received_per_sec: 0.0,
+                dns_cache_hits: 0,
+                dns_cache_misses: 0,
+                icmp_packets: 0,
+                icmp_types: Vec::new(),
             })),
             connections: Arc::new(RwLock::new(HashMap::new())),
-            resolver,
+            dns: Arc::new(ReverseDns::new()),
+            socket_owners: Arc::new(RwLock::new(SocketOwners::default())),
+            last_sample: Arc::new(RwLock::new(None)),
+            fan_out: Arc::new(std::sync::Mutex::new(FanOut::default())),
+            fan_out_config: FanOutConfig::default(),
+            beacons: Arc::new(std::sync::Mutex::new(Beacons::default())),
+            beacon_config: BeaconConfig::default(),
+            icmp: Arc::new(std::sync::Mutex::new(IcmpActivity::default())),
+            icmp_config: IcmpConfig::default(),
+            capture_interfaces: None,
+            exclude_interfaces: Vec::new(),
+            capture_filter: None,
+            #[cfg(feature = "geoip")]
+            geoip: None,
+            shutdown: CancellationToken::new(),
+            capture_mode: Arc::new(std::sync::Mutex::new(CaptureMode::Disabled)),
+            connection_ttl: Duration::from_secs(300),
+            last_socket_poll: Arc::new(RwLock::new(None)),
         })
     }
 
+    pub fn with_fan_out(mut self, config: FanOutConfig) -> Self {
+        self.fan_out_config = config;
+        self
+    }
+
+    pub fn with_beaconing(mut self, config: BeaconConfig) -> Self {
+        self.beacon_config = config;
+        self
+    }
+
+    pub fn with_icmp(mut self, config: IcmpConfig) -> Self {
+        self.icmp_config = config;
+        self
+    }
+
+    /// Restricts capture to the `include` interfaces when given, and never
+    /// captures on `exclude`. Names may end in `*` to match a prefix, e.g.
+    /// `utun*` for every VPN tunnel.
+    pub fn with_interfaces(mut self, include: Option<Vec<String>>, exclude: Vec<String>) -> Self {
+        self.capture_interfaces = include;
+        self.exclude_interfaces = exclude;
+        self
+    }
+
+    /// Captures only packets matching `filter`, in tcpdump syntax (e.g.
+    /// `tcp and not port 22`). It is compiled to BPF and run by the kernel,
+    /// so rejected packets never reach userspace. Fails on invalid syntax.
+    pub fn with_capture_filter(mut self, filter: Option<String>) -> Result<Self> {
+        if let Some(filter) = &filter {
+            pcap::Capture::dead(pcap::Linktype::ETHERNET)?
+                .compile(filter, true)
+                .map_err(|e| anyhow::anyhow!("Invalid capture_filter {:?}: {}", filter, e))?;
+        }
+        self.capture_filter = filter;
+        Ok(self)
+    }
+
+    fn should_capture(&self, name: &str) -> bool {
+        let matches = |pattern: &String| match pattern.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == pattern,
+        };
+        self.capture_interfaces.as_ref().map_or(true, |include| include.iter().any(matches))
+            && !self.exclude_interfaces.iter().any(matches)
+    }
+
+    pub fn with_connection_ttl(mut self, ttl: Duration) -> Self {
+        self.connection_ttl = ttl;
+        self
+    }
+
+    /// Tags connections with the remote end's country and ASN.
+    #[cfg(feature = "geoip")]
+    pub fn with_geoip(mut self, geoip: GeoIp) -> Self {
+        self.geoip = Some(Arc::new(geoip));
+        self
+    }
+
+    /// Starts capturing on every selected interface that is up, each on its
+    /// own thread since reads block. When no interface can be opened, usually
+    /// for lack of access to `/dev/bpf*`, connections are read from process
+    /// socket tables instead; fails only if that is unavailable too. See
+    /// `capture_mode` for what was settled on.
     pub async fn start_monitoring(&self) -> Result<()> {
         let stats = Arc::clone(&self.stats);
         let connections = Arc::clone(&self.connections);
+        let socket_owners = Arc::clone(&self.socket_owners);
+        let runtime = tokio::runtime::Handle::current();
 
+        if let Some(queue) = self.dns.take_queue() {
+            tokio::spawn(Self::resolve_names(queue, Arc::clone(&self.dns), Arc::clone(&connections)));
+        }
+
+        let mut capturing = 0;
+        let mut last_error = None;
         for interface in self.interfaces.iter() {
-            if !interface.is_up() || interface.is_loopback() {
+            if !interface.is_up() || interface.is_loopback() || !self.should_capture(&interface.name) {
                 continue;
             }
 
-            let channel = match datalink::channel(&interface, Default::default()) {
-                Ok(datalink::Channel::Ethernet(tx, rx)) => Some((tx, rx)),
-                _ => None,
+            let mut capture = match self.open_capture(interface) {
+                Ok(Some(capture)) => capture,
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!("Cannot capture on {}: {}", interface.name, e);
+                    last_error = Some(e);
+                    continue;
+                }
             };
 
-            if let Some((_tx, mut rx)) = channel {
-                let stats_clone = Arc::clone(&stats);
-                let connections_clone = Arc::clone(&connections);
-                let resolver = self.resolver.clone();
+            let stats_clone = Arc::clone(&stats);
+            let connections_clone = Arc::clone(&connections);
+            let dns = Arc::clone(&self.dns);
+            let owners_clone = Arc::clone(&socket_owners);
+            let icmp = Arc::clone(&self.icmp);
+            let local = LocalAddresses::of(interface);
+            let shutdown = self.shutdown.clone();
+            let runtime = runtime.clone();
 
-                tokio::spawn(async move {
-                    loop {
-                        match rx.next() {
+            let span = info_span!("capture", interface = %interface.name);
+            std::thread::Builder::new()
+                .name(format!("capture-{}", interface.name))
+                .spawn(move || {
+                    let _entered = span.entered();
+                    let sinks = PacketSinks {
+                        stats: &stats_clone,
+                        connections: &connections_clone,
+                        dns: &dns,
+                        socket_owners: &owners_clone,
+                        icmp: &icmp,
+                    };
+                    while !shutdown.is_cancelled() {
+                        match capture.next_packet() {
                             Ok(packet) => {
-                                if let Some(ethernet) = EthernetPacket::new(packet) {
-                                    Self::process_packet(
-                                        &ethernet,
-                                        &stats_clone,
-                                        &connections_clone,
-                                        &resolver,
-                                    ).await;
+                                if let Some(ethernet) = EthernetPacket::new(packet.data) {
+                                    runtime.block_on(Self::process_packet(&ethernet, &local, &sinks));
                                 }
                             }
+                            Err(pcap::Error::TimeoutExpired) => {}
                             Err(e) => warn!("Error receiving packet: {}", e),
                         }
                     }
-                });
+                })?;
+            capturing += 1;
+        }
+
+        if capturing > 0 {
+            info!("Capturing packets on {} interfaces", capturing);
+            self.set_capture_mode(CaptureMode::Raw);
+            tokio::spawn(Self::sweep_connections(
+                Arc::clone(&connections),
+                self.connection_ttl,
+                self.shutdown.clone(),
+            ));
+            return Ok(());
+        }
+
+        let reason = match last_error {
+            Some(e) => format!("packet capture unavailable ({}); run as root or grant access to /dev/bpf*", e),
+            None => "no selected network interface is up to capture on".to_string(),
+        };
+        match tokio::task::spawn_blocking(procinfo::list_sockets).await? {
+            Ok(_) => {
+                warn!("{}; falling back to process socket tables, without byte counts", reason);
+                self.set_capture_mode(CaptureMode::ProcInfo);
+                Ok(())
+            }
+            Err(e) => {
+                self.set_capture_mode(CaptureMode::Disabled);
+                Err(anyhow::anyhow!("{}, and process sockets can't be listed either: {}", reason, e))
+            }
+        }
+    }
+
+    /// Periodically drops connections idle beyond `ttl`, and closed ones once
+    /// they've lingered, so the map stays bounded on a busy host.
+    async fn sweep_connections(
+        connections: Arc<RwLock<HashMap<String, ConnectionInfo>>>,
+        ttl: Duration,
+        shutdown: CancellationToken,
+    ) {
+        let mut interval = tokio::time::interval(CONNECTION_SWEEP_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = interval.tick() => {}
+            }
+            let expired = Self::expire_connections(&mut *connections.write().await, ttl, Utc::now());
+            if expired > 0 {
+                info!("Expired {} idle connections", expired);
             }
         }
+    }
+
+    fn expire_connections(
+        connections: &mut HashMap<String, ConnectionInfo>,
+        ttl: Duration,
+        now: DateTime<Utc>,
+    ) -> usize {
+        let before = connections.len();
+        connections.retain(|_, connection| {
+            let limit = if connection.state == ConnectionState::Closed {
+                ttl.min(CLOSED_CONNECTION_LINGER)
+            } else {
+                ttl
+            };
+            let idle = now.signed_duration_since(connection.last_seen).to_std().unwrap_or_default();
+            idle <= limit
+        });
+        before - connections.len()
+    }
+
+    /// How connections are being collected.
+    pub fn capture_mode(&self) -> CaptureMode {
+        *self.capture_mode.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn set_capture_mode(&self, mode: CaptureMode) {
+        *self.capture_mode.lock().unwrap_or_else(|e| e.into_inner()) = mode;
+    }
+
+    /// In `ProcInfo` mode, rebuilds the connection table from the sockets of
+    /// every process we can inspect, at most once per `SOCKET_OWNER_REFRESH`.
+    /// Names already resolved are kept.
+    async fn poll_sockets(&self) -> Result<()> {
+        if self.capture_mode() != CaptureMode::ProcInfo {
+            return Ok(());
+        }
+        let mut last_poll = self.last_socket_poll.write().await;
+        if last_poll.is_some_and(|at| at.elapsed() < SOCKET_OWNER_REFRESH) {
+            return Ok(());
+        }
+        let sockets = tokio::task::spawn_blocking(procinfo::list_sockets).await??;
+        *last_poll = Some(Instant::now());
 
+        let mut connections = self.connections.write().await;
+        let previous = std::mem::take(&mut *connections);
+        for socket in &sockets {
+            if let Some((key, mut connection)) = Self::socket_connection(socket) {
+                connection.dns_name = previous.get(&key)
+                    .and_then(|old| old.dns_name.clone())
+                    .or_else(|| socket.remote.and_then(|remote| self.dns.name_or_enqueue(remote.ip())));
+                connections.insert(key, connection);
+            }
+        }
         Ok(())
     }
 
-    async fn process_packet(
-        ethernet: &EthernetPacket,
-        stats: &Arc<RwLock<NetworkStats>>,
-        connections: &Arc<RwLock<HashMap<String, ConnectionInfo>>>,
-        resolver: &Resolver,
-    ) {
-        let mut stats = stats.write().await;
-        stats.bytes_received += ethernet.packet().len() as u64;
+    /// A connected socket as a connection keyed like captured ones; unconnected
+    /// and listening sockets have no remote end and are skipped.
+    fn socket_connection(socket: &procinfo::SocketEntry) -> Option<(String, ConnectionInfo)> {
+        let remote = socket.remote?;
+        let state = match socket.tcp_state {
+            Some(procinfo::TSI_S_SYN_SENT) => ConnectionState::SynSent,
+            Some(procinfo::TSI_S_SYN_RECEIVED) => ConnectionState::SynReceived,
+            Some(procinfo::TSI_S_ESTABLISHED) => ConnectionState::Established,
+            Some(state) if procinfo::TSI_S_CLOSING.contains(&state) => ConnectionState::FinWait,
+            Some(procinfo::TSI_S_LISTEN) => ConnectionState::Listen,
+            Some(procinfo::TSI_S_CLOSED) => ConnectionState::Closed,
+            _ => ConnectionState::Unknown,
+        };
+        Some((
+            Self::connection_key(&socket.local, &remote),
+            ConnectionInfo {
+                local_addr: socket.local.to_string(),
+                remote_addr: remote.to_string(),
+                protocol: socket.protocol.clone(),
+                state,
+                process_id: Some(socket.pid),
+                dns_name: None,
+                country: None,
+                asn: None,
+                bytes_sent: 0,
+                bytes_received: 0,
+                last_seen: Utc::now(),
+            },
+        ))
+    }
+
+    /// Opens a capture on `interface` with the capture filter installed, or
+    /// None if it doesn't carry Ethernet frames.
+    fn open_capture(&self, interface: &NetworkInterface) -> Result<Option<pcap::Capture<pcap::Active>>, pcap::Error> {
+        let mut capture = pcap::Capture::from_device(interface.name.as_str())?
+            .immediate_mode(true)
+            .timeout(CAPTURE_READ_TIMEOUT.as_millis() as i32)
+            .open()?;
+        if capture.get_datalink() != pcap::Linktype::ETHERNET {
+            return Ok(None);
+        }
+        if let Some(filter) = &self.capture_filter {
+            capture.filter(filter, true)?;
+        }
+        Ok(Some(capture))
+    }
+
+    /// Stops the capture threads; each exits within `CAPTURE_READ_TIMEOUT`.
+    pub fn stop_monitoring(&self) {
+        self.shutdown.cancel();
+    }
+
+    async fn process_packet(ethernet: &EthernetPacket<'_>, local: &LocalAddresses, sinks: &PacketSinks<'_>) {
+        let outbound = local.is_outbound(ethernet);
+        {
+            let mut stats = sinks.stats.write().await;
+            let length = ethernet.packet().len() as u64;
+            if outbound {
+                stats.bytes_sent += length;
+            } else {
+                stats.bytes_received += length;
+            }
+        }
 
         match ethernet.get_ethertype() {
             EtherTypes::Ipv4 => {
                 if let Some(ipv4) = Ipv4Packet::new(ethernet.payload()) {
-                    match ipv4.get_next_level_protocol() {
-                        IpNextHeaderProtocols::Tcp => {
-                            if let Some(tcp) = TcpPacket::new(ipv4.payload()) {
-                                Self::process_tcp_packet(
-                                    &ipv4,
-                                    &tcp,
-                                    connections,
-                                    resolver,
-                                ).await;
-                            }
-                        }
-                        IpNextHeaderProtocols::Udp => {
-                            if let Some(udp) = UdpPacket::new(ipv4.payload()) {
-                                Self::process_udp_packet(
-                                    &ipv4,
-                                    &udp,
-                                    connections,
-                                    resolver,
-                                ).await;
-                            }
-                        }
-                        _ => {}
-                    }
+                    Self::process_ip_payload(
+                        IpAddr::V4(ipv4.get_source()),
+                        IpAddr::V4(ipv4.get_destination()),
+                        ipv4.get_next_level_protocol(),
+                        ipv4.payload(),
+                        outbound,
+                        sinks,
+                    ).await;
+                }
+            }
+            EtherTypes::Ipv6 => {
+                if let Some(ipv6) = Ipv6Packet::new(ethernet.payload()) {
+                    Self::process_ip_payload(
+                        IpAddr::V6(ipv6.get_source()),
+                        IpAddr::V6(ipv6.get_destination()),
+                        ipv6.get_next_header(),
+                        ipv6.payload(),
+                        outbound,
+                        sinks,
+                    ).await;
                 }
             }
             _ => {}
         }
     }
 
-    async fn process_tcp_packet(
-        ipv4: &Ipv4Packet,
-        tcp: &TcpPacket,
-        connections: &Arc<RwLock<HashMap<String, ConnectionInfo>>>,
-        resolver: &Resolver,
+    async fn process_ip_payload(
+        source: IpAddr,
+        destination: IpAddr,
+        protocol: IpNextHeaderProtocol,
+        payload: &[u8],
+        outbound: bool,
+        sinks: &PacketSinks<'_>,
     ) {
-        let mut connections = connections.write().await;
-        let connection_key = format!(
-            "{}:{}-{}:{}",
-            ipv4.get_source(),
-            tcp.get_source(),
-            ipv4.get_destination(),
-            tcp.get_destination()
-        );
-
-        if !connections.contains_key(&connection_key) {
-            // Perform reverse DNS lookup for new connections
-            let remote_addr = IpAddr::V4(ipv4.get_destination());
-            let dns_name = match resolver.reverse_lookup(remote_addr) {
-                Ok(response) => response.iter().next().map(|name| name.to_string()),
-                Err(_) => None,
-            };
+        match protocol {
+            IpNextHeaderProtocols::Tcp => {
+                if let Some(tcp) = TcpPacket::new(payload) {
+                    Self::process_tcp_packet(
+                        SocketAddr::new(source, tcp.get_source()),
+                        SocketAddr::new(destination, tcp.get_destination()),
+                        &tcp,
+                        outbound,
+                        sinks,
+                    ).await;
+                }
+            }
+            IpNextHeaderProtocols::Udp => {
+                if let Some(udp) = UdpPacket::new(payload) {
+                    Self::process_udp_packet(
+                        SocketAddr::new(source, udp.get_source()),
+                        SocketAddr::new(destination, udp.get_destination()),
+                        udp.payload().len() as u64,
+                        outbound,
+                        sinks,
+                    ).await;
+                }
+            }
+            IpNextHeaderProtocols::Icmp => {
+                if let Some(packet) = IcmpPacket::new(payload) {
+                    let icmp_type = packet.get_icmp_type().0;
+                    // Echo reply and echo request
+                    let echo = icmp_type == 0 || icmp_type == 8;
+                    sinks.icmp.lock().unwrap_or_else(|e| e.into_inner())
+                        .record(icmp_type, packet.get_icmp_code().0, echo, packet.payload().len());
+                }
+            }
+            IpNextHeaderProtocols::Icmpv6 => {
+                if let Some(packet) = Icmpv6Packet::new(payload) {
+                    let icmp_type = packet.get_icmpv6_type().0;
+                    let echo = icmp_type == 128 || icmp_type == 129;
+                    sinks.icmp.lock().unwrap_or_else(|e| e.into_inner())
+                        .record(icmp_type, packet.get_icmpv6_code().0, echo, packet.payload().len());
+                }
+            }
+            _ => {}
+        }
+    }
 
-            let connection = ConnectionInfo {
-                local_addr: format!("{}:{}", ipv4.get_source(), tcp.get_source()),
-                remote_addr: format!("{}:{}", ipv4.get_destination(), tcp.get_destination()),
-                protocol: Protocol::TCP,
-                state: if tcp.get_flags() & 0x02 != 0 {
-                    ConnectionState::Established
-                } else {
-                    ConnectionState::Unknown
-                },
-                process_id: None, // TODO: Implement process tracking
-                dns_name,
+    /// Drains the reverse DNS queue, running up to `DNS_CONCURRENCY` lookups at
+    /// once, and fills in the names of connections recorded without one.
+    async fn resolve_names(
+        mut queue: mpsc::Receiver<IpAddr>,
+        dns: Arc<ReverseDns>,
+        connections: Arc<RwLock<HashMap<String, ConnectionInfo>>>,
+    ) {
+        let limit = Arc::new(Semaphore::new(DNS_CONCURRENCY));
+        while let Some(ip) = queue.recv().await {
+            let permit = match Arc::clone(&limit).acquire_owned().await {
+                Ok(permit) => permit,
+                Err(_) => break,
             };
+            let dns = Arc::clone(&dns);
+            let connections = Arc::clone(&connections);
+            tokio::spawn(async move {
+                let _permit = permit;
+                if let Some(name) = dns.resolve(ip).await {
+                    Self::backfill_dns_name(&mut *connections.write().await, ip, &name);
+                }
+            });
+        }
+    }
 
-            connections.insert(connection_key, connection);
+    fn backfill_dns_name(connections: &mut HashMap<String, ConnectionInfo>, ip: IpAddr, name: &str) {
+        for connection in connections.values_mut() {
+            let matches = connection.remote_addr.parse::<SocketAddr>()
+                .is_ok_and(|remote| remote.ip() == ip);
+            if matches && connection.dns_name.is_none() {
+                connection.dns_name = Some(name.to_string());
+            }
         }
     }
 
+    /// Keys a connection by its endpoints. `SocketAddr` brackets IPv6 hosts,
+    /// so v4 and v6 keys can never collide.
+    fn connection_key(source: &SocketAddr, destination: &SocketAddr) -> String {
+        format!("{}-{}", source, destination)
+    }
+
+    async fn process_tcp_packet(
+        source: SocketAddr,
+        destination: SocketAddr,
+        tcp: &TcpPacket<'_>,
+        outbound: bool,
+        sinks: &PacketSinks<'_>,
+    ) {
+        Self::record_packet(
+            Protocol::TCP, source, destination, tcp.payload().len() as u64, outbound,
+            Some(tcp.get_flags()), sinks.connections, sinks.dns, sinks.socket_owners,
+        ).await;
+    }
+
     async fn process_udp_packet(
-        ipv4: &Ipv4Packet,
-        udp: &UdpPacket,
+        source: SocketAddr,
+        destination: SocketAddr,
+        payload_len: u64,
+        outbound: bool,
+        sinks: &PacketSinks<'_>,
+    ) {
+        Self::record_packet(
+            Protocol::UDP, source, destination, payload_len, outbound,
+            None, sinks.connections, sinks.dns, sinks.socket_owners,
+        ).await;
+    }
+
+    /// Adds a packet's payload to its connection, recording the connection on
+    /// first sight, and advances TCP connections by the packet's flags.
+    /// Connections are keyed local end first, so both directions of a flow
+    /// land on the same entry.
+    #[allow(clippy::too_many_arguments)]
+    async fn record_packet(
+        protocol: Protocol,
+        source: SocketAddr,
+        destination: SocketAddr,
+        payload_len: u64,
+        outbound: bool,
+        tcp_flags: Option<u8>,
         connections: &Arc<RwLock<HashMap<String, ConnectionInfo>>>,
-        resolver: &Resolver,
+        dns: &ReverseDns,
+        socket_owners: &RwLock<SocketOwners>,
     ) {
+        let (local, remote) = if outbound { (source, destination) } else { (destination, source) };
         let mut connections = connections.write().await;
-        let connection_key = format!(
-            "{}:{}-{}:{}",
-            ipv4.get_source(),
-            udp.get_source(),
-            ipv4.get_destination(),
-            udp.get_destination()
-        );
+        let connection_key = Self::connection_key(&local, &remote);
 
         if !connections.contains_key(&connection_key) {
-            let remote_addr = IpAddr::V4(ipv4.get_destination());
-            let dns_name = match resolver.reverse_lookup(remote_addr) {
-                Ok(response) => response.iter().next().map(|name| name.to_string()),
-                Err(_) => None,
-            };
+            // Names not yet cached are backfilled by the resolver task
+            let dns_name = dns.name_or_enqueue(remote.ip());
+            let process_id = SocketOwners::lookup(
+                socket_owners,
+                &protocol,
+                [local.port(), remote.port()],
+            ).await;
 
-            let connection = ConnectionInfo {
-                local_addr: format!("{}:{}", ipv4.get_source(), udp.get_source()),
-                remote_addr: format!("{}:{}", ipv4.get_destination(), udp.get_destination()),
-                protocol: Protocol::UDP,
+            connections.insert(connection_key.clone(), ConnectionInfo {
+                local_addr: local.to_string(),
+                remote_addr: remote.to_string(),
+                protocol,
                 state: ConnectionState::Unknown,
-                process_id: None,
+                process_id,
                 dns_name,
-            };
+                country: None,
+                asn: None,
+                bytes_sent: 0,
+                bytes_received: 0,
+                last_seen: Utc::now(),
+            });
+        }
 
-            connections.insert(connection_key, connection);
+        if let Some(connection) = connections.get_mut(&connection_key) {
+            connection.add_bytes(payload_len, outbound);
+            connection.last_seen = Utc::now();
+            if let Some(flags) = tcp_flags {
+                connection.state = connection.state.after_tcp_flags(flags);
+            }
         }
     }
 
     pub async fn get_stats(&self) -> Result<NetworkStats> {
-        Ok(self.stats.read().await.clone())
+        self.poll_sockets().await?;
+        let mut stats = self.stats.read().await.clone();
+        stats.connections = self.connections.read().await.values().cloned().collect();
+        let now = Instant::now();
+
+        let mut last_sample = self.last_sample.write().await;
+        if let Some(previous) = *last_sample {
+            let elapsed = now.duration_since(previous.at).as_secs_f64();
+            if elapsed > 0.0 {
+                stats.bytes_sent_per_sec =
+                    stats.bytes_sent.saturating_sub(previous.bytes_sent) as f64 / elapsed;
+                stats.bytes_received_per_sec =
+                    stats.bytes_received.saturating_sub(previous.bytes_received) as f64 / elapsed;
+            }
+        }
+        *last_sample = Some(ThroughputSample {
+            bytes_sent: stats.bytes_sent,
+            bytes_received: stats.bytes_received,
+            at: now,
+        });
+
+        {
+            let icmp = self.icmp.lock().unwrap_or_else(|e| e.into_inner());
+            stats.icmp_packets = icmp.packets;
+            stats.icmp_types = icmp.counts();
+        }
+
+        let dns_cache = self.dns.cache_stats();
+        stats.dns_cache_hits = dns_cache.hits;
+        stats.dns_cache_misses = dns_cache.misses;
+
+        #[cfg(feature = "geoip")]
+        if let Some(geoip) = &self.geoip {
+            for connection in &mut stats.connections {
+                geoip.enrich(connection);
+            }
+        }
+
+        Ok(stats)
     }
 
     pub async fn get_active_connections(&self) -> Result<Vec<ConnectionInfo>> {
+        self.poll_sockets().await?;
         let connections = self.connections.read().await;
         Ok(connections.values().cloned().collect())
     }
 
-    pub async fn check_suspicious_activity(&self) -> Result<Vec<String>> {
+    /// Local listeners as `(port, protocol, owning pid)`: TCP sockets in the
+    /// LISTEN state and bound UDP sockets below the ephemeral range (unconnected
+    /// UDP sockets above it are almost always clients, e.g. DNS lookups). Each
+    /// port is reported once even when bound on both IPv4 and IPv6.
+    pub async fn get_listening_ports(&self) -> Result<Vec<(u16, Protocol, Option<u32>)>> {
+        let sockets = tokio::task::spawn_blocking(procinfo::list_sockets).await??;
+
+        let mut listeners: HashMap<(u16, Protocol), Option<u32>> = HashMap::new();
+        for socket in sockets {
+            let port = socket.local.port();
+            let listening = match socket.protocol {
+                Protocol::TCP => socket.tcp_state == Some(procinfo::TSI_S_LISTEN),
+                Protocol::UDP => socket.remote.is_none() && port != 0 && port < EPHEMERAL_PORT_START,
+                _ => false,
+            };
+            if listening {
+                listeners.entry((port, socket.protocol)).or_insert(Some(socket.pid));
+            }
+        }
+
+        let mut listeners: Vec<_> = listeners.into_iter()
+            .map(|((port, protocol), pid)| (port, protocol, pid))
+            .collect();
+        listeners.sort_by_key(|(port, _, _)| *port);
+        Ok(listeners)
+    }
+
+    /// Describes connections to the policies' suspicious ports and domains.
+    pub async fn check_suspicious_activity(&self, policies: &SecurityPolicies) -> Result<Vec<String>> {
         let connections = self.connections.read().await;
         let mut suspicious = Vec::new();
 
         for conn in connections.values() {
-            // Check for common malicious ports
-            let port = conn.remote_addr.split(':').nth(1).unwrap_or("0").parse::<u16>().unwrap_or(0);
-            if Self::is_suspicious_port(port) {
-                suspicious.push(format!(
-                    "Suspicious connection to port {} from {}",
-                    port,
-                    conn.remote_addr
-                ));
+            if let Some(remote) = conn.remote_socket_addr() {
+                if policies.is_suspicious_port(remote.port()) {
+                    suspicious.push(format!(
+                        "Suspicious connection to port {} from {}",
+                        remote.port(),
+                        conn.remote_addr
+                    ));
+                }
             }
 
-            // Check for known malicious domains
             if let Some(ref dns_name) = conn.dns_name {
-                if Self::is_suspicious_domain(dns_name) {
+                if let Some(pattern) = policies.suspicious_domain(dns_name) {
                     suspicious.push(format!(
-                        "Connection to suspicious domain: {}",
-                        dns_name
+                        "Connection to suspicious domain: {} (matches {})",
+                        dns_name, pattern
                     ));
                 }
             }
@@ -262,28 +1201,126 @@ impl NetworkMonitor {
         Ok(suspicious)
     }
 
-    fn is_suspicious_port(port: u16) -> bool {
-        // Add more suspicious ports as needed
-        let suspicious_ports = [
-            22, // SSH
-            23, // Telnet
-            445, // SMB
-            3389, // RDP
-            4444, // Common malware
-            5900, // VNC
-        ];
-        suspicious_ports.contains(&port)
+    /// Alerts on processes that contacted more distinct remote ports or IPs
+    /// than `FanOutConfig` allows within its window, a sign of scanning or
+    /// C2 fan-out that the static port and domain lists miss. Meant to be
+    /// called once per collection cycle; connections without an owning pid
+    /// are ignored.
+    pub async fn check_fan_out(&self) -> Vec<SecurityAlert> {
+        let endpoints: Vec<(u32, SocketAddr)> = self.connections.read().await
+            .values()
+            .filter_map(|conn| Some((conn.process_id?, conn.remote_socket_addr()?)))
+            .collect();
+
+        let window = self.fan_out_config.window_secs;
+        self.fan_out.lock().unwrap_or_else(|e| e.into_inner())
+            .observe(endpoints, &self.fan_out_config, Instant::now())
+            .into_iter()
+            .map(|(pid, ports, ips)| SecurityAlert {
+                timestamp: Utc::now(),
+                severity: AlertSeverity::High,
+                description: format!(
+                    "PID {} contacted {} distinct remote ports on {} hosts within {}s; possible port scan or C2 beaconing",
+                    pid, ports, ips, window
+                ),
+                source: FAN_OUT_SOURCE.to_string(),
+                recommendation: Some(format!("Identify and investigate the process with PID {}", pid)),
+                count: 1,
+                would_enforce: false,
+                rule: None,
+                pid: Some(pid),
+                remote_ip: None,
+            })
+            .collect()
     }
 
-    fn is_suspicious_domain(domain: &str) -> bool {
-        // Add more suspicious domain patterns
-        let suspicious_patterns = [
-            ".xyz",
-            ".top",
-            "pastebin.com",
-            "ngrok.io",
-        ];
-        suspicious_patterns.iter().any(|&pattern| domain.contains(pattern))
+    /// Alerts on remote endpoints whose connections are established at a
+    /// steady period, e.g. an implant checking in every 60s give or take some
+    /// jitter. Connections are timestamped when first seen by this check, so
+    /// it must be called once per collection cycle and periods are only as
+    /// precise as the cycle interval.
+    pub async fn check_beaconing(&self) -> Vec<SecurityAlert> {
+        let connections: Vec<(String, SocketAddr)> = self.connections.read().await
+            .iter()
+            .filter(|(_, conn)| conn.state != ConnectionState::Listen)
+            .filter_map(|(key, conn)| Some((key.clone(), conn.remote_socket_addr()?)))
+            .filter(|(_, remote)| {
+                remote.port() != 0 && !remote.ip().is_loopback() && !remote.ip().is_unspecified()
+            })
+            .collect();
+
+        self.beacons.lock().unwrap_or_else(|e| e.into_inner())
+            .observe(connections, &self.beacon_config, Instant::now())
+            .into_iter()
+            .map(|beacon| SecurityAlert {
+                timestamp: Utc::now(),
+                severity: AlertSeverity::Medium,
+                description: format!(
+                    "Connections to {} recur every {:.1}s (jitter {:.0}%) over {} intervals; possible C2 beaconing",
+                    beacon.remote,
+                    beacon.period.as_secs_f64(),
+                    beacon.jitter * 100.0,
+                    beacon.samples
+                ),
+                source: BEACON_SOURCE.to_string(),
+                recommendation: Some(format!(
+                    "Identify the process connecting to {} and check whether the traffic is expected",
+                    beacon.remote
+                )),
+                count: 1,
+                would_enforce: false,
+                rule: None,
+                pid: None,
+                remote_ip: Some(beacon.remote.ip()),
+            })
+            .collect()
+    }
+
+    /// Alerts when ICMP traffic since the previous call exceeds `IcmpConfig`,
+    /// flagging floods and data smuggled in echo payloads. Meant to be called
+    /// once per collection cycle; the first call only sets the baseline.
+    pub async fn check_icmp(&self) -> Vec<SecurityAlert> {
+        let rates = self.icmp.lock().unwrap_or_else(|e| e.into_inner()).rates(Instant::now());
+        let Some((packets_per_sec, echo_bytes_per_sec)) = rates else {
+            return Vec::new();
+        };
+
+        let mut alerts = Vec::new();
+        if packets_per_sec > self.icmp_config.max_packets_per_sec {
+            alerts.push(SecurityAlert {
+                timestamp: Utc::now(),
+                severity: AlertSeverity::Medium,
+                description: format!(
+                    "ICMP traffic at {:.0} packets/s exceeds {:.0}/s; possible ping flood or sweep",
+                    packets_per_sec, self.icmp_config.max_packets_per_sec
+                ),
+                source: ICMP_SOURCE.to_string(),
+                recommendation: Some("Check which hosts are sending or receiving the ICMP traffic".to_string()),
+                count: 1,
+                would_enforce: false,
+                rule: None,
+                pid: None,
+                remote_ip: None,
+            });
+        }
+        if echo_bytes_per_sec > self.icmp_config.max_echo_bytes_per_sec {
+            alerts.push(SecurityAlert {
+                timestamp: Utc::now(),
+                severity: AlertSeverity::High,
+                description: format!(
+                    "ICMP echo payloads at {:.0} bytes/s exceed {:.0}/s; possible ICMP tunnel",
+                    echo_bytes_per_sec, self.icmp_config.max_echo_bytes_per_sec
+                ),
+                source: ICMP_SOURCE.to_string(),
+                recommendation: Some("Capture the echo traffic and inspect its payloads for exfiltrated data".to_string()),
+                count: 1,
+                would_enforce: false,
+                rule: None,
+                pid: None,
+                remote_ip: None,
+            });
+        }
+        alerts
     }
 }
 
@@ -304,4 +1341,314 @@ mod tests {
         let stats = monitor.get_stats().await;
         assert!(stats.is_ok());
     }
+
+    #[test]
+    fn test_fan_out_flags_port_sprays_once_per_window() {
+        let config = FanOutConfig {
+            window_secs: 60,
+            max_remote_ports: 3,
+            max_remote_ips: 2,
+        };
+        let target: IpAddr = "10.0.0.5".parse().unwrap();
+        let mut fan_out = FanOut::default();
+        let start = Instant::now();
+
+        // A browser-like process: one port, two hosts
+        let normal = [
+            (10, SocketAddr::new(target, 443)),
+            (10, SocketAddr::new("10.0.0.6".parse().unwrap(), 443)),
+        ];
+        assert!(fan_out.observe(normal, &config, start).is_empty());
+
+        let scan: Vec<(u32, SocketAddr)> = (20..25).map(|port| (20, SocketAddr::new(target, port))).collect();
+        assert_eq!(fan_out.observe(scan.clone(), &config, start), vec![(20, 5, 1)]);
+        assert!(fan_out.observe(scan.clone(), &config, start + Duration::from_secs(1)).is_empty());
+
+        // Once the window has passed, the old endpoints no longer count
+        assert!(fan_out.observe(scan, &config, start + Duration::from_secs(61)).is_empty());
+    }
+
+    #[test]
+    fn test_beaconing_flags_regular_reconnects() {
+        let config = BeaconConfig {
+            min_samples: 4,
+            max_jitter: 0.1,
+            min_period_secs: 10,
+            max_period_secs: 600,
+        };
+        let c2: SocketAddr = "203.0.113.7:443".parse().unwrap();
+        let web: SocketAddr = "198.51.100.3:443".parse().unwrap();
+        let mut beacons = Beacons::default();
+        let start = Instant::now();
+
+        // The implant reconnects every 60s +/- 2s; the browser at random
+        let c2_offsets = [0, 61, 119, 181, 240];
+        let web_offsets = [5, 20, 140, 150, 300];
+        let mut flagged = Vec::new();
+        for tick in 0..=300 {
+            // Each reconnect shows up under a new key (a new source port)
+            let mut connections = Vec::new();
+            for (name, offsets, remote) in [("c2", &c2_offsets, c2), ("web", &web_offsets, web)] {
+                let n = offsets.iter().filter(|&&at| at <= tick).count();
+                if n > 0 {
+                    connections.push((format!("{}-{}", name, n), remote));
+                }
+            }
+            flagged.extend(beacons.observe(connections, &config, start + Duration::from_secs(tick)));
+        }
+
+        assert_eq!(flagged.len(), 1);
+        assert_eq!(flagged[0].remote, c2);
+        assert_eq!(flagged[0].period, Duration::from_secs(60));
+        assert_eq!(flagged[0].samples, 4);
+        assert!(flagged[0].jitter < 0.1);
+    }
+
+    #[test]
+    fn test_packet_direction() {
+        let local = LocalAddresses {
+            mac: Some(MacAddr::new(0, 1, 2, 3, 4, 5)),
+            ips: HashSet::from(["10.0.0.2".parse().unwrap()]),
+        };
+        let ipv4_frame = |source: [u8; 4], destination: [u8; 4]| {
+            let mut frame = vec![0u8; 14 + 20];
+            frame[12..14].copy_from_slice(&[0x08, 0x00]);
+            frame[14] = 0x45;
+            frame[26..30].copy_from_slice(&source);
+            frame[30..34].copy_from_slice(&destination);
+            frame
+        };
+
+        let outbound = ipv4_frame([10, 0, 0, 2], [93, 184, 216, 34]);
+        assert!(local.is_outbound(&EthernetPacket::new(&outbound).unwrap()));
+        let inbound = ipv4_frame([93, 184, 216, 34], [10, 0, 0, 2]);
+        assert!(!local.is_outbound(&EthernetPacket::new(&inbound).unwrap()));
+
+        // ARP from this interface, matched by MAC
+        let mut arp = vec![0u8; 14 + 28];
+        arp[6..12].copy_from_slice(&[0, 1, 2, 3, 4, 5]);
+        arp[12..14].copy_from_slice(&[0x08, 0x06]);
+        assert!(local.is_outbound(&EthernetPacket::new(&arp).unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_connection_bytes_accumulate_in_both_directions() {
+        let connections = Arc::new(RwLock::new(HashMap::new()));
+        let dns = ReverseDns::new();
+        let owners = RwLock::new(SocketOwners::default());
+        let local: SocketAddr = "10.0.0.2:50000".parse().unwrap();
+        let remote: SocketAddr = "93.184.216.34:443".parse().unwrap();
+
+        for (source, destination, length, outbound) in [
+            (local, remote, 500, true),
+            (remote, local, 1500, false),
+            (local, remote, 200, true),
+        ] {
+            NetworkMonitor::record_packet(
+                Protocol::TCP, source, destination, length, outbound,
+                Some(TCP_ACK), &connections, &dns, &owners,
+            ).await;
+        }
+
+        let connections = connections.read().await;
+        assert_eq!(connections.len(), 1);
+        let connection = &connections[&NetworkMonitor::connection_key(&local, &remote)];
+        assert_eq!(connection.local_addr, "10.0.0.2:50000");
+        assert_eq!(connection.bytes_sent, 700);
+        assert_eq!(connection.bytes_received, 1500);
+    }
+
+    #[test]
+    fn test_capture_filter_validated() {
+        let monitor = NetworkMonitor::new().unwrap();
+        let monitor = monitor.with_capture_filter(Some("tcp and not port 22".to_string())).unwrap();
+        assert_eq!(monitor.capture_filter.as_deref(), Some("tcp and not port 22"));
+
+        let error = NetworkMonitor::new().unwrap()
+            .with_capture_filter(Some("tcp and nto port 22".to_string()))
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(error.contains("Invalid capture_filter"), "{}", error);
+    }
+
+    #[test]
+    fn test_interface_selection() {
+        let monitor = NetworkMonitor::new().unwrap();
+        assert!(monitor.should_capture("en0"));
+        assert!(monitor.should_capture("bridge100"));
+
+        let monitor = monitor.with_interfaces(
+            Some(vec!["en0".to_string(), "utun*".to_string()]),
+            vec!["utun3".to_string()],
+        );
+        assert!(monitor.should_capture("en0"));
+        assert!(monitor.should_capture("utun0"));
+        assert!(!monitor.should_capture("utun3"));
+        assert!(!monitor.should_capture("en1"));
+
+        let monitor = monitor.with_interfaces(None, vec!["bridge*".to_string()]);
+        assert!(monitor.should_capture("en1"));
+        assert!(!monitor.should_capture("bridge100"));
+    }
+
+    #[test]
+    fn test_icmp_activity_rates() {
+        let mut icmp = IcmpActivity::default();
+        let start = Instant::now();
+        assert_eq!(icmp.rates(start), None);
+
+        for _ in 0..10 {
+            icmp.record(8, 0, true, 1000);
+        }
+        icmp.record(3, 1, false, 28);
+        assert_eq!(icmp.rates(start + Duration::from_secs(2)), Some((5.5, 5000.0)));
+        assert_eq!(icmp.counts(), vec![
+            IcmpCount { icmp_type: 3, code: 1, packets: 1 },
+            IcmpCount { icmp_type: 8, code: 0, packets: 10 },
+        ]);
+
+        // Rates cover only the traffic since the previous call
+        assert_eq!(icmp.rates(start + Duration::from_secs(3)), Some((0.0, 0.0)));
+    }
+
+    #[test]
+    fn test_tcp_state_machine() {
+        use ConnectionState::*;
+
+        let replay = |packets: &[u8]| {
+            packets.iter().fold(Unknown, |state, &flags| state.after_tcp_flags(flags))
+        };
+
+        assert_eq!(replay(&[TCP_SYN]), SynSent);
+        assert_eq!(replay(&[TCP_SYN, TCP_SYN]), SynSent);
+        assert_eq!(replay(&[TCP_SYN, TCP_SYN | TCP_ACK]), SynReceived);
+        assert_eq!(replay(&[TCP_SYN, TCP_SYN | TCP_ACK, TCP_ACK]), Established);
+        assert_eq!(replay(&[TCP_SYN, TCP_RST | TCP_ACK]), Closed);
+
+        let handshake = [TCP_SYN, TCP_SYN | TCP_ACK, TCP_ACK];
+        let teardown = [TCP_FIN | TCP_ACK, TCP_ACK, TCP_FIN | TCP_ACK, TCP_ACK];
+        assert_eq!(replay(&[&handshake[..], &teardown[..2]].concat()), FinWait);
+        assert_eq!(replay(&[&handshake[..], &teardown[..]].concat()), Closed);
+
+        // Joined mid-stream, and a port reused after close
+        assert_eq!(replay(&[TCP_ACK]), Established);
+        assert_eq!(replay(&[TCP_RST, TCP_SYN]), SynSent);
+    }
+
+    #[test]
+    fn test_expire_connections() {
+        let now = Utc::now();
+        let connection = |state, idle_secs| ConnectionInfo {
+            local_addr: "10.0.0.2:50000".to_string(),
+            remote_addr: "93.184.216.34:443".to_string(),
+            protocol: Protocol::TCP,
+            state,
+            process_id: None,
+            dns_name: None,
+            country: None,
+            asn: None,
+            bytes_sent: 0,
+            bytes_received: 0,
+            last_seen: now - chrono::Duration::seconds(idle_secs),
+        };
+        let mut connections = HashMap::from([
+            ("active".to_string(), connection(ConnectionState::Established, 5)),
+            ("idle".to_string(), connection(ConnectionState::Established, 600)),
+            ("recently closed".to_string(), connection(ConnectionState::Closed, 5)),
+            ("closed".to_string(), connection(ConnectionState::Closed, 60)),
+        ]);
+
+        let expired = NetworkMonitor::expire_connections(&mut connections, Duration::from_secs(300), now);
+        assert_eq!(expired, 2);
+        let mut remaining: Vec<_> = connections.keys().map(String::as_str).collect();
+        remaining.sort();
+        assert_eq!(remaining, ["active", "recently closed"]);
+    }
+
+    #[test]
+    fn test_socket_connection_from_proc_info() {
+        let socket = |remote: Option<&str>, tcp_state| procinfo::SocketEntry {
+            pid: 42,
+            protocol: Protocol::TCP,
+            local: "10.0.0.2:50000".parse().unwrap(),
+            remote: remote.map(|remote| remote.parse().unwrap()),
+            tcp_state,
+        };
+
+        let (key, connection) = NetworkMonitor::socket_connection(
+            &socket(Some("93.184.216.34:443"), Some(procinfo::TSI_S_ESTABLISHED)),
+        ).unwrap();
+        assert_eq!(key, "10.0.0.2:50000-93.184.216.34:443");
+        assert_eq!(connection.state, ConnectionState::Established);
+        assert_eq!(connection.process_id, Some(42));
+
+        assert!(NetworkMonitor::socket_connection(&socket(None, Some(procinfo::TSI_S_LISTEN))).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_capture_mode_settles_on_start() {
+        let monitor = NetworkMonitor::new().unwrap();
+        assert_eq!(monitor.capture_mode(), CaptureMode::Disabled);
+
+        match monitor.start_monitoring().await {
+            Ok(()) => assert_ne!(monitor.capture_mode(), CaptureMode::Disabled),
+            Err(_) => assert_eq!(monitor.capture_mode(), CaptureMode::Disabled),
+        }
+        monitor.get_active_connections().await.unwrap();
+        monitor.stop_monitoring();
+    }
+
+    #[test]
+    fn test_connection_keys_distinguish_address_families() {
+        let v4 = SocketAddr::new(IpAddr::V4("0.0.0.1".parse().unwrap()), 80);
+        let v6 = SocketAddr::new(IpAddr::V6("::1".parse().unwrap()), 80);
+        let peer = SocketAddr::new(IpAddr::V4("10.0.0.1".parse().unwrap()), 443);
+
+        let v4_key = NetworkMonitor::connection_key(&v4, &peer);
+        let v6_key = NetworkMonitor::connection_key(&v6, &peer);
+        assert_ne!(v4_key, v6_key);
+        assert_eq!(v6_key, "[::1]:80-10.0.0.1:443");
+    }
+
+    #[test]
+    fn test_backfill_dns_name() {
+        let connection = |remote: &str, dns_name: Option<&str>| ConnectionInfo {
+            local_addr: "10.0.0.2:50000".to_string(),
+            remote_addr: remote.to_string(),
+            protocol: Protocol::TCP,
+            state: ConnectionState::Established,
+            process_id: None,
+            dns_name: dns_name.map(str::to_string),
+            country: None,
+            asn: None,
+            bytes_sent: 0,
+            bytes_received: 0,
+            last_seen: Utc::now(),
+        };
+        let mut connections = HashMap::new();
+        connections.insert("a".to_string(), connection("192.0.2.1:443", None));
+        connections.insert("b".to_string(), connection("192.0.2.1:80", Some("old.example.")));
+        connections.insert("c".to_string(), connection("192.0.2.2:443", None));
+
+        NetworkMonitor::backfill_dns_name(&mut connections, "192.0.2.1".parse().unwrap(), "host.example.");
+        assert_eq!(connections["a"].dns_name.as_deref(), Some("host.example."));
+        assert_eq!(connections["b"].dns_name.as_deref(), Some("old.example."));
+        assert_eq!(connections["c"].dns_name, None);
+    }
+
+    #[tokio::test]
+    async fn test_throughput_rates() {
+        let monitor = NetworkMonitor::new().unwrap();
+        let first = monitor.get_stats().await.unwrap();
+        assert_eq!(first.bytes_received_per_sec, 0.0);
+
+        monitor.stats.write().await.bytes_received += 1000;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let second = monitor.get_stats().await.unwrap();
+        assert!(second.bytes_received_per_sec > 0.0);
+        assert!(second.bytes_received_per_sec <= 10_000.0);
+        assert_eq!(second.bytes_sent_per_sec, 0.0);
+    }
 } 
\ No newline at end of file
@@ -0,0 +1,128 @@
+use anyhow::Result;
+use libproc::libproc::file_info::{pidfdinfo, ListFDs, ProcFDType};
+use libproc::libproc::net_info::{InSockInfo, SocketFDInfo, SocketInfoKind};
+use libproc::libproc::proc_pid::{listpidinfo, pidinfo};
+use libproc::libproc::task_info::TaskInfo;
+use libproc::processes::{pids_by_type, ProcFilter};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use crate::network::Protocol;
+
+const INI_IPV4: u8 = 0x1;
+const INI_IPV6: u8 = 0x2;
+const MAX_FDS: usize = 4096;
+
+/// An internet socket owned by a process, read from its file descriptor table.
+#[derive(Debug, Clone)]
+pub struct SocketEntry {
+    pub pid: u32,
+    pub protocol: Protocol,
+    pub local: SocketAddr,
+    pub remote: Option<SocketAddr>,
+    /// Raw `TSI_S_*` state for TCP sockets
+    pub tcp_state: Option<i32>,
+}
+
+pub const TSI_S_LISTEN: i32 = 1;
+pub const TSI_S_CLOSED: i32 = 0;
+pub const TSI_S_SYN_SENT: i32 = 2;
+pub const TSI_S_SYN_RECEIVED: i32 = 3;
+pub const TSI_S_ESTABLISHED: i32 = 4;
+/// CLOSE_WAIT through TIME_WAIT, the states after either side sent a FIN
+pub const TSI_S_CLOSING: std::ops::RangeInclusive<i32> = 5..=10;
+
+/// Enumerates the internet sockets of every process we are allowed to inspect.
+/// This needs no special privileges, but only sees processes of the same user
+/// unless running as root.
+pub fn list_sockets() -> Result<Vec<SocketEntry>> {
+    let mut sockets = Vec::new();
+
+    for pid in pids_by_type(ProcFilter::All)? {
+        // Processes exit or deny access while we walk them; skip those
+        let fds = match listpidinfo::<ListFDs>(pid as i32, MAX_FDS) {
+            Ok(fds) => fds,
+            Err(_) => continue,
+        };
+
+        for fd in fds {
+            if !matches!(ProcFDType::from(fd.proc_fdtype), ProcFDType::Socket) {
+                continue;
+            }
+
+            if let Ok(info) = pidfdinfo::<SocketFDInfo>(pid as i32, fd.proc_fd) {
+                if let Some(entry) = socket_entry(pid, &info) {
+                    sockets.push(entry);
+                }
+            }
+        }
+    }
+
+    Ok(sockets)
+}
+
+/// Sums the context switches of every process we can inspect. Exited processes
+/// drop out of the total, so the sum is only meaningful as a short-term delta.
+pub fn total_context_switches() -> Result<u64> {
+    let total = pids_by_type(ProcFilter::All)?
+        .into_iter()
+        .filter_map(|pid| pidinfo::<TaskInfo>(pid as i32, 0).ok())
+        .map(|info| info.pti_csw.max(0) as u64)
+        .sum();
+
+    Ok(total)
+}
+
+/// Number of threads in a process, or `None` if it exited or we may not
+/// inspect it.
+pub fn thread_count(pid: u32) -> Option<u32> {
+    pidinfo::<TaskInfo>(pid as i32, 0)
+        .ok()
+        .map(|info| info.pti_threadnum.max(0) as u32)
+}
+
+fn socket_entry(pid: u32, info: &SocketFDInfo) -> Option<SocketEntry> {
+    let (protocol, in_info, tcp_state) = unsafe {
+        match SocketInfoKind::from(info.psi.soi_kind) {
+            SocketInfoKind::Tcp => {
+                let tcp = info.psi.soi_proto.pri_tcp;
+                (Protocol::TCP, tcp.tcpsi_ini, Some(tcp.tcpsi_state))
+            }
+            SocketInfoKind::In => (Protocol::UDP, info.psi.soi_proto.pri_in, None),
+            _ => return None,
+        }
+    };
+
+    let local_ip = socket_ip(&in_info, false)?;
+    let remote_ip = socket_ip(&in_info, true)?;
+    let local_port = port(in_info.insi_lport);
+    let remote_port = port(in_info.insi_fport);
+
+    Some(SocketEntry {
+        pid,
+        protocol,
+        local: SocketAddr::new(local_ip, local_port),
+        remote: if remote_port == 0 || remote_ip.is_unspecified() {
+            None
+        } else {
+            Some(SocketAddr::new(remote_ip, remote_port))
+        },
+        tcp_state,
+    })
+}
+
+fn socket_ip(info: &InSockInfo, remote: bool) -> Option<IpAddr> {
+    let addr = if remote { &info.insi_faddr } else { &info.insi_laddr };
+    unsafe {
+        if info.insi_vflag & INI_IPV4 != 0 {
+            Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(addr.ina_46.i46a_addr4.s_addr))))
+        } else if info.insi_vflag & INI_IPV6 != 0 {
+            Some(IpAddr::V6(Ipv6Addr::from(addr.ina_6.s6_addr)))
+        } else {
+            None
+        }
+    }
+}
+
+fn port(raw: i32) -> u16 {
+    // Ports are stored in network byte order in the low 16 bits
+    u16::from_be(raw as u16)
+}
@@ -1,11 +1,11 @@
 use anyhow::Result;
 use pyo3::prelude::*;
 use pyo3::types::{PyDict, PyList};
-use numpy::{PyArray1, PyArray2};
+use numpy::PyArray1;
 use crate::SystemState;
+use crate::features::{feature_matrix, FEATURE_COUNT};
 use std::sync::Arc;
 use tokio::sync::RwLock;
-use log::{info, error};
 
 pub struct PythonAnalyzer {
     py_runtime: Arc<RwLock<Option<PyObject>>>,
@@ -16,6 +16,7 @@ impl PythonAnalyzer {
         Python::with_gil(|py| {
             // Initialize Python runtime
             let locals = PyDict::new(py);
+            locals.set_item("N_FEATURES", FEATURE_COUNT)?;
             
             // Import required Python modules
             let code = r#"
@@ -40,9 +41,13 @@ class AnomalyDetector:
         if os.path.exists(model_path):
             try:
                 loaded = joblib.load(model_path)
-                self.model = loaded['model']
-                self.scaler = loaded['scaler']
-                self.is_fitted = True
+                # Models fit on a different feature layout can't score current states
+                if loaded.get('n_features') == N_FEATURES:
+                    self.model = loaded['model']
+                    self.scaler = loaded['scaler']
+                    self.is_fitted = True
+                else:
+                    print("Discarding saved model with an outdated feature layout")
             except Exception as e:
                 print(f"Error loading model: {e}")
     
@@ -56,7 +61,8 @@ class AnomalyDetector:
         os.makedirs(model_path, exist_ok=True)
         joblib.dump({
             'model': self.model,
-            'scaler': self.scaler
+            'scaler': self.scaler,
+            'n_features': N_FEATURES
         }, os.path.join(model_path, 'isolation_forest.joblib'))
     
     def predict(self, X):
@@ -85,35 +91,25 @@ detector = AnomalyDetector()
         })
     }
 
-    pub async fn analyze_state(&self, states: &[SystemState]) -> Result<Vec<(f64, bool)>> {
+    /// A new reference to the Python detector, taken without holding the
+    /// runtime lock while Python runs.
+    async fn detector(&self) -> Result<PyObject> {
+        let runtime = self.py_runtime.read().await;
+        let detector = runtime.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Python runtime not initialized"))?;
+        Ok(Python::with_gil(|py| detector.clone_ref(py)))
+    }
+
+    /// Scores each state; `allowed_ports` feeds the disallowed-port feature.
+    pub async fn analyze_state(&self, states: &[SystemState], allowed_ports: &[u16]) -> Result<Vec<(f64, bool)>> {
+        if states.is_empty() {
+            return Ok(Vec::new());
+        }
+        let detector = self.detector().await?;
+        let features = feature_matrix(states, allowed_ports);
+
         Python::with_gil(|py| {
-            let detector = self.py_runtime.read().await
-                .as_ref()
-                .ok_or_else(|| anyhow::anyhow!("Python runtime not initialized"))?
-                .clone_ref(py);
-
-            // Convert states to numpy array
-            let features: Vec<f64> = states.iter().flat_map(|state| {
-                vec![
-                    state.cpu_usage as f64,
-                    state.memory_usage as f64,
-                    state.disk_usage as f64,
-                    state.network_stats.bytes_sent as f64,
-                    state.network_stats.bytes_received as f64,
-                    state.active_processes.len() as f64,
-                ]
-            }).collect();
-
-            let n_samples = states.len();
-            let n_features = 6;
-
-            // Create a 2D array from the flattened features
-            let array = PyArray2::<f64>::new(
-                py,
-                [n_samples, n_features],
-                false
-            );
-            array.as_slice_mut().unwrap().copy_from_slice(&features);
+            let array = PyArray1::from_vec(py, features).reshape([states.len(), FEATURE_COUNT])?;
 
             // Get predictions and anomaly scores
             let predictions = detector.call_method1(py, "predict", (array,))?;
@@ -132,35 +128,15 @@ detector = AnomalyDetector()
         })
     }
 
-    pub async fn train_model(&self, states: &[SystemState]) -> Result<()> {
+    pub async fn train_model(&self, states: &[SystemState], allowed_ports: &[u16]) -> Result<()> {
+        if states.is_empty() {
+            return Ok(());
+        }
+        let detector = self.detector().await?;
+        let features = feature_matrix(states, allowed_ports);
+
         Python::with_gil(|py| {
-            let detector = self.py_runtime.read().await
-                .as_ref()
-                .ok_or_else(|| anyhow::anyhow!("Python runtime not initialized"))?
-                .clone_ref(py);
-
-            // Convert states to numpy array for training
-            let features: Vec<f64> = states.iter().flat_map(|state| {
-                vec![
-                    state.cpu_usage as f64,
-                    state.memory_usage as f64,
-                    state.disk_usage as f64,
-                    state.network_stats.bytes_sent as f64,
-                    state.network_stats.bytes_received as f64,
-                    state.active_processes.len() as f64,
-                ]
-            }).collect();
-
-            let n_samples = states.len();
-            let n_features = 6;
-
-            // Create a 2D array from the flattened features
-            let array = PyArray2::<f64>::new(
-                py,
-                [n_samples, n_features],
-                false
-            );
-            array.as_slice_mut().unwrap().copy_from_slice(&features);
+            let array = PyArray1::from_vec(py, features).reshape([states.len(), FEATURE_COUNT])?;
 
             // Train the model
             detector.call_method1(py, "fit", (array,))?;
@@ -196,13 +172,18 @@ mod tests {
                     bytes_received: 1000,
                     connections: vec![],
                     suspicious_activity: vec![],
+                    bytes_sent_per_sec: 0.0,
+                    bytes_received_per_sec: 0.0,
+                    dns_cache_hits: 0,
+                    dns_cache_misses: 0,
+                    icmp_packets: 0,
+                    icmp_types: Vec::new(),
                 },
-                active_processes: vec![],
-                security_alerts: vec![],
+                ..Default::default()
             },
         ];
 
-        let result = analyzer.analyze_state(&states).await;
+        let result = analyzer.analyze_state(&states, &[]).await;
         assert!(result.is_ok());
     }
 } 
\ No newline at end of file
@@ -2,21 +2,26 @@ use anyhow::Result;
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::RwLock;
-use crate::SystemState;
-use log::{info, warn, error};
+use crate::{SystemState, SecurityAlert, AlertSeverity, ProcessInfo};
+use crate::codesign;
+use crate::network::ConnectionInfo;
+use ipnet::IpNet;
+use std::net::IpAddr;
+use crate::exec_control::ExecPolicy;
+use crate::extensions::{self, APPLE_TEAM_ID};
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use tracing::{info, warn, error};
+use regex::Regex;
 use ring::digest::{Context, SHA256};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::fs;
-use core_foundation::{
-    base::TCFType,
-    string::CFString,
-    url::{CFURL, CFURLRef},
-    bundle::CFBundle,
-};
-use darwin_libproc::task_info;
+use std::os::unix::fs::MetadataExt;
+use std::io::{Read, Write};
 use mach::traps;
 use libc;
 use std::collections::HashSet;
+use std::ffi::CString;
 use security_framework::os::macos::keychain::{SecKeychain, SecKeychainSettings};
 use security_framework::os::macos::access::SecAccess;
 use security_framework::os::macos::identity::SecIdentity;
@@ -49,58 +54,757 @@ use security_framework::os::macos::keychain::SecKeychainGetKeychainACL;
 
 pub struct SecurityManager {
     keychain: SecKeychain,
-    policies: SecurityPolicies,
+    // std lock: reads are short and also happen from the sync checks below
+    policies: Arc<std::sync::RwLock<SecurityPolicies>>,
+    suspicious_matcher: Arc<std::sync::RwLock<ProcessMatcher>>,
     process_hashes: Arc<RwLock<HashMap<u32, String>>>,
-    codesign_cache: Arc<RwLock<HashMap<String, bool>>>,
+    /// Binary hashes by path, reused until the file changes
+    file_hashes: Arc<RwLock<HashMap<PathBuf, CachedHash>>>,
+    /// Signature verdicts by binary path, with the hash of the binary checked
+    codesign_cache: Arc<RwLock<HashMap<String, (String, bool)>>>,
+    /// Approved binary hashes; `None` until `load_known_hashes` is called
+    known_hashes: Arc<RwLock<Option<KnownHashes>>>,
+    service_liveness: Arc<RwLock<HashMap<String, ServiceLiveness>>>,
+    /// Since when swapping has stayed above `max_swap_rate`
+    swapping_since: Arc<RwLock<Option<DateTime<Utc>>>>,
+    enforcement_mode: EnforcementMode,
 }
 
+/// A file's hash and the metadata it was computed for. The change time is
+/// kept alongside the modification time because only the latter can be set
+/// back by the file's owner.
 #[derive(Debug, Clone)]
+struct CachedHash {
+    modified: std::time::SystemTime,
+    changed: (i64, i64),
+    len: u64,
+    hash: String,
+}
+
+impl CachedHash {
+    fn matches(&self, metadata: &fs::Metadata) -> bool {
+        metadata.modified().is_ok_and(|modified| modified == self.modified)
+            && (metadata.ctime(), metadata.ctime_nsec()) == self.changed
+            && metadata.len() == self.len
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ServiceLiveness {
+    last_seen: DateTime<Utc>,
+    present: bool,
+    alerted: bool,
+}
+
+/// Result of a liveness pass over the expected (critical) services.
+#[derive(Debug, Clone, Default)]
+pub struct LivenessReport {
+    /// Alerts for services missing longer than the grace period
+    pub alerts: Vec<SecurityAlert>,
+    /// Services that reappeared after having been alerted on
+    pub recovered: Vec<String>,
+}
+
+/// Every policy a state broke, with what to do about each.
+#[derive(Debug, Clone, Default)]
+pub struct PolicyViolation {
+    violations: Vec<Breach>,
+}
+
+/// One broken policy
+#[derive(Debug, Clone)]
+struct Breach {
+    rule: String,
+    description: String,
+    recommendation: String,
+    pid: Option<u32>,
+    remote_ip: Option<IpAddr>,
+}
+
+impl PolicyViolation {
+    /// Records a host-wide breach, such as CPU or disk usage
+    fn push(&mut self, rule: String, description: String, recommendation: String) {
+        self.violations.push(Breach { rule, description, recommendation, pid: None, remote_ip: None });
+    }
+
+    /// Records a breach by one process, keyed on `kind` and its pid
+    fn push_process(&mut self, kind: &str, pid: u32, description: String, recommendation: String) {
+        self.violations.push(Breach {
+            rule: format!("{}:{}", kind, pid),
+            description,
+            recommendation,
+            pid: Some(pid),
+            remote_ip: None,
+        });
+    }
+
+    /// Records a breach by one connection, keyed on `kind` and its remote address
+    fn push_connection(&mut self, kind: &str, connection: &ConnectionInfo, description: String, recommendation: String) {
+        self.violations.push(Breach {
+            rule: format!("{}:{}", kind, connection.remote_addr),
+            description,
+            recommendation,
+            pid: connection.process_id,
+            remote_ip: connection.remote_socket_addr().map(|addr| addr.ip()),
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.violations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    pub fn description(&self) -> String {
+        self.violations.iter()
+            .map(|breach| breach.description.as_str())
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+
+    /// Next steps for the operator, one per distinct violation
+    pub fn recommend(&self) -> Option<String> {
+        let mut recommendations: Vec<&str> = Vec::new();
+        for breach in &self.violations {
+            if !recommendations.contains(&breach.recommendation.as_str()) {
+                recommendations.push(&breach.recommendation);
+            }
+        }
+        if recommendations.is_empty() {
+            None
+        } else {
+            Some(recommendations.join("; "))
+        }
+    }
+
+    /// One alert per broken policy, keyed on its rule so a violation that
+    /// persists with changing readings folds into the alert it first raised.
+    pub fn alerts(&self) -> Vec<SecurityAlert> {
+        let now = Utc::now();
+        self.violations.iter()
+            .map(|breach| SecurityAlert {
+                timestamp: now,
+                severity: AlertSeverity::High,
+                description: breach.description.clone(),
+                source: POLICY_CHECK_SOURCE.to_string(),
+                recommendation: Some(breach.recommendation.clone()),
+                count: 1,
+                would_enforce: false,
+                rule: Some(breach.rule.clone()),
+                pid: breach.pid,
+                remote_ip: breach.remote_ip,
+            })
+            .collect()
+    }
+}
+
+fn block_outbound(connection: &ConnectionInfo) -> String {
+    match connection.remote_socket_addr() {
+        Some(addr) => format!("Block outbound to {}", addr.ip()),
+        None => format!("Block outbound to {}", connection.remote_addr),
+    }
+}
+
+/// Whether active responses (process termination, exec denial, dropping
+/// privileges) fire or are only reported.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EnforcementMode {
+    #[default]
+    Enforce,
+    /// Log and store violations, marking alerts `would_enforce` where a
+    /// response was held back
+    Monitor,
+}
+
+pub const SERVICE_LIVENESS_SOURCE: &str = "Service Liveness";
+pub const POLICY_CHECK_SOURCE: &str = "Security Policy Check";
+pub const PROCESS_ENFORCEMENT_SOURCE: &str = "Process Enforcement";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct SecurityPolicies {
     max_cpu_usage: f32,
     max_memory_usage: f32,
+    /// Usage above which each mounted filesystem is flagged on its own
+    max_disk_usage: f32,
+    /// One-minute load average per physical core above which the host is
+    /// considered saturated
+    max_load_average: f64,
+    /// Swap-ins plus swap-outs, in pages per second, above which the host is
+    /// considered to be thrashing
+    max_swap_rate: f64,
+    /// How long swapping must stay above `max_swap_rate` before it's flagged
+    swap_sustain_secs: u64,
+    /// Regexes (or substrings, see `suspicious_process_match`) checked against
+    /// each process name and command line
     suspicious_processes: Vec<String>,
+    suspicious_process_match: ProcessMatchMode,
     allowed_ports: Vec<u16>,
     allowed_domains: Vec<String>,
+    /// Remote ports worth reporting, as single ports or `"start-end"` ranges.
+    /// Must not overlap `allowed_ports`.
+    suspicious_ports: Vec<PortRange>,
+    /// Domains worth reporting; each matches itself and its subdomains, so a
+    /// bare TLD such as `xyz` matches every `.xyz` name
+    suspicious_domains: Vec<String>,
+    /// Networks connections may reach, e.g. corporate subnets and RFC 1918
+    /// ranges; any connection elsewhere is flagged. Empty disables the check.
+    /// Loopback and unspecified addresses are never flagged.
+    allowed_cidrs: Vec<IpNet>,
+    /// ISO country codes connections may reach; others are flagged when the
+    /// connection's country is known (see `geoip`). Empty disables the check.
+    allowed_countries: Vec<String>,
+    /// Leaf certificate common-name prefixes trusted to sign running
+    /// binaries; `Apple` stands for Apple's platform binaries
     allowed_signing_authorities: Vec<String>,
+    /// Developer ID team IDs trusted to sign running binaries
+    allowed_team_ids: Vec<String>,
     allowed_paths: HashSet<String>,
+    /// Directories binaries shouldn't run from, signed or not; a leading `~/`
+    /// matches inside any user's home
+    suspicious_exec_paths: Vec<String>,
+    /// Process names, or absolute executable paths when the name alone is
+    /// too easy to imitate
+    expected_processes: Vec<String>,
+    expected_process_grace_secs: u64,
+    exec_policy: ExecPolicy,
+    allowed_extension_teams: Vec<String>,
+    extension_check_interval_secs: u64,
+    max_process_cpu: f32,
+    max_process_memory: f32,
+    process_limit_overrides: HashMap<String, ProcessLimits>,
+    /// Kill processes matching `suspicious_processes` instead of only reporting them
+    auto_terminate_suspicious: bool,
+    /// Log the processes auto-termination would kill without signalling them
+    terminate_dry_run: bool,
+    /// Time between SIGTERM and SIGKILL
+    terminate_grace_secs: u64,
+    /// Long-running services and browsers that should never start an interactive shell
+    server_processes: Vec<String>,
+}
+
+impl SecurityPolicies {
+    /// Loads policies from a TOML, YAML or JSON file, chosen by extension (TOML
+    /// otherwise). Fields missing from the file keep their defaults.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read policy file {}: {}", path.display(), e))?;
+        let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+
+        let policies: SecurityPolicies = match extension {
+            "yaml" | "yml" => serde_yaml::from_str(&contents).map_err(|e| {
+                let line = e.location().map(|l| l.line()).unwrap_or(0);
+                anyhow::anyhow!("Failed to parse policy file {} at line {}: {}", path.display(), line, e)
+            })?,
+            "json" => serde_json::from_str(&contents).map_err(|e| {
+                anyhow::anyhow!("Failed to parse policy file {} at line {}: {}", path.display(), e.line(), e)
+            })?,
+            _ => toml::from_str(&contents).map_err(|e| {
+                let line = e.span()
+                    .map(|span| contents[..span.start].matches('\n').count() + 1)
+                    .unwrap_or(0);
+                anyhow::anyhow!("Failed to parse policy file {} at line {}: {}", path.display(), line, e.message())
+            })?,
+        };
+
+        policies.validate()
+            .map_err(|e| anyhow::anyhow!("Invalid policy file {}: {}", path.display(), e))?;
+        info!("Loaded security policies from {}", path.display());
+        Ok(policies)
+    }
+
+    pub fn allowed_ports(&self) -> &[u16] {
+        &self.allowed_ports
+    }
+
+    pub fn server_processes(&self) -> &[String] {
+        &self.server_processes
+    }
+
+    pub fn is_suspicious_port(&self, port: u16) -> bool {
+        self.suspicious_ports.iter().any(|range| range.contains(port))
+    }
+
+    /// The `suspicious_domains` entry `domain` falls under, if any.
+    pub fn suspicious_domain(&self, domain: &str) -> Option<&str> {
+        let domain = domain.trim_end_matches('.');
+        self.suspicious_domains.iter()
+            .map(String::as_str)
+            .find(|pattern| {
+                let pattern = pattern.trim_start_matches('.');
+                let Some(split) = domain.len().checked_sub(pattern.len()) else {
+                    return false;
+                };
+                domain.is_char_boundary(split)
+                    && domain[split..].eq_ignore_ascii_case(pattern)
+                    && (split == 0 || domain[..split].ends_with('.'))
+            })
+    }
+
+    /// The remote IP of `connection` when `allowed_cidrs` is set and none of
+    /// its ranges contain it.
+    pub fn disallowed_remote_ip(&self, connection: &ConnectionInfo) -> Option<IpAddr> {
+        if self.allowed_cidrs.is_empty() {
+            return None;
+        }
+        let ip = connection.remote_socket_addr()?.ip();
+        // Compare v4-mapped v6 addresses against the v4 ranges
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            IpAddr::V4(_) => ip,
+        };
+        if ip.is_loopback() || ip.is_unspecified() || self.allowed_cidrs.iter().any(|net| net.contains(&ip)) {
+            return None;
+        }
+        Some(ip)
+    }
+
+    /// The country of `connection`'s remote end when `allowed_countries` is
+    /// set and doesn't include it. Connections without a known country pass.
+    pub fn unexpected_country<'a>(&self, connection: &'a ConnectionInfo) -> Option<&'a str> {
+        let country = connection.country.as_deref()?;
+        if self.allowed_countries.is_empty()
+            || self.allowed_countries.iter().any(|allowed| allowed.eq_ignore_ascii_case(country))
+        {
+            return None;
+        }
+        Some(country)
+    }
+
+    /// The `suspicious_exec_paths` entry `exe` lies under, if any.
+    pub fn suspicious_exec_path(&self, exe: &Path) -> Option<&str> {
+        let exe = exe.to_str()?;
+        self.suspicious_exec_paths.iter()
+            .find(|dir| match dir.strip_prefix("~/") {
+                Some(relative) => exe.strip_prefix("/Users/")
+                    .and_then(|rest| rest.split_once('/'))
+                    .is_some_and(|(_, in_home)| in_home.starts_with(relative)),
+                None => exe.starts_with(dir.as_str()),
+            })
+            .map(String::as_str)
+    }
+
+    /// Code requirement running binaries must satisfy, built from the allowed
+    /// signing authorities and team IDs.
+    pub fn code_requirement(&self) -> Result<String> {
+        codesign::requirement(&self.allowed_signing_authorities, &self.allowed_team_ids)
+    }
+
+    /// Rejects values serde accepts but the checks can't use: port 0, relative
+    /// paths and out-of-range percentages.
+    pub fn validate(&self) -> Result<()> {
+        if let Some(port) = self.allowed_ports.iter().find(|port| **port == 0) {
+            return Err(anyhow::anyhow!("allowed_ports contains invalid port {}", port));
+        }
+
+        if let Some(port) = self.allowed_ports.iter().find(|port| self.is_suspicious_port(**port)) {
+            return Err(anyhow::anyhow!("port {} is in both allowed_ports and suspicious_ports", port));
+        }
+        if let Some(pattern) = self.suspicious_domains.iter().find(|pattern| pattern.trim_start_matches('.').is_empty()) {
+            return Err(anyhow::anyhow!("suspicious_domains entry {:?} is empty", pattern));
+        }
+
+        for path in self.allowed_paths.iter().chain(self.exec_policy.allowlist.iter()) {
+            if !Path::new(path).is_absolute() {
+                return Err(anyhow::anyhow!("path entry {:?} must be absolute", path));
+            }
+        }
+        for path in &self.suspicious_exec_paths {
+            if !path.starts_with('/') && !path.starts_with("~/") {
+                return Err(anyhow::anyhow!("suspicious_exec_paths entry {:?} must be absolute or start with ~/", path));
+            }
+        }
+
+        ProcessMatcher::new(self)?;
+
+        if !self.allowed_signing_authorities.is_empty() || !self.allowed_team_ids.is_empty() {
+            self.code_requirement()?;
+        }
+
+        for (name, value) in [
+            ("max_cpu_usage", self.max_cpu_usage),
+            ("max_memory_usage", self.max_memory_usage),
+            ("max_disk_usage", self.max_disk_usage),
+            ("max_process_cpu", self.max_process_cpu),
+            ("max_process_memory", self.max_process_memory),
+        ] {
+            if !(0.0..=100.0).contains(&value) {
+                return Err(anyhow::anyhow!("{} must be between 0 and 100, got {}", name, value));
+            }
+        }
+        if self.max_load_average.is_nan() || self.max_load_average <= 0.0 {
+            return Err(anyhow::anyhow!("max_load_average must be positive, got {}", self.max_load_average));
+        }
+        if self.extension_check_interval_secs == 0 {
+            return Err(anyhow::anyhow!("extension_check_interval_secs must be at least 1"));
+        }
+
+        Ok(())
+    }
+}
+
+/// An inclusive range of ports, written as `4444` or `"6660-6669"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "PortRangeRepr", into = "PortRangeRepr")]
+pub struct PortRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl PortRange {
+    pub fn single(port: u16) -> Self {
+        Self { start: port, end: port }
+    }
+
+    pub fn contains(&self, port: u16) -> bool {
+        (self.start..=self.end).contains(&port)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum PortRangeRepr {
+    Port(u16),
+    Range(String),
+}
+
+impl TryFrom<PortRangeRepr> for PortRange {
+    type Error = String;
+
+    fn try_from(repr: PortRangeRepr) -> std::result::Result<Self, String> {
+        let text = match repr {
+            PortRangeRepr::Port(port) => return Ok(Self::single(port)),
+            PortRangeRepr::Range(text) => text,
+        };
+        let parse = |port: &str| port.trim().parse::<u16>()
+            .map_err(|_| format!("invalid port range {:?}", text));
+        let range = match text.split_once('-') {
+            Some((start, end)) => Self { start: parse(start)?, end: parse(end)? },
+            None => Self::single(parse(&text)?),
+        };
+        if range.start > range.end {
+            return Err(format!("invalid port range {:?}: start is after end", text));
+        }
+        Ok(range)
+    }
+}
+
+impl From<PortRange> for PortRangeRepr {
+    fn from(range: PortRange) -> Self {
+        if range.start == range.end {
+            PortRangeRepr::Port(range.start)
+        } else {
+            PortRangeRepr::Range(format!("{}-{}", range.start, range.end))
+        }
+    }
+}
+
+/// How entries in `suspicious_processes` are interpreted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProcessMatchMode {
+    /// Each entry is a regex; use `^name$` to match an exact binary
+    #[default]
+    Regex,
+    /// Each entry matches anywhere in the name or command line, as before regex support
+    Substring,
+}
+
+/// `suspicious_processes` compiled once per policy load.
+#[derive(Debug, Clone)]
+enum ProcessMatcher {
+    Regex(Vec<Regex>),
+    Substring(Vec<String>),
+}
+
+impl ProcessMatcher {
+    fn new(policies: &SecurityPolicies) -> Result<Self> {
+        match policies.suspicious_process_match {
+            ProcessMatchMode::Regex => policies.suspicious_processes.iter()
+                .map(|pattern| Regex::new(pattern).map_err(|e| {
+                    anyhow::anyhow!("invalid suspicious_processes pattern {:?}: {}", pattern, e)
+                }))
+                .collect::<Result<Vec<_>>>()
+                .map(ProcessMatcher::Regex),
+            ProcessMatchMode::Substring => Ok(ProcessMatcher::Substring(policies.suspicious_processes.clone())),
+        }
+    }
+
+    /// Returns what matched: the process name, or the matching part of the
+    /// command line (which is how renamed binaries give themselves away).
+    fn find(&self, name: &str, command: &str) -> Option<SuspiciousMatch> {
+        match self {
+            ProcessMatcher::Regex(patterns) => {
+                if patterns.iter().any(|pattern| pattern.is_match(name)) {
+                    return Some(SuspiciousMatch::Name);
+                }
+                patterns.iter()
+                    .find_map(|pattern| pattern.find(command))
+                    .map(|found| SuspiciousMatch::Argument(found.as_str().trim().to_string()))
+            }
+            ProcessMatcher::Substring(patterns) => {
+                if patterns.iter().any(|pattern| name.contains(pattern.as_str())) {
+                    return Some(SuspiciousMatch::Name);
+                }
+                patterns.iter()
+                    .find(|pattern| command.contains(pattern.as_str()))
+                    .map(|pattern| SuspiciousMatch::Argument(pattern.clone()))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum SuspiciousMatch {
+    Name,
+    Argument(String),
 }
 
-pub fn drop_privileges() -> Result<()> {
-    // Check if running as root
+impl std::fmt::Display for SuspiciousMatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SuspiciousMatch::Name => write!(f, "matches a suspicious process name"),
+            SuspiciousMatch::Argument(argument) => write!(f, "command line contains {:?}", argument),
+        }
+    }
+}
+
+fn log_policy_diff(old: &SecurityPolicies, new: &SecurityPolicies) {
+    fn diff<T: PartialEq + std::fmt::Debug>(label: &str, old: &[T], new: &[T]) {
+        let added: Vec<&T> = new.iter().filter(|item| !old.contains(item)).collect();
+        let removed: Vec<&T> = old.iter().filter(|item| !new.contains(item)).collect();
+        if !added.is_empty() {
+            info!("Policy reload: added {} {:?}", label, added);
+        }
+        if !removed.is_empty() {
+            info!("Policy reload: removed {} {:?}", label, removed);
+        }
+    }
+
+    diff("allowed ports", &old.allowed_ports, &new.allowed_ports);
+    diff("allowed domains", &old.allowed_domains, &new.allowed_domains);
+    diff("allowed CIDRs", &old.allowed_cidrs, &new.allowed_cidrs);
+    diff("allowed countries", &old.allowed_countries, &new.allowed_countries);
+    diff("suspicious processes", &old.suspicious_processes, &new.suspicious_processes);
+    diff("suspicious exec paths", &old.suspicious_exec_paths, &new.suspicious_exec_paths);
+}
+
+/// Per-process-name override of the per-process limits; unset fields keep the global limit.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProcessLimits {
+    pub max_cpu: Option<f32>,
+    pub max_memory: Option<f32>,
+}
+
+/// Executable path of `pid`, from libproc.
+pub fn process_path(pid: i32) -> Result<PathBuf> {
+    libproc::libproc::proc_pid::pidpath(pid)
+        .map(PathBuf::from)
+        .map_err(|e| anyhow::anyhow!("Failed to resolve the executable of PID {}: {}", pid, e))
+}
+
+/// Waits out the grace period after SIGTERM and sends SIGKILL if `pid` is
+/// still alive.
+async fn escalate_termination(pid: libc::pid_t, grace: std::time::Duration) {
+    let deadline = tokio::time::Instant::now() + grace;
+    while tokio::time::Instant::now() < deadline {
+        // Signal 0 only checks whether the process still exists
+        if unsafe { libc::kill(pid, 0) } != 0 {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+
+    if unsafe { libc::kill(pid, libc::SIGKILL) } != 0 {
+        let error = std::io::Error::last_os_error();
+        // It exited between the last check and SIGKILL
+        if error.raw_os_error() != Some(libc::ESRCH) {
+            error!("Failed to send SIGKILL to PID {}: {}", pid, error);
+        }
+        return;
+    }
+    warn!("PID {} ignored SIGTERM, sent SIGKILL", pid);
+}
+
+/// SHA-256 of the file at `path` as lowercase hex, read in chunks so large
+/// binaries aren't loaded whole.
+pub fn file_hash<P: AsRef<Path>>(path: P) -> Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut context = Context::new(&SHA256);
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        context.update(&buf[..n]);
+    }
+    Ok(context.finish().as_ref().iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Account the service switches to after startup when launched as root.
+pub const DEFAULT_SERVICE_USER: &str = "ange-gardien";
+
+/// Looks `user` up in the password database and returns its uid and primary gid.
+pub fn lookup_user(user: &str) -> Result<(libc::uid_t, libc::gid_t)> {
+    let name = CString::new(user)?;
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+
+    let rc = unsafe {
+        libc::getpwnam_r(name.as_ptr(), &mut entry, buf.as_mut_ptr(), buf.len(), &mut result)
+    };
+    if rc != 0 {
+        return Err(anyhow::anyhow!(
+            "Failed to look up user {}: {}",
+            user,
+            std::io::Error::from_raw_os_error(rc)
+        ));
+    }
+    if result.is_null() {
+        return Err(anyhow::anyhow!(
+            "User {} does not exist; create it or set service_user to an existing account",
+            user
+        ));
+    }
+    Ok((entry.pw_uid, entry.pw_gid))
+}
+
+/// Switches the process to `user` when running as root: supplementary
+/// groups, then gid, then uid, so no step is left running with root's
+/// groups. Does nothing when already unprivileged.
+pub fn drop_privileges(user: &str) -> Result<()> {
     if unsafe { libc::geteuid() } != 0 {
+        info!("Not running as root, no privileges to drop");
         return Ok(());
     }
 
-    info!("Dropping root privileges...");
+    let (uid, gid) = lookup_user(user)?;
+    info!("Dropping root privileges to {} (uid {}, gid {})", user, uid, gid);
 
-    // Create a non-privileged user if it doesn't exist
-    let guardian_user = "ange-gardien";
-    
-    // Drop privileges using macOS-specific APIs
+    let name = CString::new(user)?;
+    let last_error = || std::io::Error::last_os_error();
     unsafe {
-        // Set up the credentials
-        let mut cred = std::mem::zeroed::<libc::posix_cred_t>();
-        if libc::posix_cred_get(&mut cred) != 0 {
-            return Err(anyhow::anyhow!("Failed to get credentials"));
+        if libc::initgroups(name.as_ptr(), gid as _) != 0 {
+            return Err(anyhow::anyhow!("Failed to set groups for {}: {}", user, last_error()));
         }
+        if libc::setgid(gid) != 0 {
+            return Err(anyhow::anyhow!("Failed to set gid {}: {}", gid, last_error()));
+        }
+        if libc::setuid(uid) != 0 {
+            return Err(anyhow::anyhow!("Failed to set uid {}: {}", uid, last_error()));
+        }
+        // The drop must be permanent
+        if uid != 0 && libc::setuid(0) == 0 {
+            return Err(anyhow::anyhow!("Privileges were not dropped: root could be regained"));
+        }
+    }
+
+    info!("Successfully dropped privileges to {}", user);
+    Ok(())
+}
 
-        // Modify the credentials
-        cred.cr_uid = 501; // Standard user UID range
-        cred.cr_groups[0] = 501; // Standard user GID range
-        cred.cr_ngroups = 1;
+/// Ids tried for a new service account: below 500 macOS treats an account as
+/// a system account, and Apple's own daemons sit below 400.
+const SERVICE_ID_MIN: u32 = 400;
+const SERVICE_ID_MAX: u32 = 500;
 
-        // Set the new credentials
-        if libc::posix_cred_set(&cred) != 0 {
-            return Err(anyhow::anyhow!("Failed to set credentials"));
+/// Creates `user` as a hidden macOS system account with a matching group, no
+/// shell and no home, unless it already exists. Needs root.
+pub fn create_service_user(user: &str) -> Result<(libc::uid_t, libc::gid_t)> {
+    if let Ok(ids) = lookup_user(user) {
+        info!("Service user {} already exists", user);
+        return Ok(ids);
+    }
+
+    let id = (SERVICE_ID_MIN..SERVICE_ID_MAX)
+        .find(|id| unsafe { libc::getpwuid(*id).is_null() && libc::getgrgid(*id).is_null() })
+        .ok_or_else(|| anyhow::anyhow!("No free system uid/gid in {}..{}", SERVICE_ID_MIN, SERVICE_ID_MAX))?;
+    let id_str = id.to_string();
+    let group = format!("/Groups/{}", user);
+    let record = format!("/Users/{}", user);
+
+    for args in [
+        vec![".", "-create", group.as_str()],
+        vec![".", "-create", group.as_str(), "PrimaryGroupID", id_str.as_str()],
+        vec![".", "-create", record.as_str()],
+        vec![".", "-create", record.as_str(), "UniqueID", id_str.as_str()],
+        vec![".", "-create", record.as_str(), "PrimaryGroupID", id_str.as_str()],
+        vec![".", "-create", record.as_str(), "UserShell", "/usr/bin/false"],
+        vec![".", "-create", record.as_str(), "NFSHomeDirectory", "/var/empty"],
+        vec![".", "-create", record.as_str(), "IsHidden", "1"],
+    ] {
+        let status = std::process::Command::new("dscl").args(&args).status()?;
+        if !status.success() {
+            return Err(anyhow::anyhow!("dscl {} exited with {}", args.join(" "), status));
         }
     }
 
-    info!("Successfully dropped privileges to {}", guardian_user);
-    Ok(())
+    info!("Created service user {} (uid/gid {})", user, id);
+    lookup_user(user)
+}
+
+/// Allowlist of approved binary hashes, stored in `shasum -a 256` format:
+/// one `<hex>  <path>` line per binary. The path is only informational.
+#[derive(Debug)]
+struct KnownHashes {
+    file: PathBuf,
+    hashes: HashSet<String>,
+    /// Record unknown hashes as approved instead of alerting on them
+    enroll: bool,
+}
+
+impl KnownHashes {
+    fn load(file: &Path, enroll: bool) -> Result<Self> {
+        let mut hashes = HashSet::new();
+        match fs::read_to_string(file) {
+            Ok(contents) => {
+                for (number, line) in contents.lines().enumerate() {
+                    let line = line.trim();
+                    if line.is_empty() || line.starts_with('#') {
+                        continue;
+                    }
+                    let hash = line.split_whitespace().next().unwrap_or_default();
+                    if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                        return Err(anyhow::anyhow!(
+                            "Invalid SHA-256 on line {} of {}",
+                            number + 1,
+                            file.display()
+                        ));
+                    }
+                    hashes.insert(hash.to_ascii_lowercase());
+                }
+            }
+            // Enrollment starts the file from scratch
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && enroll => {}
+            Err(e) => {
+                return Err(anyhow::anyhow!("Failed to read known hashes from {}: {}", file.display(), e));
+            }
+        }
+
+        Ok(Self {
+            file: file.to_path_buf(),
+            hashes,
+            enroll,
+        })
+    }
+
+    fn approve(&mut self, hash: &str, binary: &Path) -> Result<()> {
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(&self.file)?;
+        writeln!(file, "{}  {}", hash, binary.display())?;
+        self.hashes.insert(hash.to_string());
+        Ok(())
+    }
 }
 
 impl SecurityManager {
-    pub fn new() -> Result<Self> {
+    /// Creates the manager with `policies`, or the built-in defaults when `None`.
+    pub fn new(policies: Option<SecurityPolicies>) -> Result<Self> {
         let keychain = match SecKeychainCopyDefault() {
             Ok(keychain) => keychain,
             Err(_) => {
@@ -111,169 +815,615 @@ impl SecurityManager {
             }
         };
 
-        let policies = SecurityPolicies::default();
+        Self::with_keychain(keychain, policies.unwrap_or_default())
+    }
 
+    fn with_keychain(keychain: SecKeychain, policies: SecurityPolicies) -> Result<Self> {
         Ok(Self {
             keychain,
-            policies,
+            suspicious_matcher: Arc::new(std::sync::RwLock::new(ProcessMatcher::new(&policies)?)),
+            policies: Arc::new(std::sync::RwLock::new(policies)),
             process_hashes: Arc::new(RwLock::new(HashMap::new())),
+            file_hashes: Arc::new(RwLock::new(HashMap::new())),
             codesign_cache: Arc::new(RwLock::new(HashMap::new())),
+            known_hashes: Arc::new(RwLock::new(None)),
+            service_liveness: Arc::new(RwLock::new(HashMap::new())),
+            swapping_since: Arc::new(RwLock::new(None)),
+            enforcement_mode: EnforcementMode::default(),
         })
     }
 
-    pub async fn check_policies(&self, state: &SystemState) -> Result<Option<String>> {
-        let policies = self.policies.clone();
-        let mut violations = Vec::new();
+    pub fn with_enforcement_mode(mut self, mode: EnforcementMode) -> Self {
+        self.enforcement_mode = mode;
+        self
+    }
+
+    pub fn enforcement_mode(&self) -> EnforcementMode {
+        self.enforcement_mode
+    }
+
+    pub async fn check_service_liveness(&self, state: &SystemState) -> Result<LivenessReport> {
+        let policies = self.policies();
+        let mut report = LivenessReport::default();
+
+        if policies.expected_processes.is_empty() {
+            return Ok(report);
+        }
+
+        let now = state.timestamp;
+        let grace = chrono::Duration::seconds(policies.expected_process_grace_secs as i64);
+        let mut liveness = self.service_liveness.write().await;
+
+        for expected in &policies.expected_processes {
+            let running = state.active_processes.iter()
+                .any(|process| Self::matches_expected_process(expected, process));
+
+            // Services start out as "seen now" so the grace period also covers startup
+            let entry = liveness.entry(expected.clone()).or_insert_with(|| ServiceLiveness {
+                last_seen: now,
+                present: running,
+                alerted: false,
+            });
+
+            if running {
+                if entry.alerted {
+                    info!("Critical service {} is running again", expected);
+                    report.recovered.push(expected.clone());
+                }
+                entry.last_seen = now;
+                entry.present = true;
+                entry.alerted = false;
+                continue;
+            }
+
+            entry.present = false;
+            if !entry.alerted && now - entry.last_seen > grace {
+                warn!("Critical service {} is not running", expected);
+                entry.alerted = true;
+                report.alerts.push(SecurityAlert {
+                    timestamp: Utc::now(),
+                    severity: AlertSeverity::High,
+                    description: Self::liveness_description(expected),
+                    source: SERVICE_LIVENESS_SOURCE.to_string(),
+                    recommendation: Some(format!("Check why {} stopped and restart it", expected)),
+                    count: 1,
+                    would_enforce: false,
+                    rule: None,
+                    pid: None,
+                    remote_ip: None,
+                });
+            }
+        }
+
+        Ok(report)
+    }
+
+    pub fn policies(&self) -> SecurityPolicies {
+        self.read_policies().clone()
+    }
+
+    fn read_policies(&self) -> std::sync::RwLockReadGuard<'_, SecurityPolicies> {
+        // Writers only swap in a whole value, so a poisoned lock still holds valid policies
+        self.policies.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Swaps in new policies, logging what changed. Checks already in progress
+    /// finish with the policies they started with. Fails, leaving the current
+    /// policies in place, if a suspicious process pattern doesn't compile.
+    pub fn set_policies(&self, policies: SecurityPolicies) -> Result<()> {
+        let matcher = ProcessMatcher::new(&policies)?;
+        let mut current = self.policies.write().unwrap_or_else(|e| e.into_inner());
+        log_policy_diff(&current, &policies);
+        *current = policies;
+        *self.suspicious_matcher.write().unwrap_or_else(|e| e.into_inner()) = matcher;
+        Ok(())
+    }
+
+    /// Re-reads the policy file at `path`. If it fails to load or validate the
+    /// current policies stay in effect and the error is returned.
+    pub fn reload_policies<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let policies = SecurityPolicies::from_file(path)?;
+        self.set_policies(policies)
+    }
+
+    pub fn extension_check_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.read_policies().extension_check_interval_secs.max(1))
+    }
+
+    /// Flags loaded kernel and system extensions not signed by an allowed team.
+    pub async fn check_extensions(&self) -> Result<Vec<SecurityAlert>> {
+        let allowed_teams = self.read_policies().allowed_extension_teams.clone();
+        let loaded = tokio::task::spawn_blocking(extensions::list_loaded_extensions).await??;
+        Ok(extensions::audit_extensions(&loaded, &allowed_teams))
+    }
+
+    pub fn exec_policy(&self) -> ExecPolicy {
+        self.read_policies().exec_policy.clone()
+    }
+
+    /// Returns whether each expected service is currently present.
+    pub async fn get_service_status(&self) -> HashMap<String, bool> {
+        let liveness = self.service_liveness.read().await;
+        liveness.iter()
+            .map(|(name, status)| (name.clone(), status.present))
+            .collect()
+    }
+
+    /// `file_hash` of `path`, read again only when the file's size or times
+    /// changed since the last call.
+    async fn cached_file_hash(&self, path: &Path) -> Result<String> {
+        let metadata = fs::metadata(path)?;
+        if let Some(cached) = self.file_hashes.read().await.get(path) {
+            if cached.matches(&metadata) {
+                return Ok(cached.hash.clone());
+            }
+        }
+
+        let hash = file_hash(path)?;
+        self.file_hashes.write().await.insert(path.to_path_buf(), CachedHash {
+            modified: metadata.modified()?,
+            changed: (metadata.ctime(), metadata.ctime_nsec()),
+            len: metadata.len(),
+            hash: hash.clone(),
+        });
+        Ok(hash)
+    }
+
+    pub fn liveness_description(service: &str) -> String {
+        format!("Expected service not running: {}", service)
+    }
+
+    fn matches_expected_process(expected: &str, process: &ProcessInfo) -> bool {
+        let expected_path = Path::new(expected);
+        if !expected.contains('/') {
+            return expected == process.name;
+        }
+
+        // Process names are truncated by the kernel, so they only rule out
+        // processes whose file name can't match
+        let file_name = match expected_path.file_name() {
+            Some(name) => name.to_string_lossy(),
+            None => return false,
+        };
+        if process.name.is_empty() || !file_name.starts_with(&process.name) {
+            return false;
+        }
+
+        // Absolute entries name one binary, so a same-named one elsewhere
+        // doesn't count; relative ones match on the file name alone
+        if expected_path.is_absolute() {
+            process_path(process.pid as i32)
+                .map(|path| path == expected_path)
+                .unwrap_or(false)
+        } else {
+            file_name == process.name
+        }
+    }
+
+    #[tracing::instrument(name = "policy_check", skip_all, fields(violations = tracing::field::Empty))]
+    pub async fn check_policies(&self, state: &SystemState) -> Result<Option<PolicyViolation>> {
+        let policies = self.policies();
+        let mut violations = PolicyViolation::default();
 
         // Check CPU usage
         if state.cpu_usage > policies.max_cpu_usage {
-            violations.push(format!(
-                "CPU usage too high: {:.1}% (max: {:.1}%)",
-                state.cpu_usage,
-                policies.max_cpu_usage
-            ));
+            violations.push(
+                "cpu".to_string(),
+                format!(
+                    "CPU usage too high: {:.1}% (max: {:.1}%)",
+                    state.cpu_usage,
+                    policies.max_cpu_usage
+                ),
+                "Find the processes driving CPU usage with `top -o cpu`".to_string()
+            );
         }
 
         // Check memory usage
         if state.memory_usage > policies.max_memory_usage {
-            violations.push(format!(
-                "Memory usage too high: {:.1}% (max: {:.1}%)",
-                state.memory_usage,
-                policies.max_memory_usage
-            ));
+            violations.push(
+                "memory".to_string(),
+                format!(
+                    "Memory usage too high: {:.1}% (max: {:.1}%)",
+                    state.memory_usage,
+                    policies.max_memory_usage
+                ),
+                "Find the processes holding memory with `top -o mem`".to_string()
+            );
+        }
+
+        // Load is relative to the core count, so one threshold fits any machine
+        if let Some(metrics) = state.system_metrics.as_ref().filter(|m| m.physical_cpu_count > 0) {
+            let per_core = metrics.load_average / metrics.physical_cpu_count as f64;
+            if per_core > policies.max_load_average {
+                violations.push(
+                    "load".to_string(),
+                    format!(
+                        "Load average too high: {:.2} on {} cores ({:.2} per core, max: {:.2})",
+                        metrics.load_average,
+                        metrics.physical_cpu_count,
+                        per_core,
+                        policies.max_load_average
+                    ),
+                    "Look for runaway or stuck processes with `ps -A -o pid,stat,%cpu,comm`".to_string()
+                );
+            }
+        }
+
+        // A burst of swapping is normal; only flag it once it persists
+        if let Some(metrics) = &state.system_metrics {
+            let swap_rate = metrics.swap_ins + metrics.swap_outs;
+            let mut swapping_since = self.swapping_since.write().await;
+            if swap_rate > policies.max_swap_rate {
+                let since = *swapping_since.get_or_insert(state.timestamp);
+                let sustained = state.timestamp - since;
+                if sustained >= chrono::Duration::seconds(policies.swap_sustain_secs as i64) {
+                    violations.push(
+                        "swap".to_string(),
+                        format!(
+                            "Sustained swapping: {:.0} pages/s for {}s (max: {:.0} pages/s), memory pressure {}",
+                            swap_rate,
+                            sustained.num_seconds(),
+                            policies.max_swap_rate,
+                            metrics.memory_pressure
+                        ),
+                        "Stop or restart the largest processes to relieve memory pressure".to_string()
+                    );
+                }
+            } else {
+                *swapping_since = None;
+            }
+        }
+
+        // Check each mount, so the one filling up is named
+        for disk in &state.disks {
+            if disk.usage_percent > policies.max_disk_usage {
+                violations.push(
+                    format!("disk:{}", disk.mount_point),
+                    format!(
+                        "Disk usage too high on {}: {:.1}% (max: {:.1}%)",
+                        disk.mount_point,
+                        disk.usage_percent,
+                        policies.max_disk_usage
+                    ),
+                    format!("Free space on {}", disk.mount_point)
+                );
+            }
         }
 
         // Check for suspicious processes and code signing
         for process in &state.active_processes {
-            if policies.suspicious_processes.iter().any(|p| process.name.contains(p)) {
-                violations.push(format!(
-                    "Suspicious process detected: {} (PID: {})",
-                    process.name,
-                    process.pid
-                ));
+            let limits = policies.process_limit_overrides.get(&process.name);
+            let max_cpu = limits.and_then(|l| l.max_cpu).unwrap_or(policies.max_process_cpu);
+            let max_memory = limits.and_then(|l| l.max_memory).unwrap_or(policies.max_process_memory);
+
+            if process.cpu_usage > max_cpu {
+                violations.push_process(
+                    "process_cpu",
+                    process.pid,
+                    format!(
+                        "Process {} (PID: {}) CPU usage too high: {:.1}% (max: {:.1}%)",
+                        process.name,
+                        process.pid,
+                        process.cpu_usage,
+                        max_cpu
+                    ),
+                    format!("Investigate PID {}; consider `kill {}`", process.pid, process.pid)
+                );
+            }
+
+            if process.memory_usage > max_memory {
+                violations.push_process(
+                    "process_memory",
+                    process.pid,
+                    format!(
+                        "Process {} (PID: {}) memory usage too high: {:.1}% (max: {:.1}%)",
+                        process.name,
+                        process.pid,
+                        process.memory_usage,
+                        max_memory
+                    ),
+                    format!("Investigate PID {}; consider `kill {}`", process.pid, process.pid)
+                );
+            }
+
+            if let Some(found) = self.find_suspicious_process(process) {
+                violations.push_process(
+                    "suspicious_process",
+                    process.pid,
+                    format!(
+                        "Suspicious process detected: {} (PID: {}): {}",
+                        process.name,
+                        process.pid,
+                        found
+                    ),
+                    format!("Investigate PID {}; consider `kill -9 {}`", process.pid, process.pid)
+                );
             }
 
+            // Get process path using libproc on macOS
+            let path = match process_path(process.pid as i32) {
+                Ok(path) => path,
+                Err(_) => continue, // Process might have terminated
+            };
+            if let Some(dir) = policies.suspicious_exec_path(&path) {
+                violations.push_process(
+                    "exec_path",
+                    process.pid,
+                    format!(
+                        "Process {} (PID: {}) is running from {}, under suspicious location {}",
+                        process.name,
+                        process.pid,
+                        path.display(),
+                        dir
+                    ),
+                    format!("Investigate PID {}; consider `kill -9 {}` and removing {}", process.pid, process.pid, path.display())
+                );
+            }
+
+            // Unreadable binaries are still signature-checked, just not cached
+            let hash = self.cached_file_hash(&path).await.ok();
+
             // Check process code signing
-            if let Err(e) = self.verify_process_codesign(process.pid).await {
-                violations.push(format!(
-                    "Code signing verification failed for {} (PID: {}): {}",
-                    process.name,
+            if let Err(e) = self.verify_process_codesign(&path, hash.as_deref()).await {
+                violations.push_process(
+                    "codesign",
                     process.pid,
-                    e
-                ));
+                    format!(
+                        "Code signing verification failed for {} (PID: {}): {}",
+                        process.name,
+                        process.pid,
+                        e
+                    ),
+                    format!("Investigate PID {}; consider `kill -9 {}`", process.pid, process.pid)
+                );
             }
 
             // Check process binary integrity
-            if let Err(e) = self.verify_process_integrity(process.pid).await {
-                violations.push(format!(
-                    "Process integrity check failed for {} (PID: {}): {}",
-                    process.name,
-                    process.pid,
-                    e
-                ));
+            if let Some(hash) = hash {
+                if let Err(e) = self.verify_process_integrity(process.pid, &path, hash).await {
+                    violations.push_process(
+                        "integrity",
+                        process.pid,
+                        format!(
+                            "Process integrity check failed for {} (PID: {}): {}",
+                            process.name,
+                            process.pid,
+                            e
+                        ),
+                        format!("Investigate PID {}; consider `kill -9 {}` and reinstalling {}", process.pid, process.pid, path.display())
+                    );
+                }
             }
         }
 
         // Check network connections
         for connection in &state.network_stats.connections {
             let port = connection.remote_addr
-                .split(':')
-                .nth(1)
+                .rsplit(':')
+                .next()
                 .and_then(|p| p.parse::<u16>().ok())
                 .unwrap_or(0);
 
             if !policies.allowed_ports.contains(&port) {
-                violations.push(format!(
-                    "Unauthorized network connection to port {} ({})",
-                    port,
-                    connection.remote_addr
-                ));
+                violations.push_connection(
+                    "port",
+                    connection,
+                    format!(
+                        "Unauthorized network connection to port {} ({})",
+                        port,
+                        connection.remote_addr
+                    ),
+                    block_outbound(connection)
+                );
+            }
+
+            if let Some(ip) = policies.disallowed_remote_ip(connection) {
+                violations.push_connection(
+                    "network",
+                    connection,
+                    format!(
+                        "Connection to {} outside the allowed networks ({})",
+                        ip,
+                        connection.remote_addr
+                    ),
+                    block_outbound(connection)
+                );
+            }
+
+            if let Some(country) = policies.unexpected_country(connection) {
+                violations.push_connection(
+                    "country",
+                    connection,
+                    format!(
+                        "Connection to {} in unexpected country {}",
+                        connection.remote_addr,
+                        country
+                    ),
+                    block_outbound(connection)
+                );
             }
 
             if let Some(ref domain) = connection.dns_name {
                 if !policies.allowed_domains.iter().any(|d| domain.ends_with(d)) {
-                    violations.push(format!(
-                        "Connection to unauthorized domain: {}",
-                        domain
-                    ));
+                    violations.push_connection(
+                        "domain",
+                        connection,
+                        format!(
+                            "Connection to unauthorized domain: {}",
+                            domain
+                        ),
+                        format!("Block outbound to {}", domain)
+                    );
                 }
             }
         }
 
+        tracing::Span::current().record("violations", violations.len());
         if violations.is_empty() {
             Ok(None)
         } else {
-            Ok(Some(violations.join("; ")))
+            Ok(Some(violations))
         }
     }
 
-    async fn verify_process_codesign(&self, pid: u32) -> Result<()> {
-        // Get process path using libproc on macOS
-        let path = match darwin_libproc::pid_path::pidpath(pid) {
-            Ok(path) => path,
-            Err(_) => return Ok(()), // Process might have terminated
-        };
+    fn find_suspicious_process(&self, process: &ProcessInfo) -> Option<SuspiciousMatch> {
+        self.suspicious_matcher.read()
+            .unwrap_or_else(|e| e.into_inner())
+            .find(&process.name, &process.command)
+    }
 
-        let path_str = path.to_str().unwrap_or("");
-        
-        // Check cache first
-        let cache = self.codesign_cache.read().await;
-        if let Some(&is_signed) = cache.get(path_str) {
-            return if is_signed {
-                Ok(())
-            } else {
-                Err(anyhow::anyhow!("Invalid code signature"))
+    /// Terminates suspicious processes when `auto_terminate_suspicious` is set,
+    /// returning a Critical alert for each one killed. In dry-run mode the
+    /// candidates are only logged; in monitor mode they are alerted on with
+    /// `would_enforce` set but left running.
+    pub async fn enforce_suspicious_processes(&self, state: &SystemState) -> Vec<SecurityAlert> {
+        let policies = self.policies();
+        let mut alerts = Vec::new();
+        if !policies.auto_terminate_suspicious {
+            return alerts;
+        }
+
+        for process in &state.active_processes {
+            let reason = match self.find_suspicious_process(process) {
+                Some(found) => found,
+                None => continue,
             };
+
+            if policies.terminate_dry_run {
+                warn!("[dry run] Would terminate {} (PID: {}): {}", process.name, process.pid, reason);
+                continue;
+            }
+            if self.enforcement_mode == EnforcementMode::Monitor {
+                warn!("[monitor] Would terminate {} (PID: {}): {}", process.name, process.pid, reason);
+                alerts.push(SecurityAlert {
+                    timestamp: Utc::now(),
+                    severity: AlertSeverity::Critical,
+                    description: format!(
+                        "Would terminate process {} (PID: {}): {}",
+                        process.name,
+                        process.pid,
+                        reason
+                    ),
+                    source: PROCESS_ENFORCEMENT_SOURCE.to_string(),
+                    recommendation: Some(format!("Investigate how {} was started", process.name)),
+                    count: 1,
+                    would_enforce: true,
+                    rule: None,
+                    pid: Some(process.pid),
+                    remote_ip: None,
+                });
+                continue;
+            }
+
+            match self.terminate_process(process.pid).await {
+                Ok(()) => alerts.push(SecurityAlert {
+                    timestamp: Utc::now(),
+                    severity: AlertSeverity::Critical,
+                    description: format!(
+                        "Terminated process {} (PID: {}): {}",
+                        process.name,
+                        process.pid,
+                        reason
+                    ),
+                    source: PROCESS_ENFORCEMENT_SOURCE.to_string(),
+                    recommendation: Some(format!("Investigate how {} was started", process.name)),
+                    count: 1,
+                    would_enforce: false,
+                    rule: None,
+                    pid: Some(process.pid),
+                    remote_ip: None,
+                }),
+                Err(e) => error!("Failed to terminate {} (PID: {}): {}", process.name, process.pid, e),
+            }
         }
-        drop(cache);
 
-        // Verify code signature using macOS Security framework
-        let cf_path = CFString::new(path_str);
-        let url = CFURL::from_file_system_path(
-            cf_path,
-            core_foundation::url::kCFURLPOSIXPathStyle,
-            true,
-        );
+        alerts
+    }
 
-        let bundle = CFBundle::new(url);
-        
-        let is_signed = if let Some(bundle) = bundle {
-            // Check if the bundle is signed by an allowed authority
-            let info = bundle.info_dictionary();
-            let bundle_sig = CFString::new("CFBundleSignature");
-            if let Some(signing_info) = info.find(&bundle_sig) {
-                let signing_auth = signing_info.to_string();
-                let policies = self.policies.clone();
-                policies.allowed_signing_authorities.iter().any(|auth| signing_auth.contains(auth))
-            } else {
-                false
+    /// Sends SIGTERM, then SIGKILL if the process is still alive after the
+    /// grace period. Only SIGTERM is sent before returning; the escalation
+    /// runs in a background task, so callers holding the state lock aren't
+    /// stalled by a process that ignores it.
+    pub async fn terminate_process(&self, pid: u32) -> Result<()> {
+        if pid <= 1 || pid == std::process::id() {
+            return Err(anyhow::anyhow!("Refusing to terminate PID {}", pid));
+        }
+        let pid = pid as libc::pid_t;
+
+        if unsafe { libc::kill(pid, libc::SIGTERM) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        info!("Sent SIGTERM to PID {}", pid);
+
+        let grace = std::time::Duration::from_secs(self.read_policies().terminate_grace_secs);
+        tokio::spawn(escalate_termination(pid, grace));
+        Ok(())
+    }
+
+    /// Checks the signature of the binary at `path`. Verdicts are cached
+    /// against the binary's hash, so a different binary swapped in under a
+    /// known-good path is verified afresh.
+    async fn verify_process_codesign(&self, path: &Path, hash: Option<&str>) -> Result<()> {
+        let path_str = path.to_string_lossy();
+
+        // Check cache first
+        if let Some(hash) = hash {
+            let cache = self.codesign_cache.read().await;
+            if let Some((cached_hash, is_signed)) = cache.get(path_str.as_ref()) {
+                if cached_hash == hash {
+                    return if *is_signed {
+                        Ok(())
+                    } else {
+                        Err(anyhow::anyhow!("Invalid code signature"))
+                    };
+                }
             }
-        } else {
-            false
-        };
+        }
 
-        // Update cache
-        let mut cache = self.codesign_cache.write().await;
-        cache.insert(path_str.to_string(), is_signed);
+        let requirement = self.read_policies().code_requirement()?;
+        let result = codesign::verify(path, &requirement);
 
-        if is_signed {
-            Ok(())
-        } else {
-            Err(anyhow::anyhow!("Invalid code signature"))
+        // Update cache, replacing any verdict for a previous binary at this path
+        if let Some(hash) = hash {
+            let mut cache = self.codesign_cache.write().await;
+            cache.insert(path_str.into_owned(), (hash.to_string(), result.is_ok()));
         }
+
+        result.map(|_| ())
     }
 
-    async fn verify_process_integrity(&self, pid: u32) -> Result<()> {
-        // Get process path using libproc on macOS
-        let path = match darwin_libproc::pid_path::pidpath(pid) {
-            Ok(path) => path,
-            Err(_) => return Ok(()), // Process might have terminated
-        };
+    /// Loads the allowlist of approved binary hashes from `file`. Once loaded,
+    /// integrity checks flag any binary whose hash isn't on it; with `enroll`
+    /// they add it instead, to record the current binaries as the baseline.
+    /// Returns how many hashes were loaded.
+    pub async fn load_known_hashes(&self, file: &Path, enroll: bool) -> Result<usize> {
+        let known = KnownHashes::load(file, enroll)?;
+        let count = known.hashes.len();
+        if enroll {
+            info!("Enrolling binary hashes into {} ({} already approved)", file.display(), count);
+        } else {
+            info!("Loaded {} approved binary hashes from {}", count, file.display());
+        }
+        *self.known_hashes.write().await = Some(known);
+        Ok(count)
+    }
 
-        let current_hash = match self.calculate_file_hash(&path) {
-            Ok(hash) => hash,
-            Err(_) => return Ok(()), // Skip if we can't read the file
-        };
+    /// Checks the binary against the hash allowlist, then that a pid's binary
+    /// hasn't changed since it was first seen.
+    async fn verify_process_integrity(&self, pid: u32, path: &Path, current_hash: String) -> Result<()> {
+        if let Some(known) = self.known_hashes.write().await.as_mut() {
+            if !known.hashes.contains(&current_hash) {
+                if !known.enroll {
+                    return Err(anyhow::anyhow!(
+                        "{} has unapproved hash {}",
+                        path.display(),
+                        current_hash
+                    ));
+                }
+                known.approve(&current_hash, path)?;
+                info!("Enrolled {} ({})", path.display(), current_hash);
+            }
+        }
 
         let mut hashes = self.process_hashes.write().await;
         
@@ -288,72 +1438,49 @@ impl SecurityManager {
         Ok(())
     }
 
-    fn calculate_file_hash<P: AsRef<Path>>(&self, path: P) -> Result<String> {
-        let mut context = Context::new(&SHA256);
-        let contents = fs::read(path)?;
-        context.update(&contents);
-        let digest = context.finish();
-        Ok(base64::encode(digest.as_ref()))
-    }
-
     pub fn check_process_signature(&self, pid: i32) -> Result<bool> {
-        let process_path = std::fs::read_link(format!("/proc/{}/exe", pid))?;
+        let process_path = process_path(pid)?;
         let path_str = process_path.to_string_lossy();
         
         // Check if process is from an allowed path
-        if !self.policies.allowed_paths.iter().any(|p| path_str.starts_with(p)) {
+        if !self.read_policies().allowed_paths.iter().any(|p| path_str.starts_with(p)) {
             return Ok(false);
         }
 
         // Check code signature
-        let url = CFURL::from_path(&process_path, false)?;
-        let code_ref = SecStaticCodeCreateWithPath(url.as_concrete_TypeRef(), 0, None)?;
-        let result = SecStaticCodeCheckValidity(code_ref, 0, None);
-
-        Ok(result.is_ok())
+        let requirement = self.read_policies().code_requirement()?;
+        Ok(codesign::verify(&process_path, &requirement).is_ok())
     }
 
     pub fn check_network_connection(&self, domain: &str, port: u16) -> Result<bool> {
+        let policies = self.read_policies();
+
         // Check if domain is allowed
-        if !self.policies.allowed_domains.iter().any(|d| domain.ends_with(d)) {
+        if !policies.allowed_domains.iter().any(|d| domain.ends_with(d)) {
             return Ok(false);
         }
 
         // Check if port is allowed
-        if !self.policies.allowed_ports.contains(&port) {
+        if !policies.allowed_ports.contains(&port) {
             return Ok(false);
         }
 
         Ok(true)
     }
 
-    pub fn verify_file_signature(&self, path: &str) -> Result<bool> {
-        let file_path = std::path::Path::new(path);
-        if !file_path.exists() {
-            return Ok(false);
-        }
-
-        // Generate file hash
-        let mut file = std::fs::File::open(path)?;
-        let mut hasher = ring::digest::Context::new(&ring::digest::SHA256);
-        std::io::copy(&mut file, &mut hasher)?;
-        let digest = hasher.finish();
-
-        Ok(base64::encode(digest.as_ref()))
-    }
-
     pub fn check_file_access(&self, path: &str, pid: i32) -> Result<bool> {
-        let process_path = std::fs::read_link(format!("/proc/{}/exe", pid))?;
+        let process_path = process_path(pid)?;
         let process_path_str = process_path.to_string_lossy();
+        let policies = self.read_policies();
 
         // Check if process is allowed to access this path
-        if !self.policies.allowed_paths.iter().any(|p| process_path_str.starts_with(p)) {
+        if !policies.allowed_paths.iter().any(|p| process_path_str.starts_with(p)) {
             return Ok(false);
         }
 
         // Check if file path is allowed
         let file_path = std::path::Path::new(path);
-        if !self.policies.allowed_paths.iter().any(|p| file_path.starts_with(p)) {
+        if !policies.allowed_paths.iter().any(|p| file_path.starts_with(p)) {
             return Ok(false);
         }
 
@@ -361,35 +1488,92 @@ impl SecurityManager {
     }
 }
 
-impl SecurityPolicies {
+impl Default for SecurityPolicies {
     fn default() -> Self {
         let mut policies = SecurityPolicies {
             max_cpu_usage: 90.0,
             max_memory_usage: 90.0,
+            max_disk_usage: 90.0,
+            max_load_average: 1.5,
+            max_swap_rate: 100.0,
+            swap_sustain_secs: 60,
             suspicious_processes: vec![
-                "nc".to_string(),
-                "netcat".to_string(),
-                "nmap".to_string(),
-                "wireshark".to_string(),
-                "tcpdump".to_string(),
+                "^nc$".to_string(),
+                "^netcat$".to_string(),
+                "^nmap$".to_string(),
+                "^wireshark$".to_string(),
+                "^tcpdump$".to_string(),
+                // netcat-style reverse shell, whatever the binary is called
+                r"\s-e\s+/bin/(ba|z)?sh\b".to_string(),
             ],
+            suspicious_process_match: ProcessMatchMode::Regex,
             allowed_ports: vec![
                 80, 443, 53, // Common web and DNS ports
                 22, // SSH
                 5432, 3306, // Database ports
+                8080,
             ],
             allowed_domains: vec![
                 "github.com".to_string(),
                 "api.github.com".to_string(),
                 "registry.npmjs.org".to_string(),
                 "pypi.org".to_string(),
+                "localhost".to_string(),
+                "127.0.0.1".to_string(),
+            ],
+            // SSH is allowed above, so it isn't listed here
+            suspicious_ports: vec![
+                PortRange::single(23), // Telnet
+                PortRange::single(445), // SMB
+                PortRange::single(3389), // RDP
+                PortRange::single(4444), // Common malware
+                PortRange::single(5900), // VNC
+            ],
+            suspicious_domains: vec![
+                "xyz".to_string(),
+                "top".to_string(),
+                "pastebin.com".to_string(),
+                "ngrok.io".to_string(),
             ],
             allowed_signing_authorities: vec![
                 "Apple".to_string(),
                 "Apple Development".to_string(),
                 "Developer ID Application".to_string(),
             ],
+            allowed_team_ids: Vec::new(),
+            allowed_cidrs: Vec::new(),
+            allowed_countries: Vec::new(),
             allowed_paths: HashSet::new(),
+            suspicious_exec_paths: vec![
+                "/tmp/".to_string(),
+                "/private/tmp/".to_string(),
+                "/var/tmp/".to_string(),
+                "/private/var/tmp/".to_string(),
+                "/var/folders/".to_string(),
+                "/private/var/folders/".to_string(),
+                "/Users/Shared/".to_string(),
+                "~/Downloads/".to_string(),
+            ],
+            expected_processes: Vec::new(),
+            expected_process_grace_secs: 30,
+            exec_policy: ExecPolicy::default(),
+            allowed_extension_teams: vec![APPLE_TEAM_ID.to_string()],
+            extension_check_interval_secs: 3600,
+            max_process_cpu: 50.0,
+            max_process_memory: 50.0,
+            process_limit_overrides: HashMap::new(),
+            auto_terminate_suspicious: false,
+            terminate_dry_run: false,
+            terminate_grace_secs: 5,
+            server_processes: vec![
+                "httpd".to_string(),
+                "nginx".to_string(),
+                "postgres".to_string(),
+                "mysqld".to_string(),
+                "Safari".to_string(),
+                "Google Chrome".to_string(),
+                "firefox".to_string(),
+            ],
         };
 
         // Add default allowed paths
@@ -397,15 +1581,6 @@ impl SecurityPolicies {
         policies.allowed_paths.insert("/bin".to_string());
         policies.allowed_paths.insert("/sbin".to_string());
 
-        // Add default allowed domains
-        policies.allowed_domains.insert("localhost".to_string());
-        policies.allowed_domains.insert("127.0.0.1".to_string());
-
-        // Add default allowed ports
-        policies.allowed_ports.insert(80);
-        policies.allowed_ports.insert(443);
-        policies.allowed_ports.insert(8080);
-
         policies
     }
 }
@@ -413,19 +1588,311 @@ impl SecurityPolicies {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{NetworkStats, ProcessInfo, SecurityAlert};
-    use chrono::Utc;
+    use crate::{DiskUsage, MemoryPressure, NetworkStats, SystemMetrics};
+
+    #[tokio::test]
+    async fn test_codesign_cache_follows_binary_hash() {
+        let manager = SecurityManager::new(None).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tool");
+        fs::copy("/bin/ls", &path).unwrap();
+        let signed_hash = file_hash(&path).unwrap();
+        assert!(manager.verify_process_codesign(&path, Some(&signed_hash)).await.is_ok());
+
+        // Swap an unsigned binary in under the same path
+        fs::write(&path, b"#!/bin/sh\necho pwned\n").unwrap();
+        let swapped_hash = file_hash(&path).unwrap();
+        assert!(manager.verify_process_codesign(&path, Some(&swapped_hash)).await.is_err());
+        assert_eq!(
+            manager.codesign_cache.read().await.get(path.to_str().unwrap()),
+            Some(&(swapped_hash, false))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_known_hashes_allowlist_and_enrollment() {
+        let manager = SecurityManager::new(None).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let allowlist = dir.path().join("known_hashes");
+        let approved = "a".repeat(64);
+        let unknown = "b".repeat(64);
+        let binary = Path::new("/usr/local/bin/tool");
+
+        // Without an allowlist only per-pid changes are flagged
+        assert!(manager.verify_process_integrity(1, binary, unknown.clone()).await.is_ok());
+
+        fs::write(&allowlist, format!("# approved\n{}  /usr/local/bin/tool\n", approved)).unwrap();
+        assert_eq!(manager.load_known_hashes(&allowlist, false).await.unwrap(), 1);
+        assert!(manager.verify_process_integrity(2, binary, approved.clone()).await.is_ok());
+        let error = manager.verify_process_integrity(3, binary, unknown.clone()).await.unwrap_err();
+        assert!(error.to_string().contains("/usr/local/bin/tool has unapproved hash"));
+
+        manager.load_known_hashes(&allowlist, true).await.unwrap();
+        assert!(manager.verify_process_integrity(3, binary, unknown.clone()).await.is_ok());
+        assert_eq!(manager.load_known_hashes(&allowlist, false).await.unwrap(), 2);
+
+        fs::write(&allowlist, "not-a-hash  /bin/ls\n").unwrap();
+        assert!(manager.load_known_hashes(&allowlist, false).await.is_err());
+    }
+
+    #[test]
+    fn test_allowed_cidrs() {
+        let connection = |remote: &str| ConnectionInfo {
+            local_addr: "10.0.0.2:50000".to_string(),
+            remote_addr: remote.to_string(),
+            protocol: crate::network::Protocol::TCP,
+            state: crate::network::ConnectionState::Established,
+            process_id: None,
+            dns_name: None,
+            country: None,
+            asn: None,
+            bytes_sent: 0,
+            bytes_received: 0,
+            last_seen: Utc::now(),
+        };
+
+        let policies = SecurityPolicies::default();
+        assert_eq!(policies.disallowed_remote_ip(&connection("203.0.113.9:443")), None);
+
+        let policies: SecurityPolicies = toml::from_str(
+            "allowed_cidrs = [\"10.0.0.0/8\", \"192.168.0.0/16\", \"2001:db8::/32\"]\n"
+        ).unwrap();
+        assert_eq!(policies.disallowed_remote_ip(&connection("10.1.2.3:443")), None);
+        assert_eq!(policies.disallowed_remote_ip(&connection("[2001:db8::5]:443")), None);
+        assert_eq!(policies.disallowed_remote_ip(&connection("[::ffff:192.168.1.1]:22")), None);
+        assert_eq!(policies.disallowed_remote_ip(&connection("127.0.0.1:8080")), None);
+        assert_eq!(
+            policies.disallowed_remote_ip(&connection("203.0.113.9:443")),
+            Some("203.0.113.9".parse().unwrap())
+        );
+        assert_eq!(
+            policies.disallowed_remote_ip(&connection("[2606:4700::1111]:443")),
+            Some("2606:4700::1111".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_allowed_countries() {
+        let mut connection = ConnectionInfo {
+            local_addr: "10.0.0.2:50000".to_string(),
+            remote_addr: "203.0.113.9:443".to_string(),
+            protocol: crate::network::Protocol::TCP,
+            state: crate::network::ConnectionState::Established,
+            process_id: None,
+            dns_name: None,
+            country: Some("RU".to_string()),
+            asn: Some(64500),
+            bytes_sent: 0,
+            bytes_received: 0,
+            last_seen: Utc::now(),
+        };
+        assert_eq!(SecurityPolicies::default().unexpected_country(&connection), None);
+
+        let policies = SecurityPolicies {
+            allowed_countries: vec!["us".to_string(), "FR".to_string()],
+            ..SecurityPolicies::default()
+        };
+        assert_eq!(policies.unexpected_country(&connection), Some("RU"));
+        connection.country = Some("US".to_string());
+        assert_eq!(policies.unexpected_country(&connection), None);
+        connection.country = None;
+        assert_eq!(policies.unexpected_country(&connection), None);
+    }
+
+    #[test]
+    fn test_suspicious_ports_and_domains() {
+        let policies = SecurityPolicies::default();
+        assert!(policies.validate().is_ok());
+        assert!(!policies.is_suspicious_port(22));
+        assert!(policies.is_suspicious_port(4444));
+        assert_eq!(policies.suspicious_domain("abc.ngrok.io."), Some("ngrok.io"));
+        assert_eq!(policies.suspicious_domain("Evil.XYZ"), Some("xyz"));
+        assert_eq!(policies.suspicious_domain("notngrok.io"), None);
+        assert_eq!(policies.suspicious_domain("xyz.example.com"), None);
+
+        let policies: SecurityPolicies = toml::from_str(
+            "allowed_ports = [443]\nsuspicious_ports = [22, \"6660-6669\"]",
+        ).unwrap();
+        assert!(policies.is_suspicious_port(22));
+        assert!(policies.is_suspicious_port(6667));
+        assert!(!policies.is_suspicious_port(6670));
+        assert!(toml::from_str::<SecurityPolicies>("suspicious_ports = [\"10-1\"]").is_err());
+
+        let conflicting: SecurityPolicies = toml::from_str("suspicious_ports = [\"20-23\"]").unwrap();
+        assert!(conflicting.validate().is_err());
+    }
+
+    #[test]
+    fn test_zero_extension_interval_rejected() {
+        let policies: SecurityPolicies = toml::from_str("extension_check_interval_secs = 0").unwrap();
+        assert!(policies.validate().is_err());
+
+        // Set without validation, the interval still can't reach zero
+        let manager = SecurityManager::new(None).unwrap();
+        manager.set_policies(policies).unwrap();
+        assert_eq!(manager.extension_check_interval(), std::time::Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_suspicious_exec_paths() {
+        let policies = SecurityPolicies::default();
+        assert_eq!(policies.suspicious_exec_path(Path::new("/tmp/payload")), Some("/tmp/"));
+        assert_eq!(
+            policies.suspicious_exec_path(Path::new("/Users/alice/Downloads/Installer.app/Contents/MacOS/x")),
+            Some("~/Downloads/")
+        );
+        assert_eq!(policies.suspicious_exec_path(Path::new("/Users/alice/bin/tool")), None);
+        assert_eq!(policies.suspicious_exec_path(Path::new("/usr/bin/ssh")), None);
+        assert_eq!(policies.suspicious_exec_path(Path::new("/tmpfoo/tool")), None);
+
+        let invalid = SecurityPolicies {
+            suspicious_exec_paths: vec!["Downloads".to_string()],
+            ..SecurityPolicies::default()
+        };
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_file_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("abc");
+        fs::write(&path, b"abc").unwrap();
+        assert_eq!(
+            file_hash(&path).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert!(file_hash(dir.path().join("missing")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cached_file_hash_follows_changes() {
+        let manager = SecurityManager::new(None).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("binary");
+        fs::write(&path, b"abc").unwrap();
+
+        let hash = manager.cached_file_hash(&path).await.unwrap();
+        assert_eq!(hash, file_hash(&path).unwrap());
+        assert_eq!(manager.cached_file_hash(&path).await.unwrap(), hash);
+
+        fs::write(&path, b"abcd").unwrap();
+        assert_eq!(manager.cached_file_hash(&path).await.unwrap(), file_hash(&path).unwrap());
+        assert!(manager.cached_file_hash(&dir.path().join("missing")).await.is_err());
+    }
+
+    #[test]
+    fn test_process_path_of_current_process() {
+        let path = process_path(std::process::id() as i32).unwrap();
+        let expected = std::env::current_exe().unwrap();
+        assert_eq!(path.canonicalize().unwrap(), expected.canonicalize().unwrap());
+        assert!(process_path(-1).is_err());
+    }
+
+    #[test]
+    fn test_lookup_user() {
+        assert_eq!(lookup_user("root").unwrap(), (0, 0));
+        let error = lookup_user("ange-gardien-no-such-user").unwrap_err().to_string();
+        assert!(error.contains("does not exist"), "{}", error);
+    }
+
+    #[test]
+    fn test_policies_from_file() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let toml_path = dir.path().join("policies.toml");
+        fs::write(&toml_path, "allowed_ports = [443, 8443]\nsuspicious_processes = [\"miner\"]\n").unwrap();
+        let policies = SecurityPolicies::from_file(&toml_path).unwrap();
+        assert_eq!(policies.allowed_ports, vec![443, 8443]);
+        assert_eq!(policies.suspicious_processes, vec!["miner".to_string()]);
+        assert_eq!(policies.max_cpu_usage, 90.0);
+
+        let yaml_path = dir.path().join("policies.yaml");
+        fs::write(&yaml_path, "allowed_domains:\n  - example.com\nmax_process_cpu: 75\n").unwrap();
+        let policies = SecurityPolicies::from_file(&yaml_path).unwrap();
+        assert_eq!(policies.allowed_domains, vec!["example.com".to_string()]);
+        assert_eq!(policies.max_process_cpu, 75.0);
+
+        fs::write(&toml_path, "allowed_ports = [0]\n").unwrap();
+        assert!(SecurityPolicies::from_file(&toml_path).is_err());
+
+        fs::write(&toml_path, "allowed_paths = [\"usr/local/bin\"]\n").unwrap();
+        let error = SecurityPolicies::from_file(&toml_path).unwrap_err().to_string();
+        assert!(error.contains("must be absolute"), "{}", error);
+
+        fs::write(&toml_path, "allowed_ports = [70000]\n").unwrap();
+        assert!(SecurityPolicies::from_file(&toml_path).is_err());
+    }
 
     #[tokio::test]
     async fn test_security_manager_creation() {
-        let manager = SecurityManager::new().unwrap();
-        let policies = manager.policies.clone();
+        let manager = SecurityManager::new(None).unwrap();
+        let policies = manager.policies();
         assert!(policies.max_cpu_usage > 0.0);
     }
 
+    #[test]
+    fn test_suspicious_process_matching() {
+        let process = |name: &str, command: &str| ProcessInfo {
+            pid: 100,
+            ppid: 1,
+            name: name.to_string(),
+            cpu_usage: 0.0,
+            memory_usage: 0.0,
+            threads: 1,
+            start_time: Utc::now(),
+            command: command.to_string(),
+            disk_bytes_read: 0,
+            disk_bytes_written: 0,
+        };
+
+        let manager = SecurityManager::new(None).unwrap();
+        assert_eq!(manager.find_suspicious_process(&process("nc", "nc -l 4444")), Some(SuspiciousMatch::Name));
+        assert_eq!(manager.find_suspicious_process(&process("vncserver", "vncserver :1")), None);
+        assert_eq!(manager.find_suspicious_process(&process("sync", "sync")), None);
+        assert_eq!(
+            manager.find_suspicious_process(&process("kworker", "kworker 10.0.0.1 4444 -e /bin/bash")),
+            Some(SuspiciousMatch::Argument("-e /bin/bash".to_string()))
+        );
+
+        let mut policies = SecurityPolicies {
+            suspicious_processes: vec![r"-e\s+/bin/(ba)?sh".to_string()],
+            ..Default::default()
+        };
+        manager.set_policies(policies.clone()).unwrap();
+        assert!(manager.find_suspicious_process(&process("update", "update -e /bin/sh 10.0.0.1")).is_some());
+
+        policies.suspicious_process_match = ProcessMatchMode::Substring;
+        policies.suspicious_processes = vec!["nc".to_string()];
+        manager.set_policies(policies.clone()).unwrap();
+        assert_eq!(manager.find_suspicious_process(&process("vncserver", "vncserver :1")), Some(SuspiciousMatch::Name));
+
+        // A bad pattern is rejected and the previous matcher stays active
+        policies.suspicious_process_match = ProcessMatchMode::Regex;
+        policies.suspicious_processes = vec!["(".to_string()];
+        assert!(policies.validate().is_err());
+        assert!(manager.set_policies(policies).is_err());
+        assert_eq!(manager.find_suspicious_process(&process("vncserver", "vncserver :1")), Some(SuspiciousMatch::Name));
+    }
+
+    #[test]
+    fn test_reload_keeps_policies_on_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("policies.toml");
+        let manager = SecurityManager::new(None).unwrap();
+
+        fs::write(&path, "allowed_ports = [443]\nallowed_domains = [\"example.com\"]\n").unwrap();
+        manager.reload_policies(&path).unwrap();
+        assert_eq!(manager.policies().allowed_ports, vec![443]);
+
+        fs::write(&path, "allowed_ports = [443,\n").unwrap();
+        assert!(manager.reload_policies(&path).is_err());
+        assert_eq!(manager.policies().allowed_ports, vec![443]);
+        assert_eq!(manager.policies().allowed_domains, vec!["example.com".to_string()]);
+    }
+
     #[tokio::test]
     async fn test_policy_violation_detection() {
-        let manager = SecurityManager::new().unwrap();
+        let manager = SecurityManager::new(None).unwrap();
         let state = SystemState {
             timestamp: Utc::now(),
             cpu_usage: 95.0, // Should trigger violation
@@ -436,12 +1903,349 @@ mod tests {
                 bytes_received: 0,
                 connections: vec![],
                 suspicious_activity: vec![],
+                bytes_sent_per_sec: 0.0,
+                bytes_received_per_sec: 0.0,
+                dns_cache_hits: 0,
+                dns_cache_misses: 0,
+                icmp_packets: 0,
+                icmp_types: Vec::new(),
             },
-            active_processes: vec![],
-            security_alerts: vec![],
+            ..Default::default()
         };
 
         let violation = manager.check_policies(&state).await.unwrap();
         assert!(violation.is_some());
     }
-} 
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_per_process_limits() {
+        let manager = SecurityManager::new(None).unwrap();
+        manager.policies.write().unwrap().max_process_cpu = 50.0;
+        manager.policies.write().unwrap().process_limit_overrides.insert(
+            "cargo".to_string(),
+            ProcessLimits { max_cpu: Some(100.0), max_memory: None },
+        );
+
+        let process = |pid: u32, name: &str| ProcessInfo {
+            pid,
+            ppid: 1,
+            name: name.to_string(),
+            cpu_usage: 80.0,
+            memory_usage: 1.0,
+            threads: 1,
+            start_time: Utc::now(),
+            command: String::new(),
+            disk_bytes_read: 0,
+            disk_bytes_written: 0,
+        };
+        let state = SystemState {
+            timestamp: Utc::now(),
+            cpu_usage: 10.0,
+            memory_usage: 10.0,
+            disk_usage: 10.0,
+            active_processes: vec![process(1, "cargo"), process(2, "miner")],
+            ..Default::default()
+        };
+
+        let violation = manager.check_policies(&state).await.unwrap().unwrap();
+        assert!(violation.description().contains("Process miner (PID: 2) CPU usage too high"));
+        assert!(!violation.description().contains("Process cargo"));
+        assert_eq!(violation.recommend().as_deref(), Some("Investigate PID 2; consider `kill 2`"));
+    }
+
+    #[tokio::test]
+    async fn test_violation_recommendations() {
+        let manager = SecurityManager::new(None).unwrap();
+        let connection = |remote: &str| ConnectionInfo {
+            local_addr: "10.0.0.2:50000".to_string(),
+            remote_addr: remote.to_string(),
+            protocol: crate::network::Protocol::TCP,
+            state: crate::network::ConnectionState::Established,
+            process_id: None,
+            dns_name: None,
+            country: None,
+            asn: None,
+            bytes_sent: 0,
+            bytes_received: 0,
+            last_seen: Utc::now(),
+        };
+        let state = SystemState {
+            timestamp: Utc::now(),
+            cpu_usage: 95.0,
+            memory_usage: 10.0,
+            disk_usage: 10.0,
+            network_stats: NetworkStats {
+                connections: vec![connection("203.0.113.9:4444"), connection("203.0.113.9:5555")],
+                ..NetworkStats::default()
+            },
+            ..Default::default()
+        };
+
+        // Both ports are reported, but the host only needs blocking once
+        let violation = manager.check_policies(&state).await.unwrap().unwrap();
+        assert!(violation.description().contains("port 4444"));
+        assert!(violation.description().contains("port 5555"));
+        assert_eq!(
+            violation.recommend().as_deref(),
+            Some("Find the processes driving CPU usage with `top -o cpu`; Block outbound to 203.0.113.9")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_changing_readings_fold_into_one_alert() {
+        let manager = SecurityManager::new(None).unwrap();
+        let alerting = crate::alerting::AlertingConfig::default();
+        let mut live = Vec::new();
+        let mut sent = 0;
+
+        for cpu_usage in [91.0, 94.5, 97.2] {
+            let state = SystemState {
+                timestamp: Utc::now(),
+                cpu_usage,
+                memory_usage: 10.0,
+                disk_usage: 10.0,
+                ..Default::default()
+            };
+            let violation = manager.check_policies(&state).await.unwrap().unwrap();
+            sent += alerting.record(&mut live, violation.alerts()).len();
+        }
+
+        // Each cycle reads a different CPU figure, but it's the same breach
+        assert_eq!(sent, 1);
+        assert_eq!(live.len(), 1);
+        assert_eq!(live[0].rule.as_deref(), Some("cpu"));
+        assert_eq!(live[0].source, POLICY_CHECK_SOURCE);
+        assert_eq!(live[0].count, 3);
+    }
+
+    #[tokio::test]
+    async fn test_per_mount_disk_usage() {
+        let manager = SecurityManager::new(None).unwrap();
+        let disk = |mount_point: &str, usage_percent: f32| DiskUsage {
+            mount_point: mount_point.to_string(),
+            file_system: "apfs".to_string(),
+            total_bytes: 100,
+            used_bytes: usage_percent as u64,
+            usage_percent,
+        };
+        // The average looks healthy, the boot volume doesn't
+        let state = SystemState {
+            timestamp: Utc::now(),
+            cpu_usage: 10.0,
+            memory_usage: 10.0,
+            disk_usage: 50.0,
+            disks: vec![disk("/", 97.0), disk("/Volumes/Backup", 3.0)],
+            ..Default::default()
+        };
+
+        let violation = manager.check_policies(&state).await.unwrap().unwrap();
+        assert!(violation.description().contains("Disk usage too high on /: 97.0%"));
+        assert!(!violation.description().contains("/Volumes/Backup"));
+    }
+
+    #[tokio::test]
+    async fn test_load_average_relative_to_cores() {
+        let manager = SecurityManager::new(None).unwrap();
+        let mut state = SystemState {
+            timestamp: Utc::now(),
+            cpu_usage: 10.0,
+            memory_usage: 10.0,
+            disk_usage: 10.0,
+            system_metrics: Some(SystemMetrics {
+                physical_cpu_count: 8,
+                load_average: 10.0,
+                ..SystemMetrics::default()
+            }),
+            ..Default::default()
+        };
+        // 1.25 per core is within the default 1.5
+        assert!(manager.check_policies(&state).await.unwrap().is_none());
+
+        state.system_metrics.as_mut().unwrap().physical_cpu_count = 4;
+        let violation = manager.check_policies(&state).await.unwrap().unwrap();
+        assert!(violation.description().contains("Load average too high: 10.00 on 4 cores (2.50 per core, max: 1.50)"));
+    }
+
+    #[tokio::test]
+    async fn test_sustained_swapping() {
+        let manager = SecurityManager::new(None).unwrap();
+        let start = Utc::now();
+        let state = |seconds: i64, swap_outs: f64| SystemState {
+            timestamp: start + chrono::Duration::seconds(seconds),
+            cpu_usage: 10.0,
+            memory_usage: 10.0,
+            disk_usage: 10.0,
+            system_metrics: Some(SystemMetrics {
+                swap_outs,
+                memory_pressure: MemoryPressure::Critical,
+                ..SystemMetrics::default()
+            }),
+            ..Default::default()
+        };
+
+        assert!(manager.check_policies(&state(0, 500.0)).await.unwrap().is_none());
+        assert!(manager.check_policies(&state(30, 500.0)).await.unwrap().is_none());
+        let violation = manager.check_policies(&state(60, 500.0)).await.unwrap().unwrap();
+        assert!(violation.description().contains("Sustained swapping: 500 pages/s for 60s"));
+        assert!(violation.description().contains("memory pressure critical"));
+
+        // A quiet sample restarts the clock
+        assert!(manager.check_policies(&state(70, 0.0)).await.unwrap().is_none());
+        assert!(manager.check_policies(&state(80, 500.0)).await.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_expected_process_matches_absolute_path() {
+        let exe = std::env::current_exe().unwrap();
+        let this = ProcessInfo {
+            pid: std::process::id(),
+            ppid: 1,
+            name: exe.file_name().unwrap().to_string_lossy().into_owned(),
+            cpu_usage: 0.0,
+            memory_usage: 0.0,
+            threads: 1,
+            start_time: Utc::now(),
+            command: String::new(),
+            disk_bytes_read: 0,
+            disk_bytes_written: 0,
+        };
+
+        let exe = exe.canonicalize().unwrap();
+        assert!(SecurityManager::matches_expected_process(exe.to_str().unwrap(), &this));
+        assert!(SecurityManager::matches_expected_process(&this.name, &this));
+        // Same file name, different directory
+        let elsewhere = Path::new("/opt/elsewhere").join(&this.name);
+        assert!(!SecurityManager::matches_expected_process(elsewhere.to_str().unwrap(), &this));
+    }
+
+    #[tokio::test]
+    async fn test_service_liveness_alert_and_recovery() {
+        let manager = SecurityManager::new(None).unwrap();
+        manager.policies.write().unwrap().expected_processes = vec!["postgres".to_string()];
+        manager.policies.write().unwrap().expected_process_grace_secs = 10;
+
+        let postgres = ProcessInfo {
+            pid: 42,
+            ppid: 1,
+            name: "postgres".to_string(),
+            cpu_usage: 1.0,
+            memory_usage: 2.0,
+            threads: 4,
+            start_time: Utc::now(),
+            command: String::new(),
+            disk_bytes_read: 0,
+            disk_bytes_written: 0,
+        };
+        let mut state = SystemState {
+            timestamp: Utc::now(),
+            cpu_usage: 10.0,
+            memory_usage: 10.0,
+            disk_usage: 10.0,
+            active_processes: vec![postgres.clone()],
+            ..Default::default()
+        };
+
+        let report = manager.check_service_liveness(&state).await.unwrap();
+        assert!(report.alerts.is_empty());
+        assert_eq!(manager.get_service_status().await.get("postgres"), Some(&true));
+
+        // Missing, but still within the grace period
+        state.active_processes.clear();
+        state.timestamp = state.timestamp + chrono::Duration::seconds(5);
+        let report = manager.check_service_liveness(&state).await.unwrap();
+        assert!(report.alerts.is_empty());
+
+        // Missing beyond the grace period
+        state.timestamp = state.timestamp + chrono::Duration::seconds(10);
+        let report = manager.check_service_liveness(&state).await.unwrap();
+        assert_eq!(report.alerts.len(), 1);
+        assert_eq!(report.alerts[0].severity, AlertSeverity::High);
+
+        // Alerted only once while down
+        state.timestamp = state.timestamp + chrono::Duration::seconds(10);
+        let report = manager.check_service_liveness(&state).await.unwrap();
+        assert!(report.alerts.is_empty());
+
+        state.active_processes.push(postgres);
+        let report = manager.check_service_liveness(&state).await.unwrap();
+        assert_eq!(report.recovered, vec!["postgres".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_terminate_process_and_dry_run() {
+        let policies = SecurityPolicies {
+            auto_terminate_suspicious: true,
+            terminate_dry_run: true,
+            // Our unreaped child stays a zombie, so its escalation always runs to SIGKILL
+            terminate_grace_secs: 1,
+            suspicious_processes: vec!["^sleep$".to_string()],
+            ..Default::default()
+        };
+        let manager = SecurityManager::new(Some(policies)).unwrap();
+
+        let mut child = std::process::Command::new("sleep").arg("30").spawn().unwrap();
+        let state = SystemState {
+            timestamp: Utc::now(),
+            cpu_usage: 0.0,
+            memory_usage: 0.0,
+            disk_usage: 0.0,
+            active_processes: vec![ProcessInfo {
+                pid: child.id(),
+                ppid: 1,
+                name: "sleep".to_string(),
+                cpu_usage: 0.0,
+                memory_usage: 0.0,
+                threads: 1,
+                start_time: Utc::now(),
+                command: "sleep 30".to_string(),
+                disk_bytes_read: 0,
+                disk_bytes_written: 0,
+            }],
+            ..Default::default()
+        };
+
+        // Dry run leaves the process alone
+        assert!(manager.enforce_suspicious_processes(&state).await.is_empty());
+        assert!(child.try_wait().unwrap().is_none());
+
+        // So does monitor mode, but it reports what it held back
+        manager.policies.write().unwrap().terminate_dry_run = false;
+        let monitoring = SecurityManager::new(Some(manager.policies()))
+            .unwrap()
+            .with_enforcement_mode(EnforcementMode::Monitor);
+        let alerts = monitoring.enforce_suspicious_processes(&state).await;
+        assert_eq!(alerts.len(), 1);
+        assert!(alerts[0].would_enforce);
+        assert!(child.try_wait().unwrap().is_none());
+
+        let alerts = manager.enforce_suspicious_processes(&state).await;
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].severity, AlertSeverity::Critical);
+        assert!(alerts[0].description.contains(&child.id().to_string()));
+        assert!(!child.wait().unwrap().success());
+
+        assert!(manager.terminate_process(1).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_terminate_escalates_in_background() {
+        let policies = SecurityPolicies { terminate_grace_secs: 1, ..Default::default() };
+        let manager = SecurityManager::new(Some(policies)).unwrap();
+
+        let mut child = std::process::Command::new("sh")
+            .args(["-c", "trap '' TERM; sleep 30"])
+            .spawn()
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        // Returns once SIGTERM is sent, without waiting out the grace period
+        let started = std::time::Instant::now();
+        manager.terminate_process(child.id()).await.unwrap();
+        assert!(started.elapsed() < std::time::Duration::from_millis(500));
+        assert!(child.try_wait().unwrap().is_none());
+
+        tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+        let status = child.try_wait().unwrap().expect("SIGKILL after the grace period");
+        assert!(!status.success());
+    }
+}
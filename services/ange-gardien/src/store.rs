@@ -0,0 +1,398 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::RwLock;
+use crate::database::{CleanupReport, Distribution, Metric, SeverityCounts, SystemStatistics, UsageSample};
+use crate::incidents::Incident;
+use crate::{SystemState, SecurityAlert, AlertSeverity};
+
+/// Persistence for collected states, alerts and operator feedback. SQLite
+/// (`Database`) is the default backend; others plug in by implementing this.
+#[async_trait]
+pub trait StateStore: Send + Sync {
+    /// Stores the state, and each of its alerts separately for querying.
+    async fn store_state(&self, state: &SystemState) -> Result<()>;
+
+    /// Writes anything `store_state` has buffered. Backends that buffer flush
+    /// before every read too, so this only matters for durability, e.g. on
+    /// shutdown.
+    async fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Alerts newer than `since`, newest first, optionally only those at or
+    /// above `min_severity`.
+    async fn get_alerts_since(
+        &self,
+        since: DateTime<Utc>,
+        min_severity: Option<AlertSeverity>,
+    ) -> Result<Vec<SecurityAlert>>;
+
+    /// Alerts raised from `start` to `end` inclusive, oldest first.
+    async fn get_alerts_between(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<SecurityAlert>>;
+
+    /// Alert counts grouped by (source, severity) since `since`, largest first.
+    async fn get_alert_breakdown(&self, since: DateTime<Utc>) -> Result<Vec<(String, AlertSeverity, i64)>>;
+
+    /// Down-samples states older than `older_than` into one-minute averages,
+    /// returning how many raw states were folded away. Backends without a
+    /// rollup table keep every raw state.
+    async fn rollup_old_states(&self, _older_than: DateTime<Utc>) -> Result<usize> {
+        Ok(0)
+    }
+
+    /// The `limit` most recent states, newest first.
+    async fn get_system_states(&self, limit: i64) -> Result<Vec<SystemState>>;
+
+    /// States recorded from `start` to `end` inclusive, oldest first.
+    async fn get_system_states_between(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<SystemState>>;
+
+    /// States recorded since `since`, oldest first.
+    async fn get_system_states_since(&self, since: DateTime<Utc>) -> Result<Vec<SystemState>> {
+        self.get_system_states_between(since, Utc::now()).await
+    }
+
+    /// Calls `visit` with each state recorded since `since`, oldest first.
+    /// Backends that can read from a cursor override this so long ranges
+    /// aren't loaded at once.
+    async fn for_each_state_since(
+        &self,
+        since: DateTime<Utc>,
+        visit: &mut (dyn FnMut(SystemState) -> Result<()> + Send),
+    ) -> Result<()> {
+        for state in self.get_system_states_since(since).await? {
+            visit(state)?;
+        }
+        Ok(())
+    }
+
+    /// Calls `visit` with the CPU, memory and disk usage of each state
+    /// recorded since `since`, oldest first. Backends override this to read
+    /// only those columns.
+    async fn for_each_usage_since(
+        &self,
+        since: DateTime<Utc>,
+        visit: &mut (dyn FnMut(UsageSample) -> Result<()> + Send),
+    ) -> Result<()> {
+        self.for_each_state_since(since, &mut |state| visit(UsageSample::of(&state))).await
+    }
+
+    /// Calls `visit` with each alert raised since `since`, oldest first.
+    async fn for_each_alert_since(
+        &self,
+        since: DateTime<Utc>,
+        visit: &mut (dyn FnMut(SecurityAlert) -> Result<()> + Send),
+    ) -> Result<()> {
+        for alert in self.get_alerts_between(since, Utc::now()).await? {
+            visit(alert)?;
+        }
+        Ok(())
+    }
+
+    /// The most recent state at or before `at`.
+    async fn get_state_at(&self, at: DateTime<Utc>) -> Result<Option<SystemState>>;
+
+    async fn store_anomaly_feedback(&self, state: &SystemState) -> Result<()>;
+
+    /// States operators have labelled as false positives, oldest first.
+    async fn get_anomaly_feedback(&self) -> Result<Vec<SystemState>>;
+
+    /// Stores `incident`, replacing the stored copy with the same id as the
+    /// incident grows.
+    async fn store_incident(&self, incident: &Incident) -> Result<()>;
+
+    /// Incidents started after `since`, newest first.
+    async fn get_incidents_since(&self, since: DateTime<Utc>) -> Result<Vec<Incident>>;
+
+    /// Deletes states, alerts and incidents older than `older_than`.
+    async fn cleanup_old_records(&self, older_than: DateTime<Utc>) -> Result<CleanupReport>;
+
+    async fn get_statistics(&self, since: DateTime<Utc>) -> Result<SystemStatistics>;
+
+    async fn get_metric_distribution(
+        &self,
+        metric: Metric,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> Result<Distribution>;
+}
+
+/// `StateStore` kept entirely in memory, for tests and short-lived runs that
+/// shouldn't touch the filesystem. States are kept sorted by timestamp.
+#[derive(Default)]
+pub struct InMemoryStore {
+    states: RwLock<Vec<SystemState>>,
+    alerts: RwLock<Vec<SecurityAlert>>,
+    feedback: RwLock<Vec<SystemState>>,
+    incidents: RwLock<Vec<Incident>>,
+}
+
+impl InMemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn metric_value(state: &SystemState, metric: Metric) -> f64 {
+        match metric {
+            Metric::Cpu => state.cpu_usage as f64,
+            Metric::Memory => state.memory_usage as f64,
+            Metric::Disk => state.disk_usage as f64,
+        }
+    }
+
+    fn distribution<'a>(states: impl Iterator<Item = &'a SystemState>, metric: Metric) -> Distribution {
+        let mut values: Vec<f64> = states.map(|s| Self::metric_value(s, metric)).collect();
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        Distribution::from_sorted(&values)
+    }
+}
+
+fn read<T>(lock: &RwLock<T>) -> std::sync::RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(|e| e.into_inner())
+}
+
+fn write<T>(lock: &RwLock<T>) -> std::sync::RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(|e| e.into_inner())
+}
+
+#[async_trait]
+impl StateStore for InMemoryStore {
+    async fn store_state(&self, state: &SystemState) -> Result<()> {
+        let mut states = write(&self.states);
+        // Insert after any states with the same timestamp, like an append
+        let index = states.partition_point(|s| s.timestamp <= state.timestamp);
+        states.insert(index, state.clone());
+        // Like the databases, keep one copy of an alert carried through many states
+        let mut alerts = write(&self.alerts);
+        for alert in &state.security_alerts {
+            let stored = alerts.iter_mut().find(|stored| {
+                stored.timestamp == alert.timestamp
+                    && stored.severity == alert.severity
+                    && stored.source == alert.source
+                    && stored.description == alert.description
+            });
+            match stored {
+                Some(stored) => stored.count = alert.count,
+                None => alerts.push(alert.clone()),
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_alerts_since(
+        &self,
+        since: DateTime<Utc>,
+        min_severity: Option<AlertSeverity>,
+    ) -> Result<Vec<SecurityAlert>> {
+        let min_severity = min_severity.unwrap_or(AlertSeverity::Low);
+        let mut alerts: Vec<SecurityAlert> = read(&self.alerts).iter()
+            .filter(|a| a.timestamp > since && a.severity >= min_severity)
+            .cloned()
+            .collect();
+        alerts.sort_by_key(|alert| std::cmp::Reverse(alert.timestamp));
+        Ok(alerts)
+    }
+
+    async fn get_alerts_between(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<SecurityAlert>> {
+        let mut alerts: Vec<SecurityAlert> = read(&self.alerts).iter()
+            .filter(|a| a.timestamp >= start && a.timestamp <= end)
+            .cloned()
+            .collect();
+        alerts.sort_by_key(|alert| alert.timestamp);
+        Ok(alerts)
+    }
+
+    async fn get_alert_breakdown(&self, since: DateTime<Utc>) -> Result<Vec<(String, AlertSeverity, i64)>> {
+        let mut counts: HashMap<(String, AlertSeverity), i64> = HashMap::new();
+        for alert in read(&self.alerts).iter().filter(|a| a.timestamp > since) {
+            *counts.entry((alert.source.clone(), alert.severity)).or_insert(0) += 1;
+        }
+
+        let mut breakdown: Vec<(String, AlertSeverity, i64)> = counts.into_iter()
+            .map(|((source, severity), count)| (source, severity, count))
+            .collect();
+        breakdown.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(&b.0)));
+        Ok(breakdown)
+    }
+
+    async fn get_system_states(&self, limit: i64) -> Result<Vec<SystemState>> {
+        Ok(read(&self.states).iter()
+            .rev()
+            .take(limit.max(0) as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_system_states_between(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<SystemState>> {
+        let states = read(&self.states);
+        let from = states.partition_point(|s| s.timestamp < start);
+        let to = states.partition_point(|s| s.timestamp <= end).max(from);
+        Ok(states[from..to].to_vec())
+    }
+
+    async fn get_state_at(&self, at: DateTime<Utc>) -> Result<Option<SystemState>> {
+        let states = read(&self.states);
+        let end = states.partition_point(|s| s.timestamp <= at);
+        Ok(end.checked_sub(1).map(|i| states[i].clone()))
+    }
+
+    async fn store_anomaly_feedback(&self, state: &SystemState) -> Result<()> {
+        write(&self.feedback).push(state.clone());
+        Ok(())
+    }
+
+    async fn get_anomaly_feedback(&self) -> Result<Vec<SystemState>> {
+        Ok(read(&self.feedback).clone())
+    }
+
+    async fn store_incident(&self, incident: &Incident) -> Result<()> {
+        let mut incidents = write(&self.incidents);
+        match incidents.iter_mut().find(|stored| stored.id == incident.id) {
+            Some(stored) => *stored = incident.clone(),
+            None => incidents.push(incident.clone()),
+        }
+        Ok(())
+    }
+
+    async fn get_incidents_since(&self, since: DateTime<Utc>) -> Result<Vec<Incident>> {
+        let mut incidents: Vec<Incident> = read(&self.incidents).iter()
+            .filter(|i| i.started_at > since)
+            .cloned()
+            .collect();
+        incidents.sort_by_key(|incident| std::cmp::Reverse(incident.started_at));
+        Ok(incidents)
+    }
+
+    async fn cleanup_old_records(&self, older_than: DateTime<Utc>) -> Result<CleanupReport> {
+        let mut states = write(&self.states);
+        let states_before = states.len();
+        states.retain(|s| s.timestamp >= older_than);
+
+        let mut alerts = write(&self.alerts);
+        let alerts_before = alerts.len();
+        alerts.retain(|a| a.timestamp >= older_than);
+        write(&self.incidents).retain(|i| i.started_at >= older_than);
+
+        Ok(CleanupReport {
+            states_deleted: states_before - states.len(),
+            alerts_deleted: alerts_before - alerts.len(),
+            bytes_reclaimed: 0,
+        })
+    }
+
+    async fn get_statistics(&self, since: DateTime<Utc>) -> Result<SystemStatistics> {
+        let states = read(&self.states);
+        let recent: Vec<&SystemState> = states.iter().filter(|s| s.timestamp > since).collect();
+        let average = |value: fn(&SystemState) -> f32| -> f64 {
+            if recent.is_empty() {
+                return 0.0;
+            }
+            recent.iter().map(|s| value(s) as f64).sum::<f64>() / recent.len() as f64
+        };
+
+        let mut alerts_by_severity = SeverityCounts::default();
+        let mut alert_count = 0;
+        for alert in read(&self.alerts).iter().filter(|a| a.timestamp > since) {
+            alerts_by_severity.add(alert.severity, 1);
+            alert_count += 1;
+        }
+
+        Ok(SystemStatistics {
+            avg_cpu: average(|s| s.cpu_usage),
+            avg_memory: average(|s| s.memory_usage),
+            avg_disk: average(|s| s.disk_usage),
+            cpu: Self::distribution(recent.iter().copied(), Metric::Cpu),
+            memory: Self::distribution(recent.iter().copied(), Metric::Memory),
+            disk: Self::distribution(recent.iter().copied(), Metric::Disk),
+            total_records: recent.len() as i64,
+            alert_count,
+            alerts_by_severity,
+        })
+    }
+
+    async fn get_metric_distribution(
+        &self,
+        metric: Metric,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> Result<Distribution> {
+        let states = read(&self.states);
+        Ok(Self::distribution(
+            states.iter().filter(|s| s.timestamp >= since && s.timestamp <= until),
+            metric,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn state_at(timestamp: DateTime<Utc>, cpu_usage: f32) -> SystemState {
+        SystemState {
+            timestamp,
+            cpu_usage,
+            per_core_cpu: Vec::new(),
+            memory_usage: 60.0,
+            disk_usage: 70.0,
+            disks: Vec::new(),
+            network_stats: Default::default(),
+            active_processes: vec![],
+            security_alerts: vec![],
+            system_metrics: None,
+            collection_duration_ms: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_store_and_retrieve_state() {
+        let store = InMemoryStore::new();
+        let now = Utc::now();
+
+        store.store_state(&state_at(now, 50.0)).await.unwrap();
+        store.store_state(&state_at(now - Duration::minutes(5), 30.0)).await.unwrap();
+
+        let states = store.get_system_states(1).await.unwrap();
+        assert_eq!(states.len(), 1);
+        assert_eq!(states[0].cpu_usage, 50.0);
+
+        let since = store.get_system_states_since(now - Duration::minutes(10)).await.unwrap();
+        assert_eq!(since.iter().map(|s| s.cpu_usage).collect::<Vec<_>>(), vec![30.0, 50.0]);
+
+        let between = store.get_system_states_between(now - Duration::minutes(10), now - Duration::minutes(1))
+            .await
+            .unwrap();
+        assert_eq!(between.iter().map(|s| s.cpu_usage).collect::<Vec<_>>(), vec![30.0]);
+
+        let earlier = store.get_state_at(now - Duration::minutes(1)).await.unwrap().unwrap();
+        assert_eq!(earlier.cpu_usage, 30.0);
+
+        let stats = store.get_statistics(now - Duration::hours(1)).await.unwrap();
+        assert_eq!(stats.total_records, 2);
+        assert_eq!(stats.avg_cpu, 40.0);
+        assert_eq!(stats.cpu.p95, 50.0);
+        assert_eq!(stats.cpu.max, 50.0);
+
+        let report = store.cleanup_old_records(now - Duration::minutes(1)).await.unwrap();
+        assert_eq!(report.states_deleted, 1);
+        assert_eq!(store.get_system_states(10).await.unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,172 @@
+use anyhow::Result;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tracing::{debug, Instrument, Span};
+
+/// Runs `future` inside `span` and records its wall time in the span's
+/// `duration_ms` field, which must be declared (as `field::Empty`) by the caller.
+pub async fn traced<T, F>(span: Span, future: F) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    let start = Instant::now();
+    let result = future.instrument(span.clone()).await;
+    span.record("duration_ms", start.elapsed().as_millis() as u64);
+    result
+}
+
+/// Like `traced`, also adding the wall time to `elapsed`.
+pub async fn timed<T, F>(elapsed: &mut Duration, span: Span, future: F) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    let start = Instant::now();
+    let result = traced(span, future).await;
+    *elapsed += start.elapsed();
+    result
+}
+
+/// Wall time of each phase of one monitoring cycle. Phases are timed even
+/// when their spans are filtered out, so overruns can always be explained.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CycleTimings {
+    pub metrics: Duration,
+    pub network: Duration,
+    pub processes: Duration,
+    pub analysis: Duration,
+    pub storage: Duration,
+    pub security: Duration,
+    /// The whole cycle, including the checks and delivery between phases
+    pub total: Duration,
+}
+
+impl CycleTimings {
+    fn phases(&self) -> [(&'static str, Duration); 6] {
+        [
+            ("metrics", self.metrics),
+            ("network", self.network),
+            ("processes", self.processes),
+            ("analysis", self.analysis),
+            ("storage", self.storage),
+            ("security", self.security),
+        ]
+    }
+
+    /// The phase that took longest, by name
+    pub fn slowest(&self) -> (&'static str, Duration) {
+        self.phases().into_iter().max_by_key(|(_, elapsed)| *elapsed).unwrap_or_default()
+    }
+
+    pub fn log(&self) {
+        debug!(
+            metrics_ms = self.metrics.as_millis() as u64,
+            network_ms = self.network.as_millis() as u64,
+            processes_ms = self.processes.as_millis() as u64,
+            analysis_ms = self.analysis.as_millis() as u64,
+            storage_ms = self.storage.as_millis() as u64,
+            security_ms = self.security.as_millis() as u64,
+            total_ms = self.total.as_millis() as u64,
+            "Monitoring cycle timings"
+        );
+    }
+}
+
+/// How log events are written to stderr.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per event, for log aggregation
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(anyhow::anyhow!("Unknown log format: {}", s)),
+        }
+    }
+}
+
+/// Installs the global subscriber. Events at `level` and above go to stderr
+/// in `format`, unless `RUST_LOG` is set, and records from crates still on
+/// `log` are forwarded. With the `otel` feature, spans are also exported to
+/// `otlp_endpoint` over OTLP/gRPC when one is given, whatever the level.
+pub fn init_logging(level: &str, format: LogFormat, otlp_endpoint: Option<&str>) -> Result<()> {
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+    use tracing_subscriber::{EnvFilter, Layer};
+
+    let filter = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new(level))
+        .unwrap_or_else(|_| EnvFilter::new("info"));
+    let output = match format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().with_writer(std::io::stderr).boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer().json().with_writer(std::io::stderr).boxed(),
+    };
+    let subscriber = tracing_subscriber::registry().with(output.with_filter(filter));
+
+    #[cfg(feature = "otel")]
+    let subscriber = subscriber.with(otlp_endpoint.map(otel_layer).transpose()?);
+    #[cfg(not(feature = "otel"))]
+    if otlp_endpoint.is_some() {
+        return Err(anyhow::anyhow!("Exporting traces needs the otel feature, which this build lacks"));
+    }
+
+    subscriber.try_init()?;
+    Ok(())
+}
+
+#[cfg(feature = "otel")]
+fn otel_layer<S>(endpoint: &str) -> Result<tracing_opentelemetry::OpenTelemetryLayer<S, opentelemetry_sdk::trace::Tracer>>
+where
+    S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::{runtime, trace, Resource};
+
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_endpoint(endpoint))
+        .with_trace_config(trace::config().with_resource(Resource::new(vec![
+            KeyValue::new("service.name", env!("CARGO_PKG_NAME")),
+            KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+        ])))
+        .install_batch(runtime::Tokio)?;
+
+    Ok(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+#[cfg(feature = "otel")]
+pub fn shutdown_otel() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slowest_phase() {
+        let timings = CycleTimings {
+            network: Duration::from_millis(40),
+            analysis: Duration::from_millis(900),
+            storage: Duration::from_millis(15),
+            total: Duration::from_millis(1200),
+            ..CycleTimings::default()
+        };
+        assert_eq!(timings.slowest(), ("analysis", Duration::from_millis(900)));
+    }
+
+    #[test]
+    fn test_log_format_parsing() {
+        assert_eq!("json".parse::<LogFormat>().unwrap(), LogFormat::Json);
+        assert_eq!(" Text ".parse::<LogFormat>().unwrap(), LogFormat::Text);
+        assert!("yaml".parse::<LogFormat>().is_err());
+    }
+}